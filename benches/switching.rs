@@ -0,0 +1,105 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::hint::black_box;
+use windows::Win32::Media::Audio::{eConsole, eRender, Headphones, Speakers};
+use windows_core::GUID;
+
+use sound_switcheroo::backend::{AudioBackend, AudioDevice};
+use sound_switcheroo::config::{Config, DeviceConfig, MachineConfig};
+use sound_switcheroo::menu::{Endpoints, MenuModel};
+use sound_switcheroo::rotation::{select_next, RotationPolicy};
+use sound_switcheroo::simulation::{Fixture, SimulatedBackend};
+
+// More devices than any real machine has, so regressions show up clearly.
+const DEVICE_COUNT: usize = 64;
+
+fn mock_backend() -> SimulatedBackend {
+    let render = (0..DEVICE_COUNT)
+        .map(|i| AudioDevice {
+            selectable: i % 3 != 0,
+            container_id: Some(GUID::from_u128(i as u128 / 2)),
+            description: Some("Speakers".to_string()),
+            adapter: Some("High Definition Audio Device".to_string()),
+            ..AudioDevice::new(
+                format!("{{0.0.0.00000000}}.{{{i:08x}}}"),
+                format!("Speakers {i} (High Definition Audio Device)"),
+                if i % 2 == 0 { Speakers } else { Headphones },
+            )
+        })
+        .collect();
+    SimulatedBackend::from_fixture(Fixture {
+        render,
+        ..Default::default()
+    })
+}
+
+fn config_for(devices: &[AudioDevice]) -> Config {
+    Config {
+        devices: devices
+            .iter()
+            .map(|d| {
+                (
+                    d.id.clone(),
+                    DeviceConfig {
+                        selectable: d.selectable,
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn menu_model(c: &mut Criterion) {
+    let backend = mock_backend();
+    let devices = backend.devices(eRender).unwrap();
+    let config = config_for(&devices);
+    c.bench_function("menu model build", |b| {
+        b.iter(|| {
+            MenuModel::build(
+                black_box(&devices),
+                &devices[0].id,
+                &Endpoints::default(),
+                black_box(&config),
+                &MachineConfig::default(),
+                &[],
+                Some(45),
+            )
+        })
+    });
+}
+
+fn config_round_trip(c: &mut Criterion) {
+    let backend = mock_backend();
+    let config = config_for(&backend.devices(eRender).unwrap());
+    // A directory of its own, so runs side by side (and the tests) don't share the file.
+    let dir = std::env::temp_dir().join(format!("sound-switcheroo-bench-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("device_config.json");
+    c.bench_function("config save", |b| {
+        b.iter(|| config.save_to(black_box(&path)).unwrap())
+    });
+    c.bench_function("config load", |b| {
+        b.iter(|| Config::load_from(black_box(&path)).unwrap())
+    });
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+fn rotation(c: &mut Criterion) {
+    let backend = mock_backend();
+    let devices = backend.devices(eRender).unwrap();
+    let policy = RotationPolicy {
+        skip_siblings: true,
+        ..Default::default()
+    };
+    c.bench_function("select next", |b| {
+        b.iter(|| {
+            let current = backend.default_device(eRender, eConsole).unwrap();
+            let next = select_next(black_box(&current), &devices, &policy).unwrap();
+            backend.set_default_device(&next.id, eConsole).unwrap();
+        })
+    });
+}
+
+criterion_group!(benches, menu_model, config_round_trip, rotation);
+criterion_main!(benches);
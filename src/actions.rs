@@ -0,0 +1,80 @@
+use std::time::{Duration, Instant};
+
+/// Something the user (or automation) asked for. Everything that changes the default device goes
+/// through one dispatcher, so triggers can be rate limited in one place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    NextDevice,
+    // Back to the output used before the current one.
+    SwapDevices,
+    // The other way through the rotation.
+    PreviousDevice,
+    // Switch to the output device with this ID.
+    SwitchTo(String),
+    // The user picked the output device with this ID: only the app in the foreground is switched
+    // to it, if that's what is set up.
+    ChooseDevice(String),
+    NextMicrophone,
+    // Switch to the capture device with this ID.
+    SwitchMicrophoneTo(String),
+    // Apply the profile with this name.
+    ApplyProfile(String),
+    // Restore the routing snapshot with this name.
+    RestoreSnapshot(String),
+}
+
+/// Some drivers misbehave when the default device changes several times in quick succession, e.g.
+/// from a double-clicked tray icon or a bouncing hotkey.
+pub const MIN_SWITCH_INTERVAL: Duration = Duration::from_millis(300);
+
+/// Drops requests that come too soon after the last one that was allowed.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    min_interval: Duration,
+    last: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            last: None,
+        }
+    }
+
+    /// Whether a request at `now` may go ahead, recording it if so.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        if let Some(last) = self.last
+            && now.saturating_duration_since(last) < self.min_interval
+        {
+            return false;
+        }
+        self.last = Some(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_requests_inside_the_interval() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(Duration::from_millis(300));
+        assert!(limiter.allow(start));
+        assert!(!limiter.allow(start + Duration::from_millis(50)));
+        assert!(!limiter.allow(start + Duration::from_millis(299)));
+        assert!(limiter.allow(start + Duration::from_millis(300)));
+    }
+
+    #[test]
+    fn dropped_requests_do_not_extend_the_interval() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::new(Duration::from_millis(300));
+        assert!(limiter.allow(start));
+        assert!(!limiter.allow(start + Duration::from_millis(200)));
+        // Measured from the last allowed request, not the dropped one.
+        assert!(limiter.allow(start + Duration::from_millis(350)));
+    }
+}
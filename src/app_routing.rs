@@ -0,0 +1,165 @@
+#![allow(non_snake_case)]
+
+use sound_switcheroo::error::SwitcherooError;
+use std::ffi::c_void;
+use windows::Win32::Media::Audio::{eCapture, eMultimedia, EDataFlow, ERole};
+use windows::Win32::System::WinRT::RoGetActivationFactory;
+use windows_core::{Interface, HRESULT};
+use windows_strings::HSTRING;
+
+// Per-app devices, as set in Windows' "App volume and device preferences", go through an
+// undocumented WinRT factory. See EarTrumpet's AudioPolicyConfig for the same layout.
+const AUDIO_POLICY_CONFIG_CLASS: &str = "Windows.Media.Internal.AudioPolicyConfig";
+
+// The device interface classes that per-app device IDs are given in.
+const RENDER_INTERFACE: &str = "{e6327cad-dcec-4949-ae8a-991e976a79d2}";
+const CAPTURE_INTERFACE: &str = "{2eef81be-33fa-4800-9670-1cd474972c3f}";
+const MMDEVAPI_PREFIX: &str = r"\\?\SWD#MMDEVAPI#";
+
+// VTable for the undocumented AudioPolicyConfig factory, as of Windows 10 21H2.
+#[repr(C)]
+#[doc(hidden)]
+pub struct IAudioPolicyConfigFactory_Vtbl {
+    pub base__: windows_core::IInspectable_Vtbl,
+    // Volume group, ringer and chat app methods the switcher doesn't use.
+    unused: [usize; 19],
+    pub SetPersistedDefaultAudioEndpoint:
+        unsafe extern "system" fn(this: *mut c_void, u32, EDataFlow, ERole, *mut c_void) -> HRESULT,
+    pub GetPersistedDefaultAudioEndpoint: unsafe extern "system" fn(
+        this: *mut c_void,
+        u32,
+        EDataFlow,
+        ERole,
+        *mut *mut c_void,
+    ) -> HRESULT,
+    pub ClearAllPersistedApplicationDefaultEndpoints:
+        unsafe extern "system" fn(this: *mut c_void) -> HRESULT,
+}
+
+windows_core::imp::define_interface!(
+    IAudioPolicyConfigFactory,
+    IAudioPolicyConfigFactory_Vtbl,
+    0xab3d4648_e242_459f_b02f_541c70306324
+);
+windows_core::imp::interface_hierarchy!(
+    IAudioPolicyConfigFactory,
+    windows_core::IUnknown,
+    windows_core::IInspectable
+);
+
+/// The ID per-app device preferences know the endpoint with MMDevice ID `device_id` by.
+pub fn persisted_id(device_id: &str, flow: EDataFlow) -> String {
+    let interface = if flow == eCapture {
+        CAPTURE_INTERFACE
+    } else {
+        RENDER_INTERFACE
+    };
+    format!("{MMDEVAPI_PREFIX}{device_id}#{interface}")
+}
+
+/// The MMDevice ID of the endpoint a per-app device preference names, if it names one.
+pub fn device_id(persisted_id: &str) -> Option<&str> {
+    let rest = persisted_id.strip_prefix(MMDEVAPI_PREFIX)?;
+    let (device_id, _) = rest.rsplit_once('#')?;
+    Some(device_id)
+}
+
+fn factory() -> Result<IAudioPolicyConfigFactory, SwitcherooError> {
+    unsafe {
+        RoGetActivationFactory(&HSTRING::from(AUDIO_POLICY_CONFIG_CLASS)).map_err(|e| {
+            SwitcherooError::Other(format!(
+                "Per-app devices aren't available on this version of Windows: {e}"
+            ))
+        })
+    }
+}
+
+/// Sends the audio of the process with ID `pid` to the endpoint for `roles`, without changing the
+/// default device. Multimedia is always included, as Windows' own per-app setting sets both.
+pub fn route(
+    pid: u32,
+    device_id: &str,
+    flow: EDataFlow,
+    roles: &[ERole],
+) -> Result<(), SwitcherooError> {
+    set_persisted(
+        pid,
+        &HSTRING::from(persisted_id(device_id, flow)),
+        flow,
+        roles,
+    )
+}
+
+/// Sends the audio of the process with ID `pid` back to the default device for `roles`, undoing
+/// `route`.
+pub fn unroute(pid: u32, flow: EDataFlow, roles: &[ERole]) -> Result<(), SwitcherooError> {
+    // An empty ID is what Windows' own setting uses for "Default".
+    set_persisted(pid, &HSTRING::new(), flow, roles)
+}
+
+fn set_persisted(
+    pid: u32,
+    id: &HSTRING,
+    flow: EDataFlow,
+    roles: &[ERole],
+) -> Result<(), SwitcherooError> {
+    let factory = factory()?;
+    let mut roles = roles.to_vec();
+    if !roles.contains(&eMultimedia) {
+        roles.push(eMultimedia);
+    }
+    for role in roles {
+        unsafe {
+            (Interface::vtable(&factory).SetPersistedDefaultAudioEndpoint)(
+                Interface::as_raw(&factory),
+                pid,
+                flow,
+                role,
+                core::mem::transmute_copy(id),
+            )
+            .ok()?;
+        }
+    }
+    Ok(())
+}
+
+/// The endpoint the process with ID `pid` has been sent to for `role`, if it has its own.
+pub fn routed_device(
+    pid: u32,
+    flow: EDataFlow,
+    role: ERole,
+) -> Result<Option<String>, SwitcherooError> {
+    let factory = factory()?;
+    let id = unsafe {
+        let mut id = core::mem::zeroed::<HSTRING>();
+        (Interface::vtable(&factory).GetPersistedDefaultAudioEndpoint)(
+            Interface::as_raw(&factory),
+            pid,
+            flow,
+            role,
+            &mut id as *mut HSTRING as *mut _,
+        )
+        .ok()?;
+        id
+    };
+    Ok(device_id(&id.to_string_lossy()).map(str::to_string))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows::Win32::Media::Audio::eRender;
+
+    #[test]
+    fn device_ids_round_trip() {
+        let device = "{0.0.0.00000000}.{5b8e1c2a-0d4f-4a44-9c3e-0f1f2a3b4c5d}";
+        let persisted = persisted_id(device, eRender);
+        assert_eq!(
+            persisted,
+            r"\\?\SWD#MMDEVAPI#{0.0.0.00000000}.{5b8e1c2a-0d4f-4a44-9c3e-0f1f2a3b4c5d}#{e6327cad-dcec-4949-ae8a-991e976a79d2}"
+        );
+        assert_eq!(device_id(&persisted), Some(device));
+        assert!(persisted_id(device, eCapture).ends_with(CAPTURE_INTERFACE));
+        assert_eq!(device_id(""), None);
+    }
+}
@@ -0,0 +1,122 @@
+use log::debug;
+use std::fmt::Write;
+
+use sound_switcheroo::backend::AudioDevice;
+
+// Where ASIO drivers register themselves, for 64-bit and 32-bit hosts.
+const ASIO_KEYS: [&str; 2] = [r"Software\ASIO", r"Software\WOW6432Node\ASIO"];
+
+// Words in driver names that say nothing about which hardware they drive.
+const GENERIC_WORDS: &[&str] = &["asio", "audio", "driver", "device", "usb", "x64", "64bit"];
+
+/// An ASIO driver installed on the machine. Pro-audio apps using it talk to the hardware directly,
+/// bypassing the Windows endpoints (and so the default device) for the same hardware.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsioDriver {
+    pub name: String,
+    pub clsid: String,
+    pub description: Option<String>,
+}
+
+impl AsioDriver {
+    /// Whether the driver seems to be for the same hardware as an endpoint, going by a
+    /// distinctive word (usually the vendor) that both names share.
+    pub fn shadows(&self, device_name: &str) -> bool {
+        let device_name = device_name.to_lowercase();
+        self.name
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| word.len() >= 3 && !GENERIC_WORDS.contains(word))
+            .any(|word| device_name.contains(word))
+    }
+}
+
+/// Lists the ASIO drivers in the registry, by name.
+pub fn drivers() -> Vec<AsioDriver> {
+    let mut drivers: Vec<AsioDriver> = Vec::new();
+    for path in ASIO_KEYS {
+        let Ok(asio) = windows_registry::LOCAL_MACHINE.open(path) else {
+            debug!("No ASIO drivers under {path}");
+            continue;
+        };
+        let Ok(names) = asio.keys() else {
+            continue;
+        };
+        for name in names {
+            if drivers.iter().any(|driver| driver.name == name) {
+                continue;
+            }
+            let Ok(key) = asio.open(&name) else {
+                continue;
+            };
+            drivers.push(AsioDriver {
+                clsid: key.get_string("CLSID").unwrap_or_default(),
+                description: key.get_string("Description").ok(),
+                name,
+            });
+        }
+    }
+    drivers.sort_by(|a, b| a.name.cmp(&b.name));
+    drivers
+}
+
+/// Describes the ASIO drivers and which endpoints they seem to shadow, and the format each endpoint
+/// is mixed in, for the Diagnostics tab.
+pub fn report(drivers: &[AsioDriver], devices: &[AudioDevice]) -> String {
+    let mut report = String::from("ASIO drivers\r\n");
+    if drivers.is_empty() {
+        report.push_str("    None installed.\r\n");
+    }
+    for driver in drivers {
+        let _ = write!(report, "    {} {}", driver.name, driver.clsid);
+        if let Some(description) = &driver.description
+            && description != &driver.name
+        {
+            let _ = write!(report, " ({description})");
+        }
+        report.push_str("\r\n");
+    }
+    report.push_str("\r\nOutput devices\r\n");
+    for device in devices {
+        let _ = write!(report, "    {}", device.friendly_name);
+        if let Some(format) = device.mix_format {
+            let _ = write!(report, " [{format}]");
+        }
+        let shadowing: Vec<_> = drivers
+            .iter()
+            .filter(|driver| driver.shadows(&device.friendly_name))
+            .map(|driver| driver.name.as_str())
+            .collect();
+        if !shadowing.is_empty() {
+            let _ = write!(
+                report,
+                " - apps using {} bypass this device",
+                shadowing.join(" or ")
+            );
+        }
+        report.push_str("\r\n");
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn driver(name: &str) -> AsioDriver {
+        AsioDriver {
+            name: name.to_string(),
+            clsid: "{00000000-0000-0000-0000-000000000000}".to_string(),
+            description: None,
+        }
+    }
+
+    #[test]
+    fn matches_drivers_to_endpoints_by_vendor() {
+        assert!(driver("Focusrite USB ASIO").shadows("Speakers (Focusrite USB Audio)"));
+        assert!(driver("Realtek ASIO").shadows("Speakers (Realtek(R) Audio)"));
+        // Only generic words in common.
+        assert!(!driver("Focusrite USB ASIO").shadows("Headphones (USB Audio Device)"));
+        assert!(!driver("ASIO4ALL v2").shadows("Speakers (Realtek(R) Audio)"));
+    }
+}
@@ -0,0 +1,87 @@
+use defer::defer;
+use log::{info, warn};
+use std::os::windows::process::CommandExt;
+use std::process::Command;
+use windows::Win32::System::Services::{
+    CloseServiceHandle, OpenSCManagerW, OpenServiceW, QueryServiceStatus, SC_MANAGER_CONNECT,
+    SERVICE_QUERY_STATUS, SERVICE_RUNNING, SERVICE_STATUS,
+};
+use windows::Win32::UI::WindowsAndMessaging::WM_APP;
+use windows_strings::w;
+
+use crate::elevation::{self, ElevatedTask};
+use sound_switcheroo::bail;
+use sound_switcheroo::error::SwitcherooError;
+
+/// Posted to the main window when a restart or start of the audio services has finished,
+/// successfully or not. LPARAM is a `Box<String>` describing the outcome, owned by the receiver.
+pub const WM_AUDIO_SERVICE_RESTARTED: u32 = WM_APP + 0x4c;
+
+// Keeps a console window from flashing up for `net`.
+const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+/// Whether Windows Audio (`Audiosrv`) is running. Without it there are no devices to list.
+pub fn is_running() -> Result<bool, SwitcherooError> {
+    unsafe {
+        let manager = OpenSCManagerW(None, None, SC_MANAGER_CONNECT)?;
+        defer!({
+            let _ = CloseServiceHandle(manager);
+        });
+        let service = OpenServiceW(manager, w!("Audiosrv"), SERVICE_QUERY_STATUS)?;
+        defer!({
+            let _ = CloseServiceHandle(service);
+        });
+        let mut status = SERVICE_STATUS::default();
+        QueryServiceStatus(service, &mut status)?;
+        Ok(status.dwCurrentState == SERVICE_RUNNING)
+    }
+}
+
+/// Restarts Windows Audio and the endpoint builder it depends on, in an elevated helper that UAC
+/// asks the user to allow. Blocks until the services are back.
+pub fn restart_elevated() -> Result<(), SwitcherooError> {
+    elevation::run(&ElevatedTask::RestartAudioService)
+}
+
+/// Starts Windows Audio, and the endpoint builder it depends on, like `restart_elevated`.
+pub fn start_elevated() -> Result<(), SwitcherooError> {
+    elevation::run(&ElevatedTask::StartAudioService)
+}
+
+/// Restarts the services from a process that is already elevated. The error says which step
+/// failed.
+pub fn restart() -> Result<(), SwitcherooError> {
+    info!("Restarting the audio services");
+    // Stopping the endpoint builder stops Audiosrv too, which `/y` agrees to, and starting Audiosrv
+    // starts it again. The stop is allowed to fail, as it does if they were already stopped.
+    let stopped = net(&["stop", "AudioEndpointBuilder", "/y"]);
+    if let Err(e) = &stopped {
+        warn!("Failed to stop the audio services: {e}");
+    }
+    if let Err(e) = net(&["start", "Audiosrv"]) {
+        match stopped {
+            // Most likely still stopping, so that's what went wrong.
+            Err(stop) => bail!("it didn't stop ({stop}), so it couldn't start again ({e})"),
+            Ok(()) => bail!("it stopped, but didn't start again: {e}"),
+        }
+    }
+    Ok(())
+}
+
+/// Starts the services from a process that is already elevated.
+pub fn start() -> Result<(), SwitcherooError> {
+    info!("Starting the audio services");
+    net(&["start", "Audiosrv"])
+}
+
+// Runs `net` and waits for it to succeed.
+fn net(args: &[&str]) -> Result<(), SwitcherooError> {
+    let status = Command::new("net")
+        .args(args)
+        .creation_flags(CREATE_NO_WINDOW)
+        .status()?;
+    if !status.success() {
+        bail!("`net {}` failed ({status})", args.join(" "));
+    }
+    Ok(())
+}
@@ -0,0 +1,756 @@
+use log::debug;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use windows::Win32::Devices::FunctionDiscovery::{
+    PKEY_DeviceInterface_FriendlyName, PKEY_Device_ContainerId, PKEY_Device_DeviceDesc,
+    PKEY_Device_FriendlyName, PKEY_Device_LocationInfo,
+};
+use windows::Win32::Foundation::PROPERTYKEY;
+use windows::Win32::Media::Audio::Apo::{
+    PKEY_FX_EndpointEffectClsid, PKEY_FX_ModeEffectClsid, PKEY_FX_PostMixEffectClsid,
+    PKEY_FX_PreMixEffectClsid, PKEY_FX_StreamEffectClsid,
+};
+use windows::Win32::Media::Audio::Endpoints::IAudioEndpointVolume;
+use windows::Win32::Media::Audio::{
+    AudioSessionStateActive, AudioSessionStateExpired, EDataFlow, ERole, EndpointFormFactor,
+    IAudioClient, IAudioRenderClient, IAudioSessionControl2, IAudioSessionManager2,
+    PKEY_AudioEndpoint_Disable_SysFx, PKEY_AudioEndpoint_FormFactor, AUDCLNT_BUFFERFLAGS_SILENT,
+    AUDCLNT_E_CPUUSAGE_EXCEEDED, AUDCLNT_E_DEVICE_IN_USE, AUDCLNT_E_EXCLUSIVE_MODE_ONLY,
+    AUDCLNT_SHAREMODE_SHARED, WAVEFORMATEX, WAVEFORMATEXTENSIBLE,
+};
+use windows::Win32::System::Com::StructuredStorage::PROPVARIANT;
+use windows::Win32::System::Com::{
+    CoCreateInstance, CoTaskMemFree, CLSCTX_ALL, STGM_READ, STGM_READWRITE,
+};
+use windows::Win32::System::Variant::{VT_CLSID, VT_LPWSTR, VT_UI4};
+use windows::Win32::UI::Shell::PropertiesSystem::IPropertyStore;
+use windows_core::{Interface, GUID};
+
+use crate::bail;
+use crate::com;
+use crate::error::SwitcherooError;
+use crate::ipc_auth;
+use crate::policy_config::{self, IPolicyConfig};
+use crate::rotation;
+use crate::safe_strings::{with_wide_str, WideString};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AudioDevice {
+    pub id: String,
+    pub friendly_name: WideString,
+    // Whether this device will be included in the rotation.
+    #[serde(default)]
+    pub selectable: bool,
+    #[serde(with = "form_factor_serde", default)]
+    pub form_factor: EndpointFormFactor,
+    // Identifies the physical device this endpoint belongs to, shared by e.g. the
+    // speaker and mic endpoints of a headset.
+    #[serde(with = "guid_serde", default)]
+    pub container_id: Option<GUID>,
+    #[serde(default)]
+    pub effects: Effects,
+    // The endpoint's own description (e.g. "Speakers"), the adapter it's on (e.g. "USB Dock
+    // Audio") and where that is, if the driver says, for telling apart endpoints with the same
+    // name.
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub adapter: Option<String>,
+    #[serde(default)]
+    pub location: Option<String>,
+    // What Windows mixes shared mode audio for the endpoint in, once `read_mix_formats` has read
+    // it.
+    #[serde(default)]
+    pub mix_format: Option<MixFormat>,
+}
+
+impl AudioDevice {
+    /// A device in the rotation with nothing else known about it, as tests and simulations start
+    /// from.
+    pub fn new(
+        id: impl Into<String>,
+        friendly_name: impl Into<WideString>,
+        form_factor: EndpointFormFactor,
+    ) -> Self {
+        Self {
+            id: id.into(),
+            friendly_name: friendly_name.into(),
+            selectable: true,
+            form_factor,
+            container_id: None,
+            effects: Effects::None,
+            description: None,
+            adapter: None,
+            location: None,
+            mix_format: None,
+        }
+    }
+
+    /// The description, adapter and location together, e.g. "Speakers on USB Dock Audio
+    /// (Port_#0001.Hub_#0004)".
+    pub fn details(&self) -> Option<String> {
+        let mut details = self.description.clone().unwrap_or_default();
+        if let Some(adapter) = &self.adapter {
+            if !details.is_empty() {
+                details.push_str(" on ");
+            }
+            details.push_str(adapter);
+        }
+        if let Some(location) = &self.location {
+            if details.is_empty() {
+                details.push_str(location);
+            } else {
+                details.push_str(&format!(" ({location})"));
+            }
+        }
+        (!details.is_empty()).then_some(details)
+    }
+}
+
+/// Whether audio processing objects (driver "enhancements" such as virtual surround or loudness
+/// equalisation) are installed on an endpoint, and if so whether they've been bypassed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Effects {
+    #[default]
+    None,
+    Enabled,
+    Bypassed,
+}
+
+// The format tags and float subformat `MixFormat::read` looks for, from mmreg.h and ksmedia.h.
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 0x0003;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+const KSDATAFORMAT_SUBTYPE_IEEE_FLOAT: GUID =
+    GUID::from_u128(0x00000003_0000_0010_8000_00aa00389b71);
+// How much `WAVEFORMATEXTENSIBLE` adds to `WAVEFORMATEX`.
+const EXTENSIBLE_SIZE: u16 = 22;
+
+/// The format an endpoint's shared mode audio is mixed in, as the Sound control panel's "Default
+/// Format" sets it. Apps playing at another rate are resampled to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MixFormat {
+    // In Hz.
+    pub sample_rate: u32,
+    // The bits each sample carries, which can be fewer than it takes up.
+    pub bits: u16,
+    pub channels: u16,
+    pub float: bool,
+}
+
+impl MixFormat {
+    /// Reads a format as Windows hands it back, with or without the extensible part.
+    ///
+    /// # Safety
+    /// `format` has to point to a `WAVEFORMATEX`, followed by as many bytes as its `cbSize` says.
+    pub unsafe fn read(format: *const WAVEFORMATEX) -> Self {
+        unsafe {
+            let basic = format.read_unaligned();
+            let mut mix_format = Self {
+                sample_rate: basic.nSamplesPerSec,
+                bits: basic.wBitsPerSample,
+                channels: basic.nChannels,
+                float: basic.wFormatTag == WAVE_FORMAT_IEEE_FLOAT,
+            };
+            if basic.wFormatTag == WAVE_FORMAT_EXTENSIBLE && basic.cbSize >= EXTENSIBLE_SIZE {
+                let extensible = (format as *const WAVEFORMATEXTENSIBLE).read_unaligned();
+                let valid_bits = extensible.Samples.wValidBitsPerSample;
+                if valid_bits > 0 {
+                    mix_format.bits = valid_bits;
+                }
+                mix_format.float = { extensible.SubFormat } == KSDATAFORMAT_SUBTYPE_IEEE_FLOAT;
+            }
+            mix_format
+        }
+    }
+}
+
+/// E.g. "48 kHz, 24-bit, stereo".
+impl fmt::Display for MixFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} kHz, {}-bit",
+            self.sample_rate as f64 / 1000.0,
+            self.bits
+        )?;
+        if self.float {
+            f.write_str(" float")?;
+        }
+        match self.channels {
+            1 => f.write_str(", mono"),
+            2 => f.write_str(", stereo"),
+            channels => write!(f, ", {channels} channels"),
+        }
+    }
+}
+
+/// The kinds of endpoint Windows knows, by name, for correcting a driver that reports the wrong
+/// one in the config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FormFactor {
+    RemoteNetworkDevice,
+    Speakers,
+    LineLevel,
+    Headphones,
+    Microphone,
+    Headset,
+    Handset,
+    UnknownDigitalPassthrough,
+    Spdif,
+    DigitalAudioDisplayDevice,
+    Unknown,
+}
+
+impl From<FormFactor> for EndpointFormFactor {
+    fn from(form_factor: FormFactor) -> Self {
+        // In the order of the enum's values.
+        EndpointFormFactor(form_factor as i32)
+    }
+}
+
+impl rotation::RotationEntry for AudioDevice {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn selectable(&self) -> bool {
+        self.selectable
+    }
+
+    fn container(&self) -> Option<u128> {
+        self.container_id.map(|guid| guid.to_u128())
+    }
+}
+
+// Windows types don't implement serde, so these store them as a plain number and string.
+mod form_factor_serde {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        form_factor: &EndpointFormFactor,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i32(form_factor.0)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<EndpointFormFactor, D::Error> {
+        i32::deserialize(deserializer).map(EndpointFormFactor)
+    }
+}
+
+mod guid_serde {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(guid: &Option<GUID>, serializer: S) -> Result<S::Ok, S::Error> {
+        guid.map(|guid| format!("{guid:?}")).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<GUID>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|s| GUID::try_from(s.as_str()).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+/// An app with audio open on an endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioSession {
+    // The app's own name for the session, or else its executable's, e.g. "firefox".
+    pub name: String,
+    // Whether it's playing now, rather than just holding the session open.
+    pub active: bool,
+}
+
+/// One entry per app, active if any of its sessions is, with those playing first.
+pub fn summarize_sessions(sessions: Vec<AudioSession>) -> Vec<AudioSession> {
+    let mut summary: Vec<AudioSession> = Vec::new();
+    for session in sessions {
+        match summary
+            .iter_mut()
+            .find(|other| other.name.eq_ignore_ascii_case(&session.name))
+        {
+            Some(other) => other.active |= session.active,
+            None => summary.push(session),
+        }
+    }
+    summary.sort_by_key(|session| (!session.active, session.name.to_lowercase()));
+    summary
+}
+
+/// The levels of the left and right channels for `balance`, from -100 (left only) to 100 (right
+/// only), with the louder side at `level`.
+pub fn channel_levels(level: f32, balance: i32) -> (f32, f32) {
+    let balance = balance.clamp(-100, 100) as f32 / 100.0;
+    (
+        level * (1.0 - balance.max(0.0)),
+        level * (1.0 + balance.min(0.0)),
+    )
+}
+
+/// The balance the left and right channels' levels amount to, as `channel_levels` takes it.
+pub fn balance_of(left: f32, right: f32) -> i32 {
+    let louder = left.max(right);
+    if louder <= 0.0 {
+        return 0;
+    }
+    ((right - left) / louder * 100.0).round() as i32
+}
+
+/// The parts of the Windows audio stack the switcher talks to. Everything that lists, reads or
+/// changes endpoints goes through it, so switching, applying the config and the menu can be tested
+/// against `SimulatedBackend` without any audio devices.
+pub trait AudioBackend: Send {
+    /// Lists the active endpoints for the given data flow.
+    fn devices(&self, flow: EDataFlow) -> Result<Vec<AudioDevice>, SwitcherooError>;
+    /// Gets the properties of one active endpoint of the given data flow.
+    fn device(&self, flow: EDataFlow, device_id: &str) -> Result<AudioDevice, SwitcherooError> {
+        match self.devices(flow)?.into_iter().find(|d| d.id == device_id) {
+            Some(device) => Ok(device),
+            None => Err(SwitcherooError::DeviceNotFound(format!(
+                "No active device {device_id}"
+            ))),
+        }
+    }
+    /// Gets the ID of the current default endpoint for the given data flow and role.
+    fn default_device(&self, flow: EDataFlow, role: ERole) -> Result<String, SwitcherooError>;
+    /// Makes the given endpoint the default for the role.
+    fn set_default_device(&self, device_id: &str, role: ERole) -> Result<(), SwitcherooError>;
+    /// Gets the endpoint's master volume, from 0.0 to 1.0.
+    fn volume(&self, device_id: &str) -> Result<f32, SwitcherooError>;
+    /// Sets the endpoint's master volume, from 0.0 to 1.0.
+    fn set_volume(&self, device_id: &str, level: f32) -> Result<(), SwitcherooError>;
+    /// Whether the endpoint is muted.
+    fn muted(&self, device_id: &str) -> Result<bool, SwitcherooError>;
+    fn set_muted(&self, device_id: &str, muted: bool) -> Result<(), SwitcherooError>;
+    /// Gets the endpoint's left/right balance, per `balance_of`, or `None` if it isn't stereo.
+    fn balance(&self, device_id: &str) -> Result<Option<i32>, SwitcherooError>;
+    /// Sets the endpoint's balance, keeping the louder side at the master volume.
+    fn set_balance(&self, device_id: &str, balance: i32) -> Result<(), SwitcherooError>;
+    /// Turns the endpoint's effects off or back on. Usually needs administrator rights.
+    fn set_effects_bypassed(&self, device_id: &str, bypassed: bool) -> Result<(), SwitcherooError>;
+    /// Lists the apps with audio sessions on the endpoint, per `summarize_sessions`.
+    fn sessions(&self, device_id: &str) -> Result<Vec<AudioSession>, SwitcherooError>;
+    /// Plays a moment of silence on the endpoint, failing if it doesn't get played, as happens
+    /// with outputs like HDMI ones with nothing listening on the other end.
+    fn check_playback(&self, device_id: &str) -> Result<(), SwitcherooError>;
+    /// Checks that defaults can be set at all, for the startup health check.
+    fn check_switching(&self) -> Result<(), SwitcherooError> {
+        Ok(())
+    }
+    /// Fills in the format each endpoint mixes shared mode audio in, where it can be read. Kept
+    /// out of `devices`, which is called far more often than the formats change.
+    fn read_mix_formats(&self, _devices: &mut [AudioDevice]) {}
+}
+
+/// The real audio stack, via COM.
+pub struct ComBackend;
+
+impl AudioBackend for ComBackend {
+    fn devices(&self, flow: EDataFlow) -> Result<Vec<AudioDevice>, SwitcherooError> {
+        get_available_audio_devices(flow)
+    }
+
+    fn default_device(&self, flow: EDataFlow, role: ERole) -> Result<String, SwitcherooError> {
+        get_current_default_endpoint(flow, role)
+    }
+
+    fn set_default_device(&self, device_id: &str, role: ERole) -> Result<(), SwitcherooError> {
+        set_default_endpoint(device_id, role)
+    }
+
+    fn volume(&self, device_id: &str) -> Result<f32, SwitcherooError> {
+        unsafe { Ok(endpoint_volume(device_id)?.GetMasterVolumeLevelScalar()?) }
+    }
+
+    fn set_volume(&self, device_id: &str, level: f32) -> Result<(), SwitcherooError> {
+        unsafe {
+            endpoint_volume(device_id)?
+                .SetMasterVolumeLevelScalar(level.clamp(0.0, 1.0), std::ptr::null())?;
+        }
+        Ok(())
+    }
+
+    fn muted(&self, device_id: &str) -> Result<bool, SwitcherooError> {
+        unsafe { Ok(endpoint_volume(device_id)?.GetMute()?.as_bool()) }
+    }
+
+    fn set_muted(&self, device_id: &str, muted: bool) -> Result<(), SwitcherooError> {
+        unsafe { endpoint_volume(device_id)?.SetMute(muted, std::ptr::null())? };
+        Ok(())
+    }
+
+    fn balance(&self, device_id: &str) -> Result<Option<i32>, SwitcherooError> {
+        unsafe {
+            let volume = endpoint_volume(device_id)?;
+            // Surround endpoints count too, by their front left and right.
+            if volume.GetChannelCount()? < 2 {
+                return Ok(None);
+            }
+            Ok(Some(balance_of(
+                volume.GetChannelVolumeLevelScalar(0)?,
+                volume.GetChannelVolumeLevelScalar(1)?,
+            )))
+        }
+    }
+
+    fn set_balance(&self, device_id: &str, balance: i32) -> Result<(), SwitcherooError> {
+        unsafe {
+            let volume = endpoint_volume(device_id)?;
+            if volume.GetChannelCount()? < 2 {
+                bail!("Balance can only be set on stereo devices");
+            }
+            let (left, right) = channel_levels(volume.GetMasterVolumeLevelScalar()?, balance);
+            volume.SetChannelVolumeLevelScalar(0, left, std::ptr::null())?;
+            volume.SetChannelVolumeLevelScalar(1, right, std::ptr::null())?;
+        }
+        Ok(())
+    }
+
+    fn set_effects_bypassed(&self, device_id: &str, bypassed: bool) -> Result<(), SwitcherooError> {
+        unsafe {
+            let device_enumerator = com::device_enumerator()?;
+            let device = with_wide_str(device_id, |id| device_enumerator.GetDevice(id))?;
+            let props = device.OpenPropertyStore(STGM_READWRITE)?;
+            props.SetValue(
+                &PKEY_AudioEndpoint_Disable_SysFx,
+                &PROPVARIANT::from(bypassed as u32),
+            )?;
+            props.Commit()?;
+        }
+        Ok(())
+    }
+
+    fn sessions(&self, device_id: &str) -> Result<Vec<AudioSession>, SwitcherooError> {
+        let mut sessions = Vec::new();
+        unsafe {
+            let device_enumerator = com::device_enumerator()?;
+            let device = with_wide_str(device_id, |id| device_enumerator.GetDevice(id))?;
+            let manager: IAudioSessionManager2 = device.Activate(CLSCTX_ALL, None)?;
+            let enumerator = manager.GetSessionEnumerator()?;
+            for i in 0..enumerator.GetCount()? {
+                let session: IAudioSessionControl2 = enumerator.GetSession(i)?.cast()?;
+                let state = session.GetState()?;
+                if state == AudioSessionStateExpired {
+                    continue;
+                }
+                let Some(name) = session_name(&session) else {
+                    continue;
+                };
+                sessions.push(AudioSession {
+                    name,
+                    active: state == AudioSessionStateActive,
+                });
+            }
+        }
+        Ok(summarize_sessions(sessions))
+    }
+
+    fn check_playback(&self, device_id: &str) -> Result<(), SwitcherooError> {
+        match play_silence(device_id) {
+            // Another app has the device to itself, or it's too busy to take another stream,
+            // which means it's there and playing.
+            Err(SwitcherooError::Com(e)) if DEVICE_BUSY.contains(&e.code()) => {
+                debug!("{device_id} is busy, so taken to be playing: {e}");
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn read_mix_formats(&self, devices: &mut [AudioDevice]) {
+        let policy_config: IPolicyConfig = match unsafe {
+            CoCreateInstance(&policy_config::CLSID_POLICY_CONFIG, None, CLSCTX_ALL)
+        } {
+            Ok(policy_config) => policy_config,
+            Err(e) => {
+                debug!("Failed to create PolicyConfig: {e}");
+                return;
+            }
+        };
+        for device in devices {
+            device.mix_format = mix_format(&policy_config, &device.id)
+                .inspect_err(|e| debug!("Failed to read the mix format: {e}"))
+                .ok();
+        }
+    }
+
+    fn check_switching(&self) -> Result<(), SwitcherooError> {
+        // The undocumented interface could go away in any Windows update.
+        let _: IPolicyConfig =
+            unsafe { CoCreateInstance(&policy_config::CLSID_POLICY_CONFIG, None, CLSCTX_ALL)? };
+        Ok(())
+    }
+}
+
+// Plays a moment of silence on the device, failing if none of it was played.
+fn play_silence(device_id: &str) -> Result<(), SwitcherooError> {
+    unsafe {
+        let device_enumerator = com::device_enumerator()?;
+        let device = with_wide_str(device_id, |id| device_enumerator.GetDevice(id))?;
+        let client: IAudioClient = device.Activate(CLSCTX_ALL, None)?;
+        let format = client.GetMixFormat()?;
+        let initialized = client.Initialize(
+            AUDCLNT_SHAREMODE_SHARED,
+            0,
+            PLAYBACK_CHECK_BUFFER,
+            0,
+            format,
+            None,
+        );
+        CoTaskMemFree(Some(format as *const _));
+        initialized?;
+        let frames = client.GetBufferSize()?;
+        let render: IAudioRenderClient = client.GetService()?;
+        render.GetBuffer(frames)?;
+        render.ReleaseBuffer(frames, AUDCLNT_BUFFERFLAGS_SILENT.0 as u32)?;
+        client.Start()?;
+        std::thread::sleep(PLAYBACK_CHECK_WAIT);
+        let left = client.GetCurrentPadding()?;
+        client.Stop()?;
+        if left >= frames {
+            bail!("Nothing was played");
+        }
+    }
+    Ok(())
+}
+
+// Errors from an endpoint that's in use rather than broken.
+const DEVICE_BUSY: [windows_core::HRESULT; 3] = [
+    AUDCLNT_E_DEVICE_IN_USE,
+    AUDCLNT_E_EXCLUSIVE_MODE_ONLY,
+    AUDCLNT_E_CPUUSAGE_EXCEEDED,
+];
+
+// The silence `check_playback` plays, in 100ns units, and how long it gives the device to start
+// playing it.
+const PLAYBACK_CHECK_BUFFER: i64 = 1_000_000;
+const PLAYBACK_CHECK_WAIT: std::time::Duration = std::time::Duration::from_millis(50);
+
+// Where the endpoint's effects are registered, any of which means there are some.
+const EFFECT_CLSID_KEYS: [PROPERTYKEY; 5] = [
+    PKEY_FX_StreamEffectClsid,
+    PKEY_FX_ModeEffectClsid,
+    PKEY_FX_EndpointEffectClsid,
+    PKEY_FX_PreMixEffectClsid,
+    PKEY_FX_PostMixEffectClsid,
+];
+
+unsafe fn read_effects(props: &IPropertyStore) -> Effects {
+    unsafe {
+        let has_effects = EFFECT_CLSID_KEYS.iter().any(|key| {
+            props
+                .GetValue(key)
+                .is_ok_and(|value| value.vt() == VT_LPWSTR)
+        });
+        if !has_effects {
+            return Effects::None;
+        }
+        match props.GetValue(&PKEY_AudioEndpoint_Disable_SysFx) {
+            Ok(value) if value.vt() == VT_UI4 && value.Anonymous.Anonymous.Anonymous.ulVal != 0 => {
+                Effects::Bypassed
+            }
+            _ => Effects::Enabled,
+        }
+    }
+}
+
+// What to call a session: the name the app gave it, unless that's a resource reference like
+// "@%SystemRoot%\...", or else the name of its executable.
+unsafe fn session_name(session: &IAudioSessionControl2) -> Option<String> {
+    unsafe {
+        if session.IsSystemSoundsSession().0 == 0 {
+            return Some("System sounds".to_string());
+        }
+        if let Ok(display_name) = session.GetDisplayName() {
+            let name = display_name.to_string().unwrap_or_default();
+            CoTaskMemFree(Some(display_name.0 as *const _));
+            if !name.is_empty() && !name.starts_with('@') {
+                return Some(name);
+            }
+        }
+        let executable = ipc_auth::process_executable(session.GetProcessId().ok()?).ok()?;
+        Some(executable.file_stem()?.to_string_lossy().into_owned())
+    }
+}
+
+/// Gets the volume control of an endpoint.
+fn endpoint_volume(device_id: &str) -> Result<IAudioEndpointVolume, SwitcherooError> {
+    unsafe {
+        let device_enumerator = com::device_enumerator()?;
+        let device = with_wide_str(device_id, |id| device_enumerator.GetDevice(id))?;
+        Ok(device.Activate(CLSCTX_ALL, None)?)
+    }
+}
+
+/// Gets the format the endpoint mixes shared mode audio in.
+fn mix_format(
+    policy_config: &IPolicyConfig,
+    device_id: &str,
+) -> Result<MixFormat, SwitcherooError> {
+    unsafe {
+        let format = with_wide_str(device_id, |id| policy_config.GetMixFormat(id))?;
+        if format.is_null() {
+            bail!("No mix format for {device_id}");
+        }
+        let mix_format = MixFormat::read(format);
+        CoTaskMemFree(Some(format as *const _));
+        Ok(mix_format)
+    }
+}
+
+/// Sets the default audio endpoint for the specified role using raw COM interface calls
+fn set_default_endpoint(device_id: &str, role: ERole) -> Result<(), SwitcherooError> {
+    unsafe {
+        debug!("Attempting to set default endpoint for device: {device_id}, role: {role:?}",);
+        let policy_config: IPolicyConfig =
+            CoCreateInstance(&policy_config::CLSID_POLICY_CONFIG, None, CLSCTX_ALL)?;
+
+        // Use safe scoped approach for string conversion
+        with_wide_str(device_id, |wide_device_id| {
+            policy_config.SetDefaultEndpoint(wide_device_id, role)
+        })?;
+        Ok(())
+    }
+}
+
+/// Gets the ID of the current default audio endpoint
+fn get_current_default_endpoint(flow: EDataFlow, role: ERole) -> Result<String, SwitcherooError> {
+    unsafe {
+        let device_enumerator = com::device_enumerator()?;
+
+        let endpoint = device_enumerator.GetDefaultAudioEndpoint(flow, role)?;
+
+        let device_id = endpoint.GetId()?;
+        let device_id_str = device_id.to_string()?;
+
+        Ok(device_id_str)
+    }
+}
+
+unsafe fn propvariant_to_string(propvar: &PROPVARIANT) -> Result<String, SwitcherooError> {
+    unsafe {
+        match propvar.vt() {
+            VT_LPWSTR => Ok(String::from_utf16_lossy(
+                propvar.Anonymous.Anonymous.Anonymous.pwszVal.as_wide(),
+            )),
+            _ => {
+                bail!("Unsupported PROPVARIANT type: {:?}", propvar.vt());
+            }
+        }
+    }
+}
+
+// A string property that's often missing or empty, depending on the driver.
+unsafe fn optional_string(props: &IPropertyStore, key: &PROPERTYKEY) -> Option<String> {
+    unsafe {
+        let value = props.GetValue(key).ok()?;
+        propvariant_to_string(&value)
+            .ok()
+            .map(|text| text.trim().to_string())
+            .filter(|text| !text.is_empty())
+    }
+}
+
+fn get_available_audio_devices(flow: EDataFlow) -> Result<Vec<AudioDevice>, SwitcherooError> {
+    let mut devices = Vec::new();
+    unsafe {
+        let device_enumerator = com::device_enumerator()?;
+        let endpoints = device_enumerator
+            .EnumAudioEndpoints(flow, windows::Win32::Media::Audio::DEVICE_STATE_ACTIVE)?;
+        for i in 0..endpoints.GetCount()? {
+            let endpoint = endpoints.Item(i)?;
+            let device_id = endpoint.GetId()?;
+            let device_id_str = device_id.to_string()?;
+            let props = endpoint.OpenPropertyStore(STGM_READ)?;
+            let friendly_name = props.GetValue(&PKEY_Device_FriendlyName)?;
+            let form_factor_var = props.GetValue(&PKEY_AudioEndpoint_FormFactor)?;
+            let form_factor: EndpointFormFactor = match form_factor_var.vt() {
+                VT_UI4 => {
+                    EndpointFormFactor(form_factor_var.Anonymous.Anonymous.Anonymous.ulVal as i32)
+                }
+                _ => {
+                    bail!(
+                        "Unsupported PROPVARIANT type for form factor: {:?}",
+                        form_factor_var,
+                    );
+                }
+            };
+            // Not every endpoint has a container, so this one is optional.
+            let container_id_var = props.GetValue(&PKEY_Device_ContainerId)?;
+            let container_id = match container_id_var.vt() {
+                VT_CLSID => Some(*container_id_var.Anonymous.Anonymous.Anonymous.puuid),
+                _ => None,
+            };
+            devices.push(AudioDevice {
+                id: device_id_str,
+                friendly_name: propvariant_to_string(&friendly_name)?.into(),
+                selectable: true,
+                form_factor,
+                container_id,
+                effects: read_effects(&props),
+                description: optional_string(&props, &PKEY_Device_DeviceDesc),
+                adapter: optional_string(&props, &PKEY_DeviceInterface_FriendlyName),
+                location: optional_string(&props, &PKEY_Device_LocationInfo),
+                mix_format: None,
+            });
+        }
+    }
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balance_turns_down_the_other_side() {
+        assert_eq!(channel_levels(0.8, 0), (0.8, 0.8));
+        assert_eq!(channel_levels(0.8, -100), (0.8, 0.0));
+        assert_eq!(channel_levels(1.0, 25), (0.75, 1.0));
+        assert_eq!(balance_of(0.75, 1.0), 25);
+        assert_eq!(balance_of(0.4, 0.2), -50);
+        assert_eq!(balance_of(0.0, 0.0), 0);
+    }
+
+    #[test]
+    fn mix_formats_are_read_and_described() {
+        let pcm = WAVEFORMATEX {
+            wFormatTag: 1,
+            nChannels: 2,
+            nSamplesPerSec: 44_100,
+            wBitsPerSample: 16,
+            ..Default::default()
+        };
+        let pcm = unsafe { MixFormat::read(&pcm) };
+        assert_eq!(pcm.to_string(), "44.1 kHz, 16-bit, stereo");
+        // As Windows usually gives it: 24 bits of each 32, or floats.
+        let mut extensible = WAVEFORMATEXTENSIBLE {
+            Format: WAVEFORMATEX {
+                wFormatTag: WAVE_FORMAT_EXTENSIBLE,
+                nChannels: 6,
+                nSamplesPerSec: 48_000,
+                wBitsPerSample: 32,
+                cbSize: EXTENSIBLE_SIZE,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        extensible.Samples.wValidBitsPerSample = 24;
+        let read = |format: &WAVEFORMATEXTENSIBLE| unsafe {
+            MixFormat::read(format as *const _ as *const WAVEFORMATEX)
+        };
+        assert_eq!(read(&extensible).to_string(), "48 kHz, 24-bit, 6 channels");
+        extensible.Samples.wValidBitsPerSample = 32;
+        extensible.SubFormat = KSDATAFORMAT_SUBTYPE_IEEE_FLOAT;
+        assert_eq!(
+            read(&extensible).to_string(),
+            "48 kHz, 32-bit float, 6 channels"
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use log::debug;
+use std::sync::Once;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::UI::WindowsAndMessaging::{
+    DefWindowProcW, GetParent, GetWindow, GetWindowLongPtrW, SendMessageW, SetWindowLongPtrW,
+    BN_CLICKED, GWLP_USERDATA, GW_OWNER, WM_APP, WM_COMMAND, WM_NCDESTROY, WM_SIZE,
+};
+use windows_strings::w;
+
+use crate::controls::{self, Column};
+use sound_switcheroo::config::Config;
+use sound_switcheroo::error::SwitcherooError;
+
+/// Sent to the settings window's owner when the behavior page is saved. LPARAM points to the new
+/// `Behavior`, which is only valid during the message.
+pub const WM_SETTINGS_BEHAVIOR_CHANGED: u32 = WM_APP + 0x53;
+
+// Control IDs, received in WM_COMMAND.
+const ID_SAVE: u16 = 100;
+
+static REGISTER_CLASS: Once = Once::new();
+
+/// The on/off settings of the config that the behavior page edits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Behavior {
+    pub switch_microphone_with_output: bool,
+    pub switch_communications: bool,
+    pub switch_focused_app: bool,
+    pub show_osd: bool,
+    pub remember_volumes: bool,
+    pub check_output_at_startup: bool,
+    pub usage_insights: bool,
+}
+
+impl Behavior {
+    pub fn of(config: &Config) -> Self {
+        Self {
+            switch_microphone_with_output: config.switch_microphone_with_output,
+            switch_communications: config.switch_communications,
+            switch_focused_app: config.switch_focused_app,
+            show_osd: config.show_osd,
+            remember_volumes: config.remember_volumes,
+            check_output_at_startup: config.check_output_at_startup,
+            usage_insights: config.usage_insights,
+        }
+    }
+
+    pub fn apply_to(&self, config: &mut Config) {
+        config.switch_microphone_with_output = self.switch_microphone_with_output;
+        config.switch_communications = self.switch_communications;
+        config.switch_focused_app = self.switch_focused_app;
+        config.show_osd = self.show_osd;
+        config.remember_volumes = self.remember_volumes;
+        config.check_output_at_startup = self.check_output_at_startup;
+        config.usage_insights = self.usage_insights;
+    }
+}
+
+// Kept in the page's user data.
+struct Page {
+    column: Column,
+    switch_microphone_with_output: HWND,
+    switch_communications: HWND,
+    switch_focused_app: HWND,
+    show_osd: HWND,
+    remember_volumes: HWND,
+    check_output_at_startup: HWND,
+    usage_insights: HWND,
+    // What was last loaded or saved, to tell when the settings have changed elsewhere.
+    shown: Behavior,
+}
+
+impl Page {
+    fn load(&mut self, behavior: &Behavior) {
+        self.shown = *behavior;
+        controls::set_checked(
+            self.switch_microphone_with_output,
+            behavior.switch_microphone_with_output,
+        );
+        controls::set_checked(self.switch_communications, behavior.switch_communications);
+        controls::set_checked(self.switch_focused_app, behavior.switch_focused_app);
+        controls::set_checked(self.show_osd, behavior.show_osd);
+        controls::set_checked(self.remember_volumes, behavior.remember_volumes);
+        controls::set_checked(
+            self.check_output_at_startup,
+            behavior.check_output_at_startup,
+        );
+        controls::set_checked(self.usage_insights, behavior.usage_insights);
+    }
+
+    fn behavior(&self) -> Behavior {
+        Behavior {
+            switch_microphone_with_output: controls::checked(self.switch_microphone_with_output),
+            switch_communications: controls::checked(self.switch_communications),
+            switch_focused_app: controls::checked(self.switch_focused_app),
+            show_osd: controls::checked(self.show_osd),
+            remember_volumes: controls::checked(self.remember_volumes),
+            check_output_at_startup: controls::checked(self.check_output_at_startup),
+            usage_insights: controls::checked(self.usage_insights),
+        }
+    }
+
+    // Sends the settings to the settings window's owner.
+    fn save(&mut self, page: HWND) {
+        let behavior = self.behavior();
+        self.shown = behavior;
+        debug!("Saving {behavior:?}");
+        unsafe {
+            if let Ok(settings) = GetParent(page)
+                && let Ok(owner) = GetWindow(settings, GW_OWNER)
+            {
+                SendMessageW(
+                    owner,
+                    WM_SETTINGS_BEHAVIOR_CHANGED,
+                    None,
+                    Some(LPARAM(&behavior as *const _ as isize)),
+                );
+            }
+        }
+        controls::set_text(self.column.status, "Saved.");
+    }
+}
+
+/// Creates the behavior page as a hidden child of the settings window, showing the settings of
+/// `config` it covers.
+pub fn create(parent: HWND, config: &Config) -> Result<HWND, SwitcherooError> {
+    let page = controls::create_page(
+        parent,
+        w!("SoundSwitcherooBehavior"),
+        &REGISTER_CLASS,
+        Some(page_callback),
+    )?;
+    let switch_microphone_with_output =
+        controls::checkbox(page, "Switch the &microphone of the same device too")?;
+    let switch_communications = controls::checkbox(page, "Switch communications &device too")?;
+    let switch_focused_app = controls::checkbox(page, "Switch the &focused app only")?;
+    let show_osd = controls::checkbox(page, "Show the device switched to on &screen")?;
+    let remember_volumes = controls::checkbox(page, "Remember each device's &volume")?;
+    let check_output_at_startup = controls::checkbox(page, "Check the output plays at s&tartup")?;
+    let usage_insights = controls::checkbox(page, "Keep a history for &usage insights")?;
+    let behavior = Behavior::of(config);
+    let mut page_state = Page {
+        column: Column {
+            rows: vec![
+                switch_microphone_with_output,
+                switch_communications,
+                switch_focused_app,
+                show_osd,
+                remember_volumes,
+                check_output_at_startup,
+                usage_insights,
+            ],
+            status: controls::label(page, "")?,
+            buttons: vec![controls::button(page, "&Save", ID_SAVE)?],
+        },
+        switch_microphone_with_output,
+        switch_communications,
+        switch_focused_app,
+        show_osd,
+        remember_volumes,
+        check_output_at_startup,
+        usage_insights,
+        shown: behavior,
+    };
+    page_state.load(&behavior);
+    unsafe {
+        SetWindowLongPtrW(
+            page,
+            GWLP_USERDATA,
+            Box::into_raw(Box::new(page_state)) as isize,
+        );
+    }
+    Ok(page)
+}
+
+/// Shows the settings again if they've been changed elsewhere, such as from the menu, otherwise
+/// keeping what's being edited.
+pub fn update(page: HWND, config: &Config) {
+    let behavior = Behavior::of(config);
+    if let Some(page_state) =
+        unsafe { (GetWindowLongPtrW(page, GWLP_USERDATA) as *mut Page).as_mut() }
+        && page_state.shown != behavior
+    {
+        page_state.load(&behavior);
+    }
+}
+
+unsafe extern "system" fn page_callback(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    unsafe {
+        let page = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut Page;
+        match msg {
+            WM_SIZE if !page.is_null() => {
+                (*page).column.layout(hwnd);
+                LRESULT(0)
+            }
+            WM_COMMAND if !page.is_null() => {
+                let id = (wparam.0 & 0xffff) as u16;
+                let notification = ((wparam.0 >> 16) & 0xffff) as u32;
+                if (id, notification) == (ID_SAVE, BN_CLICKED) {
+                    (*page).save(hwnd);
+                }
+                LRESULT(0)
+            }
+            WM_NCDESTROY => {
+                if !page.is_null() {
+                    SetWindowLongPtrW(hwnd, GWLP_USERDATA, 0);
+                    drop(Box::from_raw(page));
+                }
+                DefWindowProcW(hwnd, msg, wparam, lparam)
+            }
+            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sound_switcheroo::config::WhatsNewConfig;
+use sound_switcheroo::menu::{POPUP_INCLUDE_HDMI_ID, POPUP_INSIGHTS_ID, POPUP_SAVE_PROFILE_ID};
+
+/// The version running.
+pub const CURRENT: &str = env!("CARGO_PKG_VERSION");
+
+/// How long the menu entries a release added are marked as new.
+pub const BADGES_FOR: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+/// Something a release added that's worth pointing out after an update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Feature {
+    pub summary: &'static str,
+    // The ID of the menu item it added, if any, to mark as new for a while along with the submenu
+    // it's in.
+    pub menu_id: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Release {
+    pub version: &'static str,
+    pub features: &'static [Feature],
+}
+
+/// Newest first, starting with `CURRENT`.
+pub const RELEASES: &[Release] = &[Release {
+    version: "0.1.0",
+    features: &[
+        Feature {
+            summary: "Profiles switch the output, microphone and their volumes at once, from the \
+                      menu or a hotkey.",
+            menu_id: Some(POPUP_SAVE_PROFILE_ID),
+        },
+        Feature {
+            summary: "Usage insights point out devices you switch to at the same time most days, \
+                      and offer rules that do it for you.",
+            menu_id: Some(POPUP_INSIGHTS_ID),
+        },
+        Feature {
+            summary: "The Include submenu leaves HDMI, hands-free or virtual devices out.",
+            menu_id: Some(POPUP_INCLUDE_HDMI_ID),
+        },
+        Feature {
+            summary: "Rules run when a device is connected or at a time of day.",
+            menu_id: None,
+        },
+        Feature {
+            summary: "What clicking the tray icon does can be changed in the config.",
+            menu_id: None,
+        },
+    ],
+}];
+
+/// The releases after `seen_version`, or all of them if it isn't one listed.
+pub fn since(seen_version: &str) -> &'static [Release] {
+    let seen = RELEASES
+        .iter()
+        .position(|release| release.version == seen_version)
+        .unwrap_or(RELEASES.len());
+    &RELEASES[..seen]
+}
+
+/// Notes `CURRENT` as seen, returning the releases to point out since the version seen before and
+/// marking the menu entries they added as new. Nothing is new on a fresh install, and an update
+/// from before versions were noted only gets `CURRENT`'s features.
+pub fn catch_up(
+    whats_new: &mut WhatsNewConfig,
+    fresh_install: bool,
+    now: SystemTime,
+) -> &'static [Release] {
+    let releases = match whats_new.seen_version.as_deref() {
+        Some(CURRENT) => &[],
+        _ if fresh_install => &[],
+        Some(seen_version) => since(seen_version),
+        None => &RELEASES[..1],
+    };
+    whats_new.seen_version = Some(CURRENT.to_string());
+    if !releases.is_empty() {
+        whats_new.badges = releases
+            .iter()
+            .flat_map(|release| release.features)
+            .filter_map(|feature| feature.menu_id)
+            .collect();
+        whats_new.badges_until = unix_time(now + BADGES_FOR);
+    }
+    releases
+}
+
+/// Stops marking menu entries as new once `BADGES_FOR` has passed.
+pub fn expire_badges(whats_new: &mut WhatsNewConfig, now: SystemTime) {
+    if unix_time(now) >= whats_new.badges_until {
+        whats_new.badges.clear();
+    }
+}
+
+/// Lists the features of `releases`, for the What's new dialog.
+pub fn describe(releases: &[Release]) -> String {
+    let mut text = String::new();
+    for release in releases {
+        let _ = writeln!(text, "Version {}", release.version);
+        for feature in release.features {
+            let _ = writeln!(text, "\u{2022} {}", feature.summary);
+        }
+        text.push('\n');
+    }
+    text.trim_end().to_string()
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_current_version_is_listed_first() {
+        assert_eq!(RELEASES[0].version, CURRENT);
+        assert!(since(CURRENT).is_empty());
+        assert_eq!(since("0.0.1").len(), RELEASES.len());
+    }
+
+    #[test]
+    fn updates_are_pointed_out_once() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let mut whats_new = WhatsNewConfig::default();
+        assert!(catch_up(&mut whats_new, true, start).is_empty());
+        assert_eq!(whats_new.seen_version.as_deref(), Some(CURRENT));
+        assert!(whats_new.badges.is_empty());
+
+        // Updated from before versions were noted.
+        let mut whats_new = WhatsNewConfig::default();
+        assert_eq!(catch_up(&mut whats_new, false, start), &RELEASES[..1]);
+        assert!(whats_new.badges.contains(&POPUP_INSIGHTS_ID));
+        assert!(catch_up(&mut whats_new, false, start).is_empty());
+
+        expire_badges(&mut whats_new, start + BADGES_FOR / 2);
+        assert!(!whats_new.badges.is_empty());
+        expire_badges(&mut whats_new, start + BADGES_FOR);
+        assert!(whats_new.badges.is_empty());
+    }
+}
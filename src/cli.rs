@@ -0,0 +1,316 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Write};
+use windows::Win32::Media::Audio::{eCommunications, eConsole, eMultimedia, ERole};
+
+use crate::backend::AudioDevice;
+use crate::error::SwitcherooError;
+
+/// Broad reasons the app can fail, each with a stable process exit code for scripts to branch on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Other,
+    DeviceNotFound,
+    Com,
+    Config,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::DeviceNotFound => 2,
+            ErrorKind::Com => 3,
+            ErrorKind::Config => 4,
+        }
+    }
+}
+
+/// An error tagged with its `ErrorKind`. Only carries the message, so it can be sent between
+/// threads and reported as JSON.
+#[derive(Debug, Clone, Serialize)]
+pub struct Failure {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    /// Wraps any error as a config error.
+    pub fn config(error: impl fmt::Display) -> Self {
+        Self::new(ErrorKind::Config, error.to_string())
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        self.kind.exit_code()
+    }
+
+    /// The error as a single line of JSON, for `--json-errors`.
+    pub fn to_json(&self) -> String {
+        #[derive(Serialize)]
+        struct Report<'a> {
+            error: &'a Failure,
+            code: u8,
+        }
+        serde_json::to_string(&Report {
+            error: self,
+            code: self.exit_code(),
+        })
+        .unwrap_or_default()
+    }
+}
+
+impl From<&SwitcherooError> for Failure {
+    fn from(error: &SwitcherooError) -> Self {
+        Self::new(error.kind(), error.to_string())
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for Failure {}
+
+/// How listing commands print their results.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Json,
+    Csv,
+    Table,
+}
+
+/// Which of Windows' default devices a command reads or changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Role {
+    // Most apps and system sounds.
+    Console,
+    Multimedia,
+    // Calls and chat apps.
+    Communications,
+}
+
+impl Role {
+    pub fn erole(self) -> ERole {
+        match self {
+            Role::Console => eConsole,
+            Role::Multimedia => eMultimedia,
+            Role::Communications => eCommunications,
+        }
+    }
+}
+
+/// Finds a device by its ID or its name, ignoring case. Part of a name will do as long as only one
+/// device's name contains it.
+pub fn find_device<'a>(
+    devices: &'a [AudioDevice],
+    name_or_id: &str,
+) -> Result<&'a AudioDevice, Failure> {
+    let wanted = name_or_id.to_lowercase();
+    if let Some(device) = devices
+        .iter()
+        .find(|device| device.id == name_or_id || device.friendly_name.to_lowercase() == wanted)
+    {
+        return Ok(device);
+    }
+    let matches: Vec<&AudioDevice> = devices
+        .iter()
+        .filter(|device| device.friendly_name.to_lowercase().contains(&wanted))
+        .collect();
+    match matches.as_slice() {
+        [device] => Ok(device),
+        [] => Err(Failure::new(
+            ErrorKind::DeviceNotFound,
+            format!("No output device matches \"{name_or_id}\""),
+        )),
+        _ => Err(Failure::new(
+            ErrorKind::DeviceNotFound,
+            format!(
+                "\"{name_or_id}\" matches several devices: {}",
+                matches
+                    .iter()
+                    .map(|device| device.friendly_name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        )),
+    }
+}
+
+/// A device as `--list` and the named pipe describe it.
+#[derive(Debug, Serialize)]
+pub struct DeviceListing<'a> {
+    pub id: &'a str,
+    pub name: &'a str,
+    pub default: bool,
+    pub selectable: bool,
+}
+
+impl<'a> DeviceListing<'a> {
+    pub fn new(device: &'a AudioDevice, current_device_id: &str) -> Self {
+        Self {
+            id: &device.id,
+            name: &device.friendly_name,
+            default: device.id == current_device_id,
+            selectable: device.selectable,
+        }
+    }
+}
+
+/// Prints the devices, marking the one with `current_device_id` as the default.
+pub fn write_devices(
+    out: &mut impl Write,
+    devices: &[AudioDevice],
+    current_device_id: &str,
+    format: OutputFormat,
+) -> io::Result<()> {
+    let listings: Vec<DeviceListing> = devices
+        .iter()
+        .map(|device| DeviceListing::new(device, current_device_id))
+        .collect();
+    match format {
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, &listings)?;
+            writeln!(out)
+        }
+        OutputFormat::Csv => {
+            writeln!(out, "id,name,default,selectable")?;
+            for listing in &listings {
+                writeln!(
+                    out,
+                    "{},{},{},{}",
+                    csv_field(listing.id),
+                    csv_field(listing.name),
+                    listing.default,
+                    listing.selectable
+                )?;
+            }
+            Ok(())
+        }
+        OutputFormat::Table => {
+            let name_width = listings
+                .iter()
+                .map(|l| l.name.chars().count())
+                .max()
+                .unwrap_or(0)
+                .max("NAME".len());
+            writeln!(out, "  IN ROTATION  {:name_width$}  ID", "NAME")?;
+            for listing in &listings {
+                writeln!(
+                    out,
+                    "{} {:11}  {:name_width$}  {}",
+                    if listing.default { '*' } else { ' ' },
+                    if listing.selectable { "yes" } else { "no" },
+                    listing.name,
+                    listing.id
+                )?;
+            }
+            Ok(())
+        }
+    }
+}
+
+// Quotes a field if it would otherwise break the CSV row.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_errors() {
+        let failure = Failure::from(&SwitcherooError::DeviceNotFound("gone".to_string()));
+        assert_eq!(failure.kind, ErrorKind::DeviceNotFound);
+        let failure = Failure::from(&SwitcherooError::from(windows_core::Error::empty()));
+        assert_eq!(failure.kind, ErrorKind::Com);
+        let failure = Failure::from(&SwitcherooError::Other("something".to_string()));
+        assert_eq!(failure.kind, ErrorKind::Other);
+        assert_eq!(failure.message, "something");
+    }
+
+    fn devices() -> Vec<AudioDevice> {
+        serde_json::from_str(
+            r#"[
+                { "id": "speakers", "friendly_name": "Speakers", "selectable": true },
+                { "id": "tv", "friendly_name": "LG TV, \"HDMI\"", "selectable": false }
+            ]"#,
+        )
+        .unwrap()
+    }
+
+    fn listing(format: OutputFormat) -> String {
+        let mut out = Vec::new();
+        write_devices(&mut out, &devices(), "tv", format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn lists_devices_as_json() {
+        let listed: serde_json::Value = serde_json::from_str(&listing(OutputFormat::Json)).unwrap();
+        assert_eq!(listed[1]["name"], "LG TV, \"HDMI\"");
+        assert_eq!(listed[1]["default"], true);
+        assert_eq!(listed[1]["selectable"], false);
+    }
+
+    #[test]
+    fn lists_devices_as_csv() {
+        assert_eq!(
+            listing(OutputFormat::Csv),
+            "id,name,default,selectable\n\
+             speakers,Speakers,false,true\n\
+             tv,\"LG TV, \"\"HDMI\"\"\",true,false\n"
+        );
+    }
+
+    #[test]
+    fn lists_devices_as_table() {
+        assert_eq!(
+            listing(OutputFormat::Table),
+            "  IN ROTATION  NAME           ID\n  \
+             yes          Speakers       speakers\n\
+             * no           LG TV, \"HDMI\"  tv\n"
+        );
+    }
+
+    #[test]
+    fn finds_devices_by_id_or_name() {
+        let mut devices = devices();
+        assert_eq!(find_device(&devices, "tv").unwrap().id, "tv");
+        assert_eq!(find_device(&devices, "SPEAKERS").unwrap().id, "speakers");
+        assert_eq!(find_device(&devices, "lg").unwrap().id, "tv");
+        let missing = find_device(&devices, "headset").unwrap_err();
+        assert_eq!(missing.kind, ErrorKind::DeviceNotFound);
+        devices.push(
+            serde_json::from_str(r#"{ "id": "tv2", "friendly_name": "LG TV (2)" }"#).unwrap(),
+        );
+        let ambiguous = find_device(&devices, "lg").unwrap_err();
+        assert!(ambiguous.message.contains("several"));
+    }
+
+    #[test]
+    fn reports_json() {
+        let failure = Failure::new(ErrorKind::Config, "bad \"json\"");
+        assert_eq!(
+            failure.to_json(),
+            r#"{"error":{"kind":"config","message":"bad \"json\""},"code":4}"#
+        );
+    }
+}
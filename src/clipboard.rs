@@ -0,0 +1,38 @@
+use defer::defer;
+use windows::Win32::Foundation::{GlobalFree, HANDLE, HWND};
+use windows::Win32::System::DataExchange::{
+    CloseClipboard, EmptyClipboard, OpenClipboard, SetClipboardData,
+};
+use windows::Win32::System::Memory::{GlobalAlloc, GlobalLock, GlobalUnlock, GMEM_MOVEABLE};
+use windows::Win32::System::Ole::CF_UNICODETEXT;
+
+use sound_switcheroo::error::SwitcherooError;
+
+/// Replaces the clipboard contents with `text`. `owner` becomes the clipboard's owner until
+/// something else is copied.
+pub fn set_text(owner: HWND, text: &str) -> Result<(), SwitcherooError> {
+    let mut wide: Vec<u16> = text.encode_utf16().collect();
+    wide.push(0);
+    unsafe {
+        OpenClipboard(Some(owner))?;
+        defer!({
+            let _ = CloseClipboard();
+        });
+        EmptyClipboard()?;
+        let memory = GlobalAlloc(GMEM_MOVEABLE, wide.len() * std::mem::size_of::<u16>())?;
+        let locked = GlobalLock(memory) as *mut u16;
+        if locked.is_null() {
+            let _ = GlobalFree(Some(memory));
+            return Err(windows_core::Error::from_win32().into());
+        }
+        std::ptr::copy_nonoverlapping(wide.as_ptr(), locked, wide.len());
+        // Fails with NO_ERROR once the memory is unlocked, which is the point.
+        let _ = GlobalUnlock(memory);
+        // The clipboard owns the memory from here on, unless it can't take it.
+        if let Err(e) = SetClipboardData(CF_UNICODETEXT.0 as u32, Some(HANDLE(memory.0))) {
+            let _ = GlobalFree(Some(memory));
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use log::debug;
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+use windows::Win32::Media::Audio::{IMMDeviceEnumerator, MMDeviceEnumerator};
+use windows::Win32::System::Com::{
+    CoCreateInstance, CoInitializeEx, CoUninitialize, CLSCTX_ALL, COINIT_APARTMENTTHREADED,
+};
+
+use crate::error::SwitcherooError;
+
+thread_local! {
+    // How many `ComApartment`s are alive on this thread.
+    static APARTMENTS: Cell<usize> = const { Cell::new(0) };
+    // Created by the first `device_enumerator` call in an apartment, and released with it.
+    static DEVICE_ENUMERATOR: RefCell<Option<IMMDeviceEnumerator>> = const { RefCell::new(None) };
+}
+
+/// COM initialised on the calling thread, as a single-threaded apartment, until dropped. Can't be
+/// sent to another thread, as it has to be uninitialised on the one it was initialised on.
+pub struct ComApartment {
+    _thread_bound: PhantomData<*const ()>,
+}
+
+impl ComApartment {
+    pub fn init() -> Result<Self, SwitcherooError> {
+        // Succeeds, and still has to be balanced, if COM was already initialised on this thread.
+        unsafe { CoInitializeEx(None, COINIT_APARTMENTTHREADED).ok()? };
+        APARTMENTS.set(APARTMENTS.get() + 1);
+        Ok(Self {
+            _thread_bound: PhantomData,
+        })
+    }
+}
+
+impl Drop for ComApartment {
+    fn drop(&mut self) {
+        let left = APARTMENTS.get() - 1;
+        APARTMENTS.set(left);
+        if left == 0 {
+            // COM objects can't outlive the apartment they were made in.
+            DEVICE_ENUMERATOR.take();
+        }
+        unsafe { CoUninitialize() };
+    }
+}
+
+/// The thread's device enumerator, created once for as long as there's a `ComApartment` on it
+/// rather than on every call. Threads where COM was initialised some other way get a new one each
+/// time.
+pub fn device_enumerator() -> Result<IMMDeviceEnumerator, SwitcherooError> {
+    if let Some(enumerator) = DEVICE_ENUMERATOR.with_borrow(Clone::clone) {
+        return Ok(enumerator);
+    }
+    let enumerator: IMMDeviceEnumerator =
+        unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
+    if APARTMENTS.get() > 0 {
+        debug!("Caching the device enumerator");
+        DEVICE_ENUMERATOR.set(Some(enumerator.clone()));
+    }
+    Ok(enumerator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enumerator_lasts_as_long_as_the_apartment() {
+        let apartment = ComApartment::init().unwrap();
+        assert_eq!(device_enumerator().unwrap(), device_enumerator().unwrap());
+        let nested = ComApartment::init().unwrap();
+        drop(nested);
+        assert!(DEVICE_ENUMERATOR.with_borrow(Option::is_some));
+        drop(apartment);
+        assert!(DEVICE_ENUMERATOR.with_borrow(Option::is_none));
+    }
+}
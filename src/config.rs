@@ -0,0 +1,965 @@
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use windows::Win32::Media::Audio::{
+    eCommunications, eConsole, eMultimedia, DigitalAudioDisplayDevice, ERole, EndpointFormFactor,
+    RemoteNetworkDevice, Speakers,
+};
+use windows::Win32::UI::Shell::{FOLDERID_RoamingAppData, SHGetKnownFolderPath, KNOWN_FOLDER_FLAG};
+
+use crate::backend::{AudioDevice, FormFactor};
+use crate::error::SwitcherooError;
+use crate::hotkey::Hotkey;
+use crate::logging::LogLevel;
+use crate::profiles::Profile;
+use crate::rotation::RotationPolicy;
+use crate::rules::{self, Rule};
+use crate::tooltip;
+use crate::window_placement::WindowPlacement;
+
+/// Per-device settings, keyed by endpoint ID in `Config::devices`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceConfig {
+    // Whether this device will be included in the rotation.
+    pub selectable: bool,
+    // Whether to ask the user about including this device next time it is seen.
+    pub ask: bool,
+    // Position in the rotation, set by dragging devices in the settings window. Devices without
+    // one come after the rest, in the order Windows lists them.
+    pub order: Option<u32>,
+    // Unix time the device was last switched to, or when it was first checked for going unused.
+    pub last_used: Option<u64>,
+    // Marks a virtual endpoint that carries audio to a network speaker (AirPlay, Chromecast, ...):
+    // a command line that starts or signals the bridging software, run on switching to it.
+    pub remote_bridge: Option<String>,
+    // Replaces the form factor the driver reports, which is sometimes wrong, for the icon and
+    // anything else going by the kind of device.
+    pub form_factor: Option<FormFactor>,
+    // The name the device had when last seen, for recognising it if its ID changes.
+    pub name: Option<String>,
+    // Percentage the volume was at when last switched away from, restored on switching back if
+    // `remember_volumes` is on.
+    pub volume: Option<u32>,
+    // Left/right balance set in the volume popup, from -100 (left only) to 100 (right only),
+    // applied again on switching to the device as some drivers forget it.
+    pub balance: Option<i32>,
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self {
+            selectable: true,
+            ask: false,
+            order: None,
+            last_used: None,
+            remote_bridge: None,
+            form_factor: None,
+            name: None,
+            volume: None,
+            balance: None,
+        }
+    }
+}
+
+/// Everything persisted between runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub devices: HashMap<String, DeviceConfig>,
+    // Also switch the default microphone to the capture endpoint in the same container
+    // (e.g. the mic half of a headset) when switching outputs.
+    pub switch_microphone_with_output: bool,
+    // Also make the new device the default communications device, which apps like Teams and
+    // Discord use, rather than only the default device.
+    pub switch_communications: bool,
+    // Also set the multimedia role, for the odd app that still asks for it.
+    pub switch_multimedia: bool,
+    // Only move the audio of the app last in the foreground to the new device, leaving the
+    // default device alone, e.g. to send a game to headphones while music stays on speakers.
+    pub switch_focused_app: bool,
+    // Extra case-insensitive name fragments for devices to leave out of the rotation when first
+    // seen, on top of `BUILTIN_VIRTUAL_DEVICE_PATTERNS`.
+    pub virtual_device_patterns: Vec<String>,
+    pub rotation: RotationPolicy,
+    pub ipc: IpcConfig,
+    // Where windows were left, keyed by `window_placement::placement_key`.
+    pub windows: HashMap<String, WindowPlacement>,
+    // Named sets of devices, edited on the Profiles page of the settings window.
+    pub profiles: Vec<Profile>,
+    // Triggers and what to do when they happen, edited on the Rules page of the settings window.
+    pub rules: Vec<Rule>,
+    // How much to log, chosen from the Logging submenu. `RUST_LOG` takes precedence.
+    pub log_level: LogLevel,
+    // Whether the menu has Exit. Without it the app is stopped with `sound-switcheroo quit`.
+    pub show_exit: bool,
+    pub hotkeys: HotkeyConfig,
+    // Takes devices out of the rotation once they haven't been switched to for this many days,
+    // e.g. virtual endpoints left behind by apps. Off if missing.
+    pub pause_unused_after_days: Option<u32>,
+    pub voicemeeter: VoicemeeterConfig,
+    // Briefly shows the device switched to in an overlay, like the volume keys do.
+    pub show_osd: bool,
+    pub include: IncludeConfig,
+    // Endpoint IDs, most wanted first. Whenever outputs come or go, the first of them connected is
+    // made the default. Off if empty.
+    pub priority: Vec<String>,
+    // Plays a moment of silence on the default output at startup and, if it doesn't play, switches
+    // to the first device in `fallbacks` that does.
+    pub check_output_at_startup: bool,
+    // What clicking the tray icon does. Right click always shows the menu.
+    pub click_actions: ClickActions,
+    // Keeps a history of switches on this computer, for Usage insights in the menu.
+    pub usage_insights: bool,
+    pub whats_new: WhatsNewConfig,
+    // Percentage points the mouse wheel over the tray icon turns the volume up or down by per
+    // notch. 0 leaves the wheel alone.
+    pub wheel_volume_step: u32,
+    // Gives each output back the volume it had when last switched away from.
+    pub remember_volumes: bool,
+    pub night_mode: NightModeConfig,
+    // What the tray icon's tooltip says, with placeholders filled in whenever it's updated (see
+    // `tooltip::format`).
+    pub tooltip: String,
+    // Keeps a render client open on the output the next switch will most likely go to, so the
+    // switch is quicker, at the cost of keeping that output awake.
+    pub warm_standby: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            devices: HashMap::new(),
+            switch_microphone_with_output: false,
+            switch_communications: false,
+            switch_multimedia: false,
+            switch_focused_app: false,
+            virtual_device_patterns: Vec::new(),
+            rotation: RotationPolicy::default(),
+            ipc: IpcConfig::default(),
+            windows: HashMap::new(),
+            profiles: Vec::new(),
+            rules: Vec::new(),
+            log_level: LogLevel::default(),
+            show_exit: true,
+            hotkeys: HotkeyConfig::default(),
+            pause_unused_after_days: None,
+            voicemeeter: VoicemeeterConfig::default(),
+            show_osd: false,
+            include: IncludeConfig::default(),
+            priority: Vec::new(),
+            click_actions: ClickActions::default(),
+            usage_insights: true,
+            check_output_at_startup: true,
+            whats_new: WhatsNewConfig::default(),
+            wheel_volume_step: 2,
+            remember_volumes: false,
+            night_mode: NightModeConfig::default(),
+            tooltip: tooltip::DEFAULT_TEMPLATE.to_string(),
+            warm_standby: false,
+        }
+    }
+}
+
+/// Something clicking the tray icon can do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClickAction {
+    Nothing,
+    NextDevice,
+    PreviousDevice,
+    // Back to the output used before the current one.
+    SwapDevices,
+    NextMicrophone,
+    // Mutes or unmutes the current output.
+    ToggleMute,
+    // The volume slider.
+    ShowVolume,
+    // The volume mixer flyout, with a slider for each device.
+    ShowMixer,
+    ShowMenu,
+    OpenSettings,
+}
+
+/// What each kind of click on the tray icon does, e.g.
+/// `"click_actions": {"double": "swap_devices"}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClickActions {
+    pub left: ClickAction,
+    // Left click with Shift held down.
+    pub shift_left: ClickAction,
+    pub middle: ClickAction,
+    // Anything but nothing makes left clicks wait to see if a second click follows.
+    pub double: ClickAction,
+}
+
+impl Default for ClickActions {
+    fn default() -> Self {
+        Self {
+            left: ClickAction::NextDevice,
+            shift_left: ClickAction::NextDevice,
+            middle: ClickAction::NextMicrophone,
+            double: ClickAction::Nothing,
+        }
+    }
+}
+
+/// Kinds of device to show in the menu and switch to, toggled from the Include submenu. Devices
+/// left out stay in the config as they were.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IncludeConfig {
+    // Monitors and TVs, over HDMI or DisplayPort.
+    pub hdmi: bool,
+    // The low-quality side of Bluetooth headsets, used for calls.
+    pub hands_free: bool,
+    // Those matching `looks_virtual`.
+    pub virtual_devices: bool,
+}
+
+impl Default for IncludeConfig {
+    fn default() -> Self {
+        Self {
+            hdmi: true,
+            hands_free: true,
+            virtual_devices: true,
+        }
+    }
+}
+
+/// Keeps speakers quiet overnight: their volume is capped between `start` and `end`, unless
+/// lifted from the menu for the night.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NightModeConfig {
+    pub enabled: bool,
+    // Local times like `22:00`. The night runs past midnight when `end` is the earlier.
+    pub start: String,
+    pub end: String,
+    // The highest volume speakers are allowed, in percent.
+    pub max_volume: u32,
+    // Also switches from speakers to the first device in the rotation that isn't, as night starts.
+    pub switch_away: bool,
+}
+
+impl Default for NightModeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            start: "22:00".to_string(),
+            end: "07:00".to_string(),
+            max_volume: 30,
+            switch_away: false,
+        }
+    }
+}
+
+impl NightModeConfig {
+    /// Whether the local `time` (`HH:MM`) is at night, when night mode is on.
+    pub fn covers(&self, time: &str) -> bool {
+        let minute =
+            |time: &str| rules::parse_time(time).map(|(hours, minutes)| hours * 60 + minutes);
+        let (Some(start), Some(end), Some(now)) =
+            (minute(&self.start), minute(&self.end), minute(time))
+        else {
+            return false;
+        };
+        self.enabled
+            && if start <= end {
+                (start..end).contains(&now)
+            } else {
+                now >= start || now < end
+            }
+    }
+
+    /// Whether devices of this kind are heard around the room, and so limited at night.
+    pub fn limits(form_factor: EndpointFormFactor) -> bool {
+        [Speakers, DigitalAudioDisplayDevice, RemoteNetworkDevice].contains(&form_factor)
+    }
+}
+
+/// What's been pointed out after updates.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WhatsNewConfig {
+    // The version last run, whose features have been pointed out.
+    pub seen_version: Option<String>,
+    // IDs of menu items marked as new, until `badges_until` (Unix time).
+    pub badges: Vec<u32>,
+    pub badges_until: u64,
+}
+
+/// Who may use the control surfaces, on top of presenting the token.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IpcConfig {
+    // Serves commands on a named pipe, for Stream Deck scripts, AutoHotkey and the like.
+    pub enabled: bool,
+    // Executables (full paths or file names) allowed to connect. Empty allows any with the token.
+    pub allowed_clients: Vec<String>,
+}
+
+/// Hotkeys that switch output devices from anywhere, e.g. `"next_device": "Ctrl+Alt+F12"`.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HotkeyConfig {
+    // Does what left clicking the tray icon does.
+    pub next_device: Option<Hotkey>,
+    // Goes the other way through the rotation.
+    pub previous_device: Option<Hotkey>,
+    // Flips between the current output and the one before it.
+    pub swap_devices: Option<Hotkey>,
+    // Mutes or unmutes the default microphone, marking the tray icon while it's muted.
+    pub mute_microphone: Option<Hotkey>,
+    // Hotkeys that switch straight to a device, e.g. `"Ctrl+Alt+1": "{0.0.0.00000000}.{...}"`.
+    pub devices: HashMap<Hotkey, String>,
+}
+
+impl HotkeyConfig {
+    /// The per-device hotkeys in a stable order, for numbering them.
+    pub fn device_hotkeys(&self) -> Vec<(Hotkey, &str)> {
+        let mut hotkeys: Vec<_> = self
+            .devices
+            .iter()
+            .map(|(hotkey, device_id)| (*hotkey, device_id.as_str()))
+            .collect();
+        hotkeys.sort_by_key(|(hotkey, _)| (hotkey.modifiers, hotkey.key));
+        hotkeys
+    }
+
+    /// Hotkeys set for more than one thing, here or on `profiles`, as each can only be registered
+    /// once.
+    pub fn clashes(&self, profiles: &[Profile]) -> Vec<Hotkey> {
+        let mut seen = Vec::new();
+        let mut clashes = Vec::new();
+        let all = [
+            self.next_device,
+            self.previous_device,
+            self.swap_devices,
+            self.mute_microphone,
+        ]
+        .into_iter()
+        .flatten()
+        .chain(self.devices.keys().copied())
+        .chain(profiles.iter().filter_map(|profile| profile.hotkey));
+        for hotkey in all {
+            if seen.contains(&hotkey) {
+                if !clashes.contains(&hotkey) {
+                    clashes.push(hotkey);
+                }
+            } else {
+                seen.push(hotkey);
+            }
+        }
+        clashes
+    }
+}
+
+/// Switching through Voicemeeter, for setups where the default device has to stay on one of its
+/// virtual inputs.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VoicemeeterConfig {
+    // Whether switching to a physical output makes it Voicemeeter's hardware out rather than the
+    // default device. Chosen from the menu, which offers it when Voicemeeter is installed.
+    pub enabled: bool,
+    // Which hardware out, from 0 for A1.
+    pub bus: u32,
+}
+
+/// Settings an administrator makes for everyone on the machine, in the registry under
+/// `HKEY_LOCAL_MACHINE\Software\PurpleHatstands\SoundSwitcheroo`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MachineConfig {
+    // Hides Exit and the settings in the menu and never saves the config, so a shared machine
+    // keeps the rotation it was set up with. Set by a non-zero `Kiosk` DWORD.
+    pub kiosk: bool,
+    // The config used in kiosk mode in place of the user's, from the `ConfigFile` string. Without
+    // one, kiosk mode runs on the defaults.
+    pub config_file: Option<PathBuf>,
+}
+
+const MACHINE_CONFIG_KEY: &str = r"Software\PurpleHatstands\SoundSwitcheroo";
+
+impl MachineConfig {
+    /// Reads the machine config, treating a missing key or value as the default.
+    pub fn load() -> Self {
+        let Ok(key) = windows_registry::LOCAL_MACHINE.open(MACHINE_CONFIG_KEY) else {
+            return Self::default();
+        };
+        Self {
+            kiosk: key.get_u32("Kiosk").is_ok_and(|kiosk| kiosk != 0),
+            config_file: key
+                .get_string("ConfigFile")
+                .ok()
+                .filter(|path| !path.trim().is_empty())
+                .map(PathBuf::from),
+        }
+    }
+}
+
+// Name fragments of outputs on monitors and TVs, whose drivers don't always say so.
+const HDMI_PATTERNS: &[&str] = &["hdmi", "displayport", "display audio"];
+
+// Name fragments of Bluetooth headsets' hands-free endpoints.
+const HANDS_FREE_PATTERNS: &[&str] = &["hands-free", "hands free"];
+
+// Name fragments of virtual endpoints that come and go with other apps and are rarely wanted in
+// the rotation.
+const BUILTIN_VIRTUAL_DEVICE_PATTERNS: &[&str] = &[
+    "steam streaming",
+    "nvidia broadcast",
+    "nvidia virtual audio",
+    "oculus virtual audio",
+    "vb-audio",
+    "cable input",
+    "voicemeeter",
+    "krisp",
+    "zoomaudiodevice",
+    "meta quest",
+];
+
+// Older versions stored a plain map of device ID -> selectable.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ConfigFile {
+    Legacy(HashMap<String, bool>),
+    Current(Box<Config>),
+}
+
+/// Gets the path to the user's roaming AppData directory
+fn get_roaming_appdata_path() -> Result<PathBuf, SwitcherooError> {
+    unsafe {
+        let path_ptr =
+            SHGetKnownFolderPath(&FOLDERID_RoamingAppData, KNOWN_FOLDER_FLAG::default(), None)?;
+
+        let path_str = path_ptr.to_string()?;
+        let path = PathBuf::from(path_str);
+
+        // Free the memory allocated by SHGetKnownFolderPath
+        windows::Win32::System::Com::CoTaskMemFree(Some(path_ptr.as_ptr() as *const _));
+
+        Ok(path)
+    }
+}
+
+/// Gets the directory the config is kept in by default, creating it if needed.
+fn get_data_dir() -> Result<PathBuf, SwitcherooError> {
+    let mut path = get_roaming_appdata_path()?;
+    path.push("PurpleHatstands");
+    path.push("SoundSwitcheroo");
+
+    // Create the directory if it doesn't exist
+    if !path.exists() {
+        fs::create_dir_all(&path)?;
+    }
+    Ok(path)
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Gets the directory plugins are run from, which may not exist.
+pub fn get_plugins_dir() -> Result<PathBuf, SwitcherooError> {
+    Ok(get_data_dir()?.join("plugins"))
+}
+
+/// Gets the directory scripts are loaded from, which may not exist.
+pub fn get_scripts_dir() -> Result<PathBuf, SwitcherooError> {
+    Ok(get_data_dir()?.join("scripts"))
+}
+
+/// Gets the full path to the AudioSwitch configuration file
+pub fn get_config_file_path() -> Result<PathBuf, SwitcherooError> {
+    let mut path = get_data_dir()?;
+    path.push("device_config.json");
+    debug!("Config file path: {}", path.display());
+    Ok(path)
+}
+
+impl Config {
+    /// The roles a device is made the default for when switching to it.
+    pub fn roles(&self) -> Vec<ERole> {
+        let mut roles = vec![eConsole];
+        if self.switch_communications {
+            roles.push(eCommunications);
+        }
+        if self.switch_multimedia {
+            roles.push(eMultimedia);
+        }
+        roles
+    }
+
+    /// Applies the saved per-device config to the current devices, recording any new devices.
+    /// New devices that look like transient virtual endpoints start out of the rotation, and other
+    /// new devices stay out of it until the user decides (except on first run).
+    pub fn apply_to_devices(&mut self, devices: &mut [AudioDevice]) {
+        let first_run = self.devices.is_empty();
+        for device in devices.iter_mut() {
+            if !self.devices.contains_key(&device.id) {
+                let device_config = if self.looks_virtual(&device.friendly_name) {
+                    info!(
+                        "Excluding virtual device from rotation: {}",
+                        device.friendly_name
+                    );
+                    DeviceConfig {
+                        selectable: false,
+                        ask: false,
+                        ..Default::default()
+                    }
+                } else if first_run {
+                    DeviceConfig::default()
+                } else {
+                    info!("New device: {}", device.friendly_name);
+                    DeviceConfig {
+                        selectable: false,
+                        ask: true,
+                        ..Default::default()
+                    }
+                };
+                self.devices.insert(device.id.clone(), device_config);
+            }
+            let device_config = self.devices.entry(device.id.clone()).or_default();
+            device.selectable = device_config.selectable;
+            device_config.name = Some(device.friendly_name.to_string());
+            if let Some(form_factor) = device_config.form_factor {
+                debug!(
+                    "Treating {} as {form_factor:?}, as configured",
+                    device.friendly_name
+                );
+                device.form_factor = form_factor.into();
+            }
+            debug!(
+                "Applied selectable state for device {}: {}",
+                device.friendly_name, device_config.selectable
+            );
+        }
+        self.sort_devices(devices);
+    }
+
+    /// Puts `devices` in rotation order.
+    pub fn sort_devices(&self, devices: &mut [AudioDevice]) {
+        // Stable, so devices without an order keep theirs.
+        devices.sort_by_key(|device| {
+            self.devices
+                .get(&device.id)
+                .and_then(|device_config| device_config.order)
+                .unwrap_or(u32::MAX)
+        });
+    }
+
+    /// Records a new rotation order, given as device IDs.
+    pub fn set_order(&mut self, device_ids: &[String]) {
+        for (position, id) in device_ids.iter().enumerate() {
+            self.devices.entry(id.clone()).or_default().order = Some(position as u32);
+        }
+    }
+
+    /// Notes that a device was just switched to.
+    pub fn record_use(&mut self, device_id: &str, now: SystemTime) {
+        self.devices
+            .entry(device_id.to_string())
+            .or_default()
+            .last_used = Some(unix_time(now));
+    }
+
+    /// Notes the volume a device is being switched away from at, if volumes are remembered.
+    pub fn remember_volume(&mut self, device_id: &str, percent: u32) {
+        if self.remember_volumes {
+            self.devices
+                .entry(device_id.to_string())
+                .or_default()
+                .volume = Some(percent);
+        }
+    }
+
+    /// The volume to give a device being switched to, if volumes are remembered and it has one.
+    pub fn remembered_volume(&self, device_id: &str) -> Option<u32> {
+        if !self.remember_volumes {
+            return None;
+        }
+        self.devices.get(device_id)?.volume
+    }
+
+    /// Saves the balance set for a device, leaving nothing saved once it's back in the middle.
+    pub fn set_balance(&mut self, device_id: &str, balance: i32) {
+        self.devices
+            .entry(device_id.to_string())
+            .or_default()
+            .balance = (balance != 0).then_some(balance);
+    }
+
+    /// Takes the devices that haven't been used for `pause_unused_after_days` out of the rotation
+    /// and returns their IDs. Being the current device counts as being used, and devices without a
+    /// timestamp yet count from now.
+    pub fn pause_unused(&mut self, current_device_id: &str, now: SystemTime) -> Vec<String> {
+        let Some(days) = self.pause_unused_after_days else {
+            return Vec::new();
+        };
+        let now = unix_time(now);
+        let mut paused = Vec::new();
+        for (id, device_config) in &mut self.devices {
+            if id == current_device_id {
+                device_config.last_used = Some(now);
+                continue;
+            }
+            if !device_config.selectable {
+                continue;
+            }
+            let last_used = *device_config.last_used.get_or_insert(now);
+            if now.saturating_sub(last_used) >= u64::from(days) * 24 * 60 * 60 {
+                device_config.selectable = false;
+                paused.push(id.clone());
+            }
+        }
+        paused.sort();
+        paused
+    }
+
+    /// Puts paused devices back in the rotation, as if they had just been used.
+    pub fn resume(&mut self, device_ids: &[String], now: SystemTime) {
+        for id in device_ids {
+            let device_config = self.devices.entry(id.clone()).or_default();
+            device_config.selectable = true;
+            device_config.last_used = Some(unix_time(now));
+        }
+    }
+
+    /// Whether a device name looks like a transient virtual endpoint.
+    pub fn looks_virtual(&self, friendly_name: &str) -> bool {
+        let name = friendly_name.to_lowercase();
+        BUILTIN_VIRTUAL_DEVICE_PATTERNS
+            .iter()
+            .copied()
+            .chain(self.virtual_device_patterns.iter().map(String::as_str))
+            .any(|pattern| name.contains(&pattern.to_lowercase()))
+    }
+
+    /// Whether the Include toggles leave a device out of the menu and rotation.
+    pub fn excludes(&self, device: &AudioDevice) -> bool {
+        let name = device.friendly_name.to_string().to_lowercase();
+        (!self.include.hdmi
+            && (device.form_factor == DigitalAudioDisplayDevice
+                || HDMI_PATTERNS.iter().any(|pattern| name.contains(pattern))))
+            || (!self.include.hands_free
+                && HANDS_FREE_PATTERNS
+                    .iter()
+                    .any(|pattern| name.contains(pattern)))
+            || (!self.include.virtual_devices && self.looks_virtual(&name))
+    }
+
+    /// The connected device highest in the priority list, if any are.
+    pub fn highest_priority<'a>(&self, devices: &'a [AudioDevice]) -> Option<&'a AudioDevice> {
+        self.priority
+            .iter()
+            .find_map(|id| devices.iter().find(|device| &device.id == id))
+    }
+
+    /// The devices to try in place of one that isn't working: those in the priority list in its
+    /// order, then the rest of the rotation in its order.
+    pub fn fallbacks(&self, devices: &[AudioDevice]) -> Vec<AudioDevice> {
+        let mut fallbacks: Vec<AudioDevice> = self
+            .priority
+            .iter()
+            .filter_map(|id| devices.iter().find(|device| &device.id == id).cloned())
+            .collect();
+        let mut rest: Vec<AudioDevice> = devices
+            .iter()
+            .filter(|device| device.selectable && !self.priority.contains(&device.id))
+            .cloned()
+            .collect();
+        self.sort_devices(&mut rest);
+        fallbacks.append(&mut rest);
+        fallbacks
+    }
+
+    /// Loads the config from the JSON file in the roaming AppData directory
+    pub fn load() -> Result<Self, SwitcherooError> {
+        Self::load_from(&get_config_file_path()?)
+    }
+
+    /// Saves the config to a JSON file in the roaming AppData directory
+    pub fn save(&self) -> Result<(), SwitcherooError> {
+        self.save_to(&get_config_file_path()?)
+    }
+
+    pub fn load_from(config_path: &Path) -> Result<Self, SwitcherooError> {
+        if !config_path.exists() {
+            debug!("Config file does not exist: {}", config_path.display());
+            return Ok(Self::default());
+        }
+
+        let json_data = fs::read_to_string(config_path)?;
+        let config = match serde_json::from_str(&json_data)? {
+            ConfigFile::Legacy(device_states) => Self {
+                devices: device_states
+                    .into_iter()
+                    .map(|(id, selectable)| {
+                        (
+                            id,
+                            DeviceConfig {
+                                selectable,
+                                ..Default::default()
+                            },
+                        )
+                    })
+                    .collect(),
+                ..Default::default()
+            },
+            ConfigFile::Current(config) => *config,
+        };
+
+        debug!("Loaded config from: {}", config_path.display());
+        Ok(config)
+    }
+
+    pub fn save_to(&self, config_path: &Path) -> Result<(), SwitcherooError> {
+        let json_data = serde_json::to_string_pretty(self)?;
+        fs::write(config_path, json_data)?;
+
+        debug!("Saved config to: {}", config_path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::AudioBackend;
+    use crate::simulation::headset_backend;
+    use std::time::Duration;
+    use windows::Win32::Media::Audio::{eRender, EndpointFormFactor};
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    #[test]
+    fn device_hotkeys_are_keyed_by_hotkey() {
+        let hotkeys: HotkeyConfig = serde_json::from_str(
+            r#"{"devices": {"ctrl+alt+2": "{headset}", "Ctrl+Alt+1": "{speakers}"}}"#,
+        )
+        .unwrap();
+        let device_hotkeys: Vec<_> = hotkeys
+            .device_hotkeys()
+            .into_iter()
+            .map(|(hotkey, device_id)| (hotkey.to_string(), device_id))
+            .collect();
+        assert_eq!(
+            device_hotkeys,
+            [
+                ("Ctrl+Alt+1".to_string(), "{speakers}"),
+                ("Ctrl+Alt+2".to_string(), "{headset}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn hotkeys_clash_with_each_other_and_profiles() {
+        let hotkeys: HotkeyConfig = serde_json::from_str(
+            r#"{"next_device": "Ctrl+Alt+N", "swap_devices": "Ctrl+Alt+1",
+                "devices": {"Ctrl+Alt+1": "{speakers}", "Ctrl+Alt+2": "{headset}"}}"#,
+        )
+        .unwrap();
+        assert!(hotkeys
+            .clashes(&[])
+            .iter()
+            .map(ToString::to_string)
+            .eq(["Ctrl+Alt+1"]));
+        let profiles = [Profile {
+            hotkey: Some("Ctrl+Alt+N".parse().unwrap()),
+            ..Default::default()
+        }];
+        assert_eq!(hotkeys.clashes(&profiles).len(), 2);
+        assert!(HotkeyConfig::default().clashes(&profiles).is_empty());
+    }
+
+    #[test]
+    fn include_toggles_leave_out_kinds_of_device() {
+        let device = |name: &str, form_factor| AudioDevice::new(name, name, form_factor);
+        let speakers = device(
+            "Speakers (Realtek(R) Audio)",
+            windows::Win32::Media::Audio::Speakers,
+        );
+        let tv = device(
+            "LG TV (NVIDIA High Definition Audio)",
+            DigitalAudioDisplayDevice,
+        );
+        let monitor = device("DELL U2720Q (HDMI)", windows::Win32::Media::Audio::Speakers);
+        let hands_free = device(
+            "Headset (WH-1000XM4 Hands-Free AG Audio)",
+            windows::Win32::Media::Audio::Headset,
+        );
+        let cable = device(
+            "CABLE Input (VB-Audio Virtual Cable)",
+            windows::Win32::Media::Audio::Speakers,
+        );
+        let all = [&speakers, &tv, &monitor, &hands_free, &cable];
+        let mut config = Config::default();
+        assert!(all.iter().all(|device| !config.excludes(device)));
+        config.include = IncludeConfig {
+            hdmi: false,
+            hands_free: false,
+            virtual_devices: false,
+        };
+        let included: Vec<_> = all
+            .iter()
+            .filter(|device| !config.excludes(device))
+            .map(|device| device.id.as_str())
+            .collect();
+        assert_eq!(included, ["Speakers (Realtek(R) Audio)"]);
+        config.include.hands_free = true;
+        assert!(!config.excludes(&hands_free));
+        assert!(config.excludes(&monitor));
+    }
+
+    #[test]
+    fn click_actions_default_to_the_old_behaviour() {
+        let config: Config = serde_json::from_str(
+            r#"{"click_actions": {"double": "swap_devices", "middle": "toggle_mute"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.click_actions,
+            ClickActions {
+                left: ClickAction::NextDevice,
+                shift_left: ClickAction::NextDevice,
+                middle: ClickAction::ToggleMute,
+                double: ClickAction::SwapDevices,
+            }
+        );
+        assert!(serde_json::from_str::<ClickActions>(r#"{"left": "explode"}"#).is_err());
+    }
+
+    #[test]
+    fn volumes_are_only_remembered_when_asked() {
+        let mut config = Config::default();
+        config.remember_volume("{speakers}", 30);
+        assert_eq!(config.remembered_volume("{speakers}"), None);
+        config.remember_volumes = true;
+        config.remember_volume("{speakers}", 30);
+        config.remember_volume("{headphones}", 60);
+        assert_eq!(config.remembered_volume("{speakers}"), Some(30));
+        assert_eq!(config.remembered_volume("{headphones}"), Some(60));
+        assert_eq!(config.remembered_volume("{tv}"), None);
+    }
+
+    #[test]
+    fn nights_can_run_past_midnight() {
+        let mut night_mode = NightModeConfig::default();
+        assert!(!night_mode.covers("23:00"));
+        night_mode.enabled = true;
+        assert!(night_mode.covers("23:00"));
+        assert!(night_mode.covers("00:30"));
+        assert!(!night_mode.covers("07:00"));
+        assert!(!night_mode.covers("12:00"));
+        night_mode.start = "01:00".to_string();
+        night_mode.end = "05:30".to_string();
+        assert!(night_mode.covers("05:29"));
+        assert!(!night_mode.covers("23:00"));
+        night_mode.end = "later".to_string();
+        assert!(!night_mode.covers("02:00"));
+    }
+
+    #[test]
+    fn highest_priority_device_wins() {
+        let devices: Vec<AudioDevice> = ["{speakers}", "{tv}"]
+            .into_iter()
+            .map(|id| AudioDevice::new(id, id, Default::default()))
+            .collect();
+        let mut config = Config::default();
+        assert!(config.highest_priority(&devices).is_none());
+        config.priority = vec![
+            "{headset}".to_string(),
+            "{tv}".to_string(),
+            "{speakers}".to_string(),
+        ];
+        assert_eq!(config.highest_priority(&devices).unwrap().id, "{tv}");
+        assert!(config.highest_priority(&[]).is_none());
+    }
+
+    #[test]
+    fn fallbacks_follow_the_priority_list_then_the_rotation() {
+        let devices: Vec<AudioDevice> = ["{speakers}", "{tv}", "{headset}", "{virtual}"]
+            .into_iter()
+            .map(|id| AudioDevice {
+                selectable: id != "{virtual}",
+                ..AudioDevice::new(id, id, Default::default())
+            })
+            .collect();
+        let mut config = Config {
+            priority: vec!["{tv}".to_string(), "{missing}".to_string()],
+            ..Default::default()
+        };
+        config.set_order(&["{headset}".to_string(), "{speakers}".to_string()]);
+        let ids: Vec<String> = config
+            .fallbacks(&devices)
+            .into_iter()
+            .map(|device| device.id)
+            .collect();
+        assert_eq!(ids, ["{tv}", "{headset}", "{speakers}"]);
+    }
+
+    #[test]
+    fn form_factors_are_overridden_by_name() {
+        let device_config: DeviceConfig =
+            serde_json::from_str(r#"{"form_factor": "headphones"}"#).unwrap();
+        assert_eq!(device_config.form_factor, Some(FormFactor::Headphones));
+        assert_eq!(
+            EndpointFormFactor::from(FormFactor::Spdif),
+            windows::Win32::Media::Audio::SPDIF
+        );
+        assert_eq!(
+            EndpointFormFactor::from(FormFactor::Unknown),
+            windows::Win32::Media::Audio::UnknownFormFactor
+        );
+        assert!(serde_json::from_str::<DeviceConfig>(r#"{"form_factor": "kazoo"}"#).is_err());
+    }
+
+    #[test]
+    fn unused_devices_are_paused_until_resumed() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let mut config = Config {
+            pause_unused_after_days: Some(30),
+            ..Default::default()
+        };
+        for id in ["{speakers}", "{headset}", "{virtual}"] {
+            config
+                .devices
+                .insert(id.to_string(), DeviceConfig::default());
+        }
+        // Nothing has a timestamp yet, so the clock starts now.
+        assert!(config.pause_unused("{speakers}", start).is_empty());
+        config.record_use("{headset}", start + DAY * 20);
+        assert_eq!(
+            config.pause_unused("{speakers}", start + DAY * 31),
+            ["{virtual}"]
+        );
+        assert!(!config.devices["{virtual}"].selectable);
+        // The current device stays, however long it has been current.
+        assert!(config.devices["{speakers}"].selectable);
+        config.resume(&["{virtual}".to_string()], start + DAY * 32);
+        assert!(config.devices["{virtual}"].selectable);
+        assert_eq!(
+            config.pause_unused("{virtual}", start + DAY * 51),
+            ["{headset}"]
+        );
+    }
+
+    #[test]
+    fn saved_order_is_applied() {
+        let mut devices = headset_backend().devices(eRender).unwrap();
+        let mut config = Config::default();
+        config.apply_to_devices(&mut devices);
+        assert_eq!(devices[0].id, "{0.0.0.00000000}.{speakers}");
+        config.set_order(&["{0.0.0.00000000}.{headset}".to_string()]);
+        config.apply_to_devices(&mut devices);
+        assert_eq!(devices[0].id, "{0.0.0.00000000}.{headset}");
+        assert_eq!(devices[1].id, "{0.0.0.00000000}.{speakers}");
+    }
+}
@@ -0,0 +1,73 @@
+use sound_switcheroo::error::SwitcherooError;
+use std::io::{IsTerminal, Write};
+use windows::Win32::System::Console::{
+    AllocConsole, AttachConsole, GetConsoleMode, GetStdHandle, SetConsoleMode,
+    ATTACH_PARENT_PROCESS, CONSOLE_MODE, ENABLE_VIRTUAL_TERMINAL_PROCESSING, STD_ERROR_HANDLE,
+    STD_HANDLE, STD_OUTPUT_HANDLE,
+};
+
+/// Connects stdout and stderr to the console of the shell that started the app, or a new console
+/// if there isn't one. Release builds use the GUI subsystem and otherwise have nowhere to write.
+pub fn attach() -> Result<(), SwitcherooError> {
+    unsafe {
+        if AttachConsole(ATTACH_PARENT_PROCESS).is_err() {
+            AllocConsole()?;
+        }
+        // Fails when the output is redirected, which is fine.
+        let _ = enable_colors(STD_OUTPUT_HANDLE);
+        let _ = enable_colors(STD_ERROR_HANDLE);
+    }
+    Ok(())
+}
+
+unsafe fn enable_colors(std_handle: STD_HANDLE) -> Result<(), SwitcherooError> {
+    unsafe {
+        let handle = GetStdHandle(std_handle)?;
+        let mut mode = CONSOLE_MODE::default();
+        GetConsoleMode(handle, &mut mode)?;
+        SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    Warning,
+    Error,
+}
+
+impl Status {
+    fn label(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Error => "error",
+        }
+    }
+
+    // ANSI SGR color code.
+    fn color(self) -> u8 {
+        match self {
+            Status::Ok => 32,
+            Status::Warning => 33,
+            Status::Error => 31,
+        }
+    }
+}
+
+/// Prints a status line for the user, colored when stdout is a terminal. Does nothing useful
+/// unless a console has been attached.
+pub fn status(status: Status, message: &str) {
+    let mut stdout = std::io::stdout();
+    let _ = if stdout.is_terminal() {
+        writeln!(
+            stdout,
+            "\x1b[1;{}m{:>7}\x1b[0m {message}",
+            status.color(),
+            status.label()
+        )
+    } else {
+        writeln!(stdout, "{:>7} {message}", status.label())
+    };
+}
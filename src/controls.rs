@@ -0,0 +1,434 @@
+use std::sync::Once;
+use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+use windows::Win32::Graphics::Gdi::{GetStockObject, COLOR_WINDOW, DEFAULT_GUI_FONT, HBRUSH};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::Controls::{BST_CHECKED, BST_UNCHECKED};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, GetClientRect, GetWindowTextLengthW, GetWindowTextW, MoveWindow,
+    RegisterClassExW, SendMessageW, SetWindowTextW, BM_GETCHECK, BM_SETCHECK, BS_AUTOCHECKBOX,
+    CBS_AUTOHSCROLL, CBS_DROPDOWN, CBS_DROPDOWNLIST, CB_ADDSTRING, CB_GETCURSEL, CB_RESETCONTENT,
+    CB_SETCURSEL, ES_AUTOHSCROLL, ES_AUTOVSCROLL, ES_MULTILINE, ES_READONLY, HMENU, LBS_HASSTRINGS,
+    LBS_NOINTEGRALHEIGHT, LBS_NOTIFY, LB_ADDSTRING, LB_GETCURSEL, LB_RESETCONTENT, LB_SETCURSEL,
+    WINDOW_EX_STYLE, WINDOW_STYLE, WM_SETFONT, WNDCLASSEXW, WNDPROC, WS_BORDER, WS_CHILD,
+    WS_EX_CLIENTEDGE, WS_EX_CONTROLPARENT, WS_TABSTOP, WS_VISIBLE, WS_VSCROLL,
+};
+use windows_strings::{w, PCWSTR};
+
+use sound_switcheroo::backend::AudioDevice;
+use sound_switcheroo::error::SwitcherooError;
+use sound_switcheroo::safe_strings::with_wide_str;
+
+/// Creates a hidden page of the settings window, registering its window `class` the first time.
+pub fn create_page(
+    parent: HWND,
+    class: PCWSTR,
+    registered: &Once,
+    callback: WNDPROC,
+) -> Result<HWND, SwitcherooError> {
+    unsafe {
+        let module = GetModuleHandleW(None)?;
+        registered.call_once(|| {
+            RegisterClassExW(&WNDCLASSEXW {
+                cbSize: std::mem::size_of::<WNDCLASSEXW>() as u32,
+                lpfnWndProc: callback,
+                hInstance: module.into(),
+                hbrBackground: HBRUSH((COLOR_WINDOW.0 + 1) as isize as *mut _),
+                lpszClassName: class,
+                ..Default::default()
+            });
+        });
+        Ok(CreateWindowExW(
+            WS_EX_CONTROLPARENT,
+            class,
+            PCWSTR::null(),
+            WS_CHILD,
+            0,
+            0,
+            0,
+            0,
+            Some(parent),
+            None,
+            Some(module.into()),
+            None,
+        )?)
+    }
+}
+
+/// Creates a visible control of a standard `class` in `parent`, in the usual dialog font. `id` is
+/// sent back in its WM_COMMAND notifications.
+pub fn create(
+    parent: HWND,
+    class: PCWSTR,
+    text: &str,
+    style: WINDOW_STYLE,
+    ex_style: WINDOW_EX_STYLE,
+    id: u16,
+) -> Result<HWND, SwitcherooError> {
+    unsafe {
+        let module = GetModuleHandleW(None)?;
+        let control = with_wide_str(text, |text| {
+            CreateWindowExW(
+                ex_style,
+                class,
+                text,
+                WS_CHILD | WS_VISIBLE | style,
+                0,
+                0,
+                0,
+                0,
+                Some(parent),
+                Some(HMENU(id as usize as *mut _)),
+                Some(module.into()),
+                None,
+            )
+        })?;
+        set_font(control);
+        Ok(control)
+    }
+}
+
+/// A listbox that reports selection changes.
+pub fn list(parent: HWND, id: u16) -> Result<HWND, SwitcherooError> {
+    create(
+        parent,
+        w!("LISTBOX"),
+        "",
+        WS_TABSTOP
+            | WS_BORDER
+            | WS_VSCROLL
+            | WINDOW_STYLE((LBS_NOTIFY | LBS_HASSTRINGS | LBS_NOINTEGRALHEIGHT) as u32),
+        Default::default(),
+        id,
+    )
+}
+
+/// A single-line text field.
+pub fn edit(parent: HWND) -> Result<HWND, SwitcherooError> {
+    create(
+        parent,
+        w!("EDIT"),
+        "",
+        WS_TABSTOP | WINDOW_STYLE(ES_AUTOHSCROLL as u32),
+        WS_EX_CLIENTEDGE,
+        0,
+    )
+}
+
+/// A multi-line text box that can be selected and copied from but not edited.
+pub fn read_only_text(parent: HWND, text: &str) -> Result<HWND, SwitcherooError> {
+    create(
+        parent,
+        w!("EDIT"),
+        text,
+        WS_TABSTOP
+            | WS_VSCROLL
+            | WINDOW_STYLE((ES_MULTILINE | ES_READONLY | ES_AUTOVSCROLL) as u32),
+        WS_EX_CLIENTEDGE,
+        0,
+    )
+}
+
+/// A dropdown list, which can also be typed into if `editable`.
+pub fn dropdown(parent: HWND, id: u16, editable: bool) -> Result<HWND, SwitcherooError> {
+    let kind = if editable {
+        CBS_DROPDOWN | CBS_AUTOHSCROLL
+    } else {
+        CBS_DROPDOWNLIST
+    };
+    create(
+        parent,
+        w!("COMBOBOX"),
+        "",
+        WS_TABSTOP | WS_VSCROLL | WINDOW_STYLE(kind as u32),
+        Default::default(),
+        id,
+    )
+}
+
+pub fn label(parent: HWND, text: &str) -> Result<HWND, SwitcherooError> {
+    create(
+        parent,
+        w!("STATIC"),
+        text,
+        Default::default(),
+        Default::default(),
+        0,
+    )
+}
+
+pub fn button(parent: HWND, text: &str, id: u16) -> Result<HWND, SwitcherooError> {
+    create(
+        parent,
+        w!("BUTTON"),
+        text,
+        WS_TABSTOP,
+        Default::default(),
+        id,
+    )
+}
+
+pub fn checkbox(parent: HWND, text: &str) -> Result<HWND, SwitcherooError> {
+    create(
+        parent,
+        w!("BUTTON"),
+        text,
+        WS_TABSTOP | WINDOW_STYLE(BS_AUTOCHECKBOX as u32),
+        Default::default(),
+        0,
+    )
+}
+
+pub fn checked(checkbox: HWND) -> bool {
+    unsafe { SendMessageW(checkbox, BM_GETCHECK, None, None) }.0 == BST_CHECKED.0 as isize
+}
+
+pub fn set_checked(checkbox: HWND, checked: bool) {
+    let state = if checked { BST_CHECKED } else { BST_UNCHECKED };
+    unsafe {
+        SendMessageW(checkbox, BM_SETCHECK, Some(WPARAM(state.0 as usize)), None);
+    }
+}
+
+/// Switches a control from the system font to the usual dialog font.
+pub fn set_font(control: HWND) {
+    unsafe {
+        SendMessageW(
+            control,
+            WM_SETFONT,
+            Some(WPARAM(GetStockObject(DEFAULT_GUI_FONT).0 as usize)),
+            Some(LPARAM(1)),
+        );
+    }
+}
+
+pub fn text(window: HWND) -> String {
+    unsafe {
+        let mut buffer = vec![0u16; GetWindowTextLengthW(window) as usize + 1];
+        let length = GetWindowTextW(window, &mut buffer);
+        String::from_utf16_lossy(&buffer[..length as usize])
+    }
+}
+
+pub fn set_text(window: HWND, text: &str) {
+    with_wide_str(text, |text| unsafe {
+        let _ = SetWindowTextW(window, text);
+    });
+}
+
+// Replaces the entries of a listbox or combobox, using its messages.
+fn fill<'a>(
+    control: HWND,
+    [reset, add, select]: [u32; 3],
+    labels: impl IntoIterator<Item = &'a str>,
+    selected: Option<usize>,
+) {
+    unsafe {
+        SendMessageW(control, reset, None, None);
+        for label in labels {
+            with_wide_str(label, |label| {
+                SendMessageW(control, add, None, Some(LPARAM(label.as_ptr() as isize)))
+            });
+        }
+        if let Some(selected) = selected {
+            SendMessageW(control, select, Some(WPARAM(selected)), None);
+        }
+    }
+}
+
+pub fn fill_list<'a>(
+    list: HWND,
+    labels: impl IntoIterator<Item = &'a str>,
+    selected: Option<usize>,
+) {
+    fill(
+        list,
+        [LB_RESETCONTENT, LB_ADDSTRING, LB_SETCURSEL],
+        labels,
+        selected,
+    );
+}
+
+pub fn fill_combo<'a>(
+    combo: HWND,
+    labels: impl IntoIterator<Item = &'a str>,
+    selected: Option<usize>,
+) {
+    fill(
+        combo,
+        [CB_RESETCONTENT, CB_ADDSTRING, CB_SETCURSEL],
+        labels,
+        selected,
+    );
+}
+
+/// The index of the selected entry of a listbox, if any.
+pub fn list_selection(list: HWND) -> Option<usize> {
+    usize::try_from(unsafe { SendMessageW(list, LB_GETCURSEL, None, None) }.0).ok()
+}
+
+/// (ID, name) of each device, for `fill_device_combo`.
+pub fn device_names(devices: &[AudioDevice]) -> Vec<(String, String)> {
+    devices
+        .iter()
+        .map(|device| (device.id.clone(), device.friendly_name.to_string()))
+        .collect()
+}
+
+/// Fills a dropdown with `none` if given, then `devices` as (ID, name), then `chosen` marked as not
+/// connected if it isn't one of them, and selects `chosen`. Returns the device ID behind each
+/// entry, for `combo_choice`.
+pub fn fill_device_combo(
+    combo: HWND,
+    none: Option<&str>,
+    devices: &[(String, String)],
+    chosen: Option<&String>,
+) -> Vec<Option<String>> {
+    let mut choices: Vec<(Option<String>, String)> = none
+        .map(|none| (None, none.to_string()))
+        .into_iter()
+        .collect();
+    choices.extend(
+        devices
+            .iter()
+            .map(|(id, name)| (Some(id.clone()), name.clone())),
+    );
+    if let Some(chosen) = chosen
+        && !devices.iter().any(|(id, _)| id == chosen)
+    {
+        choices.push((Some(chosen.clone()), format!("(not connected) {chosen}")));
+    }
+    let selected = choices
+        .iter()
+        .position(|(id, _)| id.as_ref() == chosen)
+        .or((!choices.is_empty()).then_some(0));
+    fill_combo(
+        combo,
+        choices.iter().map(|(_, label)| label.as_str()),
+        selected,
+    );
+    choices.into_iter().map(|(id, _)| id).collect()
+}
+
+/// The device ID behind the selected entry of a dropdown filled by `fill_device_combo`.
+pub fn combo_choice(combo: HWND, choices: &[Option<String>]) -> Option<String> {
+    combo_selection(combo)
+        .and_then(|index| choices.get(index).cloned())
+        .flatten()
+}
+
+/// The index of the selected entry of a dropdown, if any.
+pub fn combo_selection(combo: HWND) -> Option<usize> {
+    usize::try_from(unsafe { SendMessageW(combo, CB_GETCURSEL, None, None) }.0).ok()
+}
+
+const MARGIN: i32 = 8;
+const ROW_HEIGHT: i32 = 24;
+const LIST_WIDTH: i32 = 130;
+const LABEL_WIDTH: i32 = 54;
+const BUTTON_WIDTH: i32 = 64;
+
+/// The controls of a settings page that edits a list of things: the list on the left, a form for
+/// the selected one on the right with a status line beneath it, and buttons along the bottom.
+pub struct Form {
+    pub list: HWND,
+    // (label, field, whether it's a dropdown) of each row of the form.
+    pub rows: Vec<(HWND, HWND, bool)>,
+    pub status: HWND,
+    pub buttons: Vec<HWND>,
+}
+
+impl Form {
+    /// Fits the controls to `page`.
+    pub fn layout(&self, page: HWND) {
+        unsafe {
+            let mut client = Default::default();
+            if GetClientRect(page, &mut client).is_err() {
+                return;
+            }
+            let _ = MoveWindow(
+                self.list,
+                0,
+                0,
+                LIST_WIDTH,
+                client.bottom - ROW_HEIGHT - MARGIN,
+                true,
+            );
+            let left = LIST_WIDTH + MARGIN;
+            let field_left = left + LABEL_WIDTH;
+            let field_width = (client.right - field_left).max(0);
+            let mut top = 0;
+            for (label, field, dropdown) in &self.rows {
+                // Dropdowns are given room for their lists.
+                let height = if *dropdown {
+                    ROW_HEIGHT * 8
+                } else {
+                    ROW_HEIGHT - 2
+                };
+                let _ = MoveWindow(*field, field_left, top, field_width, height, true);
+                let _ = MoveWindow(*label, left, top + 3, LABEL_WIDTH, ROW_HEIGHT, true);
+                top += ROW_HEIGHT + MARGIN / 2;
+            }
+            top += MARGIN;
+            let _ = MoveWindow(
+                self.status,
+                left,
+                top,
+                client.right - left,
+                client.bottom - top - ROW_HEIGHT - MARGIN,
+                true,
+            );
+            layout_buttons(&self.buttons, client.bottom);
+        }
+    }
+}
+
+/// The controls of a settings page that edits one thing: a column of fields (usually checkboxes)
+/// with a status line beneath them, and buttons along the bottom.
+pub struct Column {
+    pub rows: Vec<HWND>,
+    pub status: HWND,
+    pub buttons: Vec<HWND>,
+}
+
+impl Column {
+    /// Fits the controls to `page`.
+    pub fn layout(&self, page: HWND) {
+        unsafe {
+            let mut client = Default::default();
+            if GetClientRect(page, &mut client).is_err() {
+                return;
+            }
+            let mut top = 0;
+            for row in &self.rows {
+                let _ = MoveWindow(*row, 0, top, client.right, ROW_HEIGHT - 2, true);
+                top += ROW_HEIGHT;
+            }
+            top += MARGIN;
+            let _ = MoveWindow(
+                self.status,
+                0,
+                top,
+                client.right,
+                client.bottom - top - ROW_HEIGHT - MARGIN,
+                true,
+            );
+            layout_buttons(&self.buttons, client.bottom);
+        }
+    }
+}
+
+// Puts buttons side by side along the bottom of a page `bottom` high.
+fn layout_buttons(buttons: &[HWND], bottom: i32) {
+    for (index, button) in buttons.iter().enumerate() {
+        unsafe {
+            let _ = MoveWindow(
+                *button,
+                index as i32 * (BUTTON_WIDTH + MARGIN),
+                bottom - ROW_HEIGHT,
+                BUTTON_WIDTH,
+                ROW_HEIGHT,
+                true,
+            );
+        }
+    }
+}
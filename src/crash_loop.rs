@@ -0,0 +1,85 @@
+use log::{debug, warn};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Starts that didn't end in a clean exit within this long count towards a crash loop.
+pub const CRASH_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// How many of them there have to be to start in safe mode.
+pub const CRASHES_FOR_SAFE_MODE: usize = 3;
+
+/// A file listing when the app started, one Unix time per line, removed again on a clean exit. What
+/// is left in it at startup are runs that crashed or were killed.
+pub struct StartupMarker {
+    path: PathBuf,
+}
+
+impl StartupMarker {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Adds this start to the marker, returning whether enough recent runs failed to exit cleanly
+    /// that this one should be in safe mode.
+    pub fn record_start(&self, now: SystemTime) -> io::Result<bool> {
+        let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut starts: Vec<u64> = match fs::read_to_string(&self.path) {
+            Ok(text) => text
+                .lines()
+                .filter_map(|line| line.trim().parse().ok())
+                .filter(|&start: &u64| now.saturating_sub(start) <= CRASH_WINDOW.as_secs())
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let crash_loop = starts.len() >= CRASHES_FOR_SAFE_MODE;
+        if crash_loop {
+            warn!(
+                "{} unclean exits in the last {} seconds",
+                starts.len(),
+                CRASH_WINDOW.as_secs()
+            );
+        }
+        starts.push(now);
+        let text: String = starts.iter().map(|start| format!("{start}\n")).collect();
+        fs::write(&self.path, text)?;
+        Ok(crash_loop)
+    }
+
+    /// Removes the marker, as the app is exiting cleanly.
+    pub fn clear(&self) {
+        debug!("Removing startup marker {}", self.path.display());
+        if let Err(e) = fs::remove_file(&self.path)
+            && e.kind() != io::ErrorKind::NotFound
+        {
+            warn!("Failed to remove startup marker: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_unclean_exits_start_safe_mode() {
+        let path = std::env::temp_dir().join(format!("startup-marker-{}", std::process::id()));
+        let marker = StartupMarker::new(path.clone());
+        let start = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        for i in 0..CRASHES_FOR_SAFE_MODE as u64 {
+            assert!(!marker.record_start(start + Duration::from_secs(i)).unwrap());
+        }
+        assert!(marker
+            .record_start(start + Duration::from_secs(10))
+            .unwrap());
+        // Crashes long ago don't count.
+        assert!(!marker.record_start(start + CRASH_WINDOW * 2).unwrap());
+        marker.record_start(start + CRASH_WINDOW * 2).unwrap();
+        marker.record_start(start + CRASH_WINDOW * 2).unwrap();
+        marker.clear();
+        assert!(!marker.record_start(start + CRASH_WINDOW * 2).unwrap());
+        marker.clear();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,123 @@
+use windows::Win32::Foundation::HWND;
+use windows::Win32::UI::Controls::{
+    TaskDialogIndirect, TASKDIALOGCONFIG, TASKDIALOGCONFIG_0, TASKDIALOG_BUTTON,
+    TDF_ALLOW_DIALOG_CANCELLATION, TDF_POSITION_RELATIVE_TO_WINDOW, TDF_USE_COMMAND_LINKS,
+    TD_ERROR_ICON, TD_INFORMATION_ICON, TD_SHIELD_ICON,
+};
+use windows::Win32::UI::WindowsAndMessaging::IDCANCEL;
+use windows_strings::PCWSTR;
+
+use sound_switcheroo::error::SwitcherooError;
+use sound_switcheroo::safe_strings::WideString;
+
+/// Returned by `TaskDialog::show` when the dialog is closed without choosing a button.
+pub const CANCELLED: i32 = IDCANCEL.0;
+
+// IDs for the buttons of the prompts below. Callers' own buttons can use anything else.
+const CLOSE: i32 = 101;
+const ELEVATE: i32 = 102;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Icon {
+    Information,
+    Error,
+    // For actions that need administrator rights.
+    Shield,
+}
+
+/// A prompt shown with TaskDialogIndirect, which needs version 6 of the common controls (selected
+/// by the application manifest).
+#[derive(Debug, Clone, Copy)]
+pub struct TaskDialog<'a> {
+    pub title: &'a str,
+    // Large text at the top, stating the question or problem.
+    pub heading: &'a str,
+    pub text: &'a str,
+    pub icon: Icon,
+    // Buttons as (ID, label), the first being the default.
+    pub buttons: &'a [(i32, &'a str)],
+    // Shows the buttons as command links, with anything after a newline in a label as a note
+    // beneath it.
+    pub command_links: bool,
+}
+
+impl TaskDialog<'_> {
+    /// Shows the dialog centred on `owner` (or the screen), and returns the ID of the button
+    /// chosen, or `CANCELLED`.
+    pub fn show(&self, owner: Option<HWND>) -> Result<i32, SwitcherooError> {
+        let title = WideString::new(self.title);
+        let heading = WideString::new(self.heading);
+        let text = WideString::new(self.text);
+        let labels: Vec<WideString> = self
+            .buttons
+            .iter()
+            .map(|(_, label)| WideString::new(*label))
+            .collect();
+        let buttons: Vec<TASKDIALOG_BUTTON> = self
+            .buttons
+            .iter()
+            .zip(&labels)
+            .map(|((id, _), label)| TASKDIALOG_BUTTON {
+                nButtonID: *id,
+                pszButtonText: PCWSTR(label.wide().as_ptr()),
+            })
+            .collect();
+        let icon = match self.icon {
+            Icon::Information => TD_INFORMATION_ICON,
+            Icon::Error => TD_ERROR_ICON,
+            Icon::Shield => TD_SHIELD_ICON,
+        };
+        let mut flags = TDF_ALLOW_DIALOG_CANCELLATION;
+        if self.command_links {
+            flags |= TDF_USE_COMMAND_LINKS;
+        }
+        if owner.is_some() {
+            flags |= TDF_POSITION_RELATIVE_TO_WINDOW;
+        }
+        let config = TASKDIALOGCONFIG {
+            cbSize: std::mem::size_of::<TASKDIALOGCONFIG>() as u32,
+            hwndParent: owner.unwrap_or_default(),
+            dwFlags: flags,
+            pszWindowTitle: PCWSTR(title.wide().as_ptr()),
+            Anonymous1: TASKDIALOGCONFIG_0 { pszMainIcon: icon },
+            pszMainInstruction: PCWSTR(heading.wide().as_ptr()),
+            pszContent: PCWSTR(text.wide().as_ptr()),
+            cButtons: buttons.len() as u32,
+            pButtons: buttons.as_ptr(),
+            nDefaultButton: self.buttons.first().map_or(0, |(id, _)| *id),
+            ..Default::default()
+        };
+        let mut chosen = CANCELLED;
+        unsafe { TaskDialogIndirect(&config, Some(&mut chosen), None, None)? };
+        Ok(chosen)
+    }
+}
+
+/// Reports something that went wrong, with just a Close button.
+pub fn error(owner: Option<HWND>, heading: &str, text: &str) -> Result<(), SwitcherooError> {
+    TaskDialog {
+        title: "Sound Switcheroo",
+        heading,
+        text,
+        icon: Icon::Error,
+        buttons: &[(CLOSE, "Close")],
+        command_links: false,
+    }
+    .show(owner)?;
+    Ok(())
+}
+
+/// Reports a failure that administrator rights may get past, and returns whether to try again with
+/// them.
+pub fn elevate(owner: Option<HWND>, heading: &str, text: &str) -> Result<bool, SwitcherooError> {
+    let chosen = TaskDialog {
+        title: "Sound Switcheroo",
+        heading,
+        text,
+        icon: Icon::Shield,
+        buttons: &[(ELEVATE, "Try as administrator"), (CLOSE, "Close")],
+        command_links: false,
+    }
+    .show(owner)?;
+    Ok(chosen == ELEVATE)
+}
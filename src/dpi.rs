@@ -0,0 +1,109 @@
+use windows::Win32::Foundation::{HWND, POINT, RECT};
+use windows::Win32::Graphics::Gdi::{MonitorFromPoint, LOGFONTW, MONITOR_DEFAULTTONEAREST};
+use windows::Win32::UI::HiDpi::{
+    GetDpiForMonitor, GetDpiForWindow, SetThreadDpiAwarenessContext, SystemParametersInfoForDpi,
+    DPI_AWARENESS_CONTEXT_UNAWARE, MDT_EFFECTIVE_DPI,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    SetWindowPos, NONCLIENTMETRICSW, SPI_GETNONCLIENTMETRICS, SWP_NOACTIVATE, SWP_NOZORDER,
+};
+
+/// The DPI that sizes are given at, i.e. 100% scaling.
+pub const DEFAULT_DPI: u32 = 96;
+
+/// Scales `size`, in pixels at 100%, to `dpi`, rounding to the nearest pixel.
+pub fn scale(size: i32, dpi: u32) -> i32 {
+    let dpi = dpi as i64;
+    let half = DEFAULT_DPI as i64 / 2;
+    let scaled = size as i64 * dpi;
+    ((scaled + if scaled < 0 { -half } else { half }) / DEFAULT_DPI as i64) as i32
+}
+
+/// The DPI of the monitor at (`x`, `y`) in screen coordinates, or of the one nearest to it, for
+/// sizing a window before it's created there.
+pub fn for_point(x: i32, y: i32) -> u32 {
+    let (mut dpi_x, mut dpi_y) = (0, 0);
+    unsafe {
+        let monitor = MonitorFromPoint(POINT { x, y }, MONITOR_DEFAULTTONEAREST);
+        match GetDpiForMonitor(monitor, MDT_EFFECTIVE_DPI, &mut dpi_x, &mut dpi_y) {
+            Ok(()) if dpi_x > 0 => dpi_x,
+            _ => DEFAULT_DPI,
+        }
+    }
+}
+
+/// The DPI of the monitor the window is on, as of the last `WM_DPICHANGED` it was sent.
+pub fn for_window(window: HWND) -> u32 {
+    match unsafe { GetDpiForWindow(window) } {
+        0 => DEFAULT_DPI,
+        dpi => dpi,
+    }
+}
+
+/// The system's message font as it's sized at `dpi`, to be adjusted and created by the caller.
+pub fn message_font(dpi: u32) -> LOGFONTW {
+    let mut metrics = NONCLIENTMETRICSW {
+        cbSize: std::mem::size_of::<NONCLIENTMETRICSW>() as u32,
+        ..Default::default()
+    };
+    unsafe {
+        if SystemParametersInfoForDpi(
+            SPI_GETNONCLIENTMETRICS.0,
+            metrics.cbSize,
+            Some(&mut metrics as *mut _ as *mut _),
+            0,
+            dpi,
+        )
+        .is_err()
+        {
+            log::error!("Failed to read the message font at {dpi} DPI");
+        }
+    }
+    metrics.lfMessageFont
+}
+
+/// Runs `create` with the thread unaware of DPI, so the top-level window it creates (and that
+/// window's children) are laid out at 100% and stretched by Windows, for windows that don't scale
+/// themselves.
+pub fn unaware<T>(create: impl FnOnce() -> T) -> T {
+    let previous = unsafe { SetThreadDpiAwarenessContext(DPI_AWARENESS_CONTEXT_UNAWARE) };
+    let created = create();
+    if !previous.is_invalid() {
+        unsafe { SetThreadDpiAwarenessContext(previous) };
+    }
+    created
+}
+
+/// Moves a window to the rectangle suggested by `WM_DPICHANGED`, pointed to by its LPARAM.
+///
+/// # Safety
+/// `suggested` has to be the LPARAM of a `WM_DPICHANGED` message.
+pub unsafe fn apply_suggested_rect(window: HWND, suggested: isize) {
+    unsafe {
+        let rect = &*(suggested as *const RECT);
+        let _ = SetWindowPos(
+            window,
+            None,
+            rect.left,
+            rect.top,
+            rect.right - rect.left,
+            rect.bottom - rect.top,
+            SWP_NOZORDER | SWP_NOACTIVATE,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_scale_with_dpi() {
+        assert_eq!(scale(16, DEFAULT_DPI), 16);
+        assert_eq!(scale(16, 144), 24);
+        assert_eq!(scale(280, 192), 560);
+        // 4K at 175%.
+        assert_eq!(scale(-5, 168), -9);
+        assert_eq!(scale(5, 120), 6);
+    }
+}
@@ -0,0 +1,188 @@
+use sound_switcheroo::error::SwitcherooError;
+use windows::Win32::Foundation::{COLORREF, RECT};
+use windows::Win32::Graphics::Gdi::{
+    CreateBitmap, CreateCompatibleBitmap, CreateCompatibleDC, CreateFontIndirectW, CreatePen,
+    CreateSolidBrush, DeleteDC, DeleteObject, DrawTextW, Ellipse, FillRect, GetDC, GetStockObject,
+    LineTo, MoveToEx, Rectangle, ReleaseDC, RoundRect, SelectObject, SetBkMode, SetTextColor,
+    BLACK_BRUSH, DT_CENTER, DT_NOPREFIX, DT_SINGLELINE, DT_VCENTER, FW_BOLD, HBRUSH, HDC, HFONT,
+    LOGFONTW, NULL_BRUSH, PS_SOLID, TRANSPARENT, WHITE_BRUSH,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateIconIndirect, DrawIconEx, GetSystemMetrics, DI_IMAGE, DI_MASK, HICON, ICONINFO, SM_CXICON,
+};
+
+// For the glyphs drawn as text.
+const FONT: &str = "Segoe UI Symbol";
+// Of the slash marking the microphone as muted.
+const RED: COLORREF = COLORREF(0x002020e0);
+
+/// What a drawn icon shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Glyph {
+    // A music note, standing in for an icon missing from the executable.
+    Placeholder,
+    // A monitor, for HDMI and DisplayPort outputs.
+    Display,
+    // A round socket, for line outs.
+    LineLevel,
+    // An optical socket, for S/PDIF.
+    Spdif,
+    // A question mark, for endpoints that don't say what they are.
+    Unknown,
+}
+
+/// Draws an icon at runtime, for the kinds of output the executable has no icons for or in place of
+/// one missing from it in a custom build. `light` draws it in white, for dark themes.
+pub fn create(glyph: Glyph, light: bool) -> Result<HICON, SwitcherooError> {
+    unsafe {
+        let size = GetSystemMetrics(SM_CXICON);
+        let mut face_name = [0u16; 32];
+        for (i, c) in FONT.encode_utf16().enumerate() {
+            face_name[i] = c;
+        }
+        let font = CreateFontIndirectW(&LOGFONTW {
+            lfHeight: -size,
+            lfWeight: FW_BOLD.0 as i32,
+            lfFaceName: face_name,
+            ..Default::default()
+        });
+        let icon = build(|dc, rect, mask| {
+            let color = match (mask, light) {
+                (true, _) => COLORREF(0x00000000),
+                (false, true) => COLORREF(0x00ffffff),
+                (false, false) => COLORREF(0x00202020),
+            };
+            draw(dc, glyph, rect, font, color);
+        });
+        let _ = DeleteObject(font.into());
+        icon
+    }
+}
+
+/// Copies `icon` with a red slash across it, marking the microphone as muted. The copy is the
+/// caller's to destroy.
+pub fn slashed(icon: HICON) -> Result<HICON, SwitcherooError> {
+    unsafe {
+        build(|dc, rect, mask| {
+            let size = rect.right;
+            let _ = DrawIconEx(
+                dc,
+                0,
+                0,
+                icon,
+                size,
+                size,
+                0,
+                None,
+                if mask { DI_MASK } else { DI_IMAGE },
+            );
+            let pen = CreatePen(
+                PS_SOLID,
+                (size / 8).max(2),
+                if mask { COLORREF(0x00000000) } else { RED },
+            );
+            let old_pen = SelectObject(dc, pen.into());
+            let _ = MoveToEx(dc, size * 3 / 16, size * 3 / 16, None);
+            let _ = LineTo(dc, size * 13 / 16, size * 13 / 16);
+            SelectObject(dc, old_pen);
+            let _ = DeleteObject(pen.into());
+        })
+    }
+}
+
+// Makes an icon the size of the system's, with `paint` drawing first its mask, in black on white,
+// and then its colors on black. It's told which it's drawing.
+unsafe fn build(paint: impl Fn(HDC, &RECT, bool)) -> Result<HICON, SwitcherooError> {
+    unsafe {
+        let size = GetSystemMetrics(SM_CXICON);
+        let rect = RECT {
+            left: 0,
+            top: 0,
+            right: size,
+            bottom: size,
+        };
+        let screen = GetDC(None);
+        let dc = CreateCompatibleDC(Some(screen));
+        let color = CreateCompatibleBitmap(screen, size, size);
+        ReleaseDC(None, screen);
+        // Monochrome: white is transparent, black shows the color bitmap.
+        let mask = CreateBitmap(size, size, 1, 1, None);
+
+        let old_bitmap = SelectObject(dc, mask.into());
+        FillRect(dc, &rect, HBRUSH(GetStockObject(WHITE_BRUSH).0));
+        paint(dc, &rect, true);
+        SelectObject(dc, color.into());
+        // Black where the mask is white leaves the screen as it was.
+        FillRect(dc, &rect, HBRUSH(GetStockObject(BLACK_BRUSH).0));
+        paint(dc, &rect, false);
+        SelectObject(dc, old_bitmap);
+        let _ = DeleteDC(dc);
+
+        let icon = CreateIconIndirect(&ICONINFO {
+            fIcon: true.into(),
+            hbmMask: mask,
+            hbmColor: color,
+            ..Default::default()
+        });
+        // The icon has its own copies.
+        let _ = DeleteObject(mask.into());
+        let _ = DeleteObject(color.into());
+        Ok(icon?)
+    }
+}
+
+// Draws the glyph in `color` on the bitmap selected into `dc`, in proportion to its size.
+unsafe fn draw(dc: HDC, glyph: Glyph, rect: &RECT, font: HFONT, color: COLORREF) {
+    let size = rect.right;
+    // Sixteenths of the icon, like pixels of a 16x16 one.
+    let at = |n: i32| n * size / 16;
+    unsafe {
+        let pen = CreatePen(PS_SOLID, (size / 12).max(1), color);
+        let brush = CreateSolidBrush(color);
+        let old_pen = SelectObject(dc, pen.into());
+        let old_brush = SelectObject(dc, GetStockObject(NULL_BRUSH));
+        match glyph {
+            Glyph::Placeholder | Glyph::Unknown => {
+                let text = if glyph == Glyph::Placeholder {
+                    "\u{266a}"
+                } else {
+                    "?"
+                };
+                SetBkMode(dc, TRANSPARENT);
+                SetTextColor(dc, color);
+                let old_font = SelectObject(dc, font.into());
+                let mut text: Vec<u16> = text.encode_utf16().collect();
+                let mut rect = *rect;
+                DrawTextW(
+                    dc,
+                    &mut text,
+                    &mut rect,
+                    DT_SINGLELINE | DT_CENTER | DT_VCENTER | DT_NOPREFIX,
+                );
+                SelectObject(dc, old_font);
+            }
+            Glyph::Display => {
+                let _ = Rectangle(dc, at(1), at(2), at(15), at(11));
+                // The stand.
+                let _ = MoveToEx(dc, at(8), at(11), None);
+                let _ = LineTo(dc, at(8), at(14));
+                let _ = MoveToEx(dc, at(5), at(14), None);
+                let _ = LineTo(dc, at(11), at(14));
+            }
+            Glyph::LineLevel => {
+                let _ = Ellipse(dc, at(2), at(2), at(14), at(14));
+                SelectObject(dc, brush.into());
+                let _ = Ellipse(dc, at(6), at(6), at(10), at(10));
+            }
+            Glyph::Spdif => {
+                let _ = RoundRect(dc, at(2), at(3), at(14), at(13), at(4), at(4));
+                SelectObject(dc, brush.into());
+                let _ = Rectangle(dc, at(6), at(6), at(10), at(10));
+            }
+        }
+        SelectObject(dc, old_brush);
+        SelectObject(dc, old_pen);
+        let _ = DeleteObject(brush.into());
+        let _ = DeleteObject(pen.into());
+    }
+}
@@ -0,0 +1,296 @@
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::windows::io::FromRawHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+use windows::Win32::Foundation::{
+    CloseHandle, ERROR_CANCELLED, ERROR_OPERATION_ABORTED, ERROR_PIPE_CONNECTED, GENERIC_READ,
+    GENERIC_WRITE, HANDLE, HWND, LPARAM, WAIT_OBJECT_0, WPARAM,
+};
+use windows::Win32::Storage::FileSystem::{
+    CreateFileW, FILE_FLAGS_AND_ATTRIBUTES, FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_SHARE_NONE,
+    OPEN_EXISTING, PIPE_ACCESS_DUPLEX,
+};
+use windows::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, GetNamedPipeClientProcessId, GetNamedPipeServerProcessId,
+    PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_WAIT,
+};
+use windows::Win32::System::Threading::{
+    GetCurrentThreadId, GetProcessId, OpenThread, WaitForSingleObject, THREAD_TERMINATE,
+};
+use windows::Win32::System::IO::CancelSynchronousIo;
+use windows::Win32::UI::Shell::{ShellExecuteExW, SEE_MASK_NOCLOSEPROCESS, SHELLEXECUTEINFOW};
+use windows::Win32::UI::WindowsAndMessaging::{PostMessageW, SW_HIDE, WM_APP};
+use windows_strings::w;
+
+use crate::audio_service;
+use sound_switcheroo::backend::{AudioBackend, ComBackend};
+use sound_switcheroo::bail;
+use sound_switcheroo::com::ComApartment;
+use sound_switcheroo::error::SwitcherooError;
+use sound_switcheroo::safe_strings::with_wide_str;
+
+/// Posted to the window given to `run_in_background` once the task is done. LPARAM is a
+/// `Box<Finished>`, owned by the receiver.
+pub const WM_ELEVATED_TASK_FINISHED: u32 = WM_APP + 0x58;
+
+// How long the helper gets to carry out a task, after which it's no longer waited for.
+const HELPER_TIMEOUT_MS: u32 = 120_000;
+
+/// Something that needs administrator rights, carried out by an elevated copy of the app so the
+/// tray app itself never has to run elevated.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "task", rename_all = "snake_case")]
+pub enum ElevatedTask {
+    RestartAudioService,
+    StartAudioService,
+    // Turns a device's driver enhancements off (or back on).
+    SetEffectsBypassed { device: String, bypassed: bool },
+}
+
+/// What the elevated copy sends back once it's done.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Outcome {
+    // Why the task failed, if it did.
+    pub error: Option<String>,
+}
+
+/// A task carried out by `run_in_background`, and why it failed if it did.
+#[derive(Debug)]
+pub struct Finished {
+    pub task: ElevatedTask,
+    pub error: Option<String>,
+}
+
+/// Carries out `task` like `run`, but on another thread, posting `WM_ELEVATED_TASK_FINISHED` to
+/// `window` once it's done so the window's thread isn't held up while UAC asks.
+pub fn run_in_background(window: HWND, task: ElevatedTask) {
+    let window = window.0 as isize;
+    std::thread::spawn(move || {
+        let error = run(&task).err().map(|e| e.to_string());
+        let finished = Box::new(Finished { task, error });
+        unsafe {
+            let _ = PostMessageW(
+                Some(HWND(window as *mut _)),
+                WM_ELEVATED_TASK_FINISHED,
+                WPARAM::default(),
+                LPARAM(Box::into_raw(finished) as isize),
+            );
+        }
+    });
+}
+
+/// Carries out `task` in an elevated copy of the app, started with `elevated --pipe NAME --parent
+/// PID` after UAC asks the user to allow it, and waits for it to finish. The task and its outcome
+/// go over a named pipe, and each end checks the other is the process it expects. Gives up on a
+/// helper that hasn't finished within two minutes.
+pub fn run(task: &ElevatedTask) -> Result<(), SwitcherooError> {
+    info!("Asking an elevated helper to carry out {task:?}");
+    let nonce = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let name = format!(
+        r"\\.\pipe\SoundSwitcheroo-elevated-{}-{nonce}",
+        std::process::id()
+    );
+    let pipe = unsafe {
+        with_wide_str(&name, |wide_name| {
+            CreateNamedPipeW(
+                wide_name,
+                PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                1,
+                4096,
+                4096,
+                0,
+                None,
+            )
+        })
+    };
+    if pipe.is_invalid() {
+        return Err(windows::core::Error::from_win32().into());
+    }
+    // Closed when dropped.
+    let mut pipe_file = unsafe { File::from_raw_handle(pipe.0) };
+    let helper_pid = launch_helper(&format!(
+        "elevated --pipe {name} --parent {}",
+        std::process::id()
+    ))?
+    .watch(name)?;
+    unsafe {
+        if let Err(e) = ConnectNamedPipe(pipe, None)
+            && e.code() != ERROR_PIPE_CONNECTED.to_hresult()
+        {
+            if e.code() == ERROR_OPERATION_ABORTED.to_hresult() {
+                bail!("The elevated helper didn't finish in time");
+            }
+            return Err(e.into());
+        }
+        let mut client_pid = 0;
+        GetNamedPipeClientProcessId(pipe, &mut client_pid)?;
+        if client_pid != helper_pid {
+            bail!("The elevated helper exited without connecting");
+        }
+    }
+    serde_json::to_writer(&mut pipe_file, task)?;
+    pipe_file.write_all(b"\n")?;
+    let mut reply = String::new();
+    if let Err(e) = pipe_file.read_to_string(&mut reply) {
+        if e.raw_os_error() == Some(ERROR_OPERATION_ABORTED.0 as i32) {
+            bail!("The elevated helper didn't finish in time");
+        }
+        return Err(e.into());
+    }
+    let outcome: Outcome = serde_json::from_str(&reply)?;
+    match outcome.error {
+        Some(error) => Err(SwitcherooError::Other(error)),
+        None => Ok(()),
+    }
+}
+
+// The elevated helper's process, owned by whoever waits for it to exit.
+struct Helper(HANDLE);
+
+impl Helper {
+    /// Waits for the helper to exit on another thread, then connects to the pipe itself so that
+    /// waiting for the helper to connect doesn't hang if it never did. If the helper takes too
+    /// long, cancels what this thread is waiting for on the pipe instead. Returns its process ID.
+    fn watch(self, pipe_name: String) -> Result<u32, SwitcherooError> {
+        let pid = unsafe { GetProcessId(self.0) };
+        let process = self.0 .0 as isize;
+        let waiting = match unsafe { OpenThread(THREAD_TERMINATE, false, GetCurrentThreadId()) } {
+            Ok(thread) => thread.0 as isize,
+            Err(e) => {
+                unsafe {
+                    let _ = CloseHandle(self.0);
+                }
+                return Err(e.into());
+            }
+        };
+        std::thread::spawn(move || unsafe {
+            let process = HANDLE(process as *mut _);
+            let waiting = HANDLE(waiting as *mut _);
+            let exited = WaitForSingleObject(process, HELPER_TIMEOUT_MS) == WAIT_OBJECT_0;
+            let _ = CloseHandle(process);
+            if !exited {
+                warn!("Elevated helper {pid} is taking too long, so no longer waiting for it");
+                let _ = CancelSynchronousIo(waiting);
+                let _ = CloseHandle(waiting);
+                return;
+            }
+            let _ = CloseHandle(waiting);
+            debug!("Elevated helper {pid} exited");
+            // Fails harmlessly if the helper connected, as the pipe only takes one client.
+            if let Ok(handle) = with_wide_str(&pipe_name, |name| {
+                CreateFileW(
+                    name,
+                    GENERIC_READ.0,
+                    FILE_SHARE_NONE,
+                    None,
+                    OPEN_EXISTING,
+                    FILE_FLAGS_AND_ATTRIBUTES(0),
+                    None,
+                )
+            }) {
+                let _ = CloseHandle(handle);
+            }
+        });
+        Ok(pid)
+    }
+}
+
+// Starts this executable again as an administrator, which UAC asks the user to allow.
+fn launch_helper(parameters: &str) -> Result<Helper, SwitcherooError> {
+    let exe = std::env::current_exe()?;
+    with_wide_str(&exe.to_string_lossy(), |exe| {
+        with_wide_str(parameters, |parameters| {
+            let mut info = SHELLEXECUTEINFOW {
+                cbSize: std::mem::size_of::<SHELLEXECUTEINFOW>() as u32,
+                fMask: SEE_MASK_NOCLOSEPROCESS,
+                lpVerb: w!("runas"),
+                lpFile: exe,
+                lpParameters: parameters,
+                nShow: SW_HIDE.0,
+                ..Default::default()
+            };
+            unsafe {
+                if let Err(e) = ShellExecuteExW(&mut info) {
+                    if e.code() == ERROR_CANCELLED.to_hresult() {
+                        bail!("Cancelled");
+                    }
+                    return Err(e.into());
+                }
+            }
+            Ok(Helper(info.hProcess))
+        })
+    })
+}
+
+/// Runs in the elevated copy: connects to the pipe made by the process `parent`, carries out the
+/// task it sends, and reports back.
+pub fn serve(pipe_name: &str, parent: u32) -> Result<(), SwitcherooError> {
+    let pipe = unsafe {
+        with_wide_str(pipe_name, |name| {
+            CreateFileW(
+                name,
+                GENERIC_READ.0 | GENERIC_WRITE.0,
+                FILE_SHARE_NONE,
+                None,
+                OPEN_EXISTING,
+                FILE_FLAGS_AND_ATTRIBUTES(0),
+                None,
+            )
+        })?
+    };
+    let mut pipe_file = unsafe { File::from_raw_handle(pipe.0) };
+    // Only the app that started this copy may tell it what to do.
+    let mut server_pid = 0;
+    unsafe { GetNamedPipeServerProcessId(pipe, &mut server_pid)? };
+    if server_pid != parent {
+        bail!("The pipe belongs to process {server_pid}, not {parent}");
+    }
+    let mut request = String::new();
+    BufReader::new(&pipe_file).read_line(&mut request)?;
+    let task: ElevatedTask = serde_json::from_str(&request)?;
+    info!("Carrying out {task:?}");
+    let outcome = Outcome {
+        error: carry_out(&task).err().map(|e| e.to_string()),
+    };
+    serde_json::to_writer(&mut pipe_file, &outcome)?;
+    Ok(())
+}
+
+fn carry_out(task: &ElevatedTask) -> Result<(), SwitcherooError> {
+    match task {
+        ElevatedTask::RestartAudioService => audio_service::restart(),
+        ElevatedTask::StartAudioService => audio_service::start(),
+        ElevatedTask::SetEffectsBypassed { device, bypassed } => {
+            let _com = ComApartment::init()?;
+            Ok(ComBackend.set_effects_bypassed(device, *bypassed)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tasks_are_tagged() {
+        let task = ElevatedTask::SetEffectsBypassed {
+            device: "{speakers}".to_string(),
+            bypassed: true,
+        };
+        let json = serde_json::to_string(&task).unwrap();
+        assert_eq!(
+            json,
+            r#"{"task":"set_effects_bypassed","device":"{speakers}","bypassed":true}"#
+        );
+        assert_eq!(serde_json::from_str::<ElevatedTask>(&json).unwrap(), task);
+        let outcome: Outcome = serde_json::from_str("{}").unwrap();
+        assert_eq!(outcome.error, None);
+    }
+}
@@ -0,0 +1,184 @@
+use log::debug;
+use windows::Win32::Devices::FunctionDiscovery::PKEY_Device_FriendlyName;
+use windows::Win32::Foundation::PROPERTYKEY;
+use windows::Win32::Media::Audio::Endpoints::{
+    IAudioEndpointVolume, IAudioEndpointVolumeCallback, IAudioEndpointVolumeCallback_Impl,
+};
+use windows::Win32::Media::Audio::{
+    eConsole, eRender, EDataFlow, ERole, IMMDeviceEnumerator, IMMNotificationClient,
+    IMMNotificationClient_Impl, AUDIO_VOLUME_NOTIFICATION_DATA, DEVICE_STATE,
+};
+use windows::Win32::System::Com::CLSCTX_ALL;
+use windows_core::{implement, PCWSTR};
+
+use crate::com;
+use crate::error::SwitcherooError;
+use crate::safe_strings::with_wide_str;
+
+/// What an `EndpointNotifications` callback is told about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndpointChange {
+    /// An audio endpoint was added, removed, enabled, disabled or renamed.
+    Endpoints,
+    /// The default output changed, whoever changed it.
+    DefaultOutput,
+}
+
+/// Calls back on changes to the audio endpoints and the default output until dropped. Unlike
+/// `WM_DEVICECHANGE`, this also covers endpoints enabled or disabled in the Sound control panel.
+///
+/// The callback runs on a thread of the audio service's choosing, and shouldn't block it: the
+/// tray app just posts itself a message.
+pub struct EndpointNotifications {
+    enumerator: IMMDeviceEnumerator,
+    client: IMMNotificationClient,
+}
+
+impl EndpointNotifications {
+    pub fn register(
+        on_change: impl Fn(EndpointChange) + Send + Sync + 'static,
+    ) -> Result<Self, SwitcherooError> {
+        unsafe {
+            let enumerator = com::device_enumerator()?;
+            let client: IMMNotificationClient = Client {
+                on_change: Box::new(on_change),
+            }
+            .into();
+            enumerator.RegisterEndpointNotificationCallback(&client)?;
+            Ok(Self { enumerator, client })
+        }
+    }
+}
+
+impl Drop for EndpointNotifications {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self
+                .enumerator
+                .UnregisterEndpointNotificationCallback(&self.client);
+        }
+    }
+}
+
+#[implement(IMMNotificationClient)]
+struct Client {
+    on_change: Box<dyn Fn(EndpointChange) + Send + Sync>,
+}
+
+impl Client {
+    fn report(
+        &self,
+        change: EndpointChange,
+        description: &str,
+        device_id: &PCWSTR,
+    ) -> windows_core::Result<()> {
+        debug!("Endpoint {description}: {}", unsafe {
+            device_id.to_string().unwrap_or_default()
+        });
+        (self.on_change)(change);
+        Ok(())
+    }
+}
+
+impl IMMNotificationClient_Impl for Client_Impl {
+    fn OnDeviceStateChanged(
+        &self,
+        device_id: &PCWSTR,
+        new_state: DEVICE_STATE,
+    ) -> windows_core::Result<()> {
+        self.report(
+            EndpointChange::Endpoints,
+            &format!("state changed to {}", new_state.0),
+            device_id,
+        )
+    }
+
+    fn OnDeviceAdded(&self, device_id: &PCWSTR) -> windows_core::Result<()> {
+        self.report(EndpointChange::Endpoints, "added", device_id)
+    }
+
+    fn OnDeviceRemoved(&self, device_id: &PCWSTR) -> windows_core::Result<()> {
+        self.report(EndpointChange::Endpoints, "removed", device_id)
+    }
+
+    fn OnDefaultDeviceChanged(
+        &self,
+        flow: EDataFlow,
+        role: ERole,
+        device_id: &PCWSTR,
+    ) -> windows_core::Result<()> {
+        // Windows reports each role separately; the console one stands for the default output.
+        if flow != eRender || role != eConsole {
+            return Ok(());
+        }
+        self.report(EndpointChange::DefaultOutput, "made the default", device_id)
+    }
+
+    fn OnPropertyValueChanged(
+        &self,
+        device_id: &PCWSTR,
+        key: &PROPERTYKEY,
+    ) -> windows_core::Result<()> {
+        // Renaming a device in Windows' settings changes its friendly name, among many properties
+        // that don't matter here.
+        if *key != PKEY_Device_FriendlyName {
+            return Ok(());
+        }
+        self.report(EndpointChange::Endpoints, "renamed", device_id)
+    }
+}
+
+/// Calls back on changes to one endpoint's volume or mute until dropped, whoever changed it. As
+/// with `EndpointNotifications`, the callback runs on one of the audio service's threads.
+pub struct VolumeNotifications {
+    device_id: String,
+    volume: IAudioEndpointVolume,
+    callback: IAudioEndpointVolumeCallback,
+}
+
+impl VolumeNotifications {
+    pub fn register(
+        device_id: &str,
+        on_change: impl Fn() + Send + Sync + 'static,
+    ) -> Result<Self, SwitcherooError> {
+        unsafe {
+            let enumerator = com::device_enumerator()?;
+            let device = with_wide_str(device_id, |id| enumerator.GetDevice(id))?;
+            let volume: IAudioEndpointVolume = device.Activate(CLSCTX_ALL, None)?;
+            let callback: IAudioEndpointVolumeCallback = VolumeClient {
+                on_change: Box::new(on_change),
+            }
+            .into();
+            volume.RegisterControlChangeNotify(&callback)?;
+            Ok(Self {
+                device_id: device_id.to_string(),
+                volume,
+                callback,
+            })
+        }
+    }
+
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+}
+
+impl Drop for VolumeNotifications {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.volume.UnregisterControlChangeNotify(&self.callback);
+        }
+    }
+}
+
+#[implement(IAudioEndpointVolumeCallback)]
+struct VolumeClient {
+    on_change: Box<dyn Fn() + Send + Sync>,
+}
+
+impl IAudioEndpointVolumeCallback_Impl for VolumeClient_Impl {
+    fn OnNotify(&self, _data: *mut AUDIO_VOLUME_NOTIFICATION_DATA) -> windows_core::Result<()> {
+        (self.on_change)();
+        Ok(())
+    }
+}
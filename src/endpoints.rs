@@ -0,0 +1,272 @@
+use log::{debug, info};
+use std::ops::Deref;
+use windows::Win32::Media::Audio::{eCapture, eRender, EDataFlow, ERole};
+
+use crate::backend::{AudioBackend, ComBackend};
+use crate::cli;
+use crate::com::ComApartment;
+use crate::error::SwitcherooError;
+
+/// An audio endpoint: one output or input as Windows lists it.
+pub use crate::backend::AudioDevice as Endpoint;
+
+/// The active endpoints of one data flow, in the order Windows lists them.
+#[derive(Debug, Clone)]
+pub struct EndpointList {
+    flow: EDataFlow,
+    endpoints: Vec<Endpoint>,
+}
+
+impl EndpointList {
+    pub fn new(flow: EDataFlow, endpoints: Vec<Endpoint>) -> Self {
+        Self { flow, endpoints }
+    }
+
+    /// Whether these are outputs (`eRender`) or inputs (`eCapture`).
+    pub fn flow(&self) -> EDataFlow {
+        self.flow
+    }
+
+    pub fn by_id(&self, id: &str) -> Option<&Endpoint> {
+        self.endpoints.iter().find(|endpoint| endpoint.id == id)
+    }
+
+    /// Finds an endpoint by its ID or name, as `--set` does: ignoring case, and taking part of a
+    /// name as long as only one endpoint's name contains it.
+    pub fn find(&self, name_or_id: &str) -> Result<&Endpoint, SwitcherooError> {
+        cli::find_device(&self.endpoints, name_or_id).map_err(Into::into)
+    }
+
+    pub fn into_vec(self) -> Vec<Endpoint> {
+        self.endpoints
+    }
+}
+
+impl Deref for EndpointList {
+    type Target = [Endpoint];
+
+    fn deref(&self) -> &[Endpoint] {
+        &self.endpoints
+    }
+}
+
+/// Reads and changes the default endpoints, and their volumes, through an `AudioBackend`.
+///
+/// `new` talks to the real audio stack, initialising COM on the calling thread for as long as the
+/// manager lives. `with_backend` takes any other backend, such as a `SimulatedBackend`.
+pub struct DefaultEndpointManager {
+    backend: Box<dyn AudioBackend>,
+    // Set when COM was initialised here. Comes after the backend so that's released first.
+    _com: Option<ComApartment>,
+}
+
+impl DefaultEndpointManager {
+    pub fn new() -> Result<Self, SwitcherooError> {
+        let com = ComApartment::init()?;
+        Ok(Self {
+            backend: Box::new(ComBackend),
+            _com: Some(com),
+        })
+    }
+
+    /// Uses `backend` as it is, leaving COM to the caller.
+    pub fn with_backend(backend: Box<dyn AudioBackend>) -> Self {
+        Self {
+            backend,
+            _com: None,
+        }
+    }
+
+    pub fn backend(&self) -> &dyn AudioBackend {
+        self.backend.as_ref()
+    }
+
+    pub fn endpoints(&self, flow: EDataFlow) -> Result<EndpointList, SwitcherooError> {
+        Ok(EndpointList::new(flow, self.backend.devices(flow)?))
+    }
+
+    pub fn outputs(&self) -> Result<EndpointList, SwitcherooError> {
+        self.endpoints(eRender)
+    }
+
+    pub fn inputs(&self) -> Result<EndpointList, SwitcherooError> {
+        self.endpoints(eCapture)
+    }
+
+    /// The ID of the default endpoint for `flow` and `role`.
+    pub fn default_id(&self, flow: EDataFlow, role: ERole) -> Result<String, SwitcherooError> {
+        self.backend.default_device(flow, role)
+    }
+
+    /// The default endpoint for `flow` and `role`, if it's among the active ones.
+    pub fn default(
+        &self,
+        flow: EDataFlow,
+        role: ERole,
+    ) -> Result<Option<Endpoint>, SwitcherooError> {
+        let id = self.default_id(flow, role)?;
+        Ok(self.endpoints(flow)?.by_id(&id).cloned())
+    }
+
+    /// Makes an endpoint the default for each of `roles`.
+    pub fn set_default(&self, endpoint_id: &str, roles: &[ERole]) -> Result<(), SwitcherooError> {
+        set_default_device(self.backend.as_ref(), endpoint_id, roles)
+    }
+
+    /// Makes the endpoint of `flow` with the given name or ID the default for each of `roles`,
+    /// returning it.
+    pub fn switch_to(
+        &self,
+        flow: EDataFlow,
+        name_or_id: &str,
+        roles: &[ERole],
+    ) -> Result<Endpoint, SwitcherooError> {
+        let endpoints = self.endpoints(flow)?;
+        let endpoint = endpoints.find(name_or_id)?;
+        info!("Switching to {}", endpoint.friendly_name);
+        self.set_default(&endpoint.id, roles)?;
+        Ok(endpoint.clone())
+    }
+
+    /// The endpoint's master volume, from 0.0 to 1.0.
+    pub fn volume(&self, endpoint_id: &str) -> Result<f32, SwitcherooError> {
+        self.backend.volume(endpoint_id)
+    }
+
+    pub fn set_volume(&self, endpoint_id: &str, level: f32) -> Result<(), SwitcherooError> {
+        self.backend.set_volume(endpoint_id, level)
+    }
+
+    pub fn muted(&self, endpoint_id: &str) -> Result<bool, SwitcherooError> {
+        self.backend.muted(endpoint_id)
+    }
+
+    pub fn set_muted(&self, endpoint_id: &str, muted: bool) -> Result<(), SwitcherooError> {
+        self.backend.set_muted(endpoint_id, muted)
+    }
+}
+
+/// Makes a device the default for each of `roles` (see `Config::roles`).
+pub fn set_default_device(
+    backend: &dyn AudioBackend,
+    device_id: &str,
+    roles: &[ERole],
+) -> Result<(), SwitcherooError> {
+    for &role in roles {
+        backend.set_default_device(device_id, role)?;
+    }
+    Ok(())
+}
+
+/// The (old, new) names of the endpoints in `after` that have a different name in `before`.
+pub fn renamed_devices(before: &[Endpoint], after: &[Endpoint]) -> Vec<(String, String)> {
+    after
+        .iter()
+        .filter_map(|device| {
+            let old = before.iter().find(|old| old.id == device.id)?;
+            (old.friendly_name.as_str() != device.friendly_name.as_str()).then(|| {
+                (
+                    old.friendly_name.to_string(),
+                    device.friendly_name.to_string(),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Switches the default microphone to the capture endpoint sharing a container with `output`,
+/// leaving it alone if there is no such endpoint.
+pub fn switch_microphone_to_sibling(
+    backend: &dyn AudioBackend,
+    output: &Endpoint,
+    roles: &[ERole],
+) -> Result<(), SwitcherooError> {
+    let Some(container_id) = output.container_id else {
+        debug!("No container ID for {}", output.friendly_name);
+        return Ok(());
+    };
+    let microphones = backend.devices(eCapture)?;
+    match microphones
+        .iter()
+        .find(|mic| mic.container_id == Some(container_id))
+    {
+        Some(mic) => {
+            info!("Switching microphone to: {}", mic.friendly_name);
+            set_default_device(backend, &mic.id, roles)
+        }
+        None => {
+            debug!("No sibling microphone for {}", output.friendly_name);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::headset_backend;
+    use windows::Win32::Media::Audio::{eCommunications, eConsole, eMultimedia};
+
+    fn manager() -> DefaultEndpointManager {
+        DefaultEndpointManager::with_backend(Box::new(headset_backend()))
+    }
+
+    #[test]
+    fn switches_by_name_for_each_role() {
+        let manager = manager();
+        let headset = manager
+            .switch_to(eRender, "earphone", &[eConsole, eMultimedia])
+            .unwrap();
+        assert_eq!(headset.id, "{0.0.0.00000000}.{headset}");
+        assert_eq!(
+            manager.default(eRender, eConsole).unwrap().unwrap().id,
+            headset.id
+        );
+        // Inputs are looked up separately.
+        assert!(manager
+            .switch_to(eCapture, "earphone", &[eConsole])
+            .is_err());
+        let mic = manager
+            .switch_to(eCapture, "headset microphone", &[eCommunications])
+            .unwrap();
+        assert_eq!(manager.inputs().unwrap().by_id(&mic.id).unwrap().id, mic.id);
+    }
+
+    #[test]
+    fn microphone_follows_headset() {
+        let backend = headset_backend();
+        let headset = &backend.devices(eRender).unwrap()[1];
+        switch_microphone_to_sibling(&backend, headset, &[eConsole]).unwrap();
+        assert_eq!(
+            backend.default_device(eCapture, eConsole).unwrap(),
+            "{0.0.1.00000000}.{headset-mic}"
+        );
+    }
+
+    #[test]
+    fn renamed_devices_are_found_by_id() {
+        let before = headset_backend().devices(eRender).unwrap();
+        let mut after = before.clone();
+        after[1].friendly_name = "Gaming Headset".into();
+        after.remove(0);
+        assert_eq!(
+            renamed_devices(&before, &after),
+            [(
+                "Headset Earphone (USB Headset)".to_string(),
+                "Gaming Headset".to_string()
+            )]
+        );
+        assert!(renamed_devices(&before, &before).is_empty());
+    }
+
+    #[test]
+    fn microphone_left_alone_without_sibling() {
+        let backend = headset_backend();
+        let speakers = &backend.devices(eRender).unwrap()[0];
+        switch_microphone_to_sibling(&backend, speakers, &[eConsole]).unwrap();
+        assert_eq!(
+            backend.default_device(eCapture, eConsole).unwrap(),
+            "{0.0.1.00000000}.{webcam-mic}"
+        );
+    }
+}
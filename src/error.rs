@@ -0,0 +1,105 @@
+use std::ffi::NulError;
+use std::io;
+use std::string::FromUtf16Error;
+use thiserror::Error;
+
+use crate::cli::{ErrorKind, Failure};
+
+/// What went wrong, for callers that react differently to each, e.g. offering another device when
+/// one isn't found, or picking an exit code.
+#[derive(Debug, Error)]
+pub enum SwitcherooError {
+    /// A call into Windows, usually the audio stack, failed.
+    #[error(transparent)]
+    Com(#[from] windows_core::Error),
+    /// No connected device has the ID or name asked for.
+    #[error("{0}")]
+    DeviceNotFound(String),
+    /// Reading or writing a file, such as the config.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// A file that can't be understood, or a setting that can't be used.
+    #[error("{0}")]
+    Config(String),
+    /// The tray icon couldn't be added or changed.
+    #[error("{0}")]
+    Tray(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl SwitcherooError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Com(_) => ErrorKind::Com,
+            Self::DeviceNotFound(_) => ErrorKind::DeviceNotFound,
+            Self::Io(_) | Self::Config(_) => ErrorKind::Config,
+            Self::Tray(_) | Self::Other(_) => ErrorKind::Other,
+        }
+    }
+}
+
+impl From<serde_json::Error> for SwitcherooError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::Config(error.to_string())
+    }
+}
+
+// For IDs and names Windows hands back as UTF-16.
+impl From<FromUtf16Error> for SwitcherooError {
+    fn from(error: FromUtf16Error) -> Self {
+        Self::Other(error.to_string())
+    }
+}
+
+// For names handed to C APIs.
+impl From<NulError> for SwitcherooError {
+    fn from(error: NulError) -> Self {
+        Self::Other(error.to_string())
+    }
+}
+
+impl From<Failure> for SwitcherooError {
+    fn from(failure: Failure) -> Self {
+        match failure.kind {
+            ErrorKind::DeviceNotFound => Self::DeviceNotFound(failure.message),
+            ErrorKind::Config => Self::Config(failure.message),
+            ErrorKind::Com | ErrorKind::Other => Self::Other(failure.message),
+        }
+    }
+}
+
+/// Returns early with a `SwitcherooError::Other` made from a `format!` message.
+#[macro_export]
+macro_rules! bail {
+    ($($arg:tt)+) => {
+        return Err($crate::error::SwitcherooError::Other(format!($($arg)+)))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions_keep_their_kind() {
+        let error = SwitcherooError::from(io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(error.kind(), ErrorKind::Config);
+        let error = SwitcherooError::from(Failure::new(ErrorKind::DeviceNotFound, "Headset"));
+        assert_eq!(error.kind(), ErrorKind::DeviceNotFound);
+        assert_eq!(
+            SwitcherooError::from(windows_core::Error::empty()).kind(),
+            ErrorKind::Com
+        );
+    }
+
+    #[test]
+    fn bail_returns_other_errors() {
+        fn fails() -> Result<(), SwitcherooError> {
+            crate::bail!("Something {}", "else");
+        }
+        let error = fails().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::Other);
+        assert_eq!(error.to_string(), "Something else");
+    }
+}
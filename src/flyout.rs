@@ -0,0 +1,411 @@
+use log::debug;
+use std::sync::Once;
+use windows::Win32::Foundation::{COLORREF, HWND, LPARAM, LRESULT, RECT, WPARAM};
+use windows::Win32::Graphics::Gdi::{
+    BeginPaint, CreateFontIndirectW, DeleteObject, DrawTextW, EndPaint, FillRect, GetSysColor,
+    GetSysColorBrush, InvalidateRect, SelectObject, SetBkMode, SetTextColor, COLOR_BTNFACE,
+    COLOR_HIGHLIGHT, COLOR_HIGHLIGHTTEXT, COLOR_WINDOW, COLOR_WINDOWTEXT, DT_END_ELLIPSIS,
+    DT_NOPREFIX, DT_SINGLELINE, DT_VCENTER, HFONT, PAINTSTRUCT, TRANSPARENT,
+};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::Controls::{
+    InitCommonControlsEx, ICC_BAR_CLASSES, INITCOMMONCONTROLSEX, TBM_SETPAGESIZE, TBM_SETPOS,
+    TBM_SETRANGE, TBS_HORZ, TBS_NOTICKS, TRACKBAR_CLASSW, WM_MOUSELEAVE,
+};
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    SetFocus, TrackMouseEvent, TME_LEAVE, TRACKMOUSEEVENT,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, DestroyWindow, DrawIconEx, GetClientRect, GetDlgCtrlID,
+    GetDlgItem, GetWindow, GetWindowLongPtrW, RegisterClassExW, SendMessageW, SetForegroundWindow,
+    SetWindowLongPtrW, SetWindowPos, ShowWindow, DI_NORMAL, GWLP_USERDATA, GW_OWNER, HICON, HMENU,
+    SWP_NOACTIVATE, SWP_NOZORDER, SW_SHOW, WA_INACTIVE, WINDOW_STYLE, WM_ACTIVATE, WM_APP,
+    WM_CTLCOLORSTATIC, WM_DPICHANGED, WM_HSCROLL, WM_LBUTTONUP, WM_MOUSEMOVE, WM_NCDESTROY,
+    WM_PAINT, WM_USER, WNDCLASSEXW, WS_BORDER, WS_CHILD, WS_EX_TOOLWINDOW, WS_EX_TOPMOST, WS_POPUP,
+    WS_TABSTOP, WS_VISIBLE,
+};
+use windows_strings::w;
+
+use crate::dpi;
+use sound_switcheroo::error::SwitcherooError;
+
+/// Sent to the flyout's owner when a device's name is clicked, with LPARAM pointing to its ID as a
+/// `String`, which is only valid during the message. Returning non-zero marks it as the default.
+pub const WM_FLYOUT_DEVICE_CHOSEN: u32 = WM_APP + 0x54;
+/// Sent to the flyout's owner whenever a device's slider moves, with the new volume percentage in
+/// WPARAM and LPARAM pointing to the device's ID as a `String`, only valid during the message.
+pub const WM_FLYOUT_VOLUME_CHANGED: u32 = WM_APP + 0x55;
+
+// Sizes at 100% scaling, scaled to the DPI of the monitor the flyout is on.
+const WIDTH: i32 = 280;
+const PADDING: i32 = 8;
+const ICON_SIZE: i32 = 16;
+// Each device has a row with its icon and name, then its slider underneath.
+const NAME_HEIGHT: i32 = 24;
+const SLIDER_HEIGHT: i32 = 28;
+const ROW_HEIGHT: i32 = NAME_HEIGHT + SLIDER_HEIGHT;
+// Not defined by the windows crate.
+const TBM_GETPOS: u32 = WM_USER;
+
+static REGISTER_CLASS: Once = Once::new();
+
+/// A device as the flyout shows it.
+#[derive(Debug, Clone)]
+pub struct FlyoutDevice {
+    pub id: String,
+    pub name: String,
+    // Owned by the caller, and kept for as long as the app runs.
+    pub icon: HICON,
+    pub percent: u32,
+    pub default: bool,
+}
+
+// Kept in the flyout's user data.
+struct State {
+    devices: Vec<FlyoutDevice>,
+    // The device whose name is under the mouse.
+    hot: Option<usize>,
+    // Of the monitor the flyout is on, with the message font at that DPI.
+    dpi: u32,
+    font: HFONT,
+}
+
+// The device whose name is at `y` in the flyout, out of `count`, as the sliders under the names
+// take their own clicks.
+fn device_at(y: i32, count: usize, dpi: u32) -> Option<usize> {
+    let (padding, row_height) = (dpi::scale(PADDING, dpi), dpi::scale(ROW_HEIGHT, dpi));
+    if y < padding {
+        return None;
+    }
+    let row = ((y - padding) / row_height) as usize;
+    ((y - padding) % row_height < dpi::scale(NAME_HEIGHT, dpi) && row < count).then_some(row)
+}
+
+// The flyout's size for `count` devices.
+fn size(count: usize, dpi: u32) -> (i32, i32) {
+    (
+        dpi::scale(WIDTH, dpi),
+        dpi::scale(count as i32 * ROW_HEIGHT + 2 * PADDING, dpi),
+    )
+}
+
+// Puts the sliders under the devices' names, for the flyout's DPI.
+unsafe fn place_sliders(flyout: HWND, count: usize, dpi: u32) {
+    let scale = |size| dpi::scale(size, dpi);
+    for index in 0..count {
+        unsafe {
+            let Ok(slider) = GetDlgItem(Some(flyout), index as i32 + 1) else {
+                continue;
+            };
+            let _ = SetWindowPos(
+                slider,
+                None,
+                scale(PADDING + ICON_SIZE),
+                scale(PADDING + index as i32 * ROW_HEIGHT + NAME_HEIGHT),
+                scale(WIDTH - 2 * PADDING - ICON_SIZE - 2),
+                scale(SLIDER_HEIGHT - 4),
+                SWP_NOZORDER | SWP_NOACTIVATE,
+            );
+        }
+    }
+}
+
+/// Opens a flyout with its bottom right corner at (`x`, `y`), like the volume popup, listing
+/// `devices` with a volume slider each. Clicking a device's name sends `WM_FLYOUT_DEVICE_CHOSEN`
+/// to `owner`, and moving a slider `WM_FLYOUT_VOLUME_CHANGED`. It closes when clicked away from.
+pub fn show(
+    owner: HWND,
+    x: i32,
+    y: i32,
+    devices: Vec<FlyoutDevice>,
+) -> Result<HWND, SwitcherooError> {
+    unsafe {
+        let module = GetModuleHandleW(None)?;
+        REGISTER_CLASS.call_once(|| {
+            InitCommonControlsEx(&INITCOMMONCONTROLSEX {
+                dwSize: std::mem::size_of::<INITCOMMONCONTROLSEX>() as u32,
+                dwICC: ICC_BAR_CLASSES,
+            })
+            .ok()
+            .unwrap_or_else(|e| log::error!("Failed to initialise common controls: {e}"));
+            RegisterClassExW(&WNDCLASSEXW {
+                cbSize: std::mem::size_of::<WNDCLASSEXW>() as u32,
+                lpfnWndProc: Some(flyout_callback),
+                hInstance: module.into(),
+                hbrBackground: GetSysColorBrush(COLOR_WINDOW),
+                lpszClassName: w!("SoundSwitcherooFlyout"),
+                ..Default::default()
+            });
+        });
+
+        // Sized for the monitor it opens on, which may not be the one the app started on.
+        let dpi = dpi::for_point(x, y);
+        let (width, height) = size(devices.len(), dpi);
+        let flyout = CreateWindowExW(
+            WS_EX_TOOLWINDOW | WS_EX_TOPMOST,
+            w!("SoundSwitcherooFlyout"),
+            w!("Volume mixer"),
+            WS_POPUP | WS_BORDER,
+            (x - width).max(0),
+            (y - height).max(0),
+            width,
+            height,
+            Some(owner),
+            None,
+            Some(module.into()),
+            None,
+        )?;
+        let mut first_slider = None;
+        for (index, device) in devices.iter().enumerate() {
+            let slider = CreateWindowExW(
+                Default::default(),
+                TRACKBAR_CLASSW,
+                w!(""),
+                WS_CHILD | WS_VISIBLE | WS_TABSTOP | WINDOW_STYLE(TBS_HORZ | TBS_NOTICKS),
+                0,
+                0,
+                0,
+                0,
+                Some(flyout),
+                // Told apart by their IDs, which are the devices' indexes plus one.
+                Some(HMENU((index + 1) as *mut _)),
+                Some(module.into()),
+                None,
+            )?;
+            SendMessageW(
+                slider,
+                TBM_SETRANGE,
+                Some(WPARAM(1)),
+                Some(LPARAM((100 << 16) as isize)),
+            );
+            SendMessageW(slider, TBM_SETPAGESIZE, None, Some(LPARAM(10)));
+            SendMessageW(
+                slider,
+                TBM_SETPOS,
+                Some(WPARAM(1)),
+                Some(LPARAM(device.percent.min(100) as isize)),
+            );
+            if device.default || first_slider.is_none() {
+                first_slider = Some(slider);
+            }
+        }
+        // The DPI can still differ from the point's if the flyout was moved to fit on a monitor.
+        let dpi = dpi::for_window(flyout);
+        place_sliders(flyout, devices.len(), dpi);
+        let state = Box::new(State {
+            devices,
+            hot: None,
+            dpi,
+            font: CreateFontIndirectW(&dpi::message_font(dpi)),
+        });
+        SetWindowLongPtrW(flyout, GWLP_USERDATA, Box::into_raw(state) as isize);
+        let _ = ShowWindow(flyout, SW_SHOW);
+        // Needed to be told when the user clicks elsewhere, and for the wheel to reach a slider.
+        let _ = SetForegroundWindow(flyout);
+        if let Some(slider) = first_slider {
+            SetFocus(Some(slider))?;
+        }
+        Ok(flyout)
+    }
+}
+
+unsafe fn paint(hwnd: HWND, state: &State) {
+    unsafe {
+        let mut paint = PAINTSTRUCT::default();
+        let dc = BeginPaint(hwnd, &mut paint);
+        let mut client = RECT::default();
+        let _ = GetClientRect(hwnd, &mut client);
+        SetBkMode(dc, TRANSPARENT);
+        let old_font = SelectObject(dc, state.font.into());
+        let scale = |size| dpi::scale(size, state.dpi);
+        let icon_size = scale(ICON_SIZE);
+        for (index, device) in state.devices.iter().enumerate() {
+            let top = scale(PADDING + index as i32 * ROW_HEIGHT);
+            let row = RECT {
+                left: scale(PADDING / 2),
+                top,
+                right: client.right - scale(PADDING / 2),
+                bottom: top + scale(NAME_HEIGHT),
+            };
+            // The default device is highlighted, as the current one is in the menu.
+            let (background, text) = if device.default {
+                (Some(COLOR_HIGHLIGHT), COLOR_HIGHLIGHTTEXT)
+            } else if state.hot == Some(index) {
+                (Some(COLOR_BTNFACE), COLOR_WINDOWTEXT)
+            } else {
+                (None, COLOR_WINDOWTEXT)
+            };
+            if let Some(background) = background {
+                FillRect(dc, &row, GetSysColorBrush(background));
+            }
+            let _ = DrawIconEx(
+                dc,
+                scale(PADDING),
+                top + (row.bottom - row.top - icon_size) / 2,
+                device.icon,
+                icon_size,
+                icon_size,
+                0,
+                None,
+                DI_NORMAL,
+            );
+            SetTextColor(dc, COLORREF(GetSysColor(text)));
+            let mut text_rect = RECT {
+                left: scale(PADDING * 2 + ICON_SIZE),
+                right: row.right - scale(PADDING),
+                ..row
+            };
+            let mut name: Vec<u16> = device.name.encode_utf16().collect();
+            DrawTextW(
+                dc,
+                &mut name,
+                &mut text_rect,
+                DT_SINGLELINE | DT_VCENTER | DT_END_ELLIPSIS | DT_NOPREFIX,
+            );
+        }
+        SelectObject(dc, old_font);
+        let _ = EndPaint(hwnd, &paint);
+    }
+}
+
+// Tells the owner a device was chosen, marking it as the default if it now is.
+unsafe fn choose(hwnd: HWND, state: &mut State, index: usize) {
+    unsafe {
+        let Ok(owner) = GetWindow(hwnd, GW_OWNER) else {
+            return;
+        };
+        debug!("Chose {} in the flyout", state.devices[index].name);
+        let id = state.devices[index].id.clone();
+        let switched = SendMessageW(
+            owner,
+            WM_FLYOUT_DEVICE_CHOSEN,
+            None,
+            Some(LPARAM(&id as *const _ as isize)),
+        );
+        if switched.0 != 0 {
+            for (i, device) in state.devices.iter_mut().enumerate() {
+                device.default = i == index;
+            }
+            let _ = InvalidateRect(Some(hwnd), None, true);
+        }
+    }
+}
+
+unsafe extern "system" fn flyout_callback(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    unsafe {
+        let state = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut State;
+        match msg {
+            WM_PAINT if !state.is_null() => {
+                paint(hwnd, &*state);
+                LRESULT(0)
+            }
+            // Trackbars paint their backgrounds with this, which would otherwise be grey.
+            WM_CTLCOLORSTATIC => LRESULT(GetSysColorBrush(COLOR_WINDOW).0 as isize),
+            WM_MOUSEMOVE if !state.is_null() => {
+                let y = ((lparam.0 >> 16) & 0xffff) as i16 as i32;
+                let hot = device_at(y, (*state).devices.len(), (*state).dpi);
+                if hot != (*state).hot {
+                    (*state).hot = hot;
+                    let _ = InvalidateRect(Some(hwnd), None, true);
+                    // To be told when the mouse leaves, to stop highlighting.
+                    let _ = TrackMouseEvent(&mut TRACKMOUSEEVENT {
+                        cbSize: std::mem::size_of::<TRACKMOUSEEVENT>() as u32,
+                        dwFlags: TME_LEAVE,
+                        hwndTrack: hwnd,
+                        dwHoverTime: 0,
+                    });
+                }
+                LRESULT(0)
+            }
+            WM_MOUSELEAVE if !state.is_null() => {
+                if (*state).hot.take().is_some() {
+                    let _ = InvalidateRect(Some(hwnd), None, true);
+                }
+                LRESULT(0)
+            }
+            WM_LBUTTONUP if !state.is_null() => {
+                let y = ((lparam.0 >> 16) & 0xffff) as i16 as i32;
+                if let Some(index) = device_at(y, (*state).devices.len(), (*state).dpi) {
+                    choose(hwnd, &mut *state, index);
+                }
+                LRESULT(0)
+            }
+            WM_HSCROLL if !state.is_null() => {
+                let slider = HWND(lparam.0 as *mut _);
+                let position = SendMessageW(slider, TBM_GETPOS, None, None).0;
+                let index = GetDlgCtrlID(slider) as usize - 1;
+                if let Some(device) = (&mut *state).devices.get_mut(index)
+                    && let Ok(owner) = GetWindow(hwnd, GW_OWNER)
+                {
+                    device.percent = position as u32;
+                    SendMessageW(
+                        owner,
+                        WM_FLYOUT_VOLUME_CHANGED,
+                        Some(WPARAM(position as usize)),
+                        Some(LPARAM(&device.id as *const _ as isize)),
+                    );
+                }
+                LRESULT(0)
+            }
+            // Moved to a monitor with another scale, or the scale changed.
+            WM_DPICHANGED if !state.is_null() => {
+                let dpi = (wparam.0 & 0xffff) as u32;
+                debug!("Flyout now at {dpi} DPI");
+                let state = &mut *state;
+                state.dpi = dpi;
+                let _ = DeleteObject(state.font.into());
+                state.font = CreateFontIndirectW(&dpi::message_font(dpi));
+                dpi::apply_suggested_rect(hwnd, lparam.0);
+                place_sliders(hwnd, state.devices.len(), dpi);
+                let _ = InvalidateRect(Some(hwnd), None, true);
+                LRESULT(0)
+            }
+            WM_ACTIVATE if (wparam.0 & 0xffff) as u32 == WA_INACTIVE => {
+                debug!("Closing flyout");
+                let _ = DestroyWindow(hwnd);
+                LRESULT(0)
+            }
+            WM_NCDESTROY => {
+                if !state.is_null() {
+                    SetWindowLongPtrW(hwnd, GWLP_USERDATA, 0);
+                    let state = Box::from_raw(state);
+                    let _ = DeleteObject(state.font.into());
+                }
+                DefWindowProcW(hwnd, msg, wparam, lparam)
+            }
+            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_hit_but_not_sliders() {
+        let dpi = dpi::DEFAULT_DPI;
+        assert_eq!(device_at(0, 2, dpi), None);
+        assert_eq!(device_at(PADDING, 2, dpi), Some(0));
+        assert_eq!(device_at(PADDING + NAME_HEIGHT, 2, dpi), None);
+        assert_eq!(device_at(PADDING + ROW_HEIGHT + 1, 2, dpi), Some(1));
+        assert_eq!(device_at(PADDING + 2 * ROW_HEIGHT, 2, dpi), None);
+    }
+
+    #[test]
+    fn rows_scale_with_the_monitor() {
+        // 200%, as on a 4K monitor.
+        assert_eq!(
+            size(2, 192),
+            (2 * WIDTH, 2 * (2 * ROW_HEIGHT + 2 * PADDING))
+        );
+        assert_eq!(device_at(PADDING + NAME_HEIGHT, 2, 192), Some(0));
+        assert_eq!(device_at(2 * (PADDING + NAME_HEIGHT), 2, 192), None);
+        assert_eq!(device_at(2 * (PADDING + ROW_HEIGHT), 2, 192), Some(1));
+    }
+}
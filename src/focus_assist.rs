@@ -0,0 +1,61 @@
+use log::debug;
+use std::ffi::c_void;
+use windows::Win32::Foundation::NTSTATUS;
+
+use sound_switcheroo::error::SwitcherooError;
+
+// Focus Assist isn't exposed through a public API, but its current profile is published as
+// Windows Notification Facility state that any process can read.
+const WNF_SHEL_QUIETHOURS_ACTIVE_PROFILE_CHANGED: u64 = 0x0d83063ea3bf1c75;
+
+#[link(name = "ntdll")]
+unsafe extern "system" {
+    fn NtQueryWnfStateData(
+        state_name: *const u64,
+        type_id: *const c_void,
+        explicit_scope: *const c_void,
+        change_stamp: *mut u32,
+        buffer: *mut c_void,
+        buffer_size: *mut u32,
+    ) -> NTSTATUS;
+}
+
+/// The Focus Assist setting chosen in the Windows action center or by an automatic rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FocusAssist {
+    Off,
+    PriorityOnly,
+    AlarmsOnly,
+}
+
+impl FocusAssist {
+    /// Whether the user would rather not be interrupted by the app's own notifications.
+    pub fn suppresses_notifications(self) -> bool {
+        self != FocusAssist::Off
+    }
+}
+
+/// Reads the current Focus Assist profile.
+pub fn current() -> Result<FocusAssist, SwitcherooError> {
+    let mut profile: u32 = 0;
+    let mut change_stamp: u32 = 0;
+    let mut size = std::mem::size_of::<u32>() as u32;
+    unsafe {
+        NtQueryWnfStateData(
+            &WNF_SHEL_QUIETHOURS_ACTIVE_PROFILE_CHANGED,
+            std::ptr::null(),
+            std::ptr::null(),
+            &mut change_stamp,
+            &mut profile as *mut u32 as *mut c_void,
+            &mut size,
+        )
+        .ok()?;
+    }
+    debug!("Focus Assist profile: {profile}");
+    Ok(match profile {
+        1 => FocusAssist::PriorityOnly,
+        2 => FocusAssist::AlarmsOnly,
+        // Unpublished state (size 0) also means off.
+        _ => FocusAssist::Off,
+    })
+}
@@ -0,0 +1,114 @@
+use log::debug;
+use std::sync::atomic::{AtomicIsize, AtomicU32, Ordering};
+use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+use windows::Win32::System::Threading::GetCurrentProcessId;
+use windows::Win32::UI::Accessibility::{SetWinEventHook, UnhookWinEvent, HWINEVENTHOOK};
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetClassNameW, GetForegroundWindow, GetWindowThreadProcessId, PostMessageW,
+    EVENT_SYSTEM_FOREGROUND, WINEVENT_OUTOFCONTEXT, WM_APP,
+};
+use windows_core::Error as WindowsError;
+
+use sound_switcheroo::error::SwitcherooError;
+
+// Classes of the taskbar and tray overflow windows, which come to the foreground when the tray
+// icon is clicked but aren't what the user was working in.
+const SHELL_CLASSES: [&str; 4] = [
+    "Shell_TrayWnd",
+    "Shell_SecondaryTrayWnd",
+    "NotifyIconOverflowWindow",
+    "TopLevelWindowForOverflowXamlIsland",
+];
+
+/// Posted to the window given to `watch` when another app comes to the foreground, with WPARAM
+/// its process ID.
+pub const WM_FOREGROUND_APP_CHANGED: u32 = WM_APP + 0x56;
+
+// The process of the last app window to come to the foreground, or 0 for none yet.
+static FOCUSED_APP: AtomicU32 = AtomicU32::new(0);
+static HOOK: AtomicIsize = AtomicIsize::new(0);
+static WINDOW: AtomicIsize = AtomicIsize::new(0);
+
+/// Keeps track of the app the user was last working in, skipping the taskbar and the switcher's
+/// own windows, so it's still known after clicking the tray icon or its menu, and tells `window`
+/// when it changes. Does nothing if already watching.
+pub fn watch(window: HWND) -> Result<(), SwitcherooError> {
+    if HOOK.load(Ordering::SeqCst) != 0 {
+        return Ok(());
+    }
+    WINDOW.store(window.0 as isize, Ordering::SeqCst);
+    unsafe {
+        let _ = noted(GetForegroundWindow());
+        let hook = SetWinEventHook(
+            EVENT_SYSTEM_FOREGROUND,
+            EVENT_SYSTEM_FOREGROUND,
+            None,
+            Some(foreground_changed),
+            0,
+            0,
+            WINEVENT_OUTOFCONTEXT,
+        );
+        if hook.is_invalid() {
+            return Err(WindowsError::from_win32().into());
+        }
+        HOOK.store(hook.0 as isize, Ordering::SeqCst);
+    }
+    debug!("Watching the foreground window");
+    Ok(())
+}
+
+/// Stops keeping track of the foreground app.
+pub fn stop() {
+    let hook = HOOK.swap(0, Ordering::SeqCst);
+    if hook != 0 {
+        unsafe {
+            let _ = UnhookWinEvent(HWINEVENTHOOK(hook as *mut _));
+        }
+    }
+    FOCUSED_APP.store(0, Ordering::SeqCst);
+}
+
+/// The process ID of the app the user was last working in, while watching.
+pub fn focused_app() -> Option<u32> {
+    match FOCUSED_APP.load(Ordering::SeqCst) {
+        0 => None,
+        pid => Some(pid),
+    }
+}
+
+// Notes the window's process as the focused app, unless it's the shell's or our own, returning
+// whether that's another app than before.
+unsafe fn noted(window: HWND) -> bool {
+    unsafe {
+        let mut class = [0u16; 64];
+        let len = GetClassNameW(window, &mut class) as usize;
+        let class = String::from_utf16_lossy(&class[..len]);
+        if SHELL_CLASSES.contains(&class.as_str()) {
+            return false;
+        }
+        let mut pid = 0;
+        GetWindowThreadProcessId(window, Some(&mut pid));
+        pid != 0 && pid != GetCurrentProcessId() && FOCUSED_APP.swap(pid, Ordering::SeqCst) != pid
+    }
+}
+
+unsafe extern "system" fn foreground_changed(
+    _hook: HWINEVENTHOOK,
+    _event: u32,
+    window: HWND,
+    _object: i32,
+    _child: i32,
+    _thread: u32,
+    _time: u32,
+) {
+    unsafe {
+        if noted(window) {
+            let _ = PostMessageW(
+                Some(HWND(WINDOW.load(Ordering::SeqCst) as *mut _)),
+                WM_FOREGROUND_APP_CHANGED,
+                WPARAM(FOCUSED_APP.load(Ordering::SeqCst) as usize),
+                LPARAM::default(),
+            );
+        }
+    }
+}
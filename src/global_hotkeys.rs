@@ -0,0 +1,61 @@
+use log::{error, info};
+use windows::Win32::Foundation::HWND;
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    RegisterHotKey, UnregisterHotKey, HOT_KEY_MODIFIERS, MOD_NOREPEAT,
+};
+
+use sound_switcheroo::hotkey::Hotkey;
+
+// IDs passed to RegisterHotKey, which come back as WM_HOTKEY's WPARAM.
+pub const NEXT_DEVICE_HOTKEY_ID: i32 = 1;
+pub const PREVIOUS_DEVICE_HOTKEY_ID: i32 = 2;
+pub const SWAP_DEVICES_HOTKEY_ID: i32 = 3;
+pub const MUTE_MICROPHONE_HOTKEY_ID: i32 = 4;
+// The hotkeys for switching to a device are numbered from here, in the order of
+// `HotkeyConfig::device_hotkeys`.
+pub const FIRST_DEVICE_HOTKEY_ID: i32 = 0x100;
+// And the hotkeys for applying a profile from here, in the order of `Config::profiles`.
+pub const FIRST_PROFILE_HOTKEY_ID: i32 = 0x200;
+
+/// Hotkeys that work whichever app has focus, delivered to a window as `WM_HOTKEY` messages until
+/// dropped.
+pub struct GlobalHotkeys {
+    window: HWND,
+    ids: Vec<i32>,
+    // Those another app had already taken, so they do nothing here.
+    pub unavailable: Vec<Hotkey>,
+}
+
+impl GlobalHotkeys {
+    /// Registers each hotkey with its ID. Holding a hotkey down only sends one message.
+    pub fn register(window: HWND, hotkeys: &[(i32, Hotkey)]) -> Self {
+        let mut registered = Self {
+            window,
+            ids: Vec::new(),
+            unavailable: Vec::new(),
+        };
+        for &(id, hotkey) in hotkeys {
+            let modifiers = HOT_KEY_MODIFIERS(hotkey.modifiers) | MOD_NOREPEAT;
+            match unsafe { RegisterHotKey(Some(window), id, modifiers, hotkey.key) } {
+                Ok(()) => {
+                    info!("Registered hotkey {hotkey}");
+                    registered.ids.push(id);
+                }
+                Err(e) => {
+                    error!("Failed to register hotkey {hotkey}: {e}");
+                    registered.unavailable.push(hotkey);
+                }
+            }
+        }
+        registered
+    }
+}
+
+impl Drop for GlobalHotkeys {
+    fn drop(&mut self) {
+        for &id in &self.ids {
+            // Fails harmlessly if the window has already gone, taking its hotkeys with it.
+            let _ = unsafe { UnregisterHotKey(Some(self.window), id) };
+        }
+    }
+}
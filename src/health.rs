@@ -0,0 +1,78 @@
+use std::fmt;
+
+/// Something found wrong when the app started. It runs anyway, with a warning for a tray icon and
+/// "What's wrong?" in the menu explaining.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    // Listing the output devices failed, so there are none to switch between.
+    Com(String),
+    NoOutputDevices,
+    // The interface for setting the default device is missing, so switching will fail.
+    PolicyConfig(String),
+    // The config file couldn't be read, so the defaults are used and the file is left alone.
+    Config(String),
+    // Icons missing from the executable, by resource name.
+    Icons(Vec<String>),
+}
+
+impl Problem {
+    /// Whether finding devices again later puts this right.
+    pub fn is_about_devices(&self) -> bool {
+        matches!(self, Problem::Com(_) | Problem::NoOutputDevices)
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Com(e) => write!(f, "Windows couldn't list the audio devices: {e}"),
+            Problem::NoOutputDevices => write!(
+                f,
+                "There are no output devices. Check they're plugged in and enabled in Windows' \
+                 sound settings."
+            ),
+            Problem::PolicyConfig(e) => write!(
+                f,
+                "Windows doesn't allow changing the default device, so switching won't work: {e}"
+            ),
+            Problem::Config(e) => write!(
+                f,
+                "The config couldn't be read, so the default settings are in use until it's \
+                 fixed and the app restarted: {e}"
+            ),
+            Problem::Icons(names) => write!(
+                f,
+                "Some icons are missing from the program ({}). Reinstalling should fix this.",
+                names.join(", ")
+            ),
+        }
+    }
+}
+
+/// Describes the problems, one paragraph each, for the "What's wrong?" dialog.
+pub fn report(problems: &[Problem]) -> String {
+    problems
+        .iter()
+        .map(|problem| problem.to_string())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_each_problem_in_a_paragraph() {
+        let problems = [
+            Problem::NoOutputDevices,
+            Problem::Icons(vec!["headset_icon".to_string(), "speaker_icon".to_string()]),
+        ];
+        let report = report(&problems);
+        let paragraphs: Vec<_> = report.split("\n\n").collect();
+        assert_eq!(paragraphs.len(), 2);
+        assert!(paragraphs[1].contains("(headset_icon, speaker_icon)"));
+        assert!(problems[0].is_about_devices());
+        assert!(!problems[1].is_about_devices());
+    }
+}
@@ -0,0 +1,16 @@
+use std::io;
+use std::os::windows::process::CommandExt;
+use std::process::{Child, Command};
+
+// Keeps a console window from flashing up for each hook.
+const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+/// Starts a hook's command line with `cmd /C`, so it can use the shell's syntax, without waiting
+/// for it to finish.
+pub fn run(command: &str) -> io::Result<Child> {
+    Command::new("cmd")
+        .arg("/C")
+        .raw_arg(command)
+        .creation_flags(CREATE_NO_WINDOW)
+        .spawn()
+}
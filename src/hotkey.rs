@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::SwitcherooError;
+
+// The same values as RegisterHotKey's MOD_* flags.
+pub const MOD_ALT: u32 = 0x1;
+pub const MOD_CONTROL: u32 = 0x2;
+pub const MOD_SHIFT: u32 = 0x4;
+pub const MOD_WIN: u32 = 0x8;
+
+const MODIFIER_NAMES: [(u32, &str); 4] = [
+    (MOD_CONTROL, "Ctrl"),
+    (MOD_ALT, "Alt"),
+    (MOD_SHIFT, "Shift"),
+    (MOD_WIN, "Win"),
+];
+
+// Virtual-key codes of the keys with names, besides letters, digits and function keys.
+const KEY_NAMES: [(u32, &str); 10] = [
+    (0x20, "Space"),
+    (0x21, "PageUp"),
+    (0x22, "PageDown"),
+    (0x23, "End"),
+    (0x24, "Home"),
+    (0x25, "Left"),
+    (0x26, "Up"),
+    (0x27, "Right"),
+    (0x28, "Down"),
+    (0x2e, "Delete"),
+];
+
+/// A key combination written like `Ctrl+Alt+1`, as stored in the config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Hotkey {
+    // MOD_* flags.
+    pub modifiers: u32,
+    // Virtual-key code.
+    pub key: u32,
+}
+
+impl FromStr for Hotkey {
+    type Err = SwitcherooError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut modifiers = 0;
+        let mut key = None;
+        for part in s.split('+').map(str::trim) {
+            if let Some((flag, _)) = MODIFIER_NAMES
+                .iter()
+                .chain(&[(MOD_CONTROL, "Control")])
+                .find(|(_, name)| name.eq_ignore_ascii_case(part))
+            {
+                modifiers |= flag;
+            } else if key.is_none() {
+                key = Some(parse_key(part).ok_or_else(|| {
+                    SwitcherooError::Config(format!("Unknown key \"{part}\" in hotkey \"{s}\""))
+                })?);
+            } else {
+                return Err(SwitcherooError::Config(format!(
+                    "Hotkey \"{s}\" has more than one key"
+                )));
+            }
+        }
+        let Some(key) = key else {
+            return Err(SwitcherooError::Config(format!(
+                "Hotkey \"{s}\" has no key"
+            )));
+        };
+        if modifiers == 0 {
+            // Otherwise the key would stop working everywhere else.
+            return Err(SwitcherooError::Config(format!(
+                "Hotkey \"{s}\" needs Ctrl, Alt, Shift or Win"
+            )));
+        }
+        Ok(Self { modifiers, key })
+    }
+}
+
+fn parse_key(name: &str) -> Option<u32> {
+    let upper = name.to_ascii_uppercase();
+    if let [c] = upper.as_bytes()
+        && c.is_ascii_alphanumeric()
+    {
+        // Letters and digits are their own virtual-key codes.
+        return Some(*c as u32);
+    }
+    if let Some(n) = upper.strip_prefix('F').and_then(|n| n.parse::<u32>().ok())
+        && (1..=24).contains(&n)
+    {
+        return Some(0x70 + n - 1);
+    }
+    KEY_NAMES
+        .iter()
+        .find(|(_, key_name)| key_name.eq_ignore_ascii_case(name))
+        .map(|(code, _)| *code)
+}
+
+fn key_name(key: u32) -> String {
+    match key {
+        0x30..=0x39 | 0x41..=0x5a => char::from(key as u8).to_string(),
+        0x70..=0x87 => format!("F{}", key - 0x70 + 1),
+        _ => KEY_NAMES
+            .iter()
+            .find(|(code, _)| *code == key)
+            .map_or_else(|| format!("0x{key:02x}"), |(_, name)| name.to_string()),
+    }
+}
+
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (flag, name) in MODIFIER_NAMES {
+            if self.modifiers & flag != 0 {
+                write!(f, "{name}+")?;
+            }
+        }
+        write!(f, "{}", key_name(self.key))
+    }
+}
+
+impl TryFrom<String> for Hotkey {
+    type Error = SwitcherooError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Hotkey> for String {
+    fn from(hotkey: Hotkey) -> Self {
+        hotkey.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_normalises() {
+        let hotkey: Hotkey = "alt + control+f5".parse().unwrap();
+        assert_eq!(
+            hotkey,
+            Hotkey {
+                modifiers: MOD_ALT | MOD_CONTROL,
+                key: 0x74
+            }
+        );
+        assert_eq!(hotkey.to_string(), "Ctrl+Alt+F5");
+        assert_eq!(
+            "Win+Shift+a".parse::<Hotkey>().unwrap().to_string(),
+            "Shift+Win+A"
+        );
+        assert_eq!(
+            "Ctrl+PageUp".parse::<Hotkey>().unwrap().to_string(),
+            "Ctrl+PageUp"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_hotkeys() {
+        assert!("1".parse::<Hotkey>().is_err());
+        assert!("Ctrl+Alt".parse::<Hotkey>().is_err());
+        assert!("Ctrl+A+B".parse::<Hotkey>().is_err());
+        assert!("Ctrl+F25".parse::<Hotkey>().is_err());
+        assert!("Ctrl+Banana".parse::<Hotkey>().is_err());
+    }
+}
@@ -0,0 +1,331 @@
+use log::debug;
+use std::collections::HashMap;
+use std::sync::Once;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::UI::WindowsAndMessaging::{
+    DefWindowProcW, GetParent, GetWindow, GetWindowLongPtrW, SendMessageW, SetWindowLongPtrW,
+    BN_CLICKED, GWLP_USERDATA, GW_OWNER, LBN_SELCHANGE, WM_APP, WM_COMMAND, WM_NCDESTROY, WM_SIZE,
+};
+use windows_strings::w;
+
+use crate::controls::{self, Form};
+use sound_switcheroo::backend::AudioDevice;
+use sound_switcheroo::config::HotkeyConfig;
+use sound_switcheroo::error::SwitcherooError;
+use sound_switcheroo::hotkey::Hotkey;
+use sound_switcheroo::profiles::Profile;
+
+/// Sent to the settings window's owner when edited hotkeys are saved. LPARAM points to the new
+/// `HotkeyConfig`, which is only valid during the message.
+pub const WM_SETTINGS_HOTKEYS_CHANGED: u32 = WM_APP + 0x52;
+
+// Control IDs, received in WM_COMMAND.
+const ID_LIST: u16 = 100;
+const ID_NEW: u16 = 101;
+const ID_DELETE: u16 = 102;
+const ID_SAVE: u16 = 103;
+
+static REGISTER_CLASS: Once = Once::new();
+
+// Kept in the page's user data.
+struct Editor {
+    form: Form,
+    next_device: HWND,
+    previous_device: HWND,
+    swap_devices: HWND,
+    mute_microphone: HWND,
+    device_hotkey: HWND,
+    device: HWND,
+    // (hotkey as typed, device ID) of the per-device hotkeys being edited, saved only when Save is
+    // pressed.
+    device_hotkeys: Vec<(String, Option<String>)>,
+    selected: Option<usize>,
+    // Hotkeys of the profiles, which the ones here mustn't clash with.
+    profiles: Vec<Profile>,
+    // (ID, name) of the known outputs.
+    outputs: Vec<(String, String)>,
+    // The device ID behind each entry of the device dropdown.
+    device_choices: Vec<Option<String>>,
+}
+
+// Parses a hotkey field, which may be left empty.
+fn parse(text: &str) -> Result<Option<Hotkey>, String> {
+    match text.trim() {
+        "" => Ok(None),
+        text => text.parse::<Hotkey>().map(Some).map_err(|e| e.to_string()),
+    }
+}
+
+impl Editor {
+    fn fill_list(&self) {
+        let labels: Vec<String> = self
+            .device_hotkeys
+            .iter()
+            .map(|(hotkey, device_id)| {
+                let device = device_id
+                    .as_ref()
+                    .map(|id| {
+                        self.outputs
+                            .iter()
+                            .find(|(output, _)| output == id)
+                            .map_or(id.as_str(), |(_, name)| name.as_str())
+                    })
+                    .unwrap_or("(no device)");
+                format!("{}  {device}", hotkey.trim())
+            })
+            .collect();
+        controls::fill_list(
+            self.form.list,
+            labels.iter().map(String::as_str),
+            self.selected,
+        );
+    }
+
+    // Shows the selected per-device hotkey in its fields, which are emptied if there isn't one.
+    fn load_device_fields(&mut self) {
+        let (hotkey, device_id) = self
+            .selected
+            .and_then(|selected| self.device_hotkeys.get(selected))
+            .cloned()
+            .unwrap_or_default();
+        controls::set_text(self.device_hotkey, &hotkey);
+        self.device_choices =
+            controls::fill_device_combo(self.device, None, &self.outputs, device_id.as_ref());
+    }
+
+    fn store_device_fields(&mut self) {
+        if let Some(selected) = self.selected {
+            self.device_hotkeys[selected] = (
+                controls::text(self.device_hotkey).trim().to_string(),
+                controls::combo_choice(self.device, &self.device_choices),
+            );
+        }
+    }
+
+    fn set_status(&self, text: &str) {
+        controls::set_text(self.form.status, text);
+    }
+
+    fn select(&mut self, selected: Option<usize>) {
+        self.store_device_fields();
+        self.selected = selected;
+        self.fill_list();
+        self.load_device_fields();
+        self.set_status("");
+    }
+
+    fn add(&mut self) {
+        self.store_device_fields();
+        let device_id = self.outputs.first().map(|(id, _)| id.clone());
+        self.device_hotkeys.push((String::new(), device_id));
+        self.selected = Some(self.device_hotkeys.len() - 1);
+        self.fill_list();
+        self.load_device_fields();
+        self.set_status("Type a hotkey such as Ctrl+Alt+1 and pick the device it switches to.");
+    }
+
+    fn delete(&mut self) {
+        let Some(selected) = self.selected else {
+            return;
+        };
+        self.device_hotkeys.remove(selected);
+        self.selected = if self.device_hotkeys.is_empty() {
+            None
+        } else {
+            Some(selected.min(self.device_hotkeys.len() - 1))
+        };
+        self.fill_list();
+        self.load_device_fields();
+        self.set_status("Deleted. Save to keep the change.");
+    }
+
+    // Reads the fields into a HotkeyConfig, failing on anything that can't be understood or
+    // would be registered twice.
+    fn hotkeys(&mut self) -> Result<HotkeyConfig, String> {
+        self.store_device_fields();
+        let mut devices = HashMap::new();
+        for (text, device_id) in &self.device_hotkeys {
+            let Some(hotkey) = parse(text)? else {
+                return Err("A device hotkey is empty.".to_string());
+            };
+            let Some(device_id) = device_id else {
+                return Err(format!("{hotkey} has no device to switch to."));
+            };
+            if devices.insert(hotkey, device_id.clone()).is_some() {
+                return Err(format!("{hotkey} is the hotkey for more than one device."));
+            }
+        }
+        let hotkeys = HotkeyConfig {
+            next_device: parse(&controls::text(self.next_device))?,
+            previous_device: parse(&controls::text(self.previous_device))?,
+            swap_devices: parse(&controls::text(self.swap_devices))?,
+            mute_microphone: parse(&controls::text(self.mute_microphone))?,
+            devices,
+        };
+        let clashes = hotkeys.clashes(&self.profiles);
+        if !clashes.is_empty() {
+            let names: Vec<_> = clashes.iter().map(ToString::to_string).collect();
+            return Err(format!(
+                "Used for more than one thing, here or by a profile: {}.",
+                names.join(", ")
+            ));
+        }
+        Ok(hotkeys)
+    }
+
+    // Sends the hotkeys to the settings window's owner, unless they can't be used.
+    fn save(&mut self, page: HWND) {
+        let hotkeys = match self.hotkeys() {
+            Ok(hotkeys) => hotkeys,
+            Err(e) => {
+                self.fill_list();
+                self.set_status(&format!("Not saved.\n{e}"));
+                return;
+            }
+        };
+        self.fill_list();
+        debug!("Saving hotkeys {hotkeys:?}");
+        unsafe {
+            if let Ok(settings) = GetParent(page)
+                && let Ok(owner) = GetWindow(settings, GW_OWNER)
+            {
+                SendMessageW(
+                    owner,
+                    WM_SETTINGS_HOTKEYS_CHANGED,
+                    None,
+                    Some(LPARAM(&hotkeys as *const _ as isize)),
+                );
+            }
+        }
+        self.set_status("Saved.");
+    }
+}
+
+/// Creates the hotkeys page as a hidden child of the settings window, editing `hotkeys` with a
+/// dropdown of the known `outputs` for the per-device ones, and checking them against the
+/// hotkeys of `profiles`.
+pub fn create(
+    parent: HWND,
+    hotkeys: &HotkeyConfig,
+    outputs: &[AudioDevice],
+    profiles: &[Profile],
+) -> Result<HWND, SwitcherooError> {
+    let page = controls::create_page(
+        parent,
+        w!("SoundSwitcherooHotkeys"),
+        &REGISTER_CLASS,
+        Some(editor_callback),
+    )?;
+    let list = controls::list(page, ID_LIST)?;
+    let next_device = controls::edit(page)?;
+    let previous_device = controls::edit(page)?;
+    let swap_devices = controls::edit(page)?;
+    let mute_microphone = controls::edit(page)?;
+    let device_hotkey = controls::edit(page)?;
+    let device = controls::dropdown(page, 0, false)?;
+    let text = |hotkey: Option<Hotkey>| hotkey.map(|h| h.to_string()).unwrap_or_default();
+    controls::set_text(next_device, &text(hotkeys.next_device));
+    controls::set_text(previous_device, &text(hotkeys.previous_device));
+    controls::set_text(swap_devices, &text(hotkeys.swap_devices));
+    controls::set_text(mute_microphone, &text(hotkeys.mute_microphone));
+    let device_hotkeys: Vec<_> = hotkeys
+        .device_hotkeys()
+        .into_iter()
+        .map(|(hotkey, device_id)| (hotkey.to_string(), Some(device_id.to_string())))
+        .collect();
+    let mut editor = Editor {
+        form: Form {
+            list,
+            rows: vec![
+                (controls::label(page, "Next")?, next_device, false),
+                (controls::label(page, "Previous")?, previous_device, false),
+                (controls::label(page, "Swap")?, swap_devices, false),
+                (controls::label(page, "Mute mic")?, mute_microphone, false),
+                (controls::label(page, "Hotkey")?, device_hotkey, false),
+                (controls::label(page, "Device")?, device, true),
+            ],
+            status: controls::label(page, "")?,
+            buttons: vec![
+                controls::button(page, "&New", ID_NEW)?,
+                controls::button(page, "&Delete", ID_DELETE)?,
+                controls::button(page, "&Save", ID_SAVE)?,
+            ],
+        },
+        next_device,
+        previous_device,
+        swap_devices,
+        mute_microphone,
+        device_hotkey,
+        device,
+        selected: (!device_hotkeys.is_empty()).then_some(0),
+        device_hotkeys,
+        profiles: profiles.to_vec(),
+        outputs: controls::device_names(outputs),
+        device_choices: Vec::new(),
+    };
+    editor.fill_list();
+    editor.load_device_fields();
+    unsafe {
+        SetWindowLongPtrW(
+            page,
+            GWLP_USERDATA,
+            Box::into_raw(Box::new(editor)) as isize,
+        );
+    }
+    Ok(page)
+}
+
+/// Refreshes the device dropdown after outputs come or go, and the profiles' hotkeys after
+/// they're saved, keeping what's being edited.
+pub fn update(page: HWND, outputs: &[AudioDevice], profiles: &[Profile]) {
+    let Some(editor) =
+        (unsafe { (GetWindowLongPtrW(page, GWLP_USERDATA) as *mut Editor).as_mut() })
+    else {
+        return;
+    };
+    let device = controls::combo_choice(editor.device, &editor.device_choices);
+    editor.outputs = controls::device_names(outputs);
+    editor.profiles = profiles.to_vec();
+    editor.device_choices =
+        controls::fill_device_combo(editor.device, None, &editor.outputs, device.as_ref());
+}
+
+unsafe extern "system" fn editor_callback(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    unsafe {
+        let editor = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut Editor;
+        match msg {
+            WM_SIZE if !editor.is_null() => {
+                (*editor).form.layout(hwnd);
+                LRESULT(0)
+            }
+            WM_COMMAND if !editor.is_null() => {
+                let editor = &mut *editor;
+                let id = (wparam.0 & 0xffff) as u16;
+                let notification = ((wparam.0 >> 16) & 0xffff) as u32;
+                match (id, notification) {
+                    (ID_LIST, LBN_SELCHANGE) => {
+                        editor.select(controls::list_selection(editor.form.list))
+                    }
+                    (ID_NEW, BN_CLICKED) => editor.add(),
+                    (ID_DELETE, BN_CLICKED) => editor.delete(),
+                    (ID_SAVE, BN_CLICKED) => editor.save(hwnd),
+                    _ => {}
+                }
+                LRESULT(0)
+            }
+            WM_NCDESTROY => {
+                if !editor.is_null() {
+                    SetWindowLongPtrW(hwnd, GWLP_USERDATA, 0);
+                    drop(Box::from_raw(editor));
+                }
+                DefWindowProcW(hwnd, msg, wparam, lparam)
+            }
+            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+        }
+    }
+}
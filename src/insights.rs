@@ -0,0 +1,337 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sound_switcheroo::rules::{self, Rule, RuleAction, Trigger};
+
+/// Switches older than this are dropped from the history.
+pub const KEEP_FOR: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+// A pattern has to show on at least this many days, and on this share of them, to be reported.
+const MIN_DAYS: usize = 5;
+const MIN_PERCENT: usize = 70;
+
+/// Where the switching history for the config at `config_path` is kept.
+pub fn history_path(config_path: &Path) -> PathBuf {
+    config_path.with_file_name("history.jsonl")
+}
+
+/// One switch of the output device, kept as a line of JSON in the history file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Switch {
+    // Unix time.
+    pub at: u64,
+    // Local date and time, as `YYYY-MM-DD` and `HH:MM`.
+    pub date: String,
+    pub time: String,
+    // The device's endpoint ID and name.
+    pub device: String,
+    pub name: String,
+}
+
+impl Switch {
+    /// A switch to the device with endpoint ID `device` happening now.
+    pub fn now(device: &str, name: &str) -> Self {
+        let (date, time) = rules::local_time();
+        Self {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            date,
+            time,
+            device: device.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    // Minutes past midnight.
+    fn minute(&self) -> Option<u32> {
+        let (hours, minutes) = rules::parse_time(&self.time)?;
+        Some(hours * 60 + minutes)
+    }
+}
+
+/// The switches made on this computer, never sent anywhere, for working out `insights`.
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reads the history, skipping lines that can't be read.
+    pub fn load(&self) -> io::Result<Vec<Switch>> {
+        match fs::read_to_string(&self.path) {
+            Ok(text) => Ok(text
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Adds a switch to the end of the file.
+    pub fn record(&self, switch: Switch) -> io::Result<()> {
+        let mut line = serde_json::to_string(&switch)?;
+        line.push('\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())
+    }
+
+    /// Reads the history like `load`, dropping the switches older than `KEEP_FOR` at `now` from
+    /// the file if there are any.
+    pub fn prune(&self, now: SystemTime) -> io::Result<Vec<Switch>> {
+        let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut switches = self.load()?;
+        let count = switches.len();
+        switches.retain(|old| now.saturating_sub(old.at) <= KEEP_FOR.as_secs());
+        if switches.len() < count {
+            debug!(
+                "Dropping {} old switches from the history",
+                count - switches.len()
+            );
+            let mut text = String::new();
+            for switch in &switches {
+                text.push_str(&serde_json::to_string(switch)?);
+                text.push('\n');
+            }
+            fs::write(&self.path, text)?;
+        }
+        Ok(switches)
+    }
+
+    pub fn clear(&self) -> io::Result<()> {
+        debug!("Removing history {}", self.path.display());
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A part of the day, for spotting devices used at the same time every day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Period {
+    Morning,
+    Afternoon,
+    Evening,
+    Night,
+}
+
+impl Period {
+    /// The part of the day `minute` (past midnight) falls in.
+    pub fn of(minute: u32) -> Self {
+        match minute / 60 {
+            5..=11 => Period::Morning,
+            12..=16 => Period::Afternoon,
+            17..=21 => Period::Evening,
+            _ => Period::Night,
+        }
+    }
+
+    fn plural(self) -> &'static str {
+        match self {
+            Period::Morning => "mornings",
+            Period::Afternoon => "afternoons",
+            Period::Evening => "evenings",
+            Period::Night => "nights",
+        }
+    }
+
+    fn during(self) -> &'static str {
+        match self {
+            Period::Morning => "in the morning",
+            Period::Afternoon => "in the afternoon",
+            Period::Evening => "in the evening",
+            Period::Night => "at night",
+        }
+    }
+}
+
+/// A device switched to in the same part of the day on most days.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Insight {
+    pub device: String,
+    pub name: String,
+    pub period: Period,
+    // Days it was switched to in that part of the day, out of the days with any switch then.
+    pub days: usize,
+    pub out_of: usize,
+    // The middle of the times it was first switched to on those days, as `HH:MM`.
+    pub usual_time: String,
+}
+
+impl Insight {
+    /// A rule switching to the device at the usual time every day, unless `rules` already have
+    /// one on a schedule.
+    pub fn suggested_rule(&self, rules: &[Rule]) -> Option<Rule> {
+        let action = RuleAction::SwitchDevice {
+            device: self.device.clone(),
+        };
+        if rules
+            .iter()
+            .any(|rule| matches!(rule.trigger, Trigger::Schedule { .. }) && rule.action == action)
+        {
+            return None;
+        }
+        Some(Rule {
+            name: format!("{} {}", self.name, self.period.during()),
+            trigger: Trigger::Schedule {
+                at: self.usual_time.clone(),
+            },
+            action,
+            ..Default::default()
+        })
+    }
+}
+
+impl fmt::Display for Insight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "You switch to {} on {}% of {} ({} of {}), usually around {}.",
+            self.name,
+            self.days * 100 / self.out_of,
+            self.period.plural(),
+            self.days,
+            self.out_of,
+            self.usual_time
+        )
+    }
+}
+
+/// Finds the devices switched to in the same part of the day on most of the days the history has
+/// switches then.
+pub fn insights(history: &[Switch]) -> Vec<Insight> {
+    let mut active_days: BTreeMap<Period, BTreeSet<&str>> = BTreeMap::new();
+    // The first time each device was switched to on each day, by part of the day.
+    let mut firsts: BTreeMap<(Period, &str), BTreeMap<&str, u32>> = BTreeMap::new();
+    let mut names: HashMap<&str, &str> = HashMap::new();
+    for switch in history {
+        let Some(minute) = switch.minute() else {
+            continue;
+        };
+        let period = Period::of(minute);
+        active_days.entry(period).or_default().insert(&switch.date);
+        let first = firsts
+            .entry((period, &switch.device))
+            .or_default()
+            .entry(&switch.date)
+            .or_insert(minute);
+        *first = (*first).min(minute);
+        names.insert(&switch.device, &switch.name);
+    }
+    let mut insights = Vec::new();
+    for ((period, device), days) in firsts {
+        let out_of = active_days[&period].len();
+        if out_of < MIN_DAYS || days.len() * 100 < out_of * MIN_PERCENT {
+            continue;
+        }
+        let mut minutes: Vec<u32> = days.values().copied().collect();
+        minutes.sort_unstable();
+        let usual = minutes[minutes.len() / 2];
+        insights.push(Insight {
+            device: device.to_string(),
+            name: names[device].to_string(),
+            period,
+            days: days.len(),
+            out_of,
+            usual_time: format!("{:02}:{:02}", usual / 60, usual % 60),
+        });
+    }
+    insights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn switch(day: u32, time: &str, device: &str) -> Switch {
+        Switch {
+            at: 1_000_000 + day as u64 * 86_400,
+            date: format!("2026-03-{day:02}"),
+            time: time.to_string(),
+            device: format!("{{{device}}}"),
+            name: device.to_string(),
+        }
+    }
+
+    #[test]
+    fn spots_devices_used_at_the_same_time_of_day() {
+        let mut history = Vec::new();
+        for day in 1..=10 {
+            // Headset most mornings, speakers once.
+            if day == 4 {
+                history.push(switch(day, "08:30", "Speakers"));
+            } else {
+                history.push(switch(day, &format!("08:{:02}", day), "Headset"));
+                history.push(switch(day, "09:45", "Headset"));
+            }
+        }
+        // Too few evenings to say anything about.
+        history.push(switch(2, "19:00", "Speakers"));
+        let insights = insights(&history);
+        assert_eq!(
+            insights,
+            [Insight {
+                device: "{Headset}".to_string(),
+                name: "Headset".to_string(),
+                period: Period::Morning,
+                days: 9,
+                out_of: 10,
+                usual_time: "08:06".to_string(),
+            }]
+        );
+        assert_eq!(
+            insights[0].to_string(),
+            "You switch to Headset on 90% of mornings (9 of 10), usually around 08:06."
+        );
+        let rule = insights[0].suggested_rule(&[]).unwrap();
+        assert_eq!(rule.name, "Headset in the morning");
+        assert_eq!(
+            rule.trigger,
+            Trigger::Schedule {
+                at: "08:06".to_string()
+            }
+        );
+        // Not suggested again once there's a rule for it.
+        assert_eq!(insights[0].suggested_rule(&[rule]), None);
+    }
+
+    #[test]
+    fn history_drops_old_switches() {
+        let path = std::env::temp_dir().join(format!("history-{}.jsonl", std::process::id()));
+        let history = History::new(path.clone());
+        history.record(switch(1, "08:00", "Headset")).unwrap();
+        history.record(switch(2, "08:00", "Speakers")).unwrap();
+        let mut late = switch(1, "08:00", "Headset");
+        late.at += KEEP_FOR.as_secs() + 1;
+        history.record(late.clone()).unwrap();
+        assert_eq!(history.load().unwrap().len(), 3);
+        let kept = [switch(2, "08:00", "Speakers"), late.clone()];
+        assert_eq!(
+            history
+                .prune(UNIX_EPOCH + Duration::from_secs(late.at))
+                .unwrap(),
+            kept
+        );
+        assert_eq!(history.load().unwrap(), kept);
+        history.clear().unwrap();
+        assert!(history.load().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+use std::path::Path;
+use windows_core::GUID;
+
+// The identity of the default instance. Windows remembers tray icon settings (e.g. whether it's
+// shown or hidden in the overflow) by GUID, so this must not change.
+const DEFAULT_NOTIFY_ICON_GUID: GUID = GUID::from_u128(0x8fc84650_4bca_4125_b778_10313f9623df);
+const DEFAULT_NAME: &str = "SoundSwitcheroo";
+
+/// Names the Windows objects that must be unique to each running copy of the app, so several can
+/// run side by side with their own config and tray icon.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instance {
+    // None for the default instance.
+    key: Option<String>,
+}
+
+impl Instance {
+    /// Identifies an instance by its `--instance` name if given, otherwise by a non-default
+    /// config path.
+    pub fn new(name: Option<&str>, config_path: Option<&Path>) -> Self {
+        let key = match (name, config_path) {
+            (Some(name), _) => Some(format!("name:{name}")),
+            // Windows paths are case-insensitive.
+            (None, Some(path)) => Some(format!("path:{}", path.display()).to_lowercase()),
+            (None, None) => None,
+        };
+        Self { key }
+    }
+
+    pub fn notify_icon_guid(&self) -> GUID {
+        match &self.key {
+            None => DEFAULT_NOTIFY_ICON_GUID,
+            Some(key) => GUID::from_u128(DEFAULT_NOTIFY_ICON_GUID.to_u128() ^ fnv1a_128(key)),
+        }
+    }
+
+    pub fn window_class_name(&self) -> String {
+        self.qualify(DEFAULT_NAME)
+    }
+
+    /// Name of the mutex held while the instance is running, to stop it being started twice.
+    pub fn mutex_name(&self) -> String {
+        // Local\ scopes it to the user's session.
+        format!("Local\\{}", self.qualify("SoundSwitcherooRunning"))
+    }
+
+    /// Name of the pipe `pipe_server` serves commands on.
+    pub fn pipe_name(&self) -> String {
+        format!(r"\\.\pipe\{}", self.qualify("sound-switcheroo"))
+    }
+
+    /// Name of the log file written next to the config when there's no console.
+    pub fn log_file_name(&self) -> String {
+        format!("{}.log", self.qualify("sound-switcheroo"))
+    }
+
+    fn qualify(&self, base: &str) -> String {
+        match &self.key {
+            None => base.to_string(),
+            Some(_) => format!("{base}-{:032x}", self.notify_icon_guid().to_u128()),
+        }
+    }
+}
+
+// A stable hash, as the GUID must be the same on every run.
+fn fnv1a_128(s: &str) -> u128 {
+    const OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+    s.bytes()
+        .fold(OFFSET, |hash, b| (hash ^ b as u128).wrapping_mul(PRIME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_instance_keeps_original_names() {
+        let instance = Instance::new(None, None);
+        assert_eq!(instance.notify_icon_guid(), DEFAULT_NOTIFY_ICON_GUID);
+        assert_eq!(instance.window_class_name(), "SoundSwitcheroo");
+        assert_eq!(instance.mutex_name(), "Local\\SoundSwitcherooRunning");
+        assert_eq!(instance.pipe_name(), r"\\.\pipe\sound-switcheroo");
+        assert_eq!(instance.log_file_name(), "sound-switcheroo.log");
+    }
+
+    #[test]
+    fn named_instances_are_distinct_and_stable() {
+        let mics = Instance::new(Some("mics"), None);
+        let outputs = Instance::new(Some("outputs"), None);
+        assert_ne!(mics.notify_icon_guid(), outputs.notify_icon_guid());
+        assert_ne!(mics.notify_icon_guid(), DEFAULT_NOTIFY_ICON_GUID);
+        assert_ne!(mics.window_class_name(), outputs.window_class_name());
+        assert_ne!(mics.log_file_name(), outputs.log_file_name());
+        assert_eq!(
+            mics.notify_icon_guid(),
+            Instance::new(Some("mics"), None).notify_icon_guid()
+        );
+    }
+
+    #[test]
+    fn config_paths_compare_case_insensitively() {
+        let lower = Instance::new(None, Some(Path::new("c:\\audio\\config.json")));
+        let upper = Instance::new(None, Some(Path::new("C:\\Audio\\Config.json")));
+        assert_eq!(lower, upper);
+        // An explicit name wins over the path.
+        let named = Instance::new(Some("mics"), Some(Path::new("c:\\audio\\config.json")));
+        assert_eq!(named, Instance::new(Some("mics"), None));
+    }
+}
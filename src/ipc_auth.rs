@@ -0,0 +1,150 @@
+use crate::bail;
+use crate::error::SwitcherooError;
+use log::{debug, info};
+use std::fs;
+use std::path::{Path, PathBuf};
+use windows::Win32::Foundation::CloseHandle;
+use windows::Win32::Security::Cryptography::{BCryptGenRandom, BCRYPT_USE_SYSTEM_PREFERRED_RNG};
+use windows::Win32::System::Threading::{
+    OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION,
+};
+use windows_strings::PWSTR;
+
+/// Decides which local clients may use the control surfaces. Every request must carry the
+/// per-install token, and if an allowlist is configured the client process must also be one of
+/// the listed executables, so arbitrary local programs can't silently re-route audio.
+#[derive(Debug, Clone)]
+pub struct Authenticator {
+    token: String,
+    // Full paths or bare file names, compared case-insensitively. Empty allows any executable.
+    allowed_clients: Vec<String>,
+}
+
+impl Authenticator {
+    pub fn new(token: String, allowed_clients: Vec<String>) -> Self {
+        Self {
+            token,
+            allowed_clients,
+        }
+    }
+
+    /// Checks a request's token and the executable of the process that sent it.
+    pub fn check(&self, token: &str, client_executable: &Path) -> Result<(), SwitcherooError> {
+        if !constant_time_eq(token.as_bytes(), self.token.as_bytes()) {
+            bail!("Invalid token from {}", client_executable.display());
+        }
+        if !self.allowed_clients.is_empty() && !self.is_allowed(client_executable) {
+            bail!("{} is not an allowed client", client_executable.display());
+        }
+        Ok(())
+    }
+
+    /// Like `check`, looking up the executable from the client's process ID (e.g. from
+    /// `GetNamedPipeClientProcessId`).
+    pub fn check_process(&self, token: &str, client_pid: u32) -> Result<(), SwitcherooError> {
+        self.check(token, &process_executable(client_pid)?)
+    }
+
+    fn is_allowed(&self, executable: &Path) -> bool {
+        let full_path = executable.to_string_lossy().to_lowercase();
+        let file_name = executable
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase());
+        self.allowed_clients.iter().any(|allowed| {
+            let allowed = allowed.to_lowercase();
+            allowed == full_path || Some(&allowed) == file_name.as_ref()
+        })
+    }
+}
+
+// Doesn't stop early, so response times don't reveal how much of a guessed token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Reads the token clients must present, creating a random one the first time. It lives next to
+/// the config, so only the user (and their clients) can read it.
+pub fn load_or_create_token(token_path: &Path) -> Result<String, SwitcherooError> {
+    if let Ok(token) = fs::read_to_string(token_path) {
+        let token = token.trim();
+        if !token.is_empty() {
+            debug!("Loaded IPC token from: {}", token_path.display());
+            return Ok(token.to_string());
+        }
+    }
+    let mut bytes = [0u8; 32];
+    unsafe { BCryptGenRandom(None, &mut bytes, BCRYPT_USE_SYSTEM_PREFERRED_RNG).ok()? };
+    let token: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    fs::write(token_path, &token)?;
+    info!("Created IPC token: {}", token_path.display());
+    Ok(token)
+}
+
+/// Where the token for the config at `config_path` is kept.
+pub fn token_path(config_path: &Path) -> PathBuf {
+    config_path.with_file_name("ipc_token")
+}
+
+/// Gets the full path of a process's executable.
+pub fn process_executable(pid: u32) -> Result<PathBuf, SwitcherooError> {
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid)?;
+        let mut buffer = [0u16; 1024];
+        let mut len = buffer.len() as u32;
+        let result = QueryFullProcessImageNameW(
+            process,
+            PROCESS_NAME_WIN32,
+            PWSTR(buffer.as_mut_ptr()),
+            &mut len,
+        );
+        let _ = CloseHandle(process);
+        result
+            .map_err(|e| SwitcherooError::Other(format!("Failed to query process {pid}: {e}")))?;
+        Ok(PathBuf::from(String::from_utf16_lossy(
+            &buffer[..len as usize],
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN: &str = "0123456789abcdef";
+
+    #[test]
+    fn rejects_wrong_tokens() {
+        let auth = Authenticator::new(TOKEN.to_string(), vec![]);
+        let client = Path::new(r"C:\Tools\streamdeck.exe");
+        assert!(auth.check(TOKEN, client).is_ok());
+        assert!(auth.check("0123456789abcdeF", client).is_err());
+        assert!(auth.check("", client).is_err());
+    }
+
+    #[test]
+    fn allowlist_matches_paths_and_file_names() {
+        let auth = Authenticator::new(
+            TOKEN.to_string(),
+            vec![
+                "AutoHotkey64.exe".to_string(),
+                r"c:\tools\streamdeck.exe".to_string(),
+            ],
+        );
+        assert!(auth
+            .check(
+                TOKEN,
+                Path::new(r"C:\Program Files\AutoHotkey\AutoHotkey64.exe")
+            )
+            .is_ok());
+        assert!(auth
+            .check(TOKEN, Path::new(r"C:\Tools\StreamDeck.exe"))
+            .is_ok());
+        assert!(auth
+            .check(TOKEN, Path::new(r"C:\Other\streamdeck.exe"))
+            .is_err());
+        // A valid executable still needs the token.
+        assert!(auth
+            .check("wrong", Path::new(r"C:\Tools\streamdeck.exe"))
+            .is_err());
+    }
+}
@@ -0,0 +1,61 @@
+//! The audio plumbing behind Sound Switcheroo's tray icon, usable on its own.
+//!
+//! `endpoints` is the place to start: `DefaultEndpointManager` lists the outputs and inputs and
+//! reads or changes the default ones without any COM or `unsafe` code of your own.
+//!
+//! ```no_run
+//! use sound_switcheroo::endpoints::DefaultEndpointManager;
+//! use windows::Win32::Media::Audio::{eCommunications, eConsole, eRender};
+//!
+//! let manager = DefaultEndpointManager::new()?;
+//! for output in manager.outputs()?.iter() {
+//!     println!("{} {}", output.id, output.friendly_name);
+//! }
+//! manager.switch_to(eRender, "Headset", &[eConsole, eCommunications])?;
+//! # Ok::<(), sound_switcheroo::error::SwitcherooError>(())
+//! ```
+
+pub mod backend;
+pub mod com;
+pub mod endpoint_notifications;
+pub mod endpoints;
+pub mod error;
+pub mod recording;
+pub mod simulation;
+
+mod policy_config;
+
+// Shared with the tray app and the benchmarks, which need the `app` feature (on by default), but
+// not meant as API: these follow whatever the tray app needs and change without notice. Without
+// `app` they're private.
+macro_rules! app_modules {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[cfg(feature = "app")]
+            #[doc(hidden)]
+            pub mod $name;
+            #[cfg(not(feature = "app"))]
+            #[allow(dead_code)]
+            mod $name;
+        )*
+    };
+}
+
+app_modules!(
+    actions,
+    cli,
+    config,
+    hotkey,
+    ipc_auth,
+    logging,
+    menu,
+    plugins,
+    profiles,
+    rotation,
+    rules,
+    safe_strings,
+    switcher,
+    tooltip,
+    voicemeeter,
+    window_placement,
+);
@@ -0,0 +1,120 @@
+use log::LevelFilter;
+use serde::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+// Where the log goes once `log_to_file` has been called. Until then it's stderr.
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+static LOG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+// The logger's target, following `LOG_FILE`.
+struct LogTarget;
+
+impl Write for LogTarget {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match LOG_FILE.lock().as_deref_mut() {
+            Ok(Some(file)) => file.write(buf),
+            _ => io::stderr().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match LOG_FILE.lock().as_deref_mut() {
+            Ok(Some(file)) => file.flush(),
+            _ => io::stderr().flush(),
+        }
+    }
+}
+
+/// How much is logged, chosen from the Logging submenu and kept in the config.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    // What env_logger logs without `RUST_LOG`.
+    #[default]
+    Error,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    /// In the order they're listed in the menu.
+    pub const ALL: [LogLevel; 5] = [
+        LogLevel::Off,
+        LogLevel::Error,
+        LogLevel::Info,
+        LogLevel::Debug,
+        LogLevel::Trace,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            LogLevel::Off => "Off",
+            LogLevel::Error => "Errors only",
+            LogLevel::Info => "Info",
+            LogLevel::Debug => "Debug",
+            LogLevel::Trace => "Trace",
+        }
+    }
+
+    pub fn filter(self) -> LevelFilter {
+        match self {
+            LogLevel::Off => LevelFilter::Off,
+            LogLevel::Error => LevelFilter::Error,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Trace => LevelFilter::Trace,
+        }
+    }
+}
+
+/// Whether `RUST_LOG` was set, in which case it wins over the level saved in the config.
+pub fn overridden_by_env() -> bool {
+    std::env::var_os("RUST_LOG").is_some()
+}
+
+/// Sets up the logger, writing to stderr until `log_to_file` is called. Everything gets through
+/// the logger itself; the level is enforced by `log::set_max_level` instead, so it can be changed
+/// while running.
+pub fn init() {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(LevelFilter::Trace);
+    builder.target(env_logger::Target::Pipe(Box::new(LogTarget)));
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    builder.init();
+    if !overridden_by_env() {
+        set_level(LogLevel::default());
+    }
+}
+
+/// Sends the log to a new file at `path` from now on, as a windowed app has no stderr. The log
+/// from the run before is kept next to it, with `.1` added to its name.
+pub fn log_to_file(path: &Path) -> io::Result<()> {
+    if path.exists() {
+        let mut previous = OsString::from(path);
+        previous.push(".1");
+        fs::rename(path, previous)?;
+    }
+    let file = File::create(path)?;
+    if let Ok(mut log_file) = LOG_FILE.lock() {
+        *log_file = Some(file);
+    }
+    let _ = LOG_PATH.set(path.to_path_buf());
+    Ok(())
+}
+
+/// The file `log_to_file` sent the log to, if it has been called.
+pub fn file_path() -> Option<&'static Path> {
+    LOG_PATH.get().map(PathBuf::as_path)
+}
+
+pub fn set_level(level: LogLevel) {
+    log::set_max_level(level.filter());
+}
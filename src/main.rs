@@ -1,209 +1,940 @@
-#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
+#![cfg_attr(
+    all(not(debug_assertions), not(feature = "console")),
+    windows_subsystem = "windows"
+)]
 
-use crc16::State;
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
 use defer::defer;
-use log::{debug, error, info};
-use serde::{Deserialize, Serialize};
-use simple_error::bail;
-use std::collections::HashMap;
-use std::error::Error;
-use std::fs;
-use std::path::PathBuf;
+use log::{debug, error, info, warn};
+use std::cell::{Cell, OnceCell};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::ptr::null_mut;
-use windows::Win32::Devices::FunctionDiscovery::PKEY_Device_FriendlyName;
-use windows::Win32::Foundation::{GetLastError, HWND, LPARAM, LRESULT, POINT, WPARAM};
-use windows::Win32::Media::Audio::{
-    eConsole, ERole, EndpointFormFactor, Headphones, Headset, IMMDeviceEnumerator,
-    MMDeviceEnumerator, PKEY_AudioEndpoint_FormFactor, Speakers,
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
+use windows::Win32::Foundation::{
+    CloseHandle, GetLastError, ERROR_ALREADY_EXISTS, HWND, LPARAM, LRESULT, POINT, WPARAM,
 };
-use windows::Win32::System::Com::StructuredStorage::PROPVARIANT;
-use windows::Win32::System::Com::{
-    CoCreateInstance, CoInitializeEx, CoUninitialize, CLSCTX_ALL, COINIT_APARTMENTTHREADED,
-    STGM_READ,
+use windows::Win32::Media::Audio::{
+    eCapture, eCommunications, eConsole, eRender, DigitalAudioDisplayDevice, EndpointFormFactor,
+    Handset, Headphones, Headset, LineLevel, Microphone, RemoteNetworkDevice, Speakers,
+    UnknownDigitalPassthrough, UnknownFormFactor, SPDIF,
 };
 use windows::Win32::System::LibraryLoader::GetModuleHandleW;
-use windows::Win32::System::Variant::{VT_LPWSTR, VT_UI4};
+use windows::Win32::System::Threading::CreateMutexW;
+use windows::Win32::UI::Input::KeyboardAndMouse::{GetDoubleClickTime, GetKeyState, VK_SHIFT};
 use windows::Win32::UI::Shell::{
-    FOLDERID_RoamingAppData, SHGetKnownFolderPath, ShellExecuteW, Shell_NotifyIconW,
-    KNOWN_FOLDER_FLAG, NIF_GUID, NIF_ICON, NIF_MESSAGE, NIF_SHOWTIP, NIF_TIP, NIM_ADD, NIM_DELETE,
-    NIM_MODIFY, NIM_SETVERSION, NIN_SELECT, NOTIFYICONDATAW, NOTIFYICONDATAW_0,
-    NOTIFYICON_VERSION_4,
+    ShellExecuteW, Shell_NotifyIconW, NIF_ICON, NIF_INFO, NIF_MESSAGE, NIF_SHOWTIP, NIF_TIP,
+    NIIF_INFO, NIM_DELETE, NIM_MODIFY, NIM_SETVERSION, NIN_BALLOONUSERCLICK, NIN_SELECT,
+    NOTIFYICONDATAW, NOTIFYICONDATAW_0, NOTIFYICON_VERSION_4,
 };
 use windows::Win32::UI::WindowsAndMessaging::{
-    CreatePopupMenu, CreateWindowExW, DefWindowProcW, DestroyMenu, DispatchMessageW, GetCursorPos,
-    GetMenuItemInfoW, GetMessageW, GetWindowLongPtrW, InsertMenuItemW, LoadIconW, PostMessageW,
-    PostQuitMessage, RegisterClassExW, SetForegroundWindow, SetMenuItemInfoW, SetWindowLongPtrW,
-    TrackPopupMenuEx, UnregisterClassW, GWLP_USERDATA, HICON, HMENU, MENUITEMINFOW, MFS_CHECKED,
-    MFS_DISABLED, MFT_SEPARATOR, MFT_STRING, MIIM_FTYPE, MIIM_ID, MIIM_STATE, MIIM_STRING, MSG,
-    SW_SHOWNORMAL, TPM_BOTTOMALIGN, TPM_LEFTALIGN, TPM_RIGHTBUTTON, WINDOW_EX_STYLE, WINDOW_STYLE,
-    WM_APP, WM_CLOSE, WM_COMMAND, WM_DESTROY, WM_QUIT, WM_RBUTTONUP, WNDCLASSEXW,
+    ChangeWindowMessageFilterEx, CreatePopupMenu, CreateWindowExW, DefWindowProcW, DeleteMenu,
+    DestroyIcon, DestroyMenu, DestroyWindow, DispatchMessageW, FindWindowW, GetCursorPos,
+    GetMenuItemCount, GetMenuItemInfoW, GetMessageW, GetSubMenu, GetSystemMetrics,
+    GetWindowLongPtrW, InsertMenuItemW, IsWindow, KillTimer, LoadIconW, LoadImageW, PostMessageW,
+    PostQuitMessage, RegisterClassExW, RegisterWindowMessageW, SetForegroundWindow,
+    SetMenuItemInfoW, SetTimer, SetWindowLongPtrW, TrackPopupMenuEx, UnregisterClassW,
+    DBT_CONFIGCHANGED, DBT_DEVNODES_CHANGED, GWLP_USERDATA, HICON, HMENU, IDI_WARNING, IMAGE_ICON,
+    LR_LOADFROMFILE, MENUITEMINFOW, MENU_ITEM_STATE, MFS_CHECKED, MFS_DISABLED, MFT_RADIOCHECK,
+    MFT_SEPARATOR, MFT_STRING, MF_BYPOSITION, MIIM_FTYPE, MIIM_ID, MIIM_STATE, MIIM_STRING,
+    MIIM_SUBMENU, MSG, MSGFLT_ALLOW, PBT_APMRESUMEAUTOMATIC, PBT_POWERSETTINGCHANGE, SM_CXSMICON,
+    SM_CYSMICON, SM_SYSTEMDOCKED, SW_SHOWNORMAL, TPM_BOTTOMALIGN, TPM_LEFTALIGN, TPM_RIGHTBUTTON,
+    WINDOW_EX_STYLE, WINDOW_STYLE, WM_APP, WM_CLOSE, WM_COMMAND, WM_DESTROY, WM_DEVICECHANGE,
+    WM_HOTKEY, WM_INITMENUPOPUP, WM_LBUTTONDBLCLK, WM_MBUTTONUP, WM_MOUSEMOVE, WM_POWERBROADCAST,
+    WM_QUIT, WM_RBUTTONUP, WM_SETTINGCHANGE, WM_TIMER, WNDCLASSEXW,
 };
-use windows_core::{BOOL, GUID};
+use windows_core::BOOL;
 use windows_strings::{w, PCWSTR};
 
-mod policy_config;
-mod safe_strings;
-
-use policy_config::IPolicyConfig;
-use safe_strings::with_wide_str;
-
-const NOTIFY_ICON_GUID: GUID = GUID::from_u128(0x8fc84650_4bca_4125_b778_10313f9623df);
+mod app_routing;
+mod asio;
+mod audio_service;
+mod behavior_page;
+mod changelog;
+mod clipboard;
+mod console;
+mod controls;
+mod crash_loop;
+mod dialogs;
+mod dpi;
+mod drawn_icons;
+mod elevation;
+mod flyout;
+mod focus_assist;
+mod foreground;
+mod global_hotkeys;
+mod health;
+mod hooks;
+mod hotkey_editor;
+mod insights;
+mod instance;
+mod network;
+mod osd;
+mod pipe_server;
+mod power_policy;
+mod profile_editor;
+mod remap;
+mod routing_snapshot;
+mod rule_editor;
+mod scripting;
+mod settings_window;
+mod tray_icon;
+mod tray_wheel;
+mod volume_popup;
+mod warm_standby;
 
-/// Sets the default audio endpoint for the specified role using raw COM interface calls
-fn set_default_endpoint(device_id: &str, role: ERole) -> Result<(), Box<dyn Error>> {
-    unsafe {
-        debug!("Attempting to set default endpoint for device: {device_id}, role: {role:?}",);
-        let policy_config: IPolicyConfig =
-            CoCreateInstance(&policy_config::CLSID_POLICY_CONFIG, None, CLSCTX_ALL)?;
+use audio_service::WM_AUDIO_SERVICE_RESTARTED;
+use behavior_page::{Behavior, WM_SETTINGS_BEHAVIOR_CHANGED};
+use changelog::Release;
+use console::{status, Status};
+use crash_loop::StartupMarker;
+use dialogs::{Icon, TaskDialog};
+use drawn_icons::Glyph;
+use elevation::{ElevatedTask, Finished, WM_ELEVATED_TASK_FINISHED};
+use flyout::{FlyoutDevice, WM_FLYOUT_DEVICE_CHOSEN, WM_FLYOUT_VOLUME_CHANGED};
+use foreground::WM_FOREGROUND_APP_CHANGED;
+use global_hotkeys::{
+    GlobalHotkeys, FIRST_DEVICE_HOTKEY_ID, FIRST_PROFILE_HOTKEY_ID, MUTE_MICROPHONE_HOTKEY_ID,
+    NEXT_DEVICE_HOTKEY_ID, PREVIOUS_DEVICE_HOTKEY_ID, SWAP_DEVICES_HOTKEY_ID,
+};
+use health::Problem;
+use hotkey_editor::WM_SETTINGS_HOTKEYS_CHANGED;
+use insights::{History, Switch};
+use instance::Instance;
+use network::{NetworkNotifications, WM_NETWORK_CHANGED};
+use osd::Osd;
+use pipe_server::{Exchange, PipeServer, WM_PIPE_REQUEST};
+use power_policy::{PowerNotifications, PowerPolicy};
+use profile_editor::{ProfileChanges, WM_SETTINGS_PROFILES_CHANGED};
+use routing_snapshot::RoutingSnapshot;
+use rule_editor::{RuleTest, WM_SETTINGS_RULES_CHANGED, WM_SETTINGS_TEST_RULE};
+use scripting::{Event, ScriptCommand, ScriptDevice, ScriptState, Scripts};
+use settings_window::{WM_SETTINGS_CLOSING, WM_SETTINGS_ORDER_CHANGED};
+use sound_switcheroo::actions::Action;
+use sound_switcheroo::backend::{AudioBackend, AudioDevice, AudioSession, ComBackend, Effects};
+use sound_switcheroo::bail;
+use sound_switcheroo::cli::{self, DeviceListing, ErrorKind, Failure, OutputFormat, Role};
+use sound_switcheroo::com::ComApartment;
+use sound_switcheroo::config::{
+    self, ClickAction, Config, HotkeyConfig, MachineConfig, NightModeConfig,
+};
+use sound_switcheroo::endpoint_notifications::{
+    EndpointChange, EndpointNotifications, VolumeNotifications,
+};
+use sound_switcheroo::endpoints::{set_default_device, EndpointList};
+use sound_switcheroo::error::SwitcherooError;
+use sound_switcheroo::ipc_auth::{self, Authenticator};
+use sound_switcheroo::logging;
+use sound_switcheroo::menu::{
+    self, status_label, volume_label, Endpoints, MenuEntry, MenuIds, MenuKey, MenuModel,
+    POPUP_ABOUT_ID, POPUP_EXIT_ID, POPUP_FOCUSED_APP_ID, POPUP_INCLUDE_HANDS_FREE_ID,
+    POPUP_INCLUDE_HDMI_ID, POPUP_INCLUDE_VIRTUAL_ID, POPUP_INSIGHTS_ID, POPUP_MIXER_ID,
+    POPUP_MUTE_ID, POPUP_NIGHT_LIMIT_ID, POPUP_OPEN_LOG_ID, POPUP_PROBLEMS_ID,
+    POPUP_RESTART_AUDIO_ID, POPUP_SAVE_PROFILE_ID, POPUP_SETTINGS_ID, POPUP_SNAPSHOT_ID,
+    POPUP_STATUS_ID, POPUP_SWITCH_COMMUNICATIONS_ID, POPUP_SWITCH_MIC_ID, POPUP_VOICEMEETER_ID,
+    POPUP_VOLUME_DOWN_ID, POPUP_VOLUME_ID, POPUP_VOLUME_UP_ID, VOLUME_STEP,
+};
+use sound_switcheroo::plugins::{self, Plugin, WM_PLUGINS_DISCOVERED, WM_PLUGIN_FINISHED};
+use sound_switcheroo::profiles::{self, Profile, ProfileIcon};
+use sound_switcheroo::recording::RecordingBackend;
+use sound_switcheroo::rotation;
+use sound_switcheroo::rules::{self, Rule, RuleAction, RulePart};
+use sound_switcheroo::safe_strings::{self, with_wide_str};
+use sound_switcheroo::simulation::{SimulatedBackend, Snapshot};
+use sound_switcheroo::switcher::{SwitchHost, Switcher};
+use sound_switcheroo::tooltip::{self, TooltipValues};
+use sound_switcheroo::window_placement;
+use tray_icon::TrayIconId;
+use tray_wheel::{Notches, WM_TRAY_WHEEL};
+use volume_popup::{WM_BALANCE_CHANGED, WM_VOLUME_CHANGED, WM_VOLUME_POPUP_MOVED};
+use warm_standby::{SwitchTimings, WarmStandby};
 
-        // Use safe scoped approach for string conversion
-        with_wide_str(device_id, |wide_device_id| {
-            policy_config.SetDefaultEndpoint(wide_device_id, role)
-        })?;
-        Ok(())
-    }
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Print the output devices and exit instead of starting the tray icon.
+    #[arg(long)]
+    list: bool,
+    /// Switch to an output device, by ID or (part of) its name, and exit.
+    #[arg(long, value_name = "NAME_OR_ID", conflicts_with_all = ["list", "next"])]
+    set: Option<String>,
+    /// Switch to the next device in the rotation and exit.
+    #[arg(long, conflicts_with = "list")]
+    next: bool,
+    /// Save the default devices for every role and every device's volume and mute under a name,
+    /// and exit.
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["list", "set", "next"])]
+    snapshot_audio: Option<String>,
+    /// Put the devices and volumes back as they were in a snapshot saved with --snapshot-audio,
+    /// and exit.
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["list", "set", "next", "snapshot_audio"])]
+    restore_audio: Option<String>,
+    /// Which default device --list, --set and --next use. Without it, switching sets the roles
+    /// chosen in the menu, and listing shows the console default.
+    #[arg(long, value_enum)]
+    role: Option<Role>,
+    /// How to print listings.
+    #[arg(long, value_enum, default_value_t)]
+    output: OutputFormat,
+    /// Use a fake device list from a JSON file instead of the real audio devices. Unless --config
+    /// is given, it runs as a separate instance with a scratch config, leaving the real one alone.
+    #[arg(long, value_name = "DEVICES_JSON")]
+    simulate: Option<PathBuf>,
+    /// Write the devices the app sees to a JSON file that can be replayed with --simulate.
+    #[arg(long, value_name = "FIXTURE_JSON")]
+    record: Option<PathBuf>,
+    /// Reproduce a user's machine from a diagnostics snapshot: simulate its devices, and use a
+    /// scratch copy of its config as a separate instance.
+    #[arg(long, value_name = "SNAPSHOT_JSON", conflicts_with_all = ["simulate", "config"])]
+    import_snapshot: Option<PathBuf>,
+    /// Run as a separate instance with its own tray icon, e.g. alongside one using another config.
+    #[arg(long, value_name = "NAME")]
+    instance: Option<String>,
+    /// Run without a tray icon or menus, e.g. on kiosks that only want automatic device policy.
+    /// Stop it with `sound-switcheroo quit`.
+    #[arg(long)]
+    no_tray: bool,
+    /// Use this config file instead of the one in %APPDATA%.
+    #[arg(long, value_name = "PATH", env = "AUDIOSWITCH_CONFIG")]
+    config: Option<PathBuf>,
+    /// Show status and log output in the terminal the app was started from (or a new console).
+    #[arg(long)]
+    console: bool,
+    /// Report errors on stderr as a line of JSON instead of text.
+    #[arg(long)]
+    json_errors: bool,
 }
 
-/// Gets the current default audio endpoint for debugging
-fn get_current_default_endpoint(role: ERole) -> Result<String, Box<dyn Error>> {
-    unsafe {
-        CoInitializeEx(None, COINIT_APARTMENTTHREADED).ok()?;
-        let device_enumerator: IMMDeviceEnumerator =
-            CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
-
-        let endpoint = device_enumerator
-            .GetDefaultAudioEndpoint(windows::Win32::Media::Audio::eRender, role)?;
-
-        let device_id = endpoint.GetId()?;
-        let device_id_str = device_id.to_string()?;
+#[derive(Subcommand)]
+enum Command {
+    /// Print a shell completion script, e.g. `sound-switcheroo completions powershell >> $PROFILE`.
+    Completions { shell: Shell },
+    /// Close the running app (the one for --instance or --config, if given), e.g. when Exit is
+    /// hidden from its menu.
+    Quit,
+    /// Carry out a task that needs administrator rights for the copy of the app that started this
+    /// one elevated.
+    #[command(hide = true)]
+    Elevated {
+        #[arg(long)]
+        pipe: String,
+        #[arg(long)]
+        parent: u32,
+    },
+}
 
-        Ok(device_id_str)
+impl Args {
+    // Whether the app was run as a command line tool, with output to show.
+    fn prints_output(&self) -> bool {
+        self.list
+            || self.set.is_some()
+            || self.next
+            || self.snapshot_audio.is_some()
+            || self.restore_audio.is_some()
+            || matches!(
+                self.command,
+                Some(Command::Completions { .. } | Command::Quit)
+            )
     }
 }
 
-fn string_to_tip(s: &str) -> [u16; 128] {
-    let mut ret = [0u16; 128];
-    let encoded: Vec<u16> = s.encode_utf16().collect();
-    assert!(encoded.len() < ret.len());
-    for (i, &c) in encoded.iter().enumerate() {
-        ret[i] = c;
+/// The tooltip for `device` per the config's `tooltip` template, reading only what it uses.
+/// `volume` is used when given rather than reading it again, e.g. just after changing it.
+fn device_tooltip(
+    template: &str,
+    backend: &dyn AudioBackend,
+    device: &AudioDevice,
+    volume: Option<u32>,
+    decimal_separator: &str,
+) -> [u16; 128] {
+    if template == tooltip::DEFAULT_TEMPLATE {
+        return safe_strings::truncated_from_wide(device.friendly_name.wide());
+    }
+    let mut values = TooltipValues {
+        name: &device.friendly_name,
+        volume,
+        sample_rate: device.mix_format.map(|format| format.sample_rate),
+    };
+    if volume.is_none() && tooltip::uses(template, "volume") {
+        values.volume = backend
+            .volume(&device.id)
+            .inspect_err(|e| debug!("No volume for the tooltip: {e}"))
+            .ok()
+            .map(|volume| (volume * 100.0).round() as u32);
     }
-    ret[encoded.len()] = 0; // Null-terminate the string
-    ret
+    safe_strings::truncated_wide(&tooltip::format(template, &values, decimal_separator))
 }
 
 #[derive(Debug)]
+// Each variant is only loaded the first time it's needed, to keep startup fast.
 struct AdaptiveIcon {
-    light: HICON,
-    dark: HICON,
+    source: IconSource,
+    light: OnceCell<HICON>,
+    dark: OnceCell<HICON>,
+}
+
+#[derive(Debug)]
+enum IconSource {
+    // Icon resources for light and dark themes, by name.
+    Resources(&'static str, &'static str),
+    Drawn(Glyph),
 }
 
 impl AdaptiveIcon {
-    pub fn new(light_icon_name: &str, dark_icon_name: &str) -> Result<Self, Box<dyn Error>> {
-        let light_icon = unsafe { load_icon(light_icon_name)? };
-        let dark_icon = unsafe { load_icon(dark_icon_name)? };
-        Ok(Self {
-            light: light_icon,
-            dark: dark_icon,
-        })
+    pub const fn new(light_icon_name: &'static str, dark_icon_name: &'static str) -> Self {
+        Self::from_source(IconSource::Resources(light_icon_name, dark_icon_name))
     }
 
-    pub fn icon(&self) -> Result<HICON, Box<dyn Error>> {
-        if is_dark_mode()? {
-            Ok(self.dark)
-        } else {
-            Ok(self.light)
+    /// An icon drawn at runtime, for outputs the executable has no icons for.
+    pub const fn drawn(glyph: Glyph) -> Self {
+        Self::from_source(IconSource::Drawn(glyph))
+    }
+
+    const fn from_source(source: IconSource) -> Self {
+        Self {
+            source,
+            light: OnceCell::new(),
+            dark: OnceCell::new(),
+        }
+    }
+
+    pub fn icon(&self) -> Result<HICON, SwitcherooError> {
+        let dark_mode = is_dark_mode()?;
+        let cell = if dark_mode { &self.dark } else { &self.light };
+        if let Some(&icon) = cell.get() {
+            return Ok(icon);
+        }
+        let icon = match self.source {
+            IconSource::Resources(light_name, dark_name) => {
+                let name = if dark_mode { dark_name } else { light_name };
+                // Better something in the tray than failing to start.
+                unsafe { load_icon(name) }.or_else(|e| {
+                    warn!("Drawing a placeholder for icon {name}: {e}");
+                    drawn_icons::create(Glyph::Placeholder, dark_mode)
+                })?
+            }
+            IconSource::Drawn(glyph) => drawn_icons::create(glyph, dark_mode)?,
+        };
+        Ok(*cell.get_or_init(|| icon))
+    }
+
+    /// The names of the icons that fail to load, for either theme. Placeholders are drawn for them.
+    fn missing(&self) -> Vec<&'static str> {
+        match self.source {
+            IconSource::Resources(light_name, dark_name) => [light_name, dark_name]
+                .into_iter()
+                .filter(|name| unsafe { load_icon(name) }.is_err())
+                .collect(),
+            IconSource::Drawn(_) => Vec::new(),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct AudioDevice {
-    id: String,
-    friendly_name: String,
-    // Whether this device will be included in the rotation.
-    selectable: bool,
-    #[serde(skip)]
-    form_factor: EndpointFormFactor,
+// A profile's own tray icon.
+enum ProfileTrayIcon {
+    // Loaded from a file, and destroyed along with the ActiveProfileIcon.
+    File(HICON),
+    // The icon for this kind of device.
+    BuiltIn(EndpointFormFactor),
+}
+
+/// The icon of the profile last applied, shown in the tray while the output it left as the default
+/// stays the default.
+struct ActiveProfileIcon {
+    output: String,
+    icon: ProfileTrayIcon,
+}
+
+impl Drop for ActiveProfileIcon {
+    fn drop(&mut self) {
+        if let ProfileTrayIcon::File(icon) = self.icon {
+            let _ = unsafe { DestroyIcon(icon) };
+        }
+    }
 }
 
-#[derive(Debug)]
 struct AudioSwitch {
     window: HWND,
+    // False in --no-tray mode, where there is no icon to update.
+    show_tray: bool,
+    notify_icon: TrayIconId,
     icon: AdaptiveIcon,
-    popup_menu: HMENU,
-    available_devices: Vec<AudioDevice>,
+    // Set by applying a profile with an icon, and cleared by switching.
+    profile_icon: Option<ActiveProfileIcon>,
+    // Built the first time it's shown.
+    popup_menu: Option<HMENU>,
+    settings_window: Option<HWND>,
+    // The default output and microphone when the menu was built, as it marks them.
+    popup_menu_defaults: (String, String),
+    // The "Playing" submenu, listing the apps with audio open each time it opens.
+    popup_menu_playing: Option<HMENU>,
+    // The routing snapshots saved when the menu was built, newest first, as it offers them.
+    popup_menu_snapshots: Vec<String>,
+    // What the menu's entries for devices, profiles and the like are for, by the IDs they were
+    // given when it was built.
+    popup_menu_ids: MenuIds,
+    // The outputs, and switching between them.
+    switcher: Switcher,
+    // Capture endpoints, as of the last time the menu was shown or the microphone was switched.
+    microphones: Vec<AudioDevice>,
+    config: Config,
+    config_path: PathBuf,
+    machine_config: MachineConfig,
+    // Started after repeated crashes, so the config is ignored (and never saved) and rules and
+    // hooks are off.
+    safe_mode: bool,
+    // Windows Audio was found stopped, so there are no devices until it's started again.
+    audio_service_stopped: bool,
+    // Found by the health check at startup, and explained by "What's wrong?" in the menu.
+    problems: Vec<Problem>,
+    // The hotkeys in the config's `hotkeys`. Registered again whenever they change.
+    hotkeys: Option<GlobalHotkeys>,
+    // The devices the per-device hotkeys switch to, by hotkey ID from FIRST_DEVICE_HOTKEY_ID.
+    device_hotkeys: Vec<String>,
+    // The profiles' hotkeys, by hotkey ID from FIRST_PROFILE_HOTKEY_ID and index in the config's
+    // profiles. Registered again whenever the profiles change.
+    profile_hotkeys: Option<GlobalHotkeys>,
+    // (device, output before it) when a rule switched away on the device connecting and should
+    // switch back once it's disconnected.
+    revert_to: Option<(String, String)>,
+    // When the tray icon was last double clicked, to ignore the click that ends it.
+    last_double_click: Option<Instant>,
+    // Switches made, for Usage insights.
+    history: History,
+    // The local date and time scheduled rules were last run for, so each runs once.
+    schedule_checked: Option<(String, String)>,
+    // The networks connected at the last change, so network rules only run for ones just joined.
+    networks: Vec<String>,
+    // The processes whose audio was sent elsewhere while only the focused app was switched, to
+    // send back when that's turned off.
+    routed_apps: Vec<u32>,
+    // Whether Windows said the machine was docked at the last device change.
+    docked: bool,
+    // Whether it was night for night mode at the last check, and whether the limit has been
+    // lifted from the menu until the night is over.
+    night: bool,
+    night_limit_lifted: bool,
+    // The current output's volume, while the night limit applies to it.
+    night_volume: Option<VolumeNotifications>,
+    // Taken out of the rotation for going unused, until the notification saying so is clicked.
+    paused_devices: Vec<String>,
+    // Releases whose features the "updated" notification offers to show when clicked.
+    whats_new: &'static [Release],
+    // What the notification last shown does when clicked, if anything.
+    pending_click: Cell<Option<NotificationKind>>,
+    // Whether `WM_OFFER_REMAP` has been posted and not yet answered, so it's only asked once.
+    remap_offered: bool,
+    // Whether the new device dialog is open, holding off device refreshes and further prompts.
+    asking_about_devices: bool,
+    // Created the first time a switch is shown.
+    osd: Option<Osd>,
+    // Found in the plugins directory at startup, with the menu items they add, once they've all
+    // answered.
+    plugins: Vec<Plugin>,
+    // Loaded from the scripts directory, and again whenever they change. None in safe mode.
+    scripts: Option<Scripts>,
+    // Set while carrying out what scripts asked for, so they don't set off their own events.
+    running_scripts: bool,
+    // Open on the output the next switch will most likely go to, if `warm_standby` is on.
+    standby: Option<WarmStandby>,
+    // How long switching has taken while `warm_standby` is on, to show what the standby saves.
+    switch_timings: SwitchTimings,
+    power_policy: PowerPolicy,
+    // Whether the balance was changed with the slider since the config was last saved.
+    balance_unsaved: bool,
+    // Smooth scrolling over the tray icon, until it adds up to a notch.
+    wheel_notches: Notches,
+    // From the regional settings, for the tooltip's `{rate}`. Read again when they change.
+    decimal_separator: String,
 
     headphones_icon: AdaptiveIcon,
     headset_icon: AdaptiveIcon,
     speaker_icon: AdaptiveIcon,
+    // Drawn, as there are no icons for these in the executable.
+    display_icon: AdaptiveIcon,
+    line_level_icon: AdaptiveIcon,
+    spdif_icon: AdaptiveIcon,
+    unknown_icon: AdaptiveIcon,
 }
 
 impl Drop for AudioSwitch {
     fn drop(&mut self) {
-        unsafe {
-            let _ = DestroyMenu(self.popup_menu);
+        if let Some(popup_menu) = self.popup_menu {
+            unsafe {
+                let _ = DestroyMenu(popup_menu);
+            }
         }
     }
 }
 
 impl AudioSwitch {
     #![allow(non_upper_case_globals)]
+    /// Picks the icon for a kind of device, using the closest there is for the kinds without one
+    /// of their own.
     fn icon_for_form_factor(
         &self,
         form_factor: EndpointFormFactor,
-    ) -> Result<HICON, Box<dyn Error>> {
+    ) -> Result<HICON, SwitcherooError> {
         let adaptive_icon = match form_factor {
             Headphones => &self.headphones_icon,
-            Headset => &self.headset_icon,
-            Speakers => &self.speaker_icon,
-            _ => &self.icon, // Default icon for other form factors
+            // Things held to the face or worn, which are always headsets for output.
+            Headset | Handset | Microphone => &self.headset_icon,
+            // Network endpoints are usually speakers somewhere else.
+            Speakers | RemoteNetworkDevice => &self.speaker_icon,
+            DigitalAudioDisplayDevice => &self.display_icon,
+            LineLevel => &self.line_level_icon,
+            SPDIF | UnknownDigitalPassthrough => &self.spdif_icon,
+            UnknownFormFactor => &self.unknown_icon,
+            _ => &self.icon, // A value newer than this app, or a broken driver
         };
         adaptive_icon.icon()
     }
 
-    fn current_icon(&self) -> Result<HICON, Box<dyn Error>> {
-        let current_device_id = get_current_default_endpoint(eConsole)?;
+    /// The icon for the tray while `device` is the default: the active profile's, if it has one
+    /// and left `device` as the default, otherwise the one for the kind of device.
+    fn tray_icon(&self, device: &AudioDevice) -> Result<HICON, SwitcherooError> {
+        match &self.profile_icon {
+            Some(profile_icon) if profile_icon.output == device.id => match profile_icon.icon {
+                ProfileTrayIcon::File(icon) => Ok(icon),
+                ProfileTrayIcon::BuiltIn(form_factor) => self.icon_for_form_factor(form_factor),
+            },
+            _ => self.icon_for_form_factor(device.form_factor),
+        }
+    }
+
+    fn current_icon(&self) -> Result<HICON, SwitcherooError> {
+        if self.audio_service_stopped || !self.problems.is_empty() {
+            return Ok(unsafe { LoadIconW(None, IDI_WARNING)? });
+        }
+        let current_device_id = self.switcher.backend().default_device(eRender, eConsole)?;
+        let current_device = self
+            .switcher
+            .outputs()
+            .iter()
+            .find(|d| d.id == current_device_id)
+            .ok_or_else(|| SwitcherooError::Other("Current device not found".to_string()))?;
+        self.tray_icon(current_device)
+    }
+
+    /// The tooltip for the default device, or for why there isn't one.
+    fn current_tooltip(&self) -> [u16; 128] {
+        if self.audio_service_stopped {
+            return safe_strings::truncated_wide(AUDIO_SERVICE_STOPPED);
+        }
         let current_device = self
-            .available_devices
+            .switcher
+            .backend()
+            .default_device(eRender, eConsole)
+            .ok()
+            .and_then(|id| self.switcher.outputs().iter().find(|d| d.id == id));
+        match current_device {
+            Some(device) => self.tooltip(device),
+            None => safe_strings::truncated_wide(NO_OUTPUT_DEVICES),
+        }
+    }
+
+    /// What the tray icon is added with, apart from how it's identified.
+    fn notify_icon_data(&self, icon: HICON, tooltip: [u16; 128]) -> NOTIFYICONDATAW {
+        NOTIFYICONDATAW {
+            hIcon: icon,
+            // Both NIF_TIP & NIF_SHOWTIP are required to actually show the tooltip.
+            uFlags: NIF_ICON | NIF_MESSAGE | NIF_TIP | NIF_SHOWTIP,
+            uCallbackMessage: TASKBAR_CB_ID,
+            szTip: tooltip,
+            ..self.notify_icon.data(self.window)
+        }
+    }
+
+    /// Opts the tray icon into the better callback API, once it's been added.
+    fn set_notify_icon_version(&self) -> Result<(), SwitcherooError> {
+        unsafe {
+            Shell_NotifyIconW(
+                NIM_SETVERSION,
+                &NOTIFYICONDATAW {
+                    Anonymous: NOTIFYICONDATAW_0 {
+                        uVersion: NOTIFYICON_VERSION_4,
+                    },
+                    ..self.notify_icon.data(self.window)
+                },
+            )
+            .ok()?;
+        }
+        Ok(())
+    }
+
+    /// Adds the tray icon back as it was, after Explorer restarted and took every icon with it.
+    fn restore_tray_icon(&self) -> Result<(), SwitcherooError> {
+        if !self.show_tray {
+            return Ok(());
+        }
+        info!("The taskbar was recreated, adding the tray icon back");
+        tray_icon::restore(
+            &self.notify_icon_data(self.current_icon()?, self.current_tooltip()),
+            self.notify_icon,
+        )?;
+        self.set_notify_icon_version()?;
+        if self.microphone_muted() {
+            // For the slashed icon.
+            self.show_current_device()?;
+        }
+        Ok(())
+    }
+
+    /// Brings the tray icon and tooltip up to date with the default device, e.g. after it was
+    /// changed in Windows' sound settings.
+    fn show_current_device(&self) -> Result<(), SwitcherooError> {
+        let current_device_id = self.switcher.backend().default_device(eRender, eConsole)?;
+        let Some(device) = self
+            .switcher
+            .outputs()
             .iter()
             .find(|d| d.id == current_device_id)
-            .ok_or_else(|| simple_error::SimpleError::new("Current device not found"))?;
-        self.icon_for_form_factor(current_device.form_factor)
+        else {
+            debug!("Default device {current_device_id} isn't one of ours yet");
+            return Ok(());
+        };
+        self.update_tray_icon(self.tray_icon(device)?, self.tooltip(device))
+    }
+
+    /// Refreshes the devices shortly after the last of a burst of device changes, e.g. from
+    /// connecting a USB hub, rather than once per change.
+    fn schedule_device_refresh(&self) {
+        unsafe {
+            // Setting the timer again restarts it.
+            SetTimer(
+                Some(self.window),
+                DEVICE_REFRESH_TIMER_ID,
+                DEVICE_REFRESH_DELAY_MS,
+                None,
+            );
+        }
+    }
+
+    /// Re-enumerates the devices, picking up new ones and dropping missing ones.
+    fn refresh_devices(&mut self) -> Result<(), SwitcherooError> {
+        if self.asking_about_devices {
+            // Tried again once the dialog has been answered.
+            self.schedule_device_refresh();
+            return Ok(());
+        }
+        unsafe {
+            let _ = KillTimer(Some(self.window), DEVICE_REFRESH_TIMER_ID);
+        }
+        // Everything would look newly connected after the devices were unavailable.
+        let after_outage = self.devices_unavailable();
+        let refresh = self.switcher.refresh(&mut self.config, after_outage)?;
+        self.invalidate_popup_menu();
+        self.update_settings_window();
+        if !refresh.renamed.is_empty() {
+            // For the names kept in the config and the tooltip.
+            self.save_config()?;
+            self.show_current_device()?;
+        }
+        if self.audio_service_stopped {
+            info!("Windows Audio is back");
+            self.audio_service_stopped = false;
+            self.show_current_device()?;
+        }
+        if !self.switcher.outputs().is_empty() && self.devices_unavailable() {
+            info!("Found output devices again");
+            self.problems.retain(|problem| !problem.is_about_devices());
+            self.show_current_device()?;
+        }
+        // Asked once the refresh is done, as the dialog waits for an answer. New devices wait for
+        // it too, as they may just be old ones under new IDs.
+        let remapping = self.remap_offered || !self.remaps().is_empty();
+        if remapping && !self.remap_offered {
+            unsafe {
+                PostMessageW(
+                    Some(self.window),
+                    WM_OFFER_REMAP,
+                    WPARAM::default(),
+                    LPARAM::default(),
+                )?;
+            }
+            self.remap_offered = true;
+        }
+        if refresh.new_devices {
+            self.save_config()?;
+            if !remapping {
+                self.notify_new_devices()?;
+            }
+        }
+        if !self.safe_mode {
+            // Only when outputs come or go, so choosing another device by hand sticks.
+            if refresh.outputs_changed()
+                && let Err(e) = self.switch_to_highest_priority()
+            {
+                error!("Failed to switch to the highest priority device: {e}");
+            }
+            self.run_connected_rules(&refresh.connected);
+            self.run_dock_rules();
+            for device_id in &refresh.connected {
+                if let Some(device) = self
+                    .switcher
+                    .outputs()
+                    .iter()
+                    .find(|d| &d.id == device_id)
+                    .cloned()
+                {
+                    self.fire_script_event(Event::DeviceAdded, &device);
+                }
+            }
+            if let Err(e) = self.revert_disconnected() {
+                error!("Failed to switch back after a device was disconnected: {e}");
+            }
+        }
+        // The device kept warm may have gone, or another come before it in the rotation.
+        self.prepare_standby();
+        self.pause_unused_devices()
+    }
+
+    /// Carries out the rules triggered by the devices with these IDs connecting.
+    fn run_connected_rules(&mut self, connected: &[String]) {
+        for device_id in connected {
+            let triggered: Vec<Rule> = rules::connected(&self.config.rules, device_id)
+                .cloned()
+                .collect();
+            for rule in triggered {
+                let previous = self.current_output().ok();
+                match self.run_rule_action(&rule.action) {
+                    Ok(outcome) => {
+                        info!("Rule \"{}\": when {}, {outcome}", rule.name, rule.trigger);
+                        if rule.revert
+                            && let Some(previous) = previous
+                            && &previous != device_id
+                        {
+                            self.revert_to = Some((device_id.clone(), previous));
+                        }
+                    }
+                    Err(e) => error!("Rule \"{}\" failed: {e}", rule.name),
+                }
+            }
+        }
+    }
+
+    /// Runs the rules scheduled for the current minute, once each.
+    fn run_scheduled_rules(&mut self) {
+        let now = rules::local_time();
+        if self.schedule_checked.as_ref() == Some(&now) {
+            return;
+        }
+        let triggered: Vec<Rule> = rules::scheduled(&self.config.rules, &now.1)
+            .cloned()
+            .collect();
+        self.schedule_checked = Some(now);
+        self.run_rules(triggered);
+    }
+
+    /// Runs the rules set off by the app with this process ID coming to the foreground.
+    fn run_foreground_rules(&mut self, pid: u32) {
+        if self.safe_mode || !rules::watches_foreground(&self.config.rules) {
+            return;
+        }
+        let executable = match ipc_auth::process_executable(pid) {
+            Ok(path) => path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            Err(e) => {
+                debug!("Couldn't find process {pid}'s executable: {e}");
+                return;
+            }
+        };
+        let triggered: Vec<Rule> = rules::foreground(&self.config.rules, &executable)
+            .cloned()
+            .collect();
+        self.run_rules(triggered);
+    }
+
+    /// Runs the rules set off by joining networks that weren't connected at the last change.
+    fn run_network_rules(&mut self) {
+        let networks = match network::connected_networks() {
+            Ok(networks) => networks,
+            Err(e) => {
+                error!("Failed to list the connected networks: {e}");
+                return;
+            }
+        };
+        let joined: Vec<String> = networks
+            .iter()
+            .filter(|name| !self.networks.contains(name))
+            .cloned()
+            .collect();
+        self.networks = networks;
+        for name in joined {
+            info!("Connected to network \"{name}\"");
+            let triggered: Vec<Rule> = rules::network_connected(&self.config.rules, &name)
+                .cloned()
+                .collect();
+            self.run_rules(triggered);
+        }
+    }
+
+    /// Runs the dock rules if the machine has been docked or undocked since the last check.
+    fn run_dock_rules(&mut self) {
+        let docked = system_docked();
+        if docked == self.docked {
+            return;
+        }
+        info!("{}", if docked { "Docked" } else { "Undocked" });
+        self.docked = docked;
+        let triggered: Vec<Rule> = rules::dock_changed(&self.config.rules, docked)
+            .cloned()
+            .collect();
+        self.run_rules(triggered);
+    }
+
+    // Carries out each rule's action, logging what happened.
+    fn run_rules(&mut self, triggered: Vec<Rule>) {
+        for rule in triggered {
+            match self.run_rule_action(&rule.action) {
+                Ok(outcome) => info!("Rule \"{}\": when {}, {outcome}", rule.name, rule.trigger),
+                Err(e) => error!("Rule \"{}\" failed: {e}", rule.name),
+            }
+        }
+    }
+
+    /// Switches back to the output a rule switched away from, if the device that set it off has
+    /// been disconnected.
+    fn revert_disconnected(&mut self) -> Result<(), SwitcherooError> {
+        let Some((device_id, previous)) = self
+            .revert_to
+            .take_if(|(device_id, _)| !self.switcher.outputs().iter().any(|d| &d.id == device_id))
+        else {
+            return Ok(());
+        };
+        let Some(device) = self
+            .switcher
+            .outputs()
+            .iter()
+            .find(|d| d.id == previous)
+            .cloned()
+        else {
+            info!("{device_id} was disconnected, but {previous} has gone too");
+            return Ok(());
+        };
+        info!(
+            "{device_id} was disconnected, so switching back to {}",
+            device.friendly_name
+        );
+        self.dispatch(Action::SwitchTo(device.id))
+    }
+
+    // Whether there are no devices to show, as Windows Audio is stopped or listing them failed.
+    fn devices_unavailable(&self) -> bool {
+        self.audio_service_stopped || self.problems.iter().any(Problem::is_about_devices)
+    }
+
+    // Rebuilt from the current state next time it's shown.
+    fn invalidate_popup_menu(&mut self) {
+        self.popup_menu_playing = None;
+        if let Some(popup_menu) = self.popup_menu.take() {
+            unsafe {
+                let _ = DestroyMenu(popup_menu);
+            }
+        }
     }
 
-    fn show_popup_menu(&self, x: i32, y: i32) -> Result<(), Box<dyn Error>> {
+    fn show_popup_menu(&mut self, x: i32, y: i32) -> Result<(), SwitcherooError> {
         debug!("Showing popup menu at ({x}, {y})");
         unsafe {
-            // Highlight the current device in the popup menu.
-            let current_device_id = get_current_default_endpoint(eConsole)?;
-            let current_device = self
-                .available_devices
-                .iter()
-                .find(|d| d.id == current_device_id)
-                .ok_or_else(|| simple_error::SimpleError::new("Current device not found"))?;
-
-            safe_strings::with_wide_str_mut(
-                &current_device.friendly_name,
-                |current_name| -> Result<(), Box<dyn Error>> {
-                    let mut mii = MENUITEMINFOW {
-                        cbSize: std::mem::size_of::<MENUITEMINFOW>() as u32,
-                        fMask: MIIM_STATE,
-                        ..Default::default()
+            // Mark the current device in the popup menu.
+            let current_device_id = if self.devices_unavailable() {
+                String::new()
+            } else {
+                self.current_output()?
+            };
+            let current_microphone_id = self.refresh_microphones();
+            let defaults = (current_device_id, current_microphone_id);
+            if defaults != self.popup_menu_defaults {
+                self.invalidate_popup_menu();
+            }
+            // Snapshots can also be taken from the command line.
+            let snapshots = routing_snapshot::names(&self.snapshots_dir());
+            if snapshots != self.popup_menu_snapshots {
+                self.invalidate_popup_menu();
+            }
+            let (current_device_id, current_microphone_id) = &defaults;
+            let microphones = Endpoints {
+                devices: &self.microphones,
+                current_id: current_microphone_id,
+            };
+            let volume_percent = self
+                .switcher
+                .backend()
+                .volume(current_device_id)
+                .inspect_err(|e| error!("Failed to get volume: {e}"))
+                .ok()
+                .map(|volume| (volume * 100.0).round() as u32);
+            let muted = self
+                .switcher
+                .backend()
+                .muted(current_device_id)
+                .unwrap_or(false);
+            let popup_menu = match self.popup_menu {
+                Some(popup_menu) => popup_menu,
+                None => {
+                    let model = if self.safe_mode {
+                        MenuModel::safe_mode(
+                            self.switcher.outputs(),
+                            current_device_id,
+                            &microphones,
+                            volume_percent,
+                        )
+                    } else {
+                        changelog::expire_badges(&mut self.config.whats_new, SystemTime::now());
+                        MenuModel::build(
+                            &self.included_devices(current_device_id),
+                            current_device_id,
+                            &microphones,
+                            &self.config,
+                            &self.machine_config,
+                            &self.plugins,
+                            volume_percent,
+                        )
                     };
-                    GetMenuItemInfoW(self.popup_menu, POPUP_CURRENT_DEVICE_ID, false, &mut mii)?;
-                    mii.fMask = MIIM_STRING;
-                    mii.dwTypeData = current_name;
-                    mii.dwItemData = current_device.friendly_name.chars().count();
-                    SetMenuItemInfoW(self.popup_menu, POPUP_CURRENT_DEVICE_ID, false, &mii)?;
-                    Ok(())
-                },
-            )?;
+                    let model = if self.problems.is_empty() {
+                        model
+                    } else {
+                        model.with_problems()
+                    }
+                    // Listing the sessions waits until the submenu is opened.
+                    .with_sessions(&[])
+                    .with_snapshots(&snapshots);
+                    let popup_menu = create_popup_menu(&model.entries)?;
+                    self.popup_menu = Some(popup_menu);
+                    self.popup_menu_playing = model
+                        .playing
+                        .map(|index| GetSubMenu(popup_menu, index as i32));
+                    self.popup_menu_defaults = defaults;
+                    self.popup_menu_snapshots = snapshots;
+                    self.popup_menu_ids = model.ids;
+                    popup_menu
+                }
+            };
+
+            // Rotation changes don't rebuild the menu, so the status can be out of date.
+            let status = if self.audio_service_stopped {
+                AUDIO_SERVICE_STOPPED.to_string()
+            } else {
+                status_label(self.switcher.outputs())
+            };
+            set_menu_item_label(popup_menu, POPUP_STATUS_ID, &status)?;
+            set_menu_item_label(popup_menu, POPUP_VOLUME_ID, &volume_label(volume_percent))?;
+            set_menu_item_checked(popup_menu, POPUP_MUTE_ID, muted)?;
+            if self.config.night_mode.enabled && !self.safe_mode && !self.machine_config.kiosk {
+                set_menu_item_checked(popup_menu, POPUP_NIGHT_LIMIT_ID, !self.night_limit_lifted)?;
+            }
 
             // Required to ensure the popup menu disappears again when a user clicks elsewhere.
             SetForegroundWindow(self.window).ok()?;
             TrackPopupMenuEx(
-                self.popup_menu,
+                popup_menu,
                 TPM_LEFTALIGN.0 | TPM_BOTTOMALIGN.0 | TPM_RIGHTBUTTON.0,
                 x,
                 y,
@@ -215,8 +946,72 @@ impl AudioSwitch {
         Ok(())
     }
 
-    fn menu_selection(&mut self, id: u32) -> Result<(), Box<dyn Error>> {
+    // Lists the apps with audio open as the "Playing" submenu opens, rather than each time the menu
+    // does.
+    fn fill_playing_menu(&self, submenu: HMENU) -> Result<(), SwitcherooError> {
+        if self.popup_menu_playing != Some(submenu) {
+            return Ok(());
+        }
+        unsafe {
+            for _ in 0..GetMenuItemCount(Some(submenu)) {
+                DeleteMenu(submenu, 0, MF_BYPOSITION)?;
+            }
+            insert_menu_entries(submenu, &menu::session_entries(&self.sessions()))
+        }
+    }
+
+    /// The apps with audio open on each output that has any, by the output's name.
+    fn sessions(&self) -> Vec<(String, Vec<AudioSession>)> {
+        self.switcher
+            .outputs()
+            .iter()
+            .filter_map(|device| {
+                let sessions = self
+                    .switcher
+                    .backend()
+                    .sessions(&device.id)
+                    .inspect_err(|e| debug!("Failed to list sessions on {}: {e}", device.id))
+                    .ok()?;
+                (!sessions.is_empty()).then(|| (device.friendly_name.to_string(), sessions))
+            })
+            .collect()
+    }
+
+    fn menu_selection(&mut self, id: u32) -> Result<(), SwitcherooError> {
         debug!("Menu item selected: {id}");
+        // Selections can only come from the menu, so it has been built by now.
+        let Some(popup_menu) = self.popup_menu else {
+            return Ok(());
+        };
+        // Only About, the volume controls and the devices are left in the kiosk menu, but be sure
+        // nothing else gets changed.
+        let volume_control = [
+            POPUP_VOLUME_ID,
+            POPUP_MUTE_ID,
+            POPUP_VOLUME_UP_ID,
+            POPUP_VOLUME_DOWN_ID,
+        ]
+        .contains(&id)
+            || matches!(self.popup_menu_ids.key(id), Some(MenuKey::Volume(_)));
+        let device = matches!(
+            self.popup_menu_ids.key(id),
+            Some(MenuKey::Output(_) | MenuKey::Microphone(_))
+        );
+        if self.machine_config.kiosk
+            && !volume_control
+            && !device
+            && ![POPUP_ABOUT_ID, POPUP_PROBLEMS_ID].contains(&id)
+        {
+            return Ok(());
+        }
+        // Likewise for the safe mode menu, which also has Exit.
+        if self.safe_mode
+            && !volume_control
+            && !device
+            && ![POPUP_ABOUT_ID, POPUP_PROBLEMS_ID, POPUP_EXIT_ID].contains(&id)
+        {
+            return Ok(());
+        }
         unsafe {
             match id {
                 // Exit item selected.
@@ -228,6 +1023,78 @@ impl AudioSwitch {
                         LPARAM::default(),
                     )?;
                 }
+                POPUP_SWITCH_MIC_ID => {
+                    self.config.switch_microphone_with_output =
+                        !self.config.switch_microphone_with_output;
+                    set_menu_item_checked(
+                        popup_menu,
+                        POPUP_SWITCH_MIC_ID,
+                        self.config.switch_microphone_with_output,
+                    )?;
+                    self.update_settings_window();
+                    if let Err(e) = self.save_config() {
+                        error!("Failed to save config: {e}");
+                    }
+                }
+                POPUP_SWITCH_COMMUNICATIONS_ID => {
+                    self.config.switch_communications = !self.config.switch_communications;
+                    set_menu_item_checked(
+                        popup_menu,
+                        POPUP_SWITCH_COMMUNICATIONS_ID,
+                        self.config.switch_communications,
+                    )?;
+                    self.update_settings_window();
+                    if let Err(e) = self.save_config() {
+                        error!("Failed to save config: {e}");
+                    }
+                }
+                POPUP_FOCUSED_APP_ID => {
+                    self.config.switch_focused_app = !self.config.switch_focused_app;
+                    set_menu_item_checked(
+                        popup_menu,
+                        POPUP_FOCUSED_APP_ID,
+                        self.config.switch_focused_app,
+                    )?;
+                    self.watch_foreground();
+                    self.unroute_apps();
+                    self.update_settings_window();
+                    if let Err(e) = self.save_config() {
+                        error!("Failed to save config: {e}");
+                    }
+                }
+                POPUP_INCLUDE_HDMI_ID | POPUP_INCLUDE_HANDS_FREE_ID | POPUP_INCLUDE_VIRTUAL_ID => {
+                    let include = &mut self.config.include;
+                    let toggled = match id {
+                        POPUP_INCLUDE_HDMI_ID => &mut include.hdmi,
+                        POPUP_INCLUDE_HANDS_FREE_ID => &mut include.hands_free,
+                        _ => &mut include.virtual_devices,
+                    };
+                    *toggled = !*toggled;
+                    info!("Including {:?}", self.config.include);
+                    // Devices come and go from the menu.
+                    self.invalidate_popup_menu();
+                    if let Err(e) = self.save_config() {
+                        error!("Failed to save config: {e}");
+                    }
+                }
+                POPUP_VOICEMEETER_ID => {
+                    self.config.voicemeeter.enabled = !self.config.voicemeeter.enabled;
+                    set_menu_item_checked(
+                        popup_menu,
+                        POPUP_VOICEMEETER_ID,
+                        self.config.voicemeeter.enabled,
+                    )?;
+                    if let Err(e) = self.save_config() {
+                        error!("Failed to save config: {e}");
+                    }
+                }
+                POPUP_PROBLEMS_ID => {
+                    dialogs::error(
+                        None,
+                        "Sound Switcheroo started with problems",
+                        &health::report(&self.problems),
+                    )?;
+                }
                 POPUP_ABOUT_ID => {
                     ShellExecuteW(
                         None,
@@ -238,450 +1105,2384 @@ impl AudioSwitch {
                         SW_SHOWNORMAL,
                     );
                 }
-                // Device checked / unchecked in the popup menu.
-                device_menu_id => {
-                    let device = self
-                        .available_devices
-                        .iter_mut()
-                        .find(|device| device_menu_id == device_id_to_menu_id(&device.id));
-                    match device {
-                        None => {
-                            debug!("Unknown menu item selected: {device_menu_id}");
-                            return Ok(());
-                        }
-                        Some(selected_device) => {
-                            debug!("Toggling menu item for id: {device_menu_id}");
-                            let mut mii = MENUITEMINFOW {
-                                cbSize: std::mem::size_of::<MENUITEMINFOW>() as u32,
-                                fMask: MIIM_STATE,
-                                ..Default::default()
-                            };
-                            selected_device.selectable = !selected_device.selectable;
-                            GetMenuItemInfoW(self.popup_menu, device_menu_id, false, &mut mii)?;
-                            mii.fMask = MIIM_STATE;
-                            mii.fState = if selected_device.selectable {
-                                mii.fState | MFS_CHECKED
-                            } else {
-                                mii.fState & !MFS_CHECKED
-                            };
-                            SetMenuItemInfoW(self.popup_menu, device_menu_id, false, &mii)?;
-
-                            // Save the updated selectable state
-                            if let Err(e) = save_device_selectable_state(&self.available_devices) {
-                                error!("Failed to save device selectable state: {e}");
-                            }
+                POPUP_SETTINGS_ID => self.open_settings()?,
+                POPUP_INSIGHTS_ID => self.show_insights()?,
+                POPUP_SAVE_PROFILE_ID => self.save_current_setup()?,
+                POPUP_SNAPSHOT_ID => self.take_snapshot()?,
+                POPUP_RESTART_AUDIO_ID => self.restart_audio_service(false),
+                POPUP_OPEN_LOG_ID => {
+                    let Some(path) = logging::file_path() else {
+                        // Started with --console, so the log went there.
+                        return self.show_notification("No log file", "Logs go to the console.");
+                    };
+                    with_wide_str(&path.to_string_lossy(), |path| {
+                        ShellExecuteW(
+                            None,
+                            PCWSTR(null_mut()),
+                            path,
+                            PCWSTR(null_mut()),
+                            PCWSTR(null_mut()),
+                            SW_SHOWNORMAL,
+                        )
+                    });
+                }
+                POPUP_VOLUME_ID => self.show_volume_popup()?,
+                POPUP_MIXER_ID => self.show_mixer()?,
+                POPUP_MUTE_ID => {
+                    self.toggle_mute()?;
+                }
+                POPUP_NIGHT_LIMIT_ID => {
+                    self.night_limit_lifted = !self.night_limit_lifted;
+                    info!(
+                        "Night volume limit {}",
+                        if self.night_limit_lifted {
+                            "lifted until morning"
+                        } else {
+                            "back on"
                         }
-                    }
-                    return Ok(());
+                    );
+                    self.limit_night_volume()?;
+                    self.watch_night_volume();
+                }
+                POPUP_VOLUME_UP_ID => {
+                    self.change_volume(VOLUME_STEP as i32)?;
+                }
+                POPUP_VOLUME_DOWN_ID => {
+                    self.change_volume(-(VOLUME_STEP as i32))?;
+                }
+                assigned_id => {
+                    let Some(key) = self.popup_menu_ids.key(assigned_id).cloned() else {
+                        debug!("Unknown menu item selected: {assigned_id}");
+                        return Ok(());
+                    };
+                    self.assigned_menu_selection(popup_menu, key)?;
                 }
             }
         }
         Ok(())
     }
 
-    fn next_device(&mut self) -> Result<(), Box<dyn Error>> {
-        let current_device = get_current_default_endpoint(eConsole)?;
-        debug!("Switching to next device from: {current_device}");
-        let current_index = self
-            .available_devices
-            .iter()
-            .position(|d| d.id == current_device)
-            .unwrap_or(0);
-        debug!("Current device index: {current_index}");
-        let selectable_devices: Vec<_> = self
-            .available_devices
-            .iter()
-            .enumerate()
-            .filter(|(_, d)| d.selectable)
-            .collect();
-        if selectable_devices.is_empty() {
-            debug!("No selectable devices found");
-            return Ok(());
+    /// Carries out the entries for devices, profiles and the like, whose IDs were given out as the
+    /// menu was built.
+    fn assigned_menu_selection(
+        &mut self,
+        popup_menu: HMENU,
+        key: MenuKey,
+    ) -> Result<(), SwitcherooError> {
+        debug!("Menu item selected: {key:?}");
+        match key {
+            MenuKey::Output(device_id) => self.dispatch(Action::ChooseDevice(device_id))?,
+            MenuKey::Microphone(device_id) => {
+                self.dispatch(Action::SwitchMicrophoneTo(device_id))?
+            }
+            // Device checked / unchecked in the rotation submenu.
+            MenuKey::Rotation(device_id) => {
+                let Some(selected_device) = self
+                    .switcher
+                    .outputs_mut()
+                    .iter_mut()
+                    .find(|device| device.id == device_id)
+                else {
+                    debug!("No device {device_id} to toggle");
+                    return Ok(());
+                };
+                selected_device.selectable = !selected_device.selectable;
+                if let Some(id) = self
+                    .popup_menu_ids
+                    .id(&MenuKey::Rotation(device_id.clone()))
+                {
+                    unsafe { set_menu_item_checked(popup_menu, id, selected_device.selectable)? };
+                }
+
+                // Save the updated selectable state
+                self.config.devices.entry(device_id).or_default().selectable =
+                    selected_device.selectable;
+                if let Err(e) = self.save_config() {
+                    error!("Failed to save device selectable state: {e}");
+                }
+                self.update_settings_window();
+                self.prepare_standby();
+            }
+            MenuKey::CopyName(device_id) => {
+                if let Some(device) = self.switcher.outputs().iter().find(|d| d.id == device_id) {
+                    clipboard::set_text(self.window, &device.friendly_name.to_string())?;
+                }
+            }
+            MenuKey::CopyId(device_id) => clipboard::set_text(self.window, &device_id)?,
+            MenuKey::Effects(device_id) => self.toggle_effects(&device_id),
+            MenuKey::LogLevel(level) => {
+                logging::set_level(level);
+                info!("Logging at {level:?}");
+                self.config.log_level = level;
+                self.invalidate_popup_menu();
+                if let Err(e) = self.save_config() {
+                    error!("Failed to save config: {e}");
+                }
+            }
+            MenuKey::Plugin(plugin_name, item_id) => {
+                let Some(plugin) = self
+                    .plugins
+                    .iter()
+                    .find(|plugin| plugin.name == plugin_name)
+                    .cloned()
+                else {
+                    return Ok(());
+                };
+                // Plugins can take a while, so the outcome is posted back when it's done.
+                let window = self.window.0 as isize;
+                std::thread::spawn(move || {
+                    let outcome = Box::new(plugins::activate(&plugin, &item_id));
+                    let _ = unsafe {
+                        PostMessageW(
+                            Some(HWND(window as *mut _)),
+                            WM_PLUGIN_FINISHED,
+                            WPARAM::default(),
+                            LPARAM(Box::into_raw(outcome) as isize),
+                        )
+                    };
+                });
+            }
+            MenuKey::Profile(name) => self.dispatch(Action::ApplyProfile(name))?,
+            MenuKey::Snapshot(name) => self.dispatch(Action::RestoreSnapshot(name))?,
+            MenuKey::Volume(percent) => {
+                self.set_volume_percent(percent)?;
+            }
         }
+        Ok(())
+    }
 
-        let (_, cand_device) = selectable_devices
+    fn toggle_effects(&mut self, device_id: &str) {
+        let Some(device) = self
+            .switcher
+            .outputs()
             .iter()
-            // Either the first selectable device after the current one,
-            .find(|(i, _)| *i > current_index)
-            // or the first selectable device if none found as a wraparound.
-            .or_else(|| selectable_devices.first())
-            .ok_or_else(|| simple_error::SimpleError::new("No selectable devices found"))?;
-        info!("Switching to device: {:}", cand_device.friendly_name,);
-        set_default_endpoint(&cand_device.id, eConsole)?;
-        // Update the tooltip to reflect the new current device.
-        let tooltip = cand_device.friendly_name.clone();
-        unsafe {
-            Shell_NotifyIconW(
-                NIM_MODIFY,
-                &NOTIFYICONDATAW {
-                    cbSize: std::mem::size_of::<NOTIFYICONDATAW>() as u32,
-                    hWnd: self.window,
-                    hIcon: self.icon_for_form_factor(cand_device.form_factor)?,
-                    guidItem: NOTIFY_ICON_GUID,
-                    // Both NIF_TIP & NIF_SHOWTIP are required to actually show the tooltip.
-                    uFlags: NIF_ICON | NIF_MESSAGE | NIF_GUID | NIF_TIP | NIF_SHOWTIP,
-                    uCallbackMessage: WM_APP + 0x42,
-                    szTip: string_to_tip(&tooltip),
-                    Anonymous: NOTIFYICONDATAW_0 {
-                        uVersion: NOTIFYICON_VERSION_4,
-                    },
-                    ..Default::default()
+            .find(|device| device.id == device_id)
+        else {
+            return;
+        };
+        let bypassed = device.effects == Effects::Enabled;
+        let Err(e) = self
+            .switcher
+            .backend()
+            .set_effects_bypassed(device_id, bypassed)
+        else {
+            self.effects_changed(device_id, bypassed, None);
+            return;
+        };
+        error!("Failed to change effects of {device_id}: {e}");
+        let elevate = dialogs::elevate(
+            Some(self.window),
+            &effects_heading(&device.friendly_name),
+            "This usually needs administrator rights.",
+        );
+        if elevate.unwrap_or_else(|e| {
+            error!("Failed to show dialog: {e}");
+            false
+        }) {
+            // Comes back as WM_ELEVATED_TASK_FINISHED.
+            elevation::run_in_background(
+                self.window,
+                ElevatedTask::SetEffectsBypassed {
+                    device: device_id.to_string(),
+                    bypassed,
                 },
-            )
-            .ok()?;
+            );
         }
-
-        Ok(())
     }
-}
 
-// Technically, these could collide but it's unlikely.
-const POPUP_EXIT_ID: u32 = 1;
-const POPUP_CURRENT_DEVICE_ID: u32 = 2;
-const POPUP_ABOUT_ID: u32 = 3;
-
-// Converts a device ID to a unique deterministic 16-bit ID for use in the popup menu.
-// This must only use the low 16 bits as it is received via `LOWORD` in the WM_COMMAND callback.
-fn device_id_to_menu_id(device_id: &str) -> u32 {
-    State::<crc16::ARC>::calculate(device_id.as_bytes()) as u32
-}
+    /// Shows a device's effects as bypassed or not once they've been changed, or why changing
+    /// them as administrator failed.
+    fn effects_changed(&mut self, device_id: &str, bypassed: bool, error: Option<String>) {
+        let Some(device) = self
+            .switcher
+            .outputs_mut()
+            .iter_mut()
+            .find(|device| device.id == device_id)
+        else {
+            return;
+        };
+        if let Some(e) = error {
+            error!("Failed to change effects as administrator: {e}");
+            let heading = effects_heading(&device.friendly_name);
+            let _ = dialogs::error(Some(self.window), &heading, &e);
+            return;
+        }
+        device.effects = if bypassed {
+            Effects::Bypassed
+        } else {
+            Effects::Enabled
+        };
+        // The device's label changes too.
+        self.invalidate_popup_menu();
+    }
 
-unsafe fn create_popup_menu(
-    devices: &[AudioDevice],
-    current_device: &AudioDevice,
-) -> Result<HMENU, Box<dyn Error>> {
-    unsafe {
-        let menu = CreatePopupMenu()?;
-        // Add a menu item to exit the application.
-        safe_strings::with_wide_str_mut("Exit", |exit_name| -> Result<(), Box<dyn Error>> {
-            InsertMenuItemW(
-                menu,
-                0,
-                true,
-                &MENUITEMINFOW {
-                    cbSize: std::mem::size_of::<MENUITEMINFOW>() as u32,
-                    fMask: MIIM_FTYPE | MIIM_ID | MIIM_STRING,
-                    fType: MFT_STRING,
-                    dwTypeData: exit_name,
-                    cch: exit_name.len() as u32 - 1,
-                    wID: POPUP_EXIT_ID,
-                    ..Default::default()
+    // Devices the user hasn't yet decided whether to include in the rotation.
+    fn pending_device_ids(&self) -> Vec<String> {
+        self.switcher
+            .outputs()
+            .iter()
+            .filter(|d| self.config.devices.get(&d.id).is_some_and(|c| c.ask))
+            .map(|d| d.id.clone())
+            .collect()
+    }
+
+    // Devices that seem to have come back under new IDs, when there's someone to ask about them.
+    fn remaps(&self) -> Vec<remap::Remap> {
+        if self.safe_mode || !self.show_tray || self.machine_config.kiosk {
+            return Vec::new();
+        }
+        remap::find(&self.config, self.switcher.outputs())
+    }
+
+    /// Offers to move the settings for devices that seem to have come back under new IDs, e.g.
+    /// after a driver update, to the new IDs.
+    fn offer_remap(&mut self) -> Result<(), SwitcherooError> {
+        const MOVE: i32 = 1;
+        const KEEP: i32 = 2;
+        let remaps = self.remaps();
+        if remaps.is_empty() {
+            return Ok(());
+        }
+        let names: Vec<&str> = remaps.iter().map(|remap| remap.name.as_str()).collect();
+        info!("Devices with new IDs: {}", names.join(", "));
+        let answer = TaskDialog {
+            title: "Audio devices changed",
+            heading: "Move your settings to the changed devices?",
+            text: &format!(
+                "These devices are back with new IDs, as happens after a driver update: {}. Moving keeps their place in the rotation and their profiles, rules and hotkeys.",
+                names.join(", ")
+            ),
+            icon: Icon::Information,
+            buttons: &[(MOVE, "Move settings"), (KEEP, "Treat as new devices")],
+            command_links: false,
+        }
+        .show(Some(self.window))?;
+        if answer == MOVE {
+            remap::apply(&mut self.config, &remaps);
+            for device_id in &mut self.device_hotkeys {
+                if let Some(remap) = remaps.iter().find(|remap| &remap.old_id == device_id) {
+                    *device_id = remap.new_id.clone();
+                }
+            }
+            self.config.apply_to_devices(self.switcher.outputs_mut());
+            self.invalidate_popup_menu();
+            self.update_settings_window();
+        } else {
+            remap::forget(&mut self.config, &remaps);
+        }
+        self.save_config()
+    }
+
+    /// Shows a notification about new devices, which prompts for them when clicked.
+    fn notify_new_devices(&mut self) -> Result<(), SwitcherooError> {
+        if !self.show_tray || self.machine_config.kiosk {
+            // Nowhere to answer the question (or no answer would be kept), so they stay pending.
+            return Ok(());
+        }
+        if self.asking_about_devices {
+            return Ok(());
+        }
+        let pending = self.pending_device_ids();
+        let text = match pending.as_slice() {
+            [] => return Ok(()),
+            [id] => {
+                let name = self
+                    .switcher
+                    .outputs()
+                    .iter()
+                    .find(|d| &d.id == id)
+                    .map_or("", |d| d.friendly_name.as_str());
+                format!("{name} was connected. Click to choose whether to include it in the rotation.")
+            }
+            _ => format!(
+                "{} new audio devices were connected. Click to choose whether to include them in the rotation.",
+                pending.len()
+            ),
+        };
+        info!("{text}");
+        self.show_clickable_notification(NotificationKind::NewDevices, "New audio device", &text)
+    }
+
+    /// Takes devices that have gone unused out of the rotation, if the config asks for it, with a
+    /// notification that puts them back when clicked.
+    fn pause_unused_devices(&mut self) -> Result<(), SwitcherooError> {
+        let Some(days) = self.config.pause_unused_after_days else {
+            return Ok(());
+        };
+        if self.safe_mode || self.machine_config.kiosk || self.audio_service_stopped {
+            return Ok(());
+        }
+        let current_device_id = self.switcher.backend().default_device(eRender, eConsole)?;
+        let paused = self
+            .config
+            .pause_unused(&current_device_id, SystemTime::now());
+        if paused.is_empty() {
+            return Ok(());
+        }
+        self.set_selectable(&paused, false);
+        self.save_config()?;
+        let names: Vec<_> = self
+            .switcher
+            .outputs()
+            .iter()
+            .filter(|d| paused.contains(&d.id))
+            .map(|d| d.friendly_name.to_string())
+            .collect();
+        let text = match names.as_slice() {
+            [name] if paused.len() == 1 => format!(
+                "{name} wasn't used for {days} days, so it was taken out of the rotation. Click to put it back."
+            ),
+            _ => format!(
+                "{} devices weren't used for {days} days, so they were taken out of the rotation. Click to put them back.",
+                paused.len()
+            ),
+        };
+        info!("{text}");
+        self.paused_devices = paused;
+        self.show_clickable_notification(
+            NotificationKind::PausedDevices,
+            "Unused devices paused",
+            &text,
+        )
+    }
+
+    /// Undoes `pause_unused_devices`.
+    fn resume_paused_devices(&mut self) -> Result<(), SwitcherooError> {
+        let paused = std::mem::take(&mut self.paused_devices);
+        info!(
+            "Putting {} paused devices back in the rotation",
+            paused.len()
+        );
+        self.config.resume(&paused, SystemTime::now());
+        self.set_selectable(&paused, true);
+        self.save_config()
+    }
+
+    // Updates the listed devices' rotation marks wherever they're shown.
+    fn set_selectable(&mut self, device_ids: &[String], selectable: bool) {
+        for device in self.switcher.outputs_mut() {
+            if device_ids.contains(&device.id) {
+                device.selectable = selectable;
+            }
+        }
+        self.invalidate_popup_menu();
+        self.update_settings_window();
+    }
+
+    /// Shows a balloon notification from the tray icon, unless Focus Assist is holding them back.
+    fn show_notification(&self, title: &str, text: &str) -> Result<(), SwitcherooError> {
+        self.show_balloon(None, title, text)
+    }
+
+    /// Shows a notification like `show_notification` that does what `kind` says when clicked.
+    fn show_clickable_notification(
+        &self,
+        kind: NotificationKind,
+        title: &str,
+        text: &str,
+    ) -> Result<(), SwitcherooError> {
+        self.show_balloon(Some(kind), title, text)
+    }
+
+    fn show_balloon(
+        &self,
+        click: Option<NotificationKind>,
+        title: &str,
+        text: &str,
+    ) -> Result<(), SwitcherooError> {
+        if !self.show_tray || !notifications_allowed() {
+            return Ok(());
+        }
+        unsafe {
+            Shell_NotifyIconW(
+                NIM_MODIFY,
+                &NOTIFYICONDATAW {
+                    uFlags: NIF_INFO | self.notify_icon.flags(),
+                    szInfoTitle: safe_strings::truncated_wide(title),
+                    szInfo: safe_strings::truncated_wide(text),
+                    dwInfoFlags: NIIF_INFO,
+                    ..self.notify_icon.data(self.window)
                 },
-            )?;
-            Ok(())
-        })?;
-        // Add a separator.
-        InsertMenuItemW(
-            menu,
-            0,
-            true,
-            &MENUITEMINFOW {
-                cbSize: std::mem::size_of::<MENUITEMINFOW>() as u32,
-                fMask: MIIM_FTYPE,
-                fType: MFT_SEPARATOR,
-                ..Default::default()
-            },
+            )
+            .ok()?;
+        }
+        // It replaces any notification still showing.
+        self.pending_click.set(click);
+        Ok(())
+    }
+
+    /// After an update, marks the menu entries it added as new and offers to list its features.
+    fn catch_up_on_changes(&mut self, fresh_install: bool) -> Result<(), SwitcherooError> {
+        let seen_version = self.config.whats_new.seen_version.clone();
+        let releases =
+            changelog::catch_up(&mut self.config.whats_new, fresh_install, SystemTime::now());
+        if self.config.whats_new.seen_version != seen_version {
+            self.save_config()?;
+        }
+        // Not over a notification still waiting to be clicked.
+        if releases.is_empty() || self.pending_click.get().is_some() {
+            return Ok(());
+        }
+        info!("Updated to {}", changelog::CURRENT);
+        self.whats_new = releases;
+        self.show_clickable_notification(
+            NotificationKind::WhatsNew,
+            &format!("Sound Switcheroo updated to {}", changelog::CURRENT),
+            "Click to see what's new. New menu entries are marked for a while.",
+        )
+    }
+
+    fn show_whats_new(&mut self) -> Result<(), SwitcherooError> {
+        let releases = std::mem::take(&mut self.whats_new);
+        TaskDialog {
+            title: "Sound Switcheroo",
+            heading: "What's new",
+            text: &changelog::describe(releases),
+            icon: Icon::Information,
+            buttons: &[(1, "OK")],
+            command_links: false,
+        }
+        .show(Some(self.window))?;
+        Ok(())
+    }
+
+    /// Asks once whether to include the new devices in the rotation and records the answer.
+    fn prompt_new_devices(&mut self) -> Result<(), SwitcherooError> {
+        self.ask_about_devices(self.pending_device_ids())
+    }
+
+    // Asks whether to include the devices with these IDs in the rotation, all in one dialog. Only
+    // their IDs and names are held while it's open, as it handles messages that can refresh the
+    // devices.
+    fn ask_about_devices(&mut self, ids: Vec<String>) -> Result<(), SwitcherooError> {
+        const YES: i32 = 1;
+        const NO: i32 = 2;
+        const LATER: i32 = 3;
+        if self.asking_about_devices {
+            return Ok(());
+        }
+        let names: Vec<String> = ids
+            .iter()
+            .filter_map(|id| self.switcher.outputs().iter().find(|d| &d.id == id))
+            .map(|device| device.friendly_name.to_string())
+            .collect();
+        let heading = match names.as_slice() {
+            [] => return Ok(()),
+            [name] => format!("Include {name} in the rotation?"),
+            _ => format!("Include these {} devices in the rotation?", names.len()),
+        };
+        let text = format!(
+            "{}Devices in the rotation take turns when you left click the tray icon. Each can be \
+             changed from the Rotation submenu later.",
+            if names.len() > 1 {
+                format!("{}\n\n", names.join("\n"))
+            } else {
+                String::new()
+            }
+        );
+        self.asking_about_devices = true;
+        let answer = TaskDialog {
+            title: "New audio device",
+            heading: &heading,
+            text: &text,
+            icon: Icon::Information,
+            buttons: &[(YES, "Yes"), (NO, "No"), (LATER, "Always ask later")],
+            command_links: false,
+        }
+        .show(Some(self.window));
+        self.asking_about_devices = false;
+        let selectable = match answer? {
+            YES => true,
+            NO => false,
+            _ => {
+                debug!("Deferring decision for {}", names.join(", "));
+                return Ok(());
+            }
+        };
+        for id in ids {
+            // Gone while the dialog was open.
+            let Some(device) = self.switcher.outputs_mut().iter_mut().find(|d| d.id == id) else {
+                continue;
+            };
+            let device_config = self.config.devices.entry(id.clone()).or_default();
+            device_config.selectable = selectable;
+            device_config.ask = false;
+            device.selectable = selectable;
+            if let Some(popup_menu) = self.popup_menu
+                && let Some(menu_id) = self.popup_menu_ids.id(&MenuKey::Rotation(id))
+            {
+                unsafe { set_menu_item_checked(popup_menu, menu_id, selectable)? };
+            }
+        }
+        self.save_config()
+    }
+
+    /// Welcomes a new user and lets them choose the rotation straight away, or go with every
+    /// (non-virtual) device.
+    fn first_run_wizard(&mut self) -> Result<(), SwitcherooError> {
+        const ALL: i32 = 1;
+        const CHOOSE: i32 = 2;
+        let answer = TaskDialog {
+            title: "Sound Switcheroo",
+            heading: "Welcome to Sound Switcheroo",
+            text: "Left click the speaker icon in the tray to switch to the next output device, \
+                   or right click it for the menu.",
+            icon: Icon::Information,
+            buttons: &[
+                (
+                    ALL,
+                    "Switch between all my devices\nVirtual devices are left out. \
+                     You can change this from the menu at any time.",
+                ),
+                (CHOOSE, "Let me choose\nAsk about each device now."),
+            ],
+            command_links: true,
+        }
+        .show(None)?;
+        if answer != CHOOSE {
+            return Ok(());
+        }
+        for device in self.switcher.outputs() {
+            if let Some(device_config) = self.config.devices.get_mut(&device.id)
+                && device_config.selectable
+            {
+                device_config.ask = true;
+            }
+        }
+        // One at a time, as asked for.
+        for id in self.pending_device_ids() {
+            self.ask_about_devices(vec![id])?;
+        }
+        Ok(())
+    }
+
+    /// Applies a volume chosen with the slider, or a preset in the menu, to the current device.
+    fn set_volume_percent(&self, percent: u32) -> Result<(), SwitcherooError> {
+        let current_device_id = self.switcher.backend().default_device(eRender, eConsole)?;
+        let percent = percent.min(self.volume_limit(&current_device_id));
+        self.switcher
+            .backend()
+            .set_volume(&current_device_id, percent as f32 / 100.0)?;
+        Ok(())
+    }
+
+    /// Applies a balance chosen with the slider to the current device, and saves it to apply again
+    /// on switching back once the slider is `finished` moving.
+    fn set_balance(&mut self, balance: i32, finished: bool) -> Result<(), SwitcherooError> {
+        let current_device_id = self.switcher.backend().default_device(eRender, eConsole)?;
+        self.switcher
+            .backend()
+            .set_balance(&current_device_id, balance)?;
+        let saved = |config: &Config| {
+            config
+                .devices
+                .get(&current_device_id)
+                .and_then(|device_config| device_config.balance)
+        };
+        let before = saved(&self.config);
+        self.config.set_balance(&current_device_id, balance);
+        self.balance_unsaved |= saved(&self.config) != before;
+        // Not on every step of a drag.
+        if finished && std::mem::take(&mut self.balance_unsaved) {
+            self.save_config()?;
+        }
+        Ok(())
+    }
+
+    /// Turns the current output's volume up or down by `step` percent, returning the new level.
+    fn change_volume(&self, step: i32) -> Result<u32, SwitcherooError> {
+        let current_device_id = self.switcher.backend().default_device(eRender, eConsole)?;
+        let volume = self.switcher.backend().volume(&current_device_id)?;
+        let percent = ((volume * 100.0).round() as i32 + step)
+            .clamp(0, self.volume_limit(&current_device_id) as i32) as u32;
+        debug!("Changing the volume to {percent}%");
+        self.switcher
+            .backend()
+            .set_volume(&current_device_id, percent as f32 / 100.0)?;
+        Ok(percent)
+    }
+
+    /// Turns the current output's volume up or down by `notches` steps of the wheel, showing the
+    /// new level in the tooltip.
+    fn scroll_volume(&self, notches: i32) -> Result<(), SwitcherooError> {
+        let percent = self.change_volume(notches * self.config.wheel_volume_step as i32)?;
+        let current_device_id = self.switcher.backend().default_device(eRender, eConsole)?;
+        let Some(device) = self
+            .switcher
+            .outputs()
+            .iter()
+            .find(|d| d.id == current_device_id)
+        else {
+            return Ok(());
+        };
+        // The tooltip as usual, with the volume after it if it doesn't already show it.
+        let template = if tooltip::uses(&self.config.tooltip, "volume") {
+            self.config.tooltip.clone()
+        } else {
+            format!("{}: {{volume}}%", self.config.tooltip)
+        };
+        self.update_tray_icon(
+            self.tray_icon(device)?,
+            device_tooltip(
+                &template,
+                self.switcher.backend(),
+                device,
+                Some(percent),
+                &self.decimal_separator,
+            ),
+        )
+    }
+
+    /// Shows what the history says about when devices get used, offering rules to switch to them
+    /// automatically.
+    fn show_insights(&mut self) -> Result<(), SwitcherooError> {
+        const OPEN: i32 = 10;
+        const CLEAR: i32 = 11;
+        const FIRST_RULE: i32 = 20;
+        let history = self.history.prune(SystemTime::now())?;
+        let insights = insights::insights(&history);
+        let suggestions: Vec<Rule> = insights
+            .iter()
+            .filter_map(|insight| insight.suggested_rule(&self.config.rules))
+            .collect();
+        let found = if insights.is_empty() {
+            format!(
+                "Nothing stands out from the {} switches so far. Patterns show after a week or two.",
+                history.len()
+            )
+        } else {
+            insights
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let text = format!(
+            "{found}\n\nThe history stays on this computer, in {}.",
+            self.history.path().display()
+        );
+        let labels: Vec<String> = suggestions
+            .iter()
+            .map(|rule| {
+                format!(
+                    "Add a rule: {}\nSwitches to it every day at {}",
+                    rule.name,
+                    rule.trigger.value()
+                )
+            })
+            .collect();
+        let mut buttons: Vec<(i32, &str)> = labels
+            .iter()
+            .enumerate()
+            .map(|(i, label)| (FIRST_RULE + i as i32, label.as_str()))
+            .collect();
+        buttons.push((OPEN, "Open the history\nA line of JSON for each switch"));
+        buttons.push((CLEAR, "Clear the history"));
+        let answer = TaskDialog {
+            title: "Usage insights",
+            heading: "How you switch devices",
+            text: &text,
+            icon: Icon::Information,
+            buttons: &buttons,
+            command_links: true,
+        }
+        .show(Some(self.window))?;
+        match answer {
+            OPEN => {
+                if !self.history.path().exists() {
+                    return self.show_notification("No history yet", "Nothing has been switched.");
+                }
+                unsafe {
+                    with_wide_str(&self.history.path().to_string_lossy(), |path| {
+                        ShellExecuteW(
+                            None,
+                            PCWSTR(null_mut()),
+                            w!("notepad.exe"),
+                            path,
+                            PCWSTR(null_mut()),
+                            SW_SHOWNORMAL,
+                        )
+                    });
+                }
+            }
+            CLEAR => {
+                info!("Clearing the switching history");
+                self.history.clear()?;
+            }
+            id if id >= FIRST_RULE
+                && let Some(rule) = suggestions.get((id - FIRST_RULE) as usize) =>
+            {
+                info!(
+                    "Adding rule \"{}\": when {}, {}",
+                    rule.name, rule.trigger, rule.action
+                );
+                self.config.rules.push(rule.clone());
+                self.watch_schedule();
+                self.update_settings_window();
+                self.save_config()?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Shows the volume slider at the cursor, for the current output.
+    fn show_volume_popup(&self) -> Result<(), SwitcherooError> {
+        let current_device_id = self.switcher.backend().default_device(eRender, eConsole)?;
+        let volume = self.switcher.backend().volume(&current_device_id)?;
+        let balance = self
+            .switcher
+            .backend()
+            .balance(&current_device_id)
+            .unwrap_or_else(|e| {
+                error!("Failed to read balance: {e}");
+                None
+            });
+        let mut cursor_pos = POINT::default();
+        unsafe { GetCursorPos(&mut cursor_pos)? };
+        let key = window_placement::placement_key(
+            volume_popup::PLACEMENT_NAME,
+            &window_placement::monitor_layout(),
+        );
+        volume_popup::show(
+            self.window,
+            cursor_pos.x,
+            cursor_pos.y,
+            self.config.windows.get(&key),
+            (volume * 100.0).round() as u32,
+            balance,
+        )?;
+        Ok(())
+    }
+
+    /// Shows the volume mixer flyout at the cursor, with the devices in the rotation and the
+    /// current one.
+    fn show_mixer(&self) -> Result<(), SwitcherooError> {
+        let current_device_id = self.switcher.backend().default_device(eRender, eConsole)?;
+        let mut devices = Vec::new();
+        for device in self.included_devices(&current_device_id) {
+            if !device.selectable && device.id != current_device_id {
+                continue;
+            }
+            let percent = self
+                .switcher
+                .backend()
+                .volume(&device.id)
+                .unwrap_or_else(|e| {
+                    error!("Failed to read the volume of {}: {e}", device.friendly_name);
+                    0.0
+                });
+            devices.push(FlyoutDevice {
+                icon: self.icon_for_form_factor(device.form_factor)?,
+                name: device.friendly_name.to_string(),
+                percent: (percent * 100.0).round() as u32,
+                default: device.id == current_device_id,
+                id: device.id,
+            });
+        }
+        let mut cursor_pos = POINT::default();
+        unsafe { GetCursorPos(&mut cursor_pos)? };
+        flyout::show(self.window, cursor_pos.x, cursor_pos.y, devices)?;
+        Ok(())
+    }
+
+    /// Switches to a device clicked in the mixer flyout, returning whether it's now the default.
+    fn choose_from_mixer(&mut self, device_id: &str) -> Result<bool, SwitcherooError> {
+        self.dispatch(Action::ChooseDevice(device_id.to_string()))?;
+        Ok(self.switcher.backend().default_device(eRender, eConsole)? == device_id)
+    }
+
+    /// Sets the volume of any device from its slider in the mixer flyout.
+    fn set_device_volume(&self, device_id: &str, percent: u32) -> Result<(), SwitcherooError> {
+        let percent = percent.min(self.volume_limit(device_id));
+        self.switcher
+            .backend()
+            .set_volume(device_id, percent as f32 / 100.0)
+    }
+
+    /// Mutes or unmutes the current output, returning whether it's now muted.
+    fn toggle_mute(&self) -> Result<bool, SwitcherooError> {
+        let current_device_id = self.switcher.backend().default_device(eRender, eConsole)?;
+        let muted = !self.switcher.backend().muted(&current_device_id)?;
+        info!("{} the output", if muted { "Muting" } else { "Unmuting" });
+        self.switcher
+            .backend()
+            .set_muted(&current_device_id, muted)?;
+        Ok(muted)
+    }
+
+    /// Turns the current output down to the night limit, if it's over it.
+    fn limit_night_volume(&self) -> Result<(), SwitcherooError> {
+        let current_device_id = self.switcher.backend().default_device(eRender, eConsole)?;
+        let limit = self.volume_limit(&current_device_id);
+        let volume = self.switcher.backend().volume(&current_device_id)?;
+        if (volume * 100.0).round() as u32 > limit {
+            info!("Turning {current_device_id} down to the night limit of {limit}%");
+            self.switcher
+                .backend()
+                .set_volume(&current_device_id, limit as f32 / 100.0)?;
+        }
+        Ok(())
+    }
+
+    /// Notes whether it's night for night mode, switching away from speakers as night starts if
+    /// asked to, and keeps the volume under the limit.
+    fn check_night_mode(&mut self) -> Result<(), SwitcherooError> {
+        let night = self.config.night_mode.covers(&rules::local_time().1);
+        if night != self.night {
+            info!("Night mode {}", if night { "starting" } else { "over" });
+            self.night = night;
+            self.night_limit_lifted = false;
+            if night && self.config.night_mode.switch_away {
+                let current_device_id = self.current_output()?;
+                let on_speakers =
+                    self.switcher.outputs().iter().any(|d| {
+                        d.id == current_device_id && NightModeConfig::limits(d.form_factor)
+                    });
+                if on_speakers
+                    && let Some(device) = self
+                        .switcher
+                        .outputs()
+                        .iter()
+                        .find(|d| d.selectable && !NightModeConfig::limits(d.form_factor))
+                        .cloned()
+                {
+                    self.dispatch(Action::SwitchTo(device.id))?;
+                }
+            }
+        }
+        if night {
+            self.limit_night_volume()?;
+        }
+        self.watch_night_volume();
+        Ok(())
+    }
+
+    /// Watches the current output's volume while the night limit applies to it, so it's turned
+    /// back down when anything else, such as the volume keys, turns it up.
+    fn watch_night_volume(&mut self) {
+        let limited = self
+            .switcher
+            .backend()
+            .default_device(eRender, eConsole)
+            .ok()
+            .filter(|device_id| self.volume_limit(device_id) < 100);
+        match limited {
+            Some(device_id)
+                if self
+                    .night_volume
+                    .as_ref()
+                    .is_some_and(|watch| watch.device_id() == device_id) => {}
+            Some(device_id) => {
+                self.night_volume = None;
+                self.night_volume = VolumeNotifications::register(
+                    &device_id,
+                    poster(self.window, WM_ENDPOINT_VOLUME_CHANGED),
+                )
+                .inspect_err(|e| error!("Failed to watch the volume for the night: {e}"))
+                .ok();
+            }
+            None => self.night_volume = None,
+        }
+    }
+
+    /// Mutes or unmutes the default microphone, slashing the tray icon while it's muted.
+    fn toggle_microphone_mute(&mut self) -> Result<(), SwitcherooError> {
+        let microphone = self.switcher.backend().default_device(eCapture, eConsole)?;
+        let muted = !self.switcher.backend().muted(&microphone)?;
+        info!(
+            "{} the microphone",
+            if muted { "Muting" } else { "Unmuting" }
+        );
+        self.switcher.backend().set_muted(&microphone, muted)?;
+        self.show_current_device()
+    }
+
+    /// Whether the default microphone is muted, asked afresh each time since anything can mute it.
+    /// Only checked with the hotkey to mute it set up, which is when the tray icon is slashed while
+    /// it is.
+    fn microphone_muted(&self) -> bool {
+        self.config.hotkeys.mute_microphone.is_some()
+            && self
+                .switcher
+                .backend()
+                .default_device(eCapture, eConsole)
+                .and_then(|microphone| self.switcher.backend().muted(&microphone))
+                .unwrap_or(false)
+    }
+
+    /// Does what the config says a click on the tray icon should.
+    fn click(&mut self, action: ClickAction) -> Result<(), SwitcherooError> {
+        debug!("Tray icon clicked: {action:?}");
+        match action {
+            ClickAction::Nothing => Ok(()),
+            ClickAction::NextDevice => self.dispatch(Action::NextDevice),
+            ClickAction::PreviousDevice => self.dispatch(Action::PreviousDevice),
+            ClickAction::SwapDevices => self.dispatch(Action::SwapDevices),
+            ClickAction::NextMicrophone => self.dispatch(Action::NextMicrophone),
+            ClickAction::ToggleMute => self.toggle_mute().map(|_| ()),
+            ClickAction::ShowVolume => self.show_volume_popup(),
+            ClickAction::ShowMixer => self.show_mixer(),
+            ClickAction::ShowMenu => {
+                let mut cursor_pos = POINT::default();
+                unsafe { GetCursorPos(&mut cursor_pos)? };
+                self.show_popup_menu(cursor_pos.x, cursor_pos.y)
+            }
+            // Not offered by the menu in these modes either.
+            ClickAction::OpenSettings if self.safe_mode || self.machine_config.kiosk => Ok(()),
+            ClickAction::OpenSettings => self.open_settings(),
+        }
+    }
+
+    /// Opens the settings window, or brings it to the front if it's already open.
+    fn open_settings(&mut self) -> Result<(), SwitcherooError> {
+        if let Some(window) = self.settings_window
+            && unsafe { IsWindow(Some(window)) }.as_bool()
+        {
+            unsafe {
+                let _ = SetForegroundWindow(window);
+            }
+            return Ok(());
+        }
+        self.settings_window = Some(settings_window::show(
+            self.window,
+            self.switcher.outputs(),
+            &self.switcher.backend().devices(eCapture)?,
+            &self.config,
+        )?);
+        Ok(())
+    }
+
+    // Keeps the settings window, if open, showing the current devices.
+    fn update_settings_window(&self) {
+        if let Some(window) = self.settings_window
+            && unsafe { IsWindow(Some(window)) }.as_bool()
+        {
+            let inputs = self
+                .switcher
+                .backend()
+                .devices(eCapture)
+                .unwrap_or_else(|e| {
+                    error!("Failed to list microphones: {e:?}");
+                    Vec::new()
+                });
+            settings_window::update(window, self.switcher.outputs(), &inputs, &self.config);
+        }
+    }
+
+    /// Keeps profiles saved from the settings window, with the rules that apply them following
+    /// renames and turned off if their profile was deleted.
+    fn set_profiles(&mut self, changes: &mut ProfileChanges) -> Result<(), SwitcherooError> {
+        debug!("Profiles changed: {changes:?}");
+        self.config.profiles = changes.profiles.to_vec();
+        let names: Vec<&str> = self
+            .config
+            .profiles
+            .iter()
+            .map(|profile| profile.name.as_str())
+            .collect();
+        changes.turned_off =
+            rules::follow_profiles(&mut self.config.rules, &changes.renamed, &names);
+        for name in &changes.turned_off {
+            warn!("Turned off rule \"{name}\", as the profile it applied was deleted");
+        }
+        self.register_profile_hotkeys();
+        self.invalidate_popup_menu();
+        if let Some(window) = self.settings_window {
+            settings_window::follow_profiles(window, &changes.renamed, &self.config.profiles);
+        }
+        // So the rules page offers them.
+        self.update_settings_window();
+        self.save_config()
+    }
+
+    /// Registers the hotkeys in the config, replacing any registered before.
+    fn register_hotkeys(&mut self) {
+        // Unregistered first, so a hotkey that moved to another action is free.
+        self.hotkeys = None;
+        let config = &self.config.hotkeys;
+        let hotkeys = [
+            (NEXT_DEVICE_HOTKEY_ID, config.next_device),
+            (PREVIOUS_DEVICE_HOTKEY_ID, config.previous_device),
+            (SWAP_DEVICES_HOTKEY_ID, config.swap_devices),
+            (MUTE_MICROPHONE_HOTKEY_ID, config.mute_microphone),
+        ];
+        let mut hotkeys: Vec<_> = hotkeys
+            .into_iter()
+            .filter_map(|(id, hotkey)| Some((id, hotkey?)))
+            .collect();
+        self.device_hotkeys.clear();
+        for (i, (hotkey, device_id)) in config.device_hotkeys().into_iter().enumerate() {
+            hotkeys.push((FIRST_DEVICE_HOTKEY_ID + i as i32, hotkey));
+            self.device_hotkeys.push(device_id.to_string());
+        }
+        self.hotkeys = Some(GlobalHotkeys::register(self.window, &hotkeys));
+    }
+
+    /// Keeps hotkeys saved from the settings window, registering them straight away.
+    fn set_hotkeys(&mut self, hotkeys: &HotkeyConfig) -> Result<(), SwitcherooError> {
+        debug!("Hotkeys changed: {hotkeys:?}");
+        self.config.hotkeys = hotkeys.clone();
+        self.register_hotkeys();
+        let unavailable: Vec<_> = self
+            .hotkeys
+            .iter()
+            .flat_map(|hotkeys| &hotkeys.unavailable)
+            .map(|hotkey| hotkey.to_string())
+            .collect();
+        if !unavailable.is_empty()
+            && let Err(e) = self.show_notification(
+                "Hotkeys unavailable",
+                &format!("Another app is using {}.", unavailable.join(", ")),
+            )
+        {
+            error!("Failed to notify about hotkeys: {e}");
+        }
+        self.save_config()
+    }
+
+    /// Keeps the on/off settings saved from the settings window.
+    fn set_behavior(&mut self, behavior: &Behavior) -> Result<(), SwitcherooError> {
+        debug!("Behavior changed: {behavior:?}");
+        behavior.apply_to(&mut self.config);
+        self.watch_foreground();
+        self.unroute_apps();
+        self.prepare_standby();
+        // The menu has check marks for some of them.
+        self.invalidate_popup_menu();
+        self.save_config()
+    }
+
+    /// Registers the profiles' hotkeys, replacing any registered before.
+    fn register_profile_hotkeys(&mut self) {
+        // Unregistered first, so a hotkey that moved to another profile is free.
+        self.profile_hotkeys = None;
+        let hotkeys: Vec<_> = self
+            .config
+            .profiles
+            .iter()
+            .enumerate()
+            .filter_map(|(i, profile)| Some((FIRST_PROFILE_HOTKEY_ID + i as i32, profile.hotkey?)))
+            .collect();
+        self.profile_hotkeys = Some(GlobalHotkeys::register(self.window, &hotkeys));
+    }
+
+    /// Keeps rules saved from the settings window.
+    fn set_rules(&mut self, rules: &[Rule]) -> Result<(), SwitcherooError> {
+        debug!("Rules changed: {rules:?}");
+        self.config.rules = rules.to_vec();
+        self.watch_foreground();
+        self.watch_schedule();
+        self.save_config()
+    }
+
+    /// Carries out a rule's action as if its trigger had just happened, or in a dry run only
+    /// describes it, for the Test button on the rules page.
+    fn test_rule(&mut self, test: &mut RuleTest) {
+        let rule = &test.rule;
+        test.outcome = if test.dry_run {
+            format!("Dry run: when {}, would {}.", rule.trigger, rule.action)
+        } else {
+            match self.run_rule_action(&rule.action) {
+                Ok(outcome) => format!("Tested: when {}, {outcome}", rule.trigger),
+                Err(e) => format!(
+                    "Tested: when {}, failed to {}: {e}",
+                    rule.trigger, rule.action
+                ),
+            }
+        };
+        info!("Testing rule \"{}\". {}", rule.name, test.outcome);
+    }
+
+    /// Carries out what a rule does, and describes what happened.
+    fn run_rule_action(&mut self, action: &RuleAction) -> Result<String, SwitcherooError> {
+        if self.safe_mode {
+            bail!("Rules are off in safe mode");
+        }
+        match action {
+            RuleAction::SwitchDevice { device } => {
+                self.try_dispatch(Action::SwitchTo(device.clone()))?;
+                let name = self
+                    .switcher
+                    .outputs()
+                    .iter()
+                    .find(|d| &d.id == device)
+                    .map_or(device.as_str(), |d| &d.friendly_name);
+                Ok(format!("switched to {name}."))
+            }
+            RuleAction::ApplyProfile { profile } => {
+                self.try_dispatch(Action::ApplyProfile(profile.clone()))?;
+                Ok(format!("applied profile \"{profile}\"."))
+            }
+            RuleAction::Notify { message } => {
+                self.show_notification("Sound Switcheroo", message)?;
+                Ok(format!("showed \"{message}\"."))
+            }
+            RuleAction::RunHook { command } => {
+                let hook = hooks::run(command)?;
+                Ok(format!("started `{command}` (process {}).", hook.id()))
+            }
+        }
+    }
+
+    /// Offered when Windows Audio is found stopped.
+    fn start_audio_service(&self) {
+        self.restart_audio_service(true);
+    }
+
+    /// Restarts the audio services, or just starts them if `start_only`. Waits for UAC and the
+    /// services on another thread, so the outcome is posted back when it's done.
+    fn restart_audio_service(&self, start_only: bool) {
+        let window = self.window.0 as isize;
+        std::thread::spawn(move || {
+            let (result, done) = if start_only {
+                (audio_service::start_elevated(), "started")
+            } else {
+                (audio_service::restart_elevated(), "restarted")
+            };
+            let outcome = Box::new(match result {
+                Ok(()) => format!("The audio service was {done}."),
+                Err(e) => format!("The audio service wasn't {done}: {e}"),
+            });
+            unsafe {
+                let _ = PostMessageW(
+                    Some(HWND(window as *mut _)),
+                    WM_AUDIO_SERVICE_RESTARTED,
+                    WPARAM::default(),
+                    LPARAM(Box::into_raw(outcome) as isize),
+                );
+            }
+        });
+    }
+
+    /// Switches to a profile's devices and sets their volumes, leaving alone any it doesn't set.
+    fn apply_profile(&mut self, profile: &Profile) -> Result<(), SwitcherooError> {
+        info!("Applying profile: {}", profile.name);
+        if let Some(output) = &profile.output {
+            self.dispatch(Action::SwitchTo(output.clone()))?;
+        }
+        if let Some(input) = &profile.input {
+            // After the output, in case that also switched the microphone.
+            self.dispatch(Action::SwitchMicrophoneTo(input.clone()))?;
+        }
+        if let Some(percent) = profile.output_volume {
+            let output = self.switcher.backend().default_device(eRender, eConsole)?;
+            let percent = percent.min(self.volume_limit(&output));
+            self.switcher
+                .backend()
+                .set_volume(&output, percent as f32 / 100.0)?;
+        }
+        if let Some(percent) = profile.input_volume {
+            let input = self.switcher.backend().default_device(eCapture, eConsole)?;
+            self.switcher
+                .backend()
+                .set_volume(&input, percent as f32 / 100.0)?;
+        }
+        for device in [
+            &profile.communications_output,
+            &profile.communications_input,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            self.switcher
+                .backend()
+                .set_default_device(device, eCommunications)?;
+        }
+        self.profile_icon = None;
+        if let Some(icon) = &profile.icon {
+            match self.load_profile_icon(icon) {
+                Ok(icon) => {
+                    self.profile_icon = Some(ActiveProfileIcon {
+                        output: self.switcher.backend().default_device(eRender, eConsole)?,
+                        icon,
+                    })
+                }
+                Err(e) => error!("Failed to load the icon of profile {}: {e}", profile.name),
+            }
+        }
+        self.show_current_device()
+    }
+
+    /// Loads a profile's `icon`, reading files relative to the config's folder.
+    fn load_profile_icon(&self, icon: &str) -> Result<ProfileTrayIcon, SwitcherooError> {
+        let dir = self.config_path.parent().unwrap_or(Path::new("."));
+        Ok(match ProfileIcon::parse(icon, dir) {
+            ProfileIcon::BuiltIn(form_factor) => ProfileTrayIcon::BuiltIn(form_factor.into()),
+            ProfileIcon::File(path) => ProfileTrayIcon::File(unsafe { load_icon_file(&path)? }),
+        })
+    }
+
+    /// Applies the profile marked to be applied at startup, or on resume from sleep, if there is
+    /// one.
+    fn apply_automatic_profile(&mut self, resume: bool) {
+        let Some(profile) = self
+            .config
+            .profiles
+            .iter()
+            .find(|profile| {
+                if resume {
+                    profile.apply_on_resume
+                } else {
+                    profile.apply_at_startup
+                }
+            })
+            .cloned()
+        else {
+            return;
+        };
+        if let Err(e) = self.dispatch_unlimited(Action::ApplyProfile(profile.name.clone())) {
+            error!("Failed to apply profile {}: {e}", profile.name);
+        }
+    }
+
+    /// Checks that the default output plays at all, as some machines come up on an HDMI output with
+    /// nothing listening, and if not switches to the first of the fallbacks that does.
+    fn check_default_output(&mut self) -> Result<(), SwitcherooError> {
+        let current_device_id = self.switcher.backend().default_device(eRender, eConsole)?;
+        let Err(e) = self.switcher.backend().check_playback(&current_device_id) else {
+            debug!("The default output is playing");
+            return Ok(());
+        };
+        let name = self
+            .switcher
+            .outputs()
+            .iter()
+            .find(|d| d.id == current_device_id)
+            .map_or(current_device_id.clone(), |d| d.friendly_name.to_string());
+        warn!("{name} isn't playing: {e}");
+        let Some(device) = self
+            .config
+            .fallbacks(self.switcher.outputs())
+            .into_iter()
+            .filter(|d| d.id != current_device_id)
+            .find(|d| match self.switcher.backend().check_playback(&d.id) {
+                Ok(()) => true,
+                Err(e) => {
+                    debug!("{} isn't playing either: {e}", d.friendly_name);
+                    false
+                }
+            })
+        else {
+            bail!("None of the other outputs are playing either");
+        };
+        self.try_dispatch(Action::SwitchTo(device.id.clone()))?;
+        self.show_notification(
+            "Output switched",
+            &format!(
+                "{name} wasn't playing, so switched to {}.",
+                device.friendly_name
+            ),
+        )
+    }
+
+    /// Saves the default devices, their volumes and the communications defaults as a new profile,
+    /// named after the devices.
+    fn save_current_setup(&mut self) -> Result<(), SwitcherooError> {
+        let mut profile = Profile::default();
+        let mut names = Vec::new();
+        for flow in [eRender, eCapture] {
+            let Ok(device) = self.switcher.backend().default_device(flow, eConsole) else {
+                names.push(None);
+                continue;
+            };
+            let volume = (self.switcher.backend().volume(&device)? * 100.0).round() as u32;
+            let communications = self
+                .switcher
+                .backend()
+                .default_device(flow, eCommunications)
+                .ok()
+                .filter(|communications| communications != &device);
+            names.push(
+                self.switcher
+                    .backend()
+                    .device(flow, &device)
+                    .ok()
+                    .map(|d| d.friendly_name.to_string()),
+            );
+            if flow == eRender {
+                profile.output = Some(device);
+                profile.output_volume = Some(volume);
+                profile.communications_output = communications;
+            } else {
+                profile.input = Some(device);
+                profile.input_volume = Some(volume);
+                profile.communications_input = communications;
+            }
+        }
+        profile.name = profiles::setup_name(
+            names[0].as_deref(),
+            names[1].as_deref(),
+            &self.config.profiles,
+        );
+        info!("Saving current setup as profile: {}", profile.name);
+        let name = profile.name.clone();
+        self.config.profiles.push(profile);
+        self.invalidate_popup_menu();
+        self.update_settings_window();
+        self.save_config()?;
+        self.show_notification(
+            "Profile saved",
+            &format!("Saved \"{name}\". Rename it in Settings > Profiles."),
+        )
+    }
+
+    /// Saves the default devices for every role and every device's volume and mute as a routing
+    /// snapshot, named after the time.
+    fn take_snapshot(&mut self) -> Result<(), SwitcherooError> {
+        let name = routing_snapshot::name_for_now();
+        RoutingSnapshot::take(self.switcher.backend())?.save(&self.snapshots_dir(), &name)?;
+        self.invalidate_popup_menu();
+        self.show_notification(
+            "Snapshot saved",
+            &format!(
+                "Restore \"{name}\" from Snapshots in the menu to put audio back as it is now."
+            ),
+        )
+    }
+
+    /// Puts the defaults and volumes back as they were in the snapshot called `name`.
+    fn restore_snapshot(&mut self, name: &str) -> Result<(), SwitcherooError> {
+        info!("Restoring snapshot: {name}");
+        let snapshot = RoutingSnapshot::load(&self.snapshots_dir(), name)?;
+        let missing = snapshot.restore(self.switcher.backend())?;
+        self.profile_icon = None;
+        self.show_current_device()?;
+        if missing.is_empty() {
+            return Ok(());
+        }
+        self.show_notification(
+            "Snapshot partly restored",
+            &format!("Not connected, so left out: {}.", missing.join(", ")),
+        )
+    }
+
+    fn snapshots_dir(&self) -> PathBuf {
+        routing_snapshot::snapshots_dir(&self.config_path)
+    }
+
+    /// Applies an order the devices were dragged into in the settings window.
+    fn reorder_devices(&mut self, device_ids: &[String]) -> Result<(), SwitcherooError> {
+        self.config.set_order(device_ids);
+        self.config.sort_devices(self.switcher.outputs_mut());
+        self.invalidate_popup_menu();
+        // The rotation may go somewhere else next.
+        self.prepare_standby();
+        self.save_config()
+    }
+
+    /// Remembers where a window was moved to, for the current monitors.
+    fn window_moved(&mut self, name: &str, window: HWND) -> Result<(), SwitcherooError> {
+        let key = window_placement::placement_key(name, &window_placement::monitor_layout());
+        let placement = window_placement::get(window)?;
+        debug!("{key} moved to {placement:?}");
+        self.config.windows.insert(key, placement);
+        self.save_config()
+    }
+
+    fn save_config(&self) -> Result<(), SwitcherooError> {
+        if self.machine_config.kiosk {
+            debug!("Not saving config in kiosk mode");
+            return Ok(());
+        }
+        if self.safe_mode {
+            // Keep the config that was ignored for when safe mode is left.
+            debug!("Not saving config in safe mode");
+            return Ok(());
+        }
+        if self
+            .problems
+            .iter()
+            .any(|problem| matches!(problem, Problem::Config(_)))
+        {
+            // Leave the unreadable config for the user to fix rather than replace it.
+            debug!("Not saving config over one that couldn't be read");
+            return Ok(());
+        }
+        self.config.save_to(&self.config_path)
+    }
+
+    /// Re-enumerates the capture endpoints, returning the default one's ID (empty if there is
+    /// none).
+    fn refresh_microphones(&mut self) -> String {
+        if self.audio_service_stopped {
+            self.microphones.clear();
+            return String::new();
+        }
+        self.microphones = self
+            .switcher
+            .backend()
+            .devices(eCapture)
+            .unwrap_or_else(|e| {
+                error!("Failed to list microphones: {e}");
+                Vec::new()
+            });
+        // Every microphone takes a turn, as there's no rotation to choose them for yet.
+        for microphone in &mut self.microphones {
+            microphone.selectable = true;
+        }
+        self.switcher
+            .backend()
+            .default_device(eCapture, eConsole)
+            .unwrap_or_default()
+    }
+
+    fn next_microphone(&mut self) -> Result<(), SwitcherooError> {
+        let current_microphone = self.refresh_microphones();
+        debug!("Switching to next microphone from: {current_microphone}");
+        let Some(microphone) = rotation::select_next(
+            &current_microphone,
+            &self.microphones,
+            &self.config.rotation,
+        ) else {
+            debug!("No other microphones found");
+            return Ok(());
+        };
+        info!("Switching microphone to: {}", microphone.friendly_name);
+        set_default_device(
+            self.switcher.backend(),
+            &microphone.id,
+            &self.config.roles(),
         )?;
+        Ok(())
+    }
 
-        for device in devices.iter().rev() {
-            debug!(
-                "Adding device to popup menu: {:?} {:?}",
-                device.friendly_name,
-                device_id_to_menu_id(&device.id)
-            );
-            safe_strings::with_wide_str_mut(
-                &device.friendly_name,
-                |device_name| -> Result<(), Box<dyn Error>> {
-                    // Insert the device into the popup menu.
-                    InsertMenuItemW(
-                        menu,
-                        0,
-                        true,
-                        &MENUITEMINFOW {
-                            cbSize: std::mem::size_of::<MENUITEMINFOW>() as u32,
-                            fMask: MIIM_FTYPE | MIIM_ID | MIIM_STRING | MIIM_STATE,
-                            fType: MFT_STRING,
-                            fState: if device.selectable {
-                                windows::Win32::UI::WindowsAndMessaging::MFS_CHECKED
-                            } else {
-                                windows::Win32::UI::WindowsAndMessaging::MFS_UNCHECKED
-                            },
-                            dwTypeData: device_name,
-                            cch: device_name.len() as u32 - 1,
-                            wID: device_id_to_menu_id(&device.id),
-                            ..Default::default()
-                        },
-                    )?;
-                    Ok(())
-                },
-            )?;
+    /// Keeps track of the app in the foreground while only its audio is switched, or rules wait
+    /// for an app to come to the foreground.
+    fn watch_foreground(&self) {
+        if !self.config.switch_focused_app
+            && (self.safe_mode || !rules::watches_foreground(&self.config.rules))
+        {
+            foreground::stop();
+        } else if let Err(e) = foreground::watch(self.window) {
+            error!("Failed to watch the foreground app: {e}");
         }
-        // Add a separator.
-        InsertMenuItemW(
-            menu,
-            0,
-            true,
-            &MENUITEMINFOW {
-                cbSize: std::mem::size_of::<MENUITEMINFOW>() as u32,
-                fMask: MIIM_FTYPE,
-                fType: MFT_SEPARATOR,
-                ..Default::default()
-            },
-        )?;
-        // Add an item for the current device.
-        safe_strings::with_wide_str_mut(
-            &current_device.friendly_name,
-            |current_name| -> Result<(), Box<dyn Error>> {
-                // Insert the current device into the popup menu.
-                InsertMenuItemW(
-                    menu,
-                    0,
-                    true,
-                    &MENUITEMINFOW {
-                        cbSize: std::mem::size_of::<MENUITEMINFOW>() as u32,
-                        fMask: MIIM_FTYPE | MIIM_STATE | MIIM_STRING | MIIM_ID,
-                        fType: MFT_STRING,
-                        dwTypeData: current_name,
-                        cch: current_name.len() as u32 - 1,
-                        fState: MFS_DISABLED,
-                        wID: POPUP_CURRENT_DEVICE_ID,
-                        ..Default::default()
-                    },
-                )?;
-                Ok(())
-            },
-        )?;
-        // Add a separator.
-        InsertMenuItemW(
-            menu,
-            0,
-            true,
-            &MENUITEMINFOW {
-                cbSize: std::mem::size_of::<MENUITEMINFOW>() as u32,
-                fMask: MIIM_FTYPE,
-                fType: MFT_SEPARATOR,
-                ..Default::default()
-            },
-        )?;
-        // Add a menu item for the about dialog.
-        safe_strings::with_wide_str_mut("About", |about_name| -> Result<(), Box<dyn Error>> {
-            InsertMenuItemW(
-                menu,
-                0,
-                true,
-                &MENUITEMINFOW {
-                    cbSize: std::mem::size_of::<MENUITEMINFOW>() as u32,
-                    fMask: MIIM_FTYPE | MIIM_ID | MIIM_STRING,
-                    fType: MFT_STRING,
-                    dwTypeData: about_name,
-                    cch: about_name.len() as u32 - 1,
-                    wID: POPUP_ABOUT_ID,
-                    ..Default::default()
-                },
-            )?;
-            Ok(())
-        })?;
-        // Add a nice name to the top of the menu.
-        safe_strings::with_wide_str_mut(
-            "Sound Switcheroo",
-            |title_name| -> Result<(), Box<dyn Error>> {
-                InsertMenuItemW(
-                    menu,
-                    0,
-                    true,
-                    &MENUITEMINFOW {
-                        cbSize: std::mem::size_of::<MENUITEMINFOW>() as u32,
-                        fMask: MIIM_FTYPE | MIIM_STATE | MIIM_STRING,
-                        fType: MFT_STRING,
-                        dwTypeData: title_name,
-                        cch: title_name.len() as u32 - 1,
-                        fState: MFS_DISABLED,
-                        ..Default::default()
-                    },
-                )?;
-                Ok(())
-            },
-        )?;
-        Ok(menu)
     }
-}
 
-unsafe fn propvariant_to_string(propvar: &PROPVARIANT) -> Result<String, Box<dyn Error>> {
-    unsafe {
-        match propvar.vt() {
-            VT_LPWSTR => Ok(String::from_utf16_lossy(
-                propvar.Anonymous.Anonymous.Anonymous.pwszVal.as_wide(),
-            )),
-            _ => {
-                bail!("Unsupported PROPVARIANT type: {:?}", propvar.vt());
+    /// Sends the apps switched on their own back to the default device, once only the focused app
+    /// is no longer switched.
+    fn unroute_apps(&mut self) {
+        if self.config.switch_focused_app {
+            return;
+        }
+        for pid in std::mem::take(&mut self.routed_apps) {
+            // Every role, whichever were switched back then. Apps that have since closed take their
+            // setting with them.
+            if let Err(e) = app_routing::unroute(pid, eRender, &[eConsole, eCommunications]) {
+                debug!("Failed to send process {pid} back to the default device: {e}");
             }
         }
     }
-}
 
-fn get_available_audio_devices() -> Result<Vec<AudioDevice>, Box<dyn Error>> {
-    let mut devices = Vec::new();
-    unsafe {
-        let device_enumerator: IMMDeviceEnumerator =
-            CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)?;
-        let endpoints = device_enumerator.EnumAudioEndpoints(
-            windows::Win32::Media::Audio::eRender,
-            windows::Win32::Media::Audio::DEVICE_STATE_ACTIVE,
-        )?;
+    /// Sends the audio of the app last in the foreground to `device`, leaving the default device
+    /// as it is.
+    fn switch_focused_app(
+        &mut self,
+        pid: u32,
+        device: &AudioDevice,
+    ) -> Result<(), SwitcherooError> {
+        let app = ipc_auth::process_executable(pid)
+            .ok()
+            .and_then(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| format!("process {pid}"));
+        info!("Switching {app} to device: {}", device.friendly_name);
+        app_routing::route(pid, &device.id, eRender, &self.config.roles())?;
+        if !self.routed_apps.contains(&pid) {
+            self.routed_apps.push(pid);
+        }
+        if self.config.show_osd
+            && let Err(e) = self.show_osd(device)
+        {
+            error!("Failed to show the OSD: {e}");
+        }
+        Ok(())
+    }
 
-        for i in 0..endpoints.GetCount()? {
-            let endpoint = endpoints.Item(i)?;
-            let device_id = endpoint.GetId()?;
-            let device_id_str = device_id.to_string()?;
-            let props = endpoint.OpenPropertyStore(STGM_READ)?;
-            let friendly_name = props.GetValue(&PKEY_Device_FriendlyName)?;
-            let form_factor_var = props.GetValue(&PKEY_AudioEndpoint_FormFactor)?;
-            let form_factor: EndpointFormFactor = match form_factor_var.vt() {
-                VT_UI4 => {
-                    EndpointFormFactor(form_factor_var.Anonymous.Anonymous.Anonymous.ulVal as i32)
-                }
-                _ => {
-                    bail!(
-                        "Unsupported PROPVARIANT type for form factor: {:?}",
-                        form_factor_var,
-                    );
+    /// Opens a render client on the output the next switch will most likely go to, if the config
+    /// asks for it, closing the one on any other.
+    fn prepare_standby(&mut self) {
+        if !self.config.warm_standby
+            || self.audio_service_stopped
+            || self.power_policy.reduce_background_activity()
+        {
+            self.standby = None;
+            return;
+        }
+        let Ok(current) = self.current_output() else {
+            return;
+        };
+        let included = self.included_devices(&current);
+        let predicted = warm_standby::predict(&current, &included, &self.config.rotation, |id| {
+            self.config.devices.get(id)?.last_used
+        });
+        if self.standby.as_ref().map(WarmStandby::device_id) == predicted.map(|d| d.id.as_str()) {
+            return;
+        }
+        self.standby = None;
+        let Some(device) = predicted else {
+            return;
+        };
+        let started = Instant::now();
+        match WarmStandby::open(&device.id) {
+            Ok(standby) => {
+                debug!(
+                    "Keeping {} warm, opened in {} ms",
+                    device.friendly_name,
+                    started.elapsed().as_millis()
+                );
+                self.standby = Some(standby);
+            }
+            Err(e) => debug!("Failed to keep {} warm: {e}", device.friendly_name),
+        }
+    }
+
+    fn tooltip(&self, device: &AudioDevice) -> [u16; 128] {
+        device_tooltip(
+            &self.config.tooltip,
+            self.switcher.backend(),
+            device,
+            None,
+            &self.decimal_separator,
+        )
+    }
+
+    /// What scripts see of a device.
+    fn script_device(&self, device: &AudioDevice) -> ScriptDevice {
+        ScriptDevice {
+            id: device.id.clone(),
+            name: device.friendly_name.to_string(),
+            selectable: device.selectable,
+            volume: self
+                .switcher
+                .backend()
+                .volume(&device.id)
+                .ok()
+                .map(|volume| (volume * 100.0).round() as u32),
+        }
+    }
+
+    fn script_state(&self) -> ScriptState {
+        ScriptState {
+            devices: self
+                .switcher
+                .outputs()
+                .iter()
+                .map(|device| self.script_device(device))
+                .collect(),
+            default_device: self.current_output().unwrap_or_default(),
+        }
+    }
+
+    /// Loads scripts that are new or have changed, running their top-level code.
+    fn reload_scripts(&mut self) {
+        let Some(mut scripts) = self.scripts.take() else {
+            return;
+        };
+        let commands = scripts.reload(|| self.script_state());
+        self.scripts = Some(scripts);
+        self.carry_out_script_commands(commands);
+    }
+
+    /// Starts or stops what runs in the background for the power policy, after it changed: the warm
+    /// standby, and the checks for the schedule, the night and scripts.
+    fn follow_power_policy(&mut self) {
+        self.prepare_standby();
+        self.watch_schedule();
+        self.watch_scripts();
+    }
+
+    /// Checks for scheduled rules and the start and end of the night while there are any to
+    /// check for: every `SCHEDULE_CHECK_MS`, or only once a minute, just after it starts, while
+    /// saving power.
+    fn watch_schedule(&self) {
+        unsafe {
+            if self.safe_mode
+                || !(self.config.night_mode.enabled || rules::watches_schedule(&self.config.rules))
+            {
+                let _ = KillTimer(Some(self.window), SCHEDULE_TIMER_ID);
+            } else if self.power_policy.reduce_background_activity() {
+                let delay = rules::until_next_minute_ms() + SCHEDULE_MINUTE_MARGIN_MS;
+                SetTimer(Some(self.window), SCHEDULE_TIMER_ID, delay, None);
+            } else {
+                SetTimer(
+                    Some(self.window),
+                    SCHEDULE_TIMER_ID,
+                    SCHEDULE_CHECK_MS,
+                    None,
+                );
+            }
+        }
+    }
+
+    /// Checks the scripts directory for changes every so often, unless saving power.
+    fn watch_scripts(&self) {
+        unsafe {
+            if self.scripts.is_some() && !self.power_policy.reduce_background_activity() {
+                SetTimer(Some(self.window), SCRIPTS_TIMER_ID, SCRIPTS_CHECK_MS, None);
+            } else {
+                let _ = KillTimer(Some(self.window), SCRIPTS_TIMER_ID);
+            }
+        }
+    }
+
+    /// Has the scripts handle `event`, unless it was set off by a script.
+    fn fire_script_event(&mut self, event: Event, device: &AudioDevice) {
+        if self.running_scripts || self.scripts.as_ref().is_none_or(Scripts::is_empty) {
+            return;
+        }
+        let script_device = self.script_device(device);
+        let state = self.script_state();
+        if let Some(scripts) = &mut self.scripts {
+            let commands = scripts.fire(event, &script_device, state);
+            self.carry_out_script_commands(commands);
+        }
+    }
+
+    fn carry_out_script_commands(&mut self, commands: Vec<ScriptCommand>) {
+        self.running_scripts = true;
+        for command in commands {
+            debug!("Script command: {command:?}");
+            let result = match &command {
+                ScriptCommand::SetDefault(name_or_id) => {
+                    match cli::find_device(self.switcher.outputs(), name_or_id) {
+                        Ok(device) => self.try_dispatch(Action::SwitchTo(device.id.clone())),
+                        Err(e) => Err(e.into()),
+                    }
+                }
+                ScriptCommand::SetVolume(name_or_id, percent) => {
+                    match cli::find_device(self.switcher.outputs(), name_or_id) {
+                        Ok(device) => self.switcher.backend().set_volume(
+                            &device.id,
+                            (*percent).min(self.volume_limit(&device.id)) as f32 / 100.0,
+                        ),
+                        Err(e) => Err(e.into()),
+                    }
                 }
+                ScriptCommand::Notify(title, text) => self.show_notification(title, text),
             };
-            devices.push(AudioDevice {
-                id: device_id_str,
-                friendly_name: propvariant_to_string(&friendly_name)?,
-                selectable: true,
-                form_factor,
-            });
+            if let Err(e) = result {
+                error!("Failed to carry out {command:?} for a script: {e}");
+            }
         }
+        self.running_scripts = false;
     }
-    Ok(devices)
-}
 
-/// Gets the path to the user's roaming AppData directory
-fn get_roaming_appdata_path() -> Result<PathBuf, Box<dyn Error>> {
-    unsafe {
-        let path_ptr =
-            SHGetKnownFolderPath(&FOLDERID_RoamingAppData, KNOWN_FOLDER_FLAG::default(), None)?;
+    fn show_osd(&mut self, device: &AudioDevice) -> Result<(), SwitcherooError> {
+        let icon = self.icon_for_form_factor(device.form_factor)?;
+        let osd = match &mut self.osd {
+            Some(osd) => osd,
+            none => none.insert(Osd::create()?),
+        };
+        osd.show(icon, &device.friendly_name, is_dark_mode().unwrap_or(false));
+        Ok(())
+    }
 
-        let path_str = path_ptr.to_string()?;
-        let path = PathBuf::from(path_str);
+    /// Carries out a command sent over the named pipe. None of them change the config, so kiosk
+    /// mode allows them all.
+    fn pipe_command(
+        &mut self,
+        command: &pipe_server::Command,
+    ) -> Result<serde_json::Value, SwitcherooError> {
+        let current_device_id = self.current_output()?;
+        match command {
+            pipe_server::Command::ListDevices => {
+                let listings: Vec<_> = self
+                    .switcher
+                    .outputs()
+                    .iter()
+                    .map(|device| DeviceListing::new(device, &current_device_id))
+                    .collect();
+                Ok(serde_json::to_value(listings)?)
+            }
+            pipe_server::Command::GetDefault => {
+                let Some(device) = self
+                    .switcher
+                    .outputs()
+                    .iter()
+                    .find(|device| device.id == current_device_id)
+                else {
+                    bail!("Current device not found: {current_device_id}");
+                };
+                Ok(serde_json::to_value(DeviceListing::new(
+                    device,
+                    &current_device_id,
+                ))?)
+            }
+            pipe_server::Command::Switch { device } => {
+                let device = cli::find_device(self.switcher.outputs(), device)?.clone();
+                self.try_dispatch(Action::SwitchTo(device.id.clone()))?;
+                Ok(serde_json::to_value(DeviceListing::new(
+                    &device, &device.id,
+                ))?)
+            }
+            pipe_server::Command::ToggleMute => {
+                Ok(serde_json::json!({ "muted": self.toggle_mute()? }))
+            }
+        }
+    }
 
-        // Free the memory allocated by SHGetKnownFolderPath
-        windows::Win32::System::Com::CoTaskMemFree(Some(path_ptr.as_ptr() as *const _));
+    fn update_tray_icon(&self, icon: HICON, tooltip: [u16; 128]) -> Result<(), SwitcherooError> {
+        if !self.show_tray {
+            return Ok(());
+        }
+        // The shell keeps its own copy, so the slashed one can go straight away.
+        let slashed = if self.microphone_muted() {
+            Some(drawn_icons::slashed(icon)?)
+        } else {
+            None
+        };
+        defer!({
+            if let Some(slashed) = slashed {
+                let _ = unsafe { DestroyIcon(slashed) };
+            }
+        });
+        let icon = slashed.unwrap_or(icon);
+        unsafe {
+            Shell_NotifyIconW(
+                NIM_MODIFY,
+                &NOTIFYICONDATAW {
+                    hIcon: icon,
+                    // Both NIF_TIP & NIF_SHOWTIP are required to actually show the tooltip.
+                    uFlags: NIF_ICON
+                        | NIF_MESSAGE
+                        | NIF_TIP
+                        | NIF_SHOWTIP
+                        | self.notify_icon.flags(),
+                    uCallbackMessage: WM_APP + 0x42,
+                    szTip: tooltip,
+                    Anonymous: NOTIFYICONDATAW_0 {
+                        uVersion: NOTIFYICON_VERSION_4,
+                    },
+                    ..self.notify_icon.data(self.window)
+                },
+            )
+            .ok()?;
+        }
 
-        Ok(path)
+        Ok(())
     }
 }
 
-/// Gets the full path to the AudioSwitch configuration file
-fn get_config_file_path() -> Result<PathBuf, Box<dyn Error>> {
-    let mut path = get_roaming_appdata_path()?;
-    path.push("PurpleHatstands");
-    path.push("SoundSwitcheroo");
+impl SwitchHost for AudioSwitch {
+    fn switcher(&self) -> &Switcher {
+        &self.switcher
+    }
+
+    fn switcher_mut(&mut self) -> &mut Switcher {
+        &mut self.switcher
+    }
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn config_mut(&mut self) -> &mut Config {
+        &mut self.config
+    }
+
+    fn carry_out(&mut self, action: Action) -> Result<(), SwitcherooError> {
+        match action {
+            Action::NextDevice => self.next_device(self.config.rotation),
+            Action::PreviousDevice => self.next_device(self.config.rotation.reversed()),
+            Action::SwapDevices => self.swap_devices(),
+            Action::SwitchTo(device_id) => {
+                let device = self.output_with_id(&device_id)?;
+                self.switch_to(&device)
+            }
+            Action::ChooseDevice(device_id) => {
+                let device = self.output_with_id(&device_id)?;
+                self.switch_chosen(&device)
+            }
+            Action::NextMicrophone => self.next_microphone(),
+            Action::SwitchMicrophoneTo(microphone_id) => {
+                self.refresh_microphones();
+                let Some(microphone) = self.microphones.iter().find(|m| m.id == microphone_id)
+                else {
+                    bail!("No such microphone: {microphone_id}");
+                };
+                info!("Switching microphone to: {}", microphone.friendly_name);
+                set_default_device(
+                    self.switcher.backend(),
+                    &microphone.id,
+                    &self.config.roles(),
+                )?;
+                Ok(())
+            }
+            Action::ApplyProfile(name) => {
+                let Some(profile) = self
+                    .config
+                    .profiles
+                    .iter()
+                    .find(|p| p.name == name)
+                    .cloned()
+                else {
+                    bail!("No such profile: {name}");
+                };
+                self.apply_profile(&profile)
+            }
+            Action::RestoreSnapshot(name) => self.restore_snapshot(&name),
+        }
+    }
+
+    /// Shows the switch in the tray icon, the OSD and the usage history, and tells scripts and
+    /// hooks about it.
+    fn switched(
+        &mut self,
+        device: &AudioDevice,
+        elapsed: Option<Duration>,
+    ) -> Result<(), SwitcherooError> {
+        // Whatever profile was applied, this is a new setup.
+        self.profile_icon = None;
+        if self.config.warm_standby
+            && let Some(elapsed) = elapsed
+        {
+            let warm = self
+                .standby
+                .as_ref()
+                .is_some_and(|standby| standby.device_id() == device.id);
+            self.switch_timings.record(warm, elapsed);
+            debug!(
+                "Switched in {} ms {}, on average {}",
+                elapsed.as_millis(),
+                if warm { "warm" } else { "cold" },
+                self.switch_timings
+            );
+        }
+        if self.night
+            && let Err(e) = self.limit_night_volume()
+        {
+            error!("Failed to limit the volume for the night: {e}");
+        }
+        // Along with the volume remembered for the device switched away from.
+        if (self.config.pause_unused_after_days.is_some() || self.config.remember_volumes)
+            && let Err(e) = self.save_config()
+        {
+            error!("Failed to save config: {e}");
+        }
+        if self.config.usage_insights
+            && !self.safe_mode
+            && !self.machine_config.kiosk
+            && let Err(e) = self
+                .history
+                .record(Switch::now(&device.id, &device.friendly_name.to_string()))
+        {
+            error!("Failed to record the switch: {e}");
+        }
+        if let Some(command) = self
+            .config
+            .devices
+            .get(&device.id)
+            .and_then(|device_config| device_config.remote_bridge.as_deref())
+        {
+            match hooks::run(command) {
+                Ok(bridge) => info!("Started `{command}` (process {})", bridge.id()),
+                Err(e) => error!("Failed to start `{command}`: {e}"),
+            }
+        }
+        if self.config.show_osd
+            && let Err(e) = self.show_osd(device)
+        {
+            error!("Failed to show the OSD: {e}");
+        }
+        self.fire_script_event(Event::Switch, device);
+        self.prepare_standby();
+        if !self.show_tray {
+            return Ok(());
+        }
+        // Update the tooltip to reflect the new current device.
+        self.update_tray_icon(self.tray_icon(device)?, self.tooltip(device))
+    }
+
+    /// The device the app last in the foreground has its audio sent to, if only its audio is
+    /// being switched and it's been given one.
+    fn focused_app_output(&self) -> Option<String> {
+        if !self.config.switch_focused_app {
+            return None;
+        }
+        app_routing::routed_device(foreground::focused_app()?, eRender, eConsole).unwrap_or_else(
+            |e| {
+                error!("Failed to read the focused app's device: {e}");
+                None
+            },
+        )
+    }
 
-    // Create the directory if it doesn't exist
-    if !path.exists() {
-        fs::create_dir_all(&path)?;
+    /// Switches to a device the user picked from the menu, a hotkey or the rotation: only for the
+    /// app in the foreground if that's what's asked for, otherwise as a whole.
+    fn switch_chosen(&mut self, device: &AudioDevice) -> Result<(), SwitcherooError> {
+        if self.config.switch_focused_app
+            && let Some(pid) = foreground::focused_app()
+        {
+            return self.switch_focused_app(pid, device);
+        }
+        self.switch_to(device)
     }
 
-    path.push("device_config.json");
-    debug!("Config file path: {}", path.display());
-    Ok(path)
+    /// The highest volume the output may be turned up to, which is lower for speakers at night.
+    fn volume_limit(&self, device_id: &str) -> u32 {
+        let limited = self.night
+            && !self.night_limit_lifted
+            && self
+                .switcher
+                .outputs()
+                .iter()
+                .any(|d| d.id == device_id && NightModeConfig::limits(d.form_factor));
+        if limited {
+            self.config.night_mode.max_volume.min(100)
+        } else {
+            100
+        }
+    }
 }
 
-/// Saves the selectable state of devices to a JSON file in the roaming AppData directory
-fn save_device_selectable_state(devices: &[AudioDevice]) -> Result<(), Box<dyn Error>> {
-    let config_path = get_config_file_path()?;
+/// Whether to show balloon notifications, respecting the user's Focus Assist setting.
+fn notifications_allowed() -> bool {
+    match focus_assist::current() {
+        Ok(focus_assist) if focus_assist.suppresses_notifications() => {
+            info!("Not showing notification while Focus Assist is {focus_assist:?}");
+            false
+        }
+        Ok(_) => true,
+        Err(e) => {
+            // Better to show a notification than to silently lose it.
+            error!("Failed to query Focus Assist: {e}");
+            true
+        }
+    }
+}
 
-    // Create a map of device_id -> selectable state
-    let device_states: HashMap<String, bool> = devices
-        .iter()
-        .map(|device| (device.id.clone(), device.selectable))
-        .collect();
+// The heading of the dialogs saying a device's effects couldn't be changed.
+fn effects_heading(name: &str) -> String {
+    format!("Couldn't change the audio effects of {name}")
+}
 
-    let json_data = serde_json::to_string_pretty(&device_states)?;
-    fs::write(&config_path, json_data)?;
+// Whether Windows reports the machine as docked.
+fn system_docked() -> bool {
+    unsafe { GetSystemMetrics(SM_SYSTEMDOCKED) != 0 }
+}
 
-    debug!(
-        "Saved device selectable state to: {}",
-        config_path.display()
-    );
-    Ok(())
+unsafe fn set_menu_item_checked(
+    menu: HMENU,
+    id: u32,
+    checked: bool,
+) -> Result<(), SwitcherooError> {
+    unsafe {
+        let mut mii = MENUITEMINFOW {
+            cbSize: std::mem::size_of::<MENUITEMINFOW>() as u32,
+            fMask: MIIM_STATE,
+            ..Default::default()
+        };
+        GetMenuItemInfoW(menu, id, false, &mut mii)?;
+        mii.fState = if checked {
+            mii.fState | MFS_CHECKED
+        } else {
+            mii.fState & !MFS_CHECKED
+        };
+        SetMenuItemInfoW(menu, id, false, &mii)?;
+        Ok(())
+    }
+}
+
+unsafe fn set_menu_item_label(menu: HMENU, id: u32, label: &str) -> Result<(), SwitcherooError> {
+    safe_strings::with_wide_str_mut(label, |label| -> Result<(), SwitcherooError> {
+        let mii = MENUITEMINFOW {
+            cbSize: std::mem::size_of::<MENUITEMINFOW>() as u32,
+            fMask: MIIM_STRING,
+            dwTypeData: label,
+            ..Default::default()
+        };
+        unsafe { SetMenuItemInfoW(menu, id, false, &mii)? };
+        Ok(())
+    })
+}
+
+unsafe fn create_popup_menu(entries: &[MenuEntry]) -> Result<HMENU, SwitcherooError> {
+    unsafe {
+        let menu = CreatePopupMenu()?;
+        insert_menu_entries(menu, entries)?;
+        Ok(menu)
+    }
 }
 
-/// Loads the selectable state of devices from the JSON file in the roaming AppData directory
-fn load_device_selectable_state() -> Result<HashMap<String, bool>, Box<dyn Error>> {
-    let config_path = get_config_file_path()?;
+// Fills `menu`, which must be empty.
+unsafe fn insert_menu_entries(menu: HMENU, entries: &[MenuEntry]) -> Result<(), SwitcherooError> {
+    unsafe {
+        for (position, entry) in entries.iter().enumerate() {
+            match entry {
+                MenuEntry::Separator => {
+                    InsertMenuItemW(
+                        menu,
+                        position as u32,
+                        true,
+                        &MENUITEMINFOW {
+                            cbSize: std::mem::size_of::<MENUITEMINFOW>() as u32,
+                            fMask: MIIM_FTYPE,
+                            fType: MFT_SEPARATOR,
+                            ..Default::default()
+                        },
+                    )?;
+                }
+                MenuEntry::Item(item) => {
+                    let mut state = MENU_ITEM_STATE::default();
+                    if item.checked {
+                        state |= MFS_CHECKED;
+                    }
+                    if item.disabled {
+                        state |= MFS_DISABLED;
+                    }
+                    let mut item_type = MFT_STRING;
+                    if item.radio {
+                        item_type |= MFT_RADIOCHECK;
+                    }
+                    safe_strings::with_wide_str_mut(
+                        &item.label,
+                        |label| -> Result<(), SwitcherooError> {
+                            InsertMenuItemW(
+                                menu,
+                                position as u32,
+                                true,
+                                &MENUITEMINFOW {
+                                    cbSize: std::mem::size_of::<MENUITEMINFOW>() as u32,
+                                    fMask: MIIM_FTYPE | MIIM_ID | MIIM_STRING | MIIM_STATE,
+                                    fType: item_type,
+                                    fState: state,
+                                    dwTypeData: label,
+                                    cch: label.len() as u32 - 1,
+                                    wID: item.id,
+                                    ..Default::default()
+                                },
+                            )?;
+                            Ok(())
+                        },
+                    )?;
+                }
+                MenuEntry::Submenu { label, entries } => {
+                    // Destroyed along with the parent menu.
+                    let submenu = create_popup_menu(entries)?;
+                    safe_strings::with_wide_str_mut(
+                        label,
+                        |label| -> Result<(), SwitcherooError> {
+                            InsertMenuItemW(
+                                menu,
+                                position as u32,
+                                true,
+                                &MENUITEMINFOW {
+                                    cbSize: std::mem::size_of::<MENUITEMINFOW>() as u32,
+                                    fMask: MIIM_FTYPE | MIIM_STRING | MIIM_SUBMENU,
+                                    fType: MFT_STRING,
+                                    dwTypeData: label,
+                                    hSubMenu: submenu,
+                                    ..Default::default()
+                                },
+                            )?;
+                            Ok(())
+                        },
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_dark_mode() -> Result<bool, SwitcherooError> {
+    let theme_key = windows_registry::CURRENT_USER
+        .open(r"Software\Microsoft\Windows\CurrentVersion\Themes\Personalize")?;
+    let light_theme = theme_key.get_u32("AppsUseLightTheme")? == 1;
+    Ok(!light_theme)
+}
+
+unsafe fn load_icon(icon_name: &str) -> Result<HICON, SwitcherooError> {
+    unsafe {
+        let module = GetModuleHandleW(None)?;
+        let icon = with_wide_str(icon_name, |wide_icon_name| {
+            LoadIconW(Some(module.into()), wide_icon_name)
+        })?;
+        if icon.is_invalid() {
+            bail!("Failed to load icon: {}", icon_name);
+        }
+        Ok(icon)
+    }
+}
 
-    if !config_path.exists() {
-        debug!("Config file does not exist: {}", config_path.display());
-        return Ok(HashMap::new());
+// At the small icon size the tray shows, rather than the large size `LR_DEFAULTSIZE` picks.
+unsafe fn load_icon_file(path: &Path) -> Result<HICON, SwitcherooError> {
+    unsafe {
+        let icon = with_wide_str(&path.to_string_lossy(), |wide_path| {
+            LoadImageW(
+                None,
+                wide_path,
+                IMAGE_ICON,
+                GetSystemMetrics(SM_CXSMICON),
+                GetSystemMetrics(SM_CYSMICON),
+                LR_LOADFROMFILE,
+            )
+        })
+        .map_err(|e| SwitcherooError::Other(format!("Failed to load {}: {e}", path.display())))?;
+        Ok(HICON(icon.0))
     }
+}
 
-    let json_data = fs::read_to_string(&config_path)?;
-    let device_states: HashMap<String, bool> = serde_json::from_str(&json_data)?;
+fn main() -> ExitCode {
+    // Checked before anything is written, so that --help and early errors are visible too.
+    let console = std::env::args_os().any(|arg| arg == "--console");
+    let json_errors = std::env::args_os().any(|arg| arg == "--json-errors");
+    if console && let Err(e) = console::attach() {
+        return report_error(&e, json_errors, false);
+    }
+    logging::init();
+    info!("Audio Switch Tool");
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        // --help and --version
+        Err(e) if !e.use_stderr() => {
+            let _ = e.print();
+            return ExitCode::SUCCESS;
+        }
+        Err(e) if !json_errors => {
+            let _ = e.print();
+            return ExitCode::from(ErrorKind::Other.exit_code());
+        }
+        Err(e) => {
+            return report_error(&SwitcherooError::Other(e.to_string()), json_errors, console)
+        }
+    };
+    if !console && args.prints_output() {
+        // Not fatal, as the output may be redirected anyway.
+        let _ = console::attach();
+    }
+    if let Some(Command::Completions { shell }) = args.command {
+        clap_complete::generate(
+            shell,
+            &mut Args::command(),
+            env!("CARGO_BIN_NAME"),
+            &mut std::io::stdout(),
+        );
+        return ExitCode::SUCCESS;
+    }
+    if let Some(Command::Quit) = args.command {
+        let instance = Instance::new(args.instance.as_deref(), args.config.as_deref());
+        return match quit(&instance) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => report_error(&e, json_errors, console),
+        };
+    }
+    if let Some(Command::Elevated { pipe, parent }) = &args.command {
+        return match elevation::serve(pipe, *parent) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                error!("Elevated task failed: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+    // Nobody would see the error otherwise.
+    let show_dialog = !console && !json_errors && !args.no_tray;
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let code = report_error(&e, json_errors, console);
+            if show_dialog {
+                let _ = dialogs::error(None, "Sound Switcheroo stopped", &e.to_string());
+            }
+            code
+        }
+    }
+}
 
-    debug!(
-        "Loaded device selectable state from: {}",
-        config_path.display()
-    );
-    Ok(device_states)
+/// Tells the user (or script) why the app failed, and picks the matching exit code.
+fn report_error(error: &SwitcherooError, json_errors: bool, console: bool) -> ExitCode {
+    let failure = Failure::from(error);
+    error!("{failure}");
+    if json_errors {
+        eprintln!("{}", failure.to_json());
+    } else if console {
+        status(Status::Error, &failure.message);
+    } else {
+        eprintln!("Error: {failure}");
+    }
+    ExitCode::from(failure.exit_code())
 }
 
-/// Applies the loaded selectable state to the current devices
-fn apply_device_selectable_state(
-    devices: &mut [AudioDevice],
-    saved_states: &HashMap<String, bool>,
-) {
-    for device in devices.iter_mut() {
-        if let Some(&selectable) = saved_states.get(&device.id) {
-            device.selectable = selectable;
-            debug!(
-                "Applied selectable state for device {}: {}",
-                device.friendly_name, selectable
-            );
+/// Carries out --list, --set or --next, without starting the tray icon.
+fn run_command(
+    backend: &dyn AudioBackend,
+    config_path: &Path,
+    args: &Args,
+) -> Result<(), SwitcherooError> {
+    let mut devices = backend.devices(eRender)?;
+    let mut config = Config::load_from(config_path).map_err(Failure::config)?;
+    config.apply_to_devices(&mut devices);
+    let devices = EndpointList::new(eRender, devices);
+    let role = args.role.map_or(eConsole, Role::erole);
+    let current_device_id = backend.default_device(eRender, role)?;
+    let device = match &args.set {
+        Some(name_or_id) => devices.find(name_or_id)?,
+        None if args.next => rotation::select_next(&current_device_id, &devices, &config.rotation)
+            .ok_or_else(|| {
+                Failure::new(ErrorKind::DeviceNotFound, "No other device in the rotation")
+            })?,
+        None => {
+            cli::write_devices(
+                &mut std::io::stdout(),
+                &devices,
+                &current_device_id,
+                args.output,
+            )?;
+            return Ok(());
         }
-    }
+    };
+    let roles = match args.role {
+        Some(role) => vec![role.erole()],
+        None => config.roles(),
+    };
+    set_default_device(backend, &device.id, &roles)?;
+    status(Status::Ok, &format!("Switched to {}", device.friendly_name));
+    Ok(())
 }
 
-fn is_dark_mode() -> Result<bool, Box<dyn Error>> {
-    let theme_key = windows_registry::CURRENT_USER
-        .open(r"Software\Microsoft\Windows\CurrentVersion\Themes\Personalize")?;
-    let light_theme = theme_key.get_u32("AppsUseLightTheme")? == 1;
-    Ok(!light_theme)
+/// The config path for a run that mustn't touch the real config, in a directory of its own in the
+/// temp directory, so the IPC token, history and snapshots kept beside it are its own too.
+fn scratch_config_path(name: &str) -> Result<PathBuf, SwitcherooError> {
+    let dir = std::env::temp_dir().join(name);
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir.join("device_config.json"))
 }
 
-unsafe fn load_icon(icon_name: &str) -> Result<HICON, Box<dyn Error>> {
-    unsafe {
-        let module = GetModuleHandleW(None)?;
-        let icon = with_wide_str(icon_name, |wide_icon_name| {
-            LoadIconW(Some(module.into()), wide_icon_name)
-        })?;
-        if icon.is_invalid() {
-            bail!("Failed to load icon: {}", icon_name);
-        }
-        Ok(icon)
-    }
+/// Asks the running instance to close, as Exit in its menu does.
+fn quit(instance: &Instance) -> Result<(), SwitcherooError> {
+    let Ok(window) = with_wide_str(&instance.window_class_name(), |class_name| unsafe {
+        FindWindowW(class_name, None)
+    }) else {
+        bail!("Sound Switcheroo isn't running");
+    };
+    info!("Asking the running app to close");
+    unsafe { PostMessageW(Some(window), WM_CLOSE, WPARAM::default(), LPARAM::default())? };
+    Ok(())
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    env_logger::init();
-    info!("Audio Switch Tool");
+fn run(args: Args) -> Result<(), SwitcherooError> {
+    let console = args.console;
     unsafe {
         debug!("Dark mode: {}", is_dark_mode()?);
-        CoInitializeEx(None, COINIT_APARTMENTTHREADED).ok()?;
+        let _com = ComApartment::init()?;
+        let mut config_override = args.config.clone();
+        let mut backend: Box<dyn AudioBackend> = match (&args.simulate, &args.import_snapshot) {
+            (Some(path), _) => {
+                // So the fake devices and choices about them don't end up in the real config.
+                let config_path = match &mut config_override {
+                    Some(config_path) => config_path,
+                    none => none.insert(scratch_config_path("sound-switcheroo-simulation")?),
+                };
+                if console {
+                    status(
+                        Status::Warning,
+                        &format!(
+                            "Simulating devices from {} with the config in {}",
+                            path.display(),
+                            config_path.display()
+                        ),
+                    );
+                }
+                Box::new(SimulatedBackend::load(path).map_err(Failure::config)?)
+            }
+            (None, Some(path)) => {
+                // So the snapshot's config doesn't replace the one in use here.
+                let config_path = scratch_config_path("sound-switcheroo-snapshot")?;
+                if console {
+                    status(
+                        Status::Warning,
+                        &format!(
+                            "Reproducing snapshot {} with its config in {}",
+                            path.display(),
+                            config_path.display()
+                        ),
+                    );
+                }
+                let backend = Snapshot::load(path)
+                    .and_then(|snapshot| snapshot.import(&config_path))
+                    .map_err(Failure::config)?;
+                config_override = Some(config_path);
+                Box::new(backend)
+            }
+            (None, None) => Box::new(ComBackend),
+        };
+        if let Some(path) = args.record.clone() {
+            if console {
+                status(
+                    Status::Ok,
+                    &format!("Recording devices to {}", path.display()),
+                );
+            }
+            backend = Box::new(RecordingBackend::new(backend, path));
+        }
+        let instance = Instance::new(args.instance.as_deref(), config_override.as_deref());
+        let config_path = match config_override {
+            Some(path) => path,
+            None => config::get_config_file_path().map_err(Failure::config)?,
+        };
+        if args.list || args.set.is_some() || args.next {
+            return run_command(backend.as_ref(), &config_path, &args);
+        }
+        if let Some(name) = &args.snapshot_audio {
+            RoutingSnapshot::take(backend.as_ref())?
+                .save(&routing_snapshot::snapshots_dir(&config_path), name)?;
+            status(Status::Ok, &format!("Saved snapshot {name}"));
+            return Ok(());
+        }
+        if let Some(name) = &args.restore_audio {
+            let missing =
+                RoutingSnapshot::load(&routing_snapshot::snapshots_dir(&config_path), name)?
+                    .restore(backend.as_ref())?;
+            if !missing.is_empty() {
+                status(
+                    Status::Warning,
+                    &format!("Not connected, so left out: {}", missing.join(", ")),
+                );
+            }
+            status(Status::Ok, &format!("Restored snapshot {name}"));
+            return Ok(());
+        }
+        let running = with_wide_str(&instance.mutex_name(), |name| {
+            CreateMutexW(None, false, name)
+        })?;
         defer!({
-            CoUninitialize();
+            let _ = CloseHandle(running);
+        });
+        if GetLastError() == ERROR_ALREADY_EXISTS {
+            bail!("Sound Switcheroo is already running with this config or instance name");
+        }
+        // A windowed app has no stderr, so without a console the log goes to a file next to the
+        // config instead, named for the instance. Only the tray opens it, so commands and second
+        // copies leave the running one's log alone.
+        if !console {
+            let path = config_path.with_file_name(instance.log_file_name());
+            if let Err(e) = logging::log_to_file(&path) {
+                error!("Failed to open the log file {}: {e}", path.display());
+            }
+        }
+        // Enumerate devices while the window is set up, as reading their properties can be slow.
+        let enumeration = std::thread::spawn(move || -> Result<_, Failure> {
+            let _com = ComApartment::init().map_err(|e| Failure::from(&e))?;
+            // The backend comes back even if listing fails, in case the audio service was just
+            // stopped and is started again later.
+            let listing = backend.devices(eRender).and_then(|mut devices| {
+                backend.read_mix_formats(&mut devices);
+                let current_device_id = backend.default_device(eRender, eConsole)?;
+                Ok((devices, current_device_id))
+            });
+            Ok((backend, listing.map_err(|e| Failure::from(&e))))
         });
         let module = GetModuleHandleW(None)?;
         // Register a window class for the taskbar icon.
-        let class = RegisterClassExW(&WNDCLASSEXW {
-            cbSize: std::mem::size_of::<WNDCLASSEXW>() as u32,
-            lpfnWndProc: Some(window_callback),
-            hInstance: module.into(),
-            lpszClassName: w!("SoundSwitcheroo"),
-            ..Default::default()
+        let class = with_wide_str(&instance.window_class_name(), |class_name| {
+            RegisterClassExW(&WNDCLASSEXW {
+                cbSize: std::mem::size_of::<WNDCLASSEXW>() as u32,
+                lpfnWndProc: Some(window_callback),
+                hInstance: module.into(),
+                lpszClassName: class_name,
+                ..Default::default()
+            })
         });
         defer!({
             // Unregister the class when done using its atom.
@@ -707,58 +3508,353 @@ fn main() -> Result<(), Box<dyn Error>> {
         .inspect_err(|err| {
             error!("Failed to create window: {:?} {:?}", err, GetLastError());
         })?;
-        let mut devices = get_available_audio_devices()?;
+        // Runs that crash before exiting cleanly are left in the marker, so a config that keeps
+        // crashing the app is ignored rather than leaving it unable to start.
+        let startup_marker = StartupMarker::new(config_path.with_extension("running"));
+        let safe_mode = startup_marker
+            .record_start(SystemTime::now())
+            .inspect_err(|e| error!("Failed to record start: {e}"))
+            .unwrap_or(false);
+        let machine_config = MachineConfig::load();
+        let kiosk = machine_config.kiosk;
+        // Plugins are asked for their menu items in the background, and added to the menu when
+        // they've answered.
+        match config::get_plugins_dir() {
+            Ok(dir) if !safe_mode && !kiosk => plugins::discover_in_background(window, dir),
+            Ok(_) => {}
+            Err(e) => error!("Failed to find plugins directory: {e}"),
+        }
+        // Problems found along the way are reported rather than stopping the app from starting.
+        let mut problems = Vec::new();
         // Load and apply device selectable state
-        let saved_states = load_device_selectable_state()?;
-        apply_device_selectable_state(&mut devices, &saved_states);
-        let current_device_id = get_current_default_endpoint(eConsole)?;
-        let current_device = devices
-            .iter()
-            .find(|d| d.id == current_device_id)
-            .ok_or_else(|| simple_error::SimpleError::new("Current device not found"))?;
-        let tooltip = current_device.friendly_name.clone();
-        let me = AudioSwitch {
+        let mut config = if safe_mode {
+            warn!("Starting in safe mode after repeated crashes");
+            Config::default()
+        } else if kiosk {
+            // Only what the administrator set up counts on a shared machine, never the user's.
+            match &machine_config.config_file {
+                Some(path) => Config::load_from(path).unwrap_or_else(|e| {
+                    error!("Failed to load the kiosk config {}: {e}", path.display());
+                    problems.push(Problem::Config(e.to_string()));
+                    Config::default()
+                }),
+                None => Config::default(),
+            }
+        } else {
+            Config::load_from(&config_path).unwrap_or_else(|e| {
+                error!("Failed to load config: {e}");
+                problems.push(Problem::Config(e.to_string()));
+                Config::default()
+            })
+        };
+        if !logging::overridden_by_env() {
+            logging::set_level(config.log_level);
+        }
+        let (backend, listing) = enumeration
+            .join()
+            .map_err(|_| SwitcherooError::Other("Device enumeration panicked".to_string()))??;
+        let (mut devices, current_device_id, audio_service_stopped) = match listing {
+            Ok((devices, current_device_id)) => {
+                if devices.is_empty() {
+                    problems.push(Problem::NoOutputDevices);
+                }
+                (devices, current_device_id, false)
+            }
+            // Rather than failing to start, wait for the service, offering to start it.
+            Err(e) if audio_service::is_running().is_ok_and(|running| !running) => {
+                warn!("Windows Audio is stopped: {e}");
+                (Vec::new(), String::new(), true)
+            }
+            Err(e) => {
+                error!("Failed to list devices: {e}");
+                problems.push(Problem::Com(e.to_string()));
+                (Vec::new(), String::new(), false)
+            }
+        };
+        if let Err(e) = backend.check_switching() {
+            error!("Can't set default devices: {e}");
+            problems.push(Problem::PolicyConfig(e.to_string()));
+        }
+        // An unreadable config looks like a first run, but isn't one, and without devices there's
+        // nothing to ask about.
+        let config_unreadable = problems.iter().any(|p| matches!(p, Problem::Config(_)));
+        let first_run = config.devices.is_empty() && !devices.is_empty() && !config_unreadable;
+        config.apply_to_devices(&mut devices);
+        let decimal_separator = tooltip::decimal_separator();
+        let (tooltip, current_device_name) = if audio_service_stopped {
+            (
+                safe_strings::truncated_wide(AUDIO_SERVICE_STOPPED),
+                AUDIO_SERVICE_STOPPED.to_string(),
+            )
+        } else if devices.is_empty() {
+            (
+                safe_strings::truncated_wide(NO_OUTPUT_DEVICES),
+                NO_OUTPUT_DEVICES.to_string(),
+            )
+        } else {
+            let current_device = devices
+                .iter()
+                .find(|d| d.id == current_device_id)
+                .ok_or_else(|| {
+                    Failure::new(
+                        ErrorKind::DeviceNotFound,
+                        format!("Current device not found: {current_device_id}"),
+                    )
+                })?;
+            (
+                device_tooltip(
+                    &config.tooltip,
+                    backend.as_ref(),
+                    current_device,
+                    None,
+                    &decimal_separator,
+                ),
+                current_device.friendly_name.to_string(),
+            )
+        };
+        let mut me = AudioSwitch {
             window,
-            icon: AdaptiveIcon::new("audio_icon", "audio_icon")?,
-            popup_menu: create_popup_menu(&devices, current_device)?,
-            available_devices: devices,
-            headphones_icon: AdaptiveIcon::new("headphones_icon", "headphones_icon_dark")?,
-            headset_icon: AdaptiveIcon::new("headset_icon", "headset_icon_dark")?,
-            speaker_icon: AdaptiveIcon::new("speaker_icon", "speaker_icon_dark")?,
+            show_tray: !args.no_tray,
+            // Until it's added, which can end up using another ID.
+            notify_icon: TrayIconId::Guid(instance.notify_icon_guid()),
+            icon: AdaptiveIcon::new("audio_icon", "audio_icon"),
+            profile_icon: None,
+            popup_menu: None,
+            popup_menu_defaults: Default::default(),
+            popup_menu_playing: None,
+            popup_menu_snapshots: Vec::new(),
+            popup_menu_ids: MenuIds::default(),
+            microphones: Vec::new(),
+            settings_window: None,
+            switcher: Switcher::new(backend, devices),
+            config,
+            history: History::new(insights::history_path(&config_path)),
+            schedule_checked: None,
+            networks: Vec::new(),
+            routed_apps: Vec::new(),
+            docked: system_docked(),
+            night: false,
+            night_limit_lifted: false,
+            night_volume: None,
+            config_path,
+            machine_config,
+            safe_mode,
+            audio_service_stopped,
+            problems,
+            hotkeys: None,
+            device_hotkeys: Vec::new(),
+            profile_hotkeys: None,
+            revert_to: None,
+            last_double_click: None,
+            paused_devices: Vec::new(),
+            whats_new: &[],
+            pending_click: Cell::new(None),
+            remap_offered: false,
+            asking_about_devices: false,
+            osd: None,
+            plugins: Vec::new(),
+            // Only watched if the directory is there at startup.
+            scripts: match config::get_scripts_dir() {
+                Ok(dir) if !safe_mode && !kiosk && dir.is_dir() => Some(Scripts::new(dir)),
+                Ok(_) => None,
+                Err(e) => {
+                    error!("Failed to find scripts directory: {e}");
+                    None
+                }
+            },
+            running_scripts: false,
+            standby: None,
+            switch_timings: SwitchTimings::default(),
+            wheel_notches: Notches::default(),
+            decimal_separator,
+            balance_unsaved: false,
+            power_policy: PowerPolicy::query().unwrap_or_else(|e| {
+                error!("Failed to query power status: {e}");
+                PowerPolicy::default()
+            }),
+            headphones_icon: AdaptiveIcon::new("headphones_icon", "headphones_icon_dark"),
+            headset_icon: AdaptiveIcon::new("headset_icon", "headset_icon_dark"),
+            speaker_icon: AdaptiveIcon::new("speaker_icon", "speaker_icon_dark"),
+            display_icon: AdaptiveIcon::drawn(Glyph::Display),
+            line_level_icon: AdaptiveIcon::drawn(Glyph::LineLevel),
+            spdif_icon: AdaptiveIcon::drawn(Glyph::Spdif),
+            unknown_icon: AdaptiveIcon::drawn(Glyph::Unknown),
         };
+        let mut missing_icons: Vec<String> = [
+            &me.icon,
+            &me.headphones_icon,
+            &me.headset_icon,
+            &me.speaker_icon,
+        ]
+        .into_iter()
+        .flat_map(AdaptiveIcon::missing)
+        .map(str::to_string)
+        .collect();
+        missing_icons.dedup();
+        if !missing_icons.is_empty() {
+            error!("Failed to load icons: {}", missing_icons.join(", "));
+            me.problems.push(Problem::Icons(missing_icons));
+        }
         // Store the AudioSwitch instance in the window's user data.
         SetWindowLongPtrW(window, GWLP_USERDATA, &me as *const _ as isize);
-        let notify_icon_data = &mut NOTIFYICONDATAW {
-            cbSize: std::mem::size_of::<NOTIFYICONDATAW>() as u32,
-            hWnd: window,
-            hIcon: me.current_icon()?,
-            guidItem: NOTIFY_ICON_GUID,
-            // Both NIF_TIP & NIF_SHOWTIP are required to actually show the tooltip.
-            uFlags: NIF_ICON | NIF_MESSAGE | NIF_GUID | NIF_TIP | NIF_SHOWTIP,
-            uCallbackMessage: WM_APP + 0x42,
-            szTip: string_to_tip(&tooltip),
-            Anonymous: NOTIFYICONDATAW_0 {
-                uVersion: NOTIFYICON_VERSION_4,
-            },
-            ..Default::default()
+        // Only needed to keep power_policy up to date, so not fatal.
+        let _power_notifications = PowerNotifications::register(window)
+            .inspect_err(|e| error!("Failed to register for power notifications: {e}"))
+            .ok();
+        // WM_DEVICECHANGE still covers most devices coming and going if this fails.
+        let endpoints_changed = poster(window, WM_AUDIO_ENDPOINTS_CHANGED);
+        let default_changed = poster(window, WM_DEFAULT_DEVICE_CHANGED);
+        let _endpoint_notifications = EndpointNotifications::register(move |change| match change {
+            EndpointChange::Endpoints => endpoints_changed(),
+            EndpointChange::DefaultOutput => default_changed(),
+        })
+        .inspect_err(|e| error!("Failed to register for endpoint notifications: {e}"))
+        .ok();
+        // For network rules, which don't run in safe mode.
+        let _network_notifications = if me.safe_mode {
+            None
+        } else {
+            me.networks = network::connected_networks().unwrap_or_else(|e| {
+                error!("Failed to list the connected networks: {e}");
+                Vec::new()
+            });
+            NetworkNotifications::register(window)
+                .inspect_err(|e| error!("Failed to register for network notifications: {e}"))
+                .ok()
+        };
+        me.register_hotkeys();
+        me.register_profile_hotkeys();
+        me.prepare_standby();
+        me.watch_schedule();
+        let _pipe_server = if me.config.ipc.enabled {
+            match ipc_auth::load_or_create_token(&ipc_auth::token_path(&me.config_path)) {
+                Ok(token) => Some(PipeServer::start(
+                    window,
+                    instance.pipe_name(),
+                    Authenticator::new(token, me.config.ipc.allowed_clients.clone()),
+                )),
+                Err(e) => {
+                    error!("Failed to load the IPC token, so not serving commands: {e}");
+                    None
+                }
+            }
+        } else {
+            None
         };
-        Shell_NotifyIconW(NIM_ADD, notify_icon_data).ok()?;
+        if me.show_tray {
+            me.notify_icon = tray_icon::add(
+                &me.notify_icon_data(me.current_icon()?, tooltip),
+                instance.notify_icon_guid(),
+            )?;
+        }
+        let notify_icon = me.notify_icon;
         defer!({
-            // Remove the icon when done.
+            // Remove the icon when done, if there is one.
             debug!("Removing taskbar icon");
-            let _ = Shell_NotifyIconW(
-                NIM_DELETE,
-                &NOTIFYICONDATAW {
-                    cbSize: std::mem::size_of::<NOTIFYICONDATAW>() as u32,
-                    uFlags: NIF_GUID,
-                    hWnd: window,
-                    guidItem: NOTIFY_ICON_GUID,
-                    ..Default::default()
-                },
-            );
+            let _ = Shell_NotifyIconW(NIM_DELETE, &notify_icon.data(window));
         });
-        // Enable better callback API.
-        Shell_NotifyIconW(NIM_SETVERSION, notify_icon_data).ok()?;
+        if me.show_tray {
+            me.set_notify_icon_version()?;
+            // Broadcast when Explorer restarts, which elevated windows don't get unless they ask.
+            if let Err(e) =
+                ChangeWindowMessageFilterEx(window, taskbar_created_message(), MSGFLT_ALLOW, None)
+            {
+                error!("Failed to allow the taskbar's messages: {e}");
+            }
+        }
+        if me.microphone_muted()
+            && let Err(e) = me.show_current_device()
+        {
+            error!("Failed to mark the microphone as muted: {e}");
+        }
+        if audio_service_stopped {
+            if let Err(e) = me.show_clickable_notification(
+                NotificationKind::AudioServiceStopped,
+                AUDIO_SERVICE_STOPPED,
+                "Click to start it.",
+            ) {
+                error!("Failed to notify about the audio service: {e}");
+            }
+        } else if safe_mode {
+            if let Err(e) = me.show_notification(
+                "Sound Switcheroo is in safe mode",
+                "It crashed several times in a row, so settings and rules are off. Exit and start \
+                 it again to leave safe mode.",
+            ) {
+                error!("Failed to notify about safe mode: {e}");
+            }
+        } else {
+            if !me.problems.is_empty()
+                && let Err(e) = me.show_notification(
+                    "Sound Switcheroo started with problems",
+                    "Choose \"What's wrong?\" in the menu for details.",
+                )
+            {
+                error!("Failed to notify about problems: {e}");
+            }
+            let names: Vec<_> = me
+                .hotkeys
+                .iter()
+                .chain(&me.profile_hotkeys)
+                .flat_map(|hotkeys| &hotkeys.unavailable)
+                .map(|hotkey| hotkey.to_string())
+                .collect();
+            if !names.is_empty()
+                && let Err(e) = me.show_notification(
+                    "Hotkeys unavailable",
+                    &format!("Another app is using {}.", names.join(", ")),
+                )
+            {
+                error!("Failed to notify about hotkeys: {e}");
+            }
+            me.apply_automatic_profile(false);
+            if me.config.check_output_at_startup
+                && !me.safe_mode
+                && !me.machine_config.kiosk
+                && !me.devices_unavailable()
+                && let Err(e) = me.check_default_output()
+            {
+                error!("Failed to check the default output: {e}");
+            }
+            me.watch_foreground();
+            me.reload_scripts();
+            me.watch_scripts();
+            if let Err(e) = me.check_night_mode() {
+                error!("Failed to apply night mode: {e}");
+            }
+            if let Err(e) = me.offer_remap() {
+                error!("Failed to offer to move settings to new device IDs: {e}");
+            }
+            if first_run
+                && me.show_tray
+                && !me.machine_config.kiosk
+                && let Err(e) = me.first_run_wizard()
+            {
+                error!("Failed to run first-run wizard: {e}");
+            }
+            if let Err(e) = me.pause_unused_devices() {
+                error!("Failed to pause unused devices: {e}");
+            }
+            if let Err(e) = me.notify_new_devices() {
+                error!("Failed to notify about new devices: {e}");
+            }
+            if !me.machine_config.kiosk
+                && let Err(e) = me.catch_up_on_changes(first_run)
+            {
+                error!("Failed to point out what's new: {e}");
+            }
+        }
+
+        if console {
+            status(
+                Status::Ok,
+                &format!(
+                    "Running with {} output devices, current: {}",
+                    me.switcher.outputs().len(),
+                    current_device_name
+                ),
+            );
+        }
 
         // Enter the message loop.
         info!("Running...");
@@ -768,6 +3864,7 @@ fn main() -> Result<(), Box<dyn Error>> {
                 BOOL(0) => {
                     assert_eq!(msg.message, WM_QUIT);
                     info!("Quitting...");
+                    startup_marker.clear();
                     break;
                 }
                 BOOL(-1) => {
@@ -784,6 +3881,65 @@ fn main() -> Result<(), Box<dyn Error>> {
 }
 
 const TASKBAR_CB_ID: u32 = WM_APP + 0x42;
+// Posted when an audio endpoint is added, removed, enabled, disabled or renamed.
+const WM_AUDIO_ENDPOINTS_CHANGED: u32 = WM_APP + 0x4d;
+// Posted when the default output device changes, whoever changed it.
+const WM_DEFAULT_DEVICE_CHANGED: u32 = WM_APP + 0x4e;
+// Posted by a device refresh that found devices under new IDs, to ask about them once it's done.
+const WM_OFFER_REMAP: u32 = WM_APP + 0x59;
+// Posted when the volume or mute of the output watched for the night limit changes.
+const WM_ENDPOINT_VOLUME_CHANGED: u32 = WM_APP + 0x5b;
+// Shown in place of the current device while there are none.
+const AUDIO_SERVICE_STOPPED: &str = "Windows Audio is stopped";
+
+/// What clicking a notification does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NotificationKind {
+    // Starts Windows Audio.
+    AudioServiceStopped,
+    // Puts the devices taken out of the rotation for going unused back.
+    PausedDevices,
+    // Lists what the update brought.
+    WhatsNew,
+    // Asks whether to include each new device in the rotation.
+    NewDevices,
+}
+const NO_OUTPUT_DEVICES: &str = "No output devices";
+const DEVICE_REFRESH_TIMER_ID: usize = 1;
+const DEVICE_REFRESH_DELAY_MS: u32 = 500;
+// Holds back a left click on the tray icon until it can't be a double click.
+const CLICK_TIMER_ID: usize = 2;
+// Checks for scheduled rules, often enough not to miss a minute.
+const SCHEDULE_TIMER_ID: usize = 3;
+const SCHEDULE_CHECK_MS: u32 = 20_000;
+// How far into the minute the check comes while saving power, so timer slack can't put it before.
+const SCHEDULE_MINUTE_MARGIN_MS: u32 = 1_000;
+// Waits for the devices to come back after the computer wakes before applying a profile.
+const RESUME_TIMER_ID: usize = 4;
+const RESUME_DELAY_MS: u32 = 3_000;
+// Checks for scripts that have been added, changed or removed.
+const SCRIPTS_TIMER_ID: usize = 5;
+const SCRIPTS_CHECK_MS: u32 = 2_000;
+
+// Posts `message` to the window from a notification callback, which runs on another thread.
+fn poster(window: HWND, message: u32) -> impl Fn() + Send + Sync + 'static {
+    let window = window.0 as isize;
+    move || unsafe {
+        let _ = PostMessageW(
+            Some(HWND(window as *mut _)),
+            message,
+            WPARAM::default(),
+            LPARAM::default(),
+        );
+    }
+}
+
+// Broadcast to top-level windows when the taskbar is created, e.g. when Explorer restarts after a
+// crash, which leaves the notification area empty.
+fn taskbar_created_message() -> u32 {
+    static MESSAGE: OnceLock<u32> = OnceLock::new();
+    *MESSAGE.get_or_init(|| unsafe { RegisterWindowMessageW(w!("TaskbarCreated")) })
+}
 #[allow(non_snake_case)]
 pub fn LOWORD(l: isize) -> isize {
     l & 0xffff
@@ -820,23 +3976,423 @@ unsafe extern "system" fn window_callback(
                 }
                 // Left click on the taskbar icon.
                 NIN_SELECT => {
-                    match raw_me.as_mut().unwrap().next_device() {
-                        Ok(()) => {}
-                        Err(e) => error!("Failed to show popup menu: {e:?}"),
+                    let me = raw_me.as_mut().unwrap();
+                    let click_actions = me.config.click_actions;
+                    let action = if GetKeyState(VK_SHIFT.0 as i32) < 0 {
+                        click_actions.shift_left
+                    } else if click_actions.double != ClickAction::Nothing {
+                        // The second click of a double click.
+                        if me.last_double_click.is_some_and(|at| {
+                            at.elapsed().as_millis() < GetDoubleClickTime() as u128
+                        }) {
+                            return LRESULT(0);
+                        }
+                        // Acts once it's clear this isn't the start of a double click.
+                        SetTimer(Some(hwnd), CLICK_TIMER_ID, GetDoubleClickTime(), None);
+                        return LRESULT(0);
+                    } else {
+                        click_actions.left
+                    };
+                    if let Err(e) = me.click(action) {
+                        error!("Failed to handle click: {e:?}");
+                    }
+                    LRESULT(0)
+                }
+                // Double click on the taskbar icon, if the config gives it something to do.
+                WM_LBUTTONDBLCLK
+                    if raw_me.as_ref().unwrap().config.click_actions.double
+                        != ClickAction::Nothing =>
+                {
+                    let me = raw_me.as_mut().unwrap();
+                    let _ = KillTimer(Some(hwnd), CLICK_TIMER_ID);
+                    me.last_double_click = Some(Instant::now());
+                    if let Err(e) = me.click(me.config.click_actions.double) {
+                        error!("Failed to handle double click: {e:?}");
+                    }
+                    LRESULT(0)
+                }
+                // The cursor moving over the icon, after which the wheel can change the volume.
+                WM_MOUSEMOVE if raw_me.as_ref().unwrap().config.wheel_volume_step > 0 => {
+                    let me = raw_me.as_ref().unwrap();
+                    if let Err(e) = tray_wheel::watch(hwnd, me.notify_icon) {
+                        error!("Failed to watch the mouse wheel: {e:?}");
+                    }
+                    LRESULT(0)
+                }
+                // Middle click on the taskbar icon.
+                WM_MBUTTONUP => {
+                    let me = raw_me.as_mut().unwrap();
+                    if let Err(e) = me.click(me.config.click_actions.middle) {
+                        error!("Failed to handle middle click: {e:?}");
+                    }
+                    LRESULT(0)
+                }
+                NIN_BALLOONUSERCLICK => {
+                    let me = raw_me.as_mut().unwrap();
+                    match me.pending_click.take() {
+                        Some(NotificationKind::AudioServiceStopped) => me.start_audio_service(),
+                        Some(NotificationKind::PausedDevices) => {
+                            if let Err(e) = me.resume_paused_devices() {
+                                error!("Failed to resume paused devices: {e:?}");
+                            }
+                        }
+                        Some(NotificationKind::WhatsNew) => {
+                            if let Err(e) = me.show_whats_new() {
+                                error!("Failed to show what's new: {e:?}");
+                            }
+                        }
+                        Some(NotificationKind::NewDevices) => {
+                            if let Err(e) = me.prompt_new_devices() {
+                                error!("Failed to prompt for new devices: {e:?}");
+                            }
+                        }
+                        None => {}
                     }
                     LRESULT(0)
                 }
                 _ => DefWindowProcW(hwnd, msg, wparam, lparam),
             },
+            WM_INITMENUPOPUP => {
+                let submenu = HMENU(wparam.0 as *mut _);
+                if let Err(e) = raw_me.as_ref().unwrap().fill_playing_menu(submenu) {
+                    error!("Failed to list the apps playing: {e:?}");
+                }
+                LRESULT(0)
+            }
             // Item in popup menu selected.
             WM_COMMAND => {
                 let chosen = LOWORD(wparam.0 as isize) as u32;
                 let _ = raw_me.as_mut().unwrap().menu_selection(chosen);
                 LRESULT(0)
             }
+            WM_POWERBROADCAST if wparam.0 as u32 == PBT_POWERSETTINGCHANGE => {
+                let me = raw_me.as_mut().unwrap();
+                if me.power_policy.handle_setting_change(lparam) {
+                    me.follow_power_policy();
+                }
+                LRESULT(1)
+            }
+            WM_POWERBROADCAST if wparam.0 as u32 == PBT_APMRESUMEAUTOMATIC => {
+                SetTimer(Some(hwnd), RESUME_TIMER_ID, RESUME_DELAY_MS, None);
+                LRESULT(1)
+            }
+            WM_TRAY_WHEEL => {
+                let me = raw_me.as_mut().unwrap();
+                let notches = me.wheel_notches.add(wparam.0 as u16 as i16);
+                if notches != 0
+                    && let Err(e) = me.scroll_volume(notches)
+                {
+                    error!("Failed to change volume: {e:?}");
+                }
+                LRESULT(0)
+            }
+            WM_VOLUME_CHANGED => {
+                if let Err(e) = raw_me.as_ref().unwrap().set_volume_percent(wparam.0 as u32) {
+                    error!("Failed to set volume: {e:?}");
+                }
+                LRESULT(0)
+            }
+            WM_FLYOUT_DEVICE_CHOSEN => {
+                let device_id = &*(lparam.0 as *const String);
+                match raw_me.as_mut().unwrap().choose_from_mixer(device_id) {
+                    Ok(switched) => LRESULT(switched as isize),
+                    Err(e) => {
+                        error!("Failed to switch from the mixer: {e:?}");
+                        LRESULT(0)
+                    }
+                }
+            }
+            WM_FLYOUT_VOLUME_CHANGED => {
+                let device_id = &*(lparam.0 as *const String);
+                if let Err(e) = raw_me
+                    .as_ref()
+                    .unwrap()
+                    .set_device_volume(device_id, wparam.0 as u32)
+                {
+                    error!("Failed to set volume: {e:?}");
+                }
+                LRESULT(0)
+            }
+            WM_BALANCE_CHANGED => {
+                if let Err(e) = raw_me
+                    .as_mut()
+                    .unwrap()
+                    .set_balance(wparam.0 as i32, lparam.0 != 0)
+                {
+                    error!("Failed to set balance: {e:?}");
+                }
+                LRESULT(0)
+            }
+            WM_SETTINGS_ORDER_CHANGED => {
+                let device_ids = &*(lparam.0 as *const Vec<String>);
+                if let Err(e) = raw_me.as_mut().unwrap().reorder_devices(device_ids) {
+                    error!("Failed to reorder devices: {e:?}");
+                }
+                LRESULT(0)
+            }
+            WM_SETTINGS_HOTKEYS_CHANGED => {
+                let hotkeys = &*(lparam.0 as *const HotkeyConfig);
+                if let Err(e) = raw_me.as_mut().unwrap().set_hotkeys(hotkeys) {
+                    error!("Failed to save hotkeys: {e:?}");
+                }
+                LRESULT(0)
+            }
+            WM_SETTINGS_BEHAVIOR_CHANGED => {
+                let behavior = &*(lparam.0 as *const Behavior);
+                if let Err(e) = raw_me.as_mut().unwrap().set_behavior(behavior) {
+                    error!("Failed to save settings: {e:?}");
+                }
+                LRESULT(0)
+            }
+            WM_SETTINGS_PROFILES_CHANGED => {
+                let changes = &mut *(lparam.0 as *mut ProfileChanges);
+                if let Err(e) = raw_me.as_mut().unwrap().set_profiles(changes) {
+                    error!("Failed to save profiles: {e:?}");
+                }
+                LRESULT(0)
+            }
+            WM_SETTINGS_RULES_CHANGED => {
+                let rules = &*(lparam.0 as *const Vec<Rule>);
+                if let Err(e) = raw_me.as_mut().unwrap().set_rules(rules) {
+                    error!("Failed to save rules: {e:?}");
+                }
+                LRESULT(0)
+            }
+            WM_SETTINGS_TEST_RULE => {
+                let test = &mut *(lparam.0 as *mut RuleTest);
+                raw_me.as_mut().unwrap().test_rule(test);
+                LRESULT(0)
+            }
+            WM_AUDIO_SERVICE_RESTARTED => {
+                let outcome = Box::from_raw(lparam.0 as *mut String);
+                info!("{outcome}");
+                let me = raw_me.as_mut().unwrap();
+                // The endpoints all went away and came back, possibly with different defaults.
+                if let Err(e) = me.refresh_devices() {
+                    error!("Failed to refresh devices: {e:?}");
+                }
+                if let Err(e) = me.show_notification("Sound Switcheroo", &outcome) {
+                    error!("Failed to show restart outcome: {e:?}");
+                }
+                LRESULT(0)
+            }
+            WM_PIPE_REQUEST => {
+                let exchange = &mut *(lparam.0 as *mut Exchange);
+                exchange.result = raw_me
+                    .as_mut()
+                    .unwrap()
+                    .pipe_command(&exchange.command)
+                    .map_err(|e| e.to_string());
+                LRESULT(0)
+            }
+            WM_ELEVATED_TASK_FINISHED => {
+                let finished = Box::from_raw(lparam.0 as *mut Finished);
+                match finished.task {
+                    ElevatedTask::SetEffectsBypassed { device, bypassed } => raw_me
+                        .as_mut()
+                        .unwrap()
+                        .effects_changed(&device, bypassed, finished.error),
+                    task => debug!("Elevated task finished: {task:?}"),
+                }
+                LRESULT(0)
+            }
+            WM_PLUGINS_DISCOVERED => {
+                let plugins = Box::from_raw(lparam.0 as *mut Vec<Plugin>);
+                debug!("Found {} plugins", plugins.len());
+                let me = raw_me.as_mut().unwrap();
+                me.plugins = *plugins;
+                me.invalidate_popup_menu();
+                LRESULT(0)
+            }
+            WM_PLUGIN_FINISHED => {
+                let outcome = Box::from_raw(lparam.0 as *mut String);
+                info!("Plugin finished. {outcome}");
+                if let Err(e) = raw_me
+                    .as_mut()
+                    .unwrap()
+                    .show_notification("Sound Switcheroo", &outcome)
+                {
+                    error!("Failed to show plugin outcome: {e:?}");
+                }
+                LRESULT(0)
+            }
+            WM_SETTINGS_CLOSING => {
+                let settings = HWND(wparam.0 as *mut _);
+                if let Err(e) = raw_me
+                    .as_mut()
+                    .unwrap()
+                    .window_moved(settings_window::PLACEMENT_NAME, settings)
+                {
+                    error!("Failed to save settings window position: {e:?}");
+                }
+                LRESULT(0)
+            }
+            WM_VOLUME_POPUP_MOVED => {
+                let popup = HWND(wparam.0 as *mut _);
+                if let Err(e) = raw_me
+                    .as_mut()
+                    .unwrap()
+                    .window_moved(volume_popup::PLACEMENT_NAME, popup)
+                {
+                    error!("Failed to save volume popup position: {e:?}");
+                }
+                LRESULT(0)
+            }
+            WM_HOTKEY => {
+                let me = raw_me.as_ref().unwrap();
+                let action = match wparam.0 as i32 {
+                    NEXT_DEVICE_HOTKEY_ID => Action::NextDevice,
+                    PREVIOUS_DEVICE_HOTKEY_ID => Action::PreviousDevice,
+                    SWAP_DEVICES_HOTKEY_ID => Action::SwapDevices,
+                    MUTE_MICROPHONE_HOTKEY_ID => {
+                        if let Err(e) = raw_me.as_mut().unwrap().toggle_microphone_mute() {
+                            error!("Failed to mute the microphone: {e:?}");
+                        }
+                        return LRESULT(0);
+                    }
+                    id if id >= FIRST_PROFILE_HOTKEY_ID => {
+                        match usize::try_from(id - FIRST_PROFILE_HOTKEY_ID)
+                            .ok()
+                            .and_then(|i| me.config.profiles.get(i))
+                        {
+                            Some(profile) => Action::ApplyProfile(profile.name.clone()),
+                            None => return LRESULT(0),
+                        }
+                    }
+                    id => match usize::try_from(id - FIRST_DEVICE_HOTKEY_ID)
+                        .ok()
+                        .and_then(|i| me.device_hotkeys.get(i))
+                    {
+                        Some(device_id) => Action::ChooseDevice(device_id.clone()),
+                        None => return LRESULT(0),
+                    },
+                };
+                if let Err(e) = raw_me.as_mut().unwrap().dispatch(action) {
+                    error!("Failed to switch device: {e:?}");
+                }
+                LRESULT(0)
+            }
+            // Hardware was added or removed somewhere, which may include audio devices. Docking or
+            // undocking is a configuration change, which may also bring devices.
+            WM_DEVICECHANGE
+                if matches!(wparam.0 as u32, DBT_DEVNODES_CHANGED | DBT_CONFIGCHANGED) =>
+            {
+                raw_me.as_ref().unwrap().schedule_device_refresh();
+                LRESULT(1)
+            }
+            WM_FOREGROUND_APP_CHANGED => {
+                raw_me
+                    .as_mut()
+                    .unwrap()
+                    .run_foreground_rules(wparam.0 as u32);
+                LRESULT(0)
+            }
+            WM_NETWORK_CHANGED => {
+                raw_me.as_mut().unwrap().run_network_rules();
+                LRESULT(0)
+            }
+            // An audio endpoint was added, removed, enabled or disabled.
+            WM_AUDIO_ENDPOINTS_CHANGED => {
+                raw_me.as_ref().unwrap().schedule_device_refresh();
+                LRESULT(0)
+            }
+            // By this app or anything else, such as Windows' sound settings.
+            WM_DEFAULT_DEVICE_CHANGED => {
+                let me = raw_me.as_mut().unwrap();
+                if !me.audio_service_stopped {
+                    me.invalidate_popup_menu();
+                    if let Err(e) = me.show_current_device() {
+                        error!("Failed to show the new default device: {e:?}");
+                    }
+                    me.watch_night_volume();
+                }
+                LRESULT(0)
+            }
+            // A system setting changed, which may be the decimal separator the tooltip uses.
+            WM_SETTINGCHANGE => {
+                let me = raw_me.as_mut().unwrap();
+                let decimal_separator = tooltip::decimal_separator();
+                if decimal_separator != me.decimal_separator {
+                    me.decimal_separator = decimal_separator;
+                    if !me.devices_unavailable()
+                        && let Err(e) = me.show_current_device()
+                    {
+                        error!("Failed to update the tooltip: {e:?}");
+                    }
+                }
+                LRESULT(0)
+            }
+            // Only watched while the night limit applies.
+            WM_ENDPOINT_VOLUME_CHANGED => {
+                if let Err(e) = raw_me.as_ref().unwrap().limit_night_volume() {
+                    error!("Failed to limit the volume for the night: {e:?}");
+                }
+                LRESULT(0)
+            }
+            WM_TIMER if wparam.0 == CLICK_TIMER_ID => {
+                let _ = KillTimer(Some(hwnd), CLICK_TIMER_ID);
+                let me = raw_me.as_mut().unwrap();
+                if let Err(e) = me.click(me.config.click_actions.left) {
+                    error!("Failed to handle click: {e:?}");
+                }
+                LRESULT(0)
+            }
+            WM_TIMER if wparam.0 == RESUME_TIMER_ID => {
+                let _ = KillTimer(Some(hwnd), RESUME_TIMER_ID);
+                raw_me.as_mut().unwrap().apply_automatic_profile(true);
+                LRESULT(0)
+            }
+            WM_TIMER if wparam.0 == SCHEDULE_TIMER_ID => {
+                let me = raw_me.as_mut().unwrap();
+                me.run_scheduled_rules();
+                if let Err(e) = me.check_night_mode() {
+                    error!("Failed to apply night mode: {e:?}");
+                }
+                // For the next minute, while saving power.
+                me.watch_schedule();
+                LRESULT(0)
+            }
+            WM_TIMER if wparam.0 == SCRIPTS_TIMER_ID => {
+                raw_me.as_mut().unwrap().reload_scripts();
+                LRESULT(0)
+            }
+            WM_OFFER_REMAP => {
+                let me = raw_me.as_mut().unwrap();
+                if let Err(e) = me.offer_remap() {
+                    error!("Failed to offer to move settings to new device IDs: {e}");
+                }
+                me.remap_offered = false;
+                // Held back until the answer.
+                if let Err(e) = me.notify_new_devices() {
+                    error!("Failed to notify about new devices: {e}");
+                }
+                LRESULT(0)
+            }
+            WM_TIMER if wparam.0 == DEVICE_REFRESH_TIMER_ID => {
+                if let Err(e) = raw_me.as_mut().unwrap().refresh_devices() {
+                    error!("Failed to refresh devices: {e:?}");
+                }
+                LRESULT(0)
+            }
+            // From Exit in the menu, or `sound-switcheroo quit`.
+            WM_CLOSE => {
+                info!("Closing");
+                let _ = DestroyWindow(hwnd);
+                LRESULT(0)
+            }
+            msg if msg == taskbar_created_message() => {
+                if let Some(me) = raw_me.as_ref()
+                    && let Err(e) = me.restore_tray_icon()
+                {
+                    error!("Failed to restore the tray icon: {e:?}");
+                }
+                LRESULT(0)
+            }
             WM_DESTROY => {
+                tray_wheel::stop();
+                foreground::stop();
                 // Save the device selectable state on exit
-                let _ = save_device_selectable_state(&raw_me.as_ref().unwrap().available_devices);
+                let _ = raw_me.as_ref().unwrap().save_config();
 
                 PostQuitMessage(0);
                 LRESULT(0)
@@ -845,3 +4401,87 @@ unsafe extern "system" fn window_callback(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sound_switcheroo::simulation::headset_backend;
+
+    // The app without a window or tray icon, on `backend`, keeping nothing on disk.
+    fn audio_switch(backend: SimulatedBackend, mut config: Config) -> AudioSwitch {
+        config.usage_insights = false;
+        let mut devices = backend.devices(eRender).unwrap();
+        config.apply_to_devices(&mut devices);
+        let config_path = std::env::temp_dir().join("sound-switcheroo-test-config.json");
+        AudioSwitch {
+            window: HWND::default(),
+            show_tray: false,
+            notify_icon: TrayIconId::Uid(0),
+            icon: AdaptiveIcon::new("audio_icon", "audio_icon"),
+            profile_icon: None,
+            popup_menu: None,
+            popup_menu_defaults: Default::default(),
+            popup_menu_playing: None,
+            popup_menu_snapshots: Vec::new(),
+            popup_menu_ids: MenuIds::default(),
+            microphones: Vec::new(),
+            settings_window: None,
+            switcher: Switcher::new(Box::new(backend), devices),
+            config,
+            history: History::new(insights::history_path(&config_path)),
+            schedule_checked: None,
+            networks: Vec::new(),
+            routed_apps: Vec::new(),
+            docked: false,
+            night: false,
+            night_limit_lifted: false,
+            night_volume: None,
+            config_path,
+            machine_config: MachineConfig::default(),
+            safe_mode: false,
+            audio_service_stopped: false,
+            problems: Vec::new(),
+            hotkeys: None,
+            device_hotkeys: Vec::new(),
+            profile_hotkeys: None,
+            revert_to: None,
+            last_double_click: None,
+            paused_devices: Vec::new(),
+            whats_new: &[],
+            pending_click: Cell::new(None),
+            remap_offered: false,
+            asking_about_devices: false,
+            osd: None,
+            plugins: Vec::new(),
+            scripts: None,
+            running_scripts: false,
+            standby: None,
+            switch_timings: SwitchTimings::default(),
+            power_policy: PowerPolicy::default(),
+            wheel_notches: Notches::default(),
+            decimal_separator: ".".to_string(),
+            balance_unsaved: false,
+            headphones_icon: AdaptiveIcon::new("headphones_icon", "headphones_icon_dark"),
+            headset_icon: AdaptiveIcon::new("headset_icon", "headset_icon_dark"),
+            speaker_icon: AdaptiveIcon::new("speaker_icon", "speaker_icon_dark"),
+            display_icon: AdaptiveIcon::drawn(Glyph::Display),
+            line_level_icon: AdaptiveIcon::drawn(Glyph::LineLevel),
+            spdif_icon: AdaptiveIcon::drawn(Glyph::Spdif),
+            unknown_icon: AdaptiveIcon::drawn(Glyph::Unknown),
+        }
+    }
+
+    #[test]
+    fn dispatched_actions_switch_the_backend() {
+        let mut me = audio_switch(headset_backend(), Config::default());
+        me.dispatch(Action::NextDevice).unwrap();
+        assert_eq!(me.current_output().unwrap(), "{0.0.0.00000000}.{headset}");
+        // Swapping back is set off by the app, so it isn't held up by the rate limiter.
+        me.dispatch_unlimited(Action::SwapDevices).unwrap();
+        assert_eq!(me.current_output().unwrap(), "{0.0.0.00000000}.{speakers}");
+        assert_eq!(
+            me.switcher.previous_output(),
+            Some("{0.0.0.00000000}.{headset}")
+        );
+    }
+}
@@ -0,0 +1,1518 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::backend::{AudioDevice, AudioSession, Effects};
+use crate::config::{Config, MachineConfig};
+use crate::logging::LogLevel;
+use crate::plugins::Plugin;
+use crate::voicemeeter;
+
+// Fixed IDs for the entries there's only ever one of. The rest are given out by `MenuIds`.
+pub const POPUP_EXIT_ID: u32 = 1;
+pub const POPUP_STATUS_ID: u32 = 2;
+pub const POPUP_ABOUT_ID: u32 = 3;
+pub const POPUP_SWITCH_MIC_ID: u32 = 4;
+pub const POPUP_VOLUME_ID: u32 = 5;
+pub const POPUP_SETTINGS_ID: u32 = 6;
+pub const POPUP_OPEN_LOG_ID: u32 = 7;
+pub const POPUP_RESTART_AUDIO_ID: u32 = 8;
+pub const POPUP_SWITCH_COMMUNICATIONS_ID: u32 = 9;
+pub const POPUP_VOICEMEETER_ID: u32 = 10;
+pub const POPUP_PROBLEMS_ID: u32 = 11;
+pub const POPUP_INCLUDE_HDMI_ID: u32 = 12;
+pub const POPUP_INCLUDE_HANDS_FREE_ID: u32 = 13;
+pub const POPUP_INCLUDE_VIRTUAL_ID: u32 = 14;
+pub const POPUP_INSIGHTS_ID: u32 = 15;
+pub const POPUP_SAVE_PROFILE_ID: u32 = 16;
+pub const POPUP_MUTE_ID: u32 = 17;
+pub const POPUP_VOLUME_UP_ID: u32 = 18;
+pub const POPUP_VOLUME_DOWN_ID: u32 = 19;
+pub const POPUP_NIGHT_LIMIT_ID: u32 = 20;
+pub const POPUP_FOCUSED_APP_ID: u32 = 21;
+pub const POPUP_MIXER_ID: u32 = 22;
+pub const POPUP_SNAPSHOT_ID: u32 = 23;
+
+/// How far the volume up and down entries turn the volume, in percent.
+pub const VOLUME_STEP: u32 = 10;
+// Levels the volume can be set to straight from the menu.
+const VOLUME_PRESETS: [u32; 4] = [100, 75, 50, 25];
+
+// Added after the label of entries a recent update brought in, shown right-aligned.
+const NEW_BADGE: &str = "\tNew";
+
+// The first ID `MenuIds` gives out, well clear of the fixed ones.
+const FIRST_ASSIGNED_ID: u32 = 100;
+
+/// What an entry with an ID from `MenuIds` is for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MenuKey {
+    // Switches to the output or microphone with this ID.
+    Output(String),
+    Microphone(String),
+    // Adds the device to the rotation or takes it out.
+    Rotation(String),
+    // Copy the device's name or endpoint ID to the clipboard.
+    CopyName(String),
+    CopyId(String),
+    // Turns the device's effects off or back on.
+    Effects(String),
+    LogLevel(LogLevel),
+    // A plugin's item, by the plugin's name and the item's ID.
+    Plugin(String, String),
+    // Applies the profile of this name.
+    Profile(String),
+    // Restores the routing snapshot of this name.
+    Snapshot(String),
+    // Sets the volume to this percentage.
+    Volume(u32),
+}
+
+/// IDs for the entries there can be any number of, such as devices and profiles, given out one
+/// after another as the menu is built, and what each of them is for. Unlike IDs made by hashing
+/// device IDs, no two can be the same.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MenuIds {
+    keys: HashMap<u32, MenuKey>,
+    ids: HashMap<MenuKey, u32>,
+}
+
+impl MenuIds {
+    /// The ID of the entry for `key`, giving it the next one if it doesn't have one yet.
+    pub fn assign(&mut self, key: MenuKey) -> u32 {
+        if let Some(&id) = self.ids.get(&key) {
+            return id;
+        }
+        let id = FIRST_ASSIGNED_ID + self.keys.len() as u32;
+        // WM_COMMAND only has room for 16 bits.
+        debug_assert!(id <= u16::MAX as u32, "Too many menu entries");
+        self.keys.insert(id, key.clone());
+        self.ids.insert(key, id);
+        id
+    }
+
+    pub fn id(&self, key: &MenuKey) -> Option<u32> {
+        self.ids.get(key).copied()
+    }
+
+    /// What the entry with the ID `id` is for, if it was given out here.
+    pub fn key(&self, id: u32) -> Option<&MenuKey> {
+        self.keys.get(&id)
+    }
+}
+
+/// Endpoints of one kind and which of them is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Endpoints<'a> {
+    pub devices: &'a [AudioDevice],
+    pub current_id: &'a str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MenuItem {
+    // Sent back in WM_COMMAND; 0 for items that can't be chosen.
+    pub id: u32,
+    pub label: String,
+    pub checked: bool,
+    pub disabled: bool,
+    // Checked with a bullet rather than a checkmark, for choosing one of a group.
+    pub radio: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MenuEntry {
+    Item(MenuItem),
+    Separator,
+    Submenu {
+        label: String,
+        entries: Vec<MenuEntry>,
+    },
+}
+
+/// The contents of the popup menu, top to bottom, independent of the Win32 menu built from it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MenuModel {
+    pub entries: Vec<MenuEntry>,
+    // What the entries' assigned IDs are for, to be kept for as long as the menu is.
+    pub ids: MenuIds,
+    // The position of the "Playing" submenu, if added, whose contents are filled in as it opens.
+    pub playing: Option<usize>,
+}
+
+impl MenuModel {
+    /// The devices are listed as radio items for switching between them, with checkboxes for the
+    /// rotation in a submenu so the two marks never share a row.
+    /// The microphones are radio items too, in a submenu after the output devices.
+    /// Profiles get a submenu before the rotation, which can also save the current setup as one.
+    /// Plugins' items come after the settings.
+    /// In kiosk mode the rotation, Exit, the settings and plugins are left out.
+    /// Exit is also left out if the config says so.
+    /// Entries added by a recent update are marked as new.
+    pub fn build(
+        devices: &[AudioDevice],
+        current_device_id: &str,
+        microphones: &Endpoints,
+        config: &Config,
+        machine_config: &MachineConfig,
+        plugins: &[Plugin],
+        volume_percent: Option<u32>,
+    ) -> Self {
+        let mut model = Self::layout(
+            devices,
+            current_device_id,
+            microphones,
+            config,
+            machine_config,
+            plugins,
+            volume_percent,
+        );
+        mark_new(&mut model.entries, &config.whats_new.badges);
+        assign_accelerators(&mut model.entries);
+        model
+    }
+
+    /// The menu after repeated crashes, when the config is ignored: just the devices to switch
+    /// between and Exit, which leaves safe mode for the next start.
+    pub fn safe_mode(
+        devices: &[AudioDevice],
+        current_device_id: &str,
+        microphones: &Endpoints,
+        volume_percent: Option<u32>,
+    ) -> Self {
+        let mut model = Self::top(
+            "Sound Switcheroo (safe mode)",
+            devices,
+            current_device_id,
+            microphones,
+            volume_percent,
+        );
+        model.entries.push(MenuEntry::Separator);
+        model.push_action(POPUP_EXIT_ID, "Exit");
+        assign_accelerators(&mut model.entries);
+        model
+    }
+
+    /// Adds "What's wrong?" under the title, for when the startup health check found problems.
+    pub fn with_problems(mut self) -> Self {
+        let mut used = self.used_accelerators();
+        self.insert(
+            1,
+            MenuEntry::Item(MenuItem {
+                id: POPUP_PROBLEMS_ID,
+                label: with_accelerator("What's wrong?", true, &mut used),
+                checked: false,
+                disabled: false,
+                radio: false,
+            }),
+        );
+        self
+    }
+
+    /// Adds a "Snapshots" submenu after the profiles, which takes a routing snapshot or restores
+    /// one of those saved, by name. Menus without profiles (safe mode and kiosks) are left alone.
+    pub fn with_snapshots(mut self, names: &[String]) -> Self {
+        let Some(profiles) = self.entries.iter().position(|entry| {
+            matches!(entry, MenuEntry::Submenu { label, .. } if label.replace('&', "").starts_with("Profiles"))
+        }) else {
+            return self;
+        };
+        let mut entries = vec![MenuEntry::Item(MenuItem {
+            id: POPUP_SNAPSHOT_ID,
+            label: "Snapshot audio state".to_string(),
+            checked: false,
+            disabled: false,
+            radio: false,
+        })];
+        if !names.is_empty() {
+            entries.push(MenuEntry::Separator);
+        }
+        entries.extend(names.iter().map(|name| {
+            MenuEntry::Item(MenuItem {
+                id: self.ids.assign(MenuKey::Snapshot(name.clone())),
+                label: format!("Restore {name}"),
+                checked: false,
+                disabled: false,
+                radio: false,
+            })
+        }));
+        assign_accelerators(&mut entries);
+        let mut used = self.used_accelerators();
+        self.insert(
+            profiles + 1,
+            MenuEntry::Submenu {
+                label: with_accelerator("Snapshots", true, &mut used),
+                entries,
+            },
+        );
+        self
+    }
+
+    /// Adds a "Playing" submenu after the devices, listing the apps with audio open on each output
+    /// by the output's name. `sessions` changes too often to be part of the menu's layout, so the
+    /// submenu can be filled in again from `session_entries` each time it opens.
+    pub fn with_sessions(mut self, sessions: &[(String, Vec<AudioSession>)]) -> Self {
+        let entries = session_entries(sessions);
+        let mut used = self.used_accelerators();
+        // Before the separator that ends the devices.
+        let first_device = self
+            .entries
+            .iter()
+            .position(|entry| matches!(entry, MenuEntry::Item(item) if item.radio))
+            .unwrap_or(0);
+        let index = self.entries[first_device..]
+            .iter()
+            .position(|entry| matches!(entry, MenuEntry::Separator))
+            .map_or(self.entries.len(), |offset| first_device + offset);
+        self.insert(
+            index,
+            MenuEntry::Submenu {
+                label: with_accelerator("Playing", true, &mut used),
+                entries,
+            },
+        );
+        self.playing = Some(index);
+        self
+    }
+
+    // Inserts at the top level, keeping track of where the "Playing" submenu ends up.
+    fn insert(&mut self, index: usize, entry: MenuEntry) {
+        if let Some(playing) = &mut self.playing
+            && index <= *playing
+        {
+            *playing += 1;
+        }
+        self.entries.insert(index, entry);
+    }
+
+    // The accelerators taken by the top level of the menu.
+    fn used_accelerators(&self) -> HashSet<char> {
+        self.entries
+            .iter()
+            .filter_map(|entry| match entry {
+                MenuEntry::Item(MenuItem { label, .. }) | MenuEntry::Submenu { label, .. } => {
+                    accelerator(label)
+                }
+                MenuEntry::Separator => None,
+            })
+            .collect()
+    }
+
+    fn layout(
+        devices: &[AudioDevice],
+        current_device_id: &str,
+        microphones: &Endpoints,
+        config: &Config,
+        machine_config: &MachineConfig,
+        plugins: &[Plugin],
+        volume_percent: Option<u32>,
+    ) -> Self {
+        let mut model = Self::top(
+            "Sound Switcheroo",
+            devices,
+            current_device_id,
+            microphones,
+            volume_percent,
+        );
+        if machine_config.kiosk {
+            return model;
+        }
+        model.entries.push(MenuEntry::Separator);
+        let mut profiles: Vec<_> = config
+            .profiles
+            .iter()
+            .map(|profile| {
+                MenuEntry::Item(MenuItem {
+                    id: model.ids.assign(MenuKey::Profile(profile.name.clone())),
+                    label: match profile.hotkey {
+                        Some(hotkey) => format!("{}\t{hotkey}", profile.name),
+                        None => profile.name.clone(),
+                    },
+                    checked: false,
+                    disabled: false,
+                    radio: false,
+                })
+            })
+            .collect();
+        if !profiles.is_empty() {
+            profiles.push(MenuEntry::Separator);
+        }
+        profiles.push(MenuEntry::Item(MenuItem {
+            id: POPUP_SAVE_PROFILE_ID,
+            label: "Save current setup as profile".to_string(),
+            checked: false,
+            disabled: false,
+            radio: false,
+        }));
+        model.entries.push(MenuEntry::Submenu {
+            label: "Profiles".to_string(),
+            entries: profiles,
+        });
+        let mut rotation = vec![
+            MenuEntry::Item(MenuItem {
+                id: 0,
+                label: "Checked devices take turns on left click".to_string(),
+                checked: false,
+                disabled: true,
+                radio: false,
+            }),
+            MenuEntry::Separator,
+        ];
+        rotation.extend(devices.iter().map(|device| {
+            MenuEntry::Item(MenuItem {
+                id: model.ids.assign(MenuKey::Rotation(device.id.clone())),
+                label: device.friendly_name.to_string(),
+                checked: device.selectable,
+                disabled: false,
+                radio: false,
+            })
+        }));
+        model.entries.push(MenuEntry::Submenu {
+            label: "Rotation".to_string(),
+            entries: rotation,
+        });
+        model.entries.push(MenuEntry::Submenu {
+            label: "Include".to_string(),
+            entries: [
+                (
+                    POPUP_INCLUDE_HDMI_ID,
+                    "HDMI and DisplayPort outputs",
+                    config.include.hdmi,
+                ),
+                (
+                    POPUP_INCLUDE_HANDS_FREE_ID,
+                    "Bluetooth hands-free",
+                    config.include.hands_free,
+                ),
+                (
+                    POPUP_INCLUDE_VIRTUAL_ID,
+                    "Virtual devices",
+                    config.include.virtual_devices,
+                ),
+            ]
+            .into_iter()
+            .map(|(id, label, checked)| {
+                MenuEntry::Item(MenuItem {
+                    id,
+                    label: label.to_string(),
+                    checked,
+                    disabled: false,
+                    radio: false,
+                })
+            })
+            .collect(),
+        });
+        // For CLI commands and bug reports, which need the exact name or ID. What the driver says
+        // about the device heads its submenu, to tell apart devices with the same name, followed by
+        // the format it's mixed in, to tell whether Windows is resampling.
+        model.entries.push(MenuEntry::Submenu {
+            label: "Copy device details".to_string(),
+            entries: devices
+                .iter()
+                .map(|device| {
+                    let mut entries: Vec<_> = device
+                        .details()
+                        .into_iter()
+                        .chain(
+                            device
+                                .mix_format
+                                .map(|format| format!("Shared mode: {format}")),
+                        )
+                        .map(|label| {
+                            MenuEntry::Item(MenuItem {
+                                id: 0,
+                                label,
+                                checked: false,
+                                disabled: true,
+                                radio: false,
+                            })
+                        })
+                        .collect();
+                    if !entries.is_empty() {
+                        entries.push(MenuEntry::Separator);
+                    }
+                    entries.extend([
+                        MenuEntry::Item(MenuItem {
+                            id: model.ids.assign(MenuKey::CopyName(device.id.clone())),
+                            label: "Copy name".to_string(),
+                            checked: false,
+                            disabled: false,
+                            radio: false,
+                        }),
+                        MenuEntry::Item(MenuItem {
+                            id: model.ids.assign(MenuKey::CopyId(device.id.clone())),
+                            label: "Copy device ID".to_string(),
+                            checked: false,
+                            disabled: false,
+                            radio: false,
+                        }),
+                    ]);
+                    MenuEntry::Submenu {
+                        label: device.friendly_name.to_string(),
+                        entries,
+                    }
+                })
+                .collect(),
+        });
+        let effects: Vec<_> = devices
+            .iter()
+            .filter(|device| device.effects != Effects::None)
+            .map(|device| {
+                MenuEntry::Item(MenuItem {
+                    id: model.ids.assign(MenuKey::Effects(device.id.clone())),
+                    label: device.friendly_name.to_string(),
+                    checked: device.effects == Effects::Enabled,
+                    disabled: false,
+                    radio: false,
+                })
+            })
+            .collect();
+        if !effects.is_empty() {
+            model.entries.push(MenuEntry::Submenu {
+                label: "Audio effects".to_string(),
+                entries: effects,
+            });
+        }
+        model.push(MenuItem {
+            id: POPUP_SWITCH_MIC_ID,
+            label: "Switch microphone with output".to_string(),
+            checked: config.switch_microphone_with_output,
+            disabled: false,
+            radio: false,
+        });
+        model.push(MenuItem {
+            id: POPUP_SWITCH_COMMUNICATIONS_ID,
+            label: "Switch communications device too".to_string(),
+            checked: config.switch_communications,
+            disabled: false,
+            radio: false,
+        });
+        model.push(MenuItem {
+            id: POPUP_FOCUSED_APP_ID,
+            label: "Switch focused app only".to_string(),
+            checked: config.switch_focused_app,
+            disabled: false,
+            radio: false,
+        });
+        // Always there while it's on, so it can be turned off again.
+        if config.voicemeeter.enabled
+            || devices
+                .iter()
+                .any(|device| voicemeeter::is_voicemeeter(&device.friendly_name))
+        {
+            model.push(MenuItem {
+                id: POPUP_VOICEMEETER_ID,
+                label: "Switch Voicemeeter's output instead".to_string(),
+                checked: config.voicemeeter.enabled,
+                disabled: false,
+                radio: false,
+            });
+        }
+        if config.night_mode.enabled {
+            // Checked when the menu is shown, as lifting the limit for the night doesn't rebuild
+            // it.
+            model.push_action(POPUP_NIGHT_LIMIT_ID, "Night volume limit");
+        }
+        let mut logging: Vec<_> = LogLevel::ALL
+            .into_iter()
+            .map(|level| {
+                MenuEntry::Item(MenuItem {
+                    id: model.ids.assign(MenuKey::LogLevel(level)),
+                    label: level.label().to_string(),
+                    checked: level == config.log_level,
+                    disabled: false,
+                    radio: true,
+                })
+            })
+            .collect();
+        logging.push(MenuEntry::Separator);
+        logging.push(MenuEntry::Item(MenuItem {
+            id: POPUP_OPEN_LOG_ID,
+            label: "Open log file".to_string(),
+            checked: false,
+            disabled: false,
+            radio: false,
+        }));
+        model.entries.push(MenuEntry::Submenu {
+            label: "Logging".to_string(),
+            entries: logging,
+        });
+        // The usual fix when Windows stops seeing devices or playing sound.
+        model.entries.push(MenuEntry::Submenu {
+            label: "Maintenance".to_string(),
+            entries: vec![MenuEntry::Item(MenuItem {
+                id: POPUP_RESTART_AUDIO_ID,
+                label: "Restart audio service".to_string(),
+                checked: false,
+                disabled: false,
+                radio: false,
+            })],
+        });
+        if config.usage_insights {
+            model.push_action(POPUP_INSIGHTS_ID, "Usage insights");
+        }
+        model.push_action(POPUP_SETTINGS_ID, "Settings");
+        model.entries.push(MenuEntry::Separator);
+        for plugin in plugins {
+            for item in &plugin.items {
+                let id = model
+                    .ids
+                    .assign(MenuKey::Plugin(plugin.name.clone(), item.id.clone()));
+                model.push_action(id, &item.label);
+            }
+        }
+        if plugins.iter().any(|plugin| !plugin.items.is_empty()) {
+            model.entries.push(MenuEntry::Separator);
+        }
+        if config.show_exit {
+            model.push_action(POPUP_EXIT_ID, "Exit");
+        } else {
+            // Nothing follows the separator.
+            model.entries.pop();
+        }
+        model
+    }
+
+    // The title, About, the status and volume, and the output devices and the Microphone submenu.
+    fn top(
+        title: &str,
+        devices: &[AudioDevice],
+        current_device_id: &str,
+        microphones: &Endpoints,
+        volume_percent: Option<u32>,
+    ) -> Self {
+        let mut model = Self::default();
+        // A nice name at the top of the menu.
+        model.push(MenuItem {
+            id: 0,
+            label: title.to_string(),
+            checked: false,
+            disabled: true,
+            radio: false,
+        });
+        model.push_action(POPUP_ABOUT_ID, "About");
+        model.entries.push(MenuEntry::Separator);
+        model.push(MenuItem {
+            id: POPUP_STATUS_ID,
+            label: status_label(devices),
+            checked: false,
+            disabled: true,
+            radio: false,
+        });
+        model.push_action(POPUP_VOLUME_ID, &volume_text(volume_percent));
+        // Checked when the menu is shown, as muting doesn't rebuild it.
+        model.push_action(POPUP_MUTE_ID, "Mute");
+        // Every device's volume at once, in a flyout.
+        model.push_action(POPUP_MIXER_ID, "Volume mixer");
+        let mut levels = vec![
+            MenuEntry::Item(MenuItem {
+                id: POPUP_VOLUME_UP_ID,
+                label: format!("Up\t+{VOLUME_STEP}%"),
+                checked: false,
+                disabled: false,
+                radio: false,
+            }),
+            MenuEntry::Item(MenuItem {
+                id: POPUP_VOLUME_DOWN_ID,
+                label: format!("Down\t-{VOLUME_STEP}%"),
+                checked: false,
+                disabled: false,
+                radio: false,
+            }),
+            MenuEntry::Separator,
+        ];
+        levels.extend(VOLUME_PRESETS.into_iter().map(|percent| {
+            MenuEntry::Item(MenuItem {
+                id: model.ids.assign(MenuKey::Volume(percent)),
+                label: format!("{percent}%"),
+                checked: false,
+                disabled: false,
+                radio: false,
+            })
+        }));
+        model.entries.push(MenuEntry::Submenu {
+            label: "Set volume".to_string(),
+            entries: levels,
+        });
+        model.entries.push(MenuEntry::Separator);
+        for device in devices {
+            // Right-aligned hint that the device has effects.
+            let label = match device.effects {
+                Effects::None => device.friendly_name.to_string(),
+                Effects::Enabled => format!("{}\tFX", device.friendly_name),
+                Effects::Bypassed => format!("{}\tFX off", device.friendly_name),
+            };
+            let id = model.ids.assign(MenuKey::Output(device.id.clone()));
+            model.push(MenuItem {
+                id,
+                label,
+                checked: device.id == current_device_id,
+                disabled: false,
+                radio: true,
+            });
+        }
+        if !microphones.devices.is_empty() {
+            model.entries.push(MenuEntry::Submenu {
+                label: "Microphone".to_string(),
+                entries: microphones
+                    .devices
+                    .iter()
+                    .map(|microphone| {
+                        MenuEntry::Item(MenuItem {
+                            id: model.ids.assign(MenuKey::Microphone(microphone.id.clone())),
+                            label: microphone.friendly_name.to_string(),
+                            checked: microphone.id == microphones.current_id,
+                            disabled: false,
+                            radio: true,
+                        })
+                    })
+                    .collect(),
+            });
+        }
+        model
+    }
+
+    fn push(&mut self, item: MenuItem) {
+        self.entries.push(MenuEntry::Item(item));
+    }
+
+    fn push_action(&mut self, id: u32, label: &str) {
+        self.push(MenuItem {
+            id,
+            label: label.to_string(),
+            checked: false,
+            disabled: false,
+            radio: false,
+        });
+    }
+}
+
+/// What the "Playing" submenu lists: the apps with audio open on each output in `sessions`, by the
+/// output's name.
+pub fn session_entries(sessions: &[(String, Vec<AudioSession>)]) -> Vec<MenuEntry> {
+    let mut entries: Vec<MenuEntry> = sessions
+        .iter()
+        .filter(|(_, sessions)| !sessions.is_empty())
+        .map(|(device_name, sessions)| MenuEntry::Submenu {
+            label: device_name.clone(),
+            entries: sessions
+                .iter()
+                .map(|session| {
+                    MenuEntry::Item(MenuItem {
+                        id: 0,
+                        label: if session.active {
+                            format!("{}\tPlaying", session.name)
+                        } else {
+                            session.name.clone()
+                        },
+                        checked: false,
+                        disabled: true,
+                        radio: false,
+                    })
+                })
+                .collect(),
+        })
+        .collect();
+    if entries.is_empty() {
+        entries.push(MenuEntry::Item(MenuItem {
+            id: 0,
+            label: "No apps have audio open".to_string(),
+            checked: false,
+            disabled: true,
+            radio: false,
+        }));
+    }
+    assign_accelerators(&mut entries);
+    entries
+}
+
+// Marks the items whose IDs are in `badges` as new, in any submenu, and the submenus holding them
+// so the mark shows before they're opened. Returns whether any were marked.
+fn mark_new(entries: &mut [MenuEntry], badges: &[u32]) -> bool {
+    let mut marked = false;
+    for entry in entries {
+        let (label, new) = match entry {
+            MenuEntry::Item(item) => (&mut item.label, item.id != 0 && badges.contains(&item.id)),
+            MenuEntry::Submenu { label, entries } => (label, mark_new(entries, badges)),
+            MenuEntry::Separator => continue,
+        };
+        if new {
+            label.push_str(NEW_BADGE);
+            marked = true;
+        }
+    }
+    marked
+}
+
+/// Gives every enabled entry a distinct accelerator key (the underlined letter that picks it while
+/// the menu is open), within each menu and submenu. The fixed entries go first so they keep their
+/// letters whatever the devices are called. Literal `&`s in labels are escaped along the way.
+fn assign_accelerators(entries: &mut [MenuEntry]) {
+    let mut used = HashSet::new();
+    // Devices are the radio items.
+    for devices in [false, true] {
+        for entry in entries.iter_mut() {
+            match entry {
+                MenuEntry::Item(item) if item.radio == devices => {
+                    item.label = with_accelerator(&item.label, !item.disabled, &mut used);
+                }
+                MenuEntry::Submenu { label, entries } if !devices => {
+                    *label = with_accelerator(label, true, &mut used);
+                    assign_accelerators(entries);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// Marks the first unused letter, preferring the start of a word, or else prefixes an unused
+// digit. Anything after a tab is right-aligned hint text and left alone.
+fn with_accelerator(label: &str, enabled: bool, used: &mut HashSet<char>) -> String {
+    let (text, hint) = match label.split_once('\t') {
+        Some((text, hint)) => (text, Some(hint)),
+        None => (label, None),
+    };
+    let chars: Vec<char> = text.chars().collect();
+    let candidate = |&i: &usize| {
+        chars[i].is_alphanumeric() && !used.contains(&chars[i].to_lowercase().next().unwrap())
+    };
+    let word_starts = (0..chars.len()).filter(|&i| i == 0 || !chars[i - 1].is_alphanumeric());
+    let chosen = if enabled {
+        word_starts
+            .clone()
+            .find(candidate)
+            .or_else(|| (0..chars.len()).find(candidate))
+    } else {
+        None
+    };
+    let mut marked = String::new();
+    if enabled
+        && chosen.is_none()
+        && let Some(digit) = ('1'..='9').find(|digit| !used.contains(digit))
+    {
+        used.insert(digit);
+        marked.push('&');
+        marked.push(digit);
+        marked.push(' ');
+    }
+    for (i, &c) in chars.iter().enumerate() {
+        if Some(i) == chosen {
+            used.insert(c.to_lowercase().next().unwrap());
+            marked.push('&');
+        }
+        if c == '&' {
+            marked.push('&');
+        }
+        marked.push(c);
+    }
+    if let Some(hint) = hint {
+        marked.push('\t');
+        marked.push_str(hint);
+    }
+    marked
+}
+
+// The key `with_accelerator` marked in a label, if any.
+fn accelerator(label: &str) -> Option<char> {
+    let mut chars = label.chars();
+    while let Some(c) = chars.next() {
+        if c == '&' {
+            match chars.next() {
+                Some('&') => {}
+                next => return next.and_then(|c| c.to_lowercase().next()),
+            }
+        }
+    }
+    None
+}
+
+/// Summary shown near the top of the menu, in place of repeating the current device.
+pub fn status_label(devices: &[AudioDevice]) -> String {
+    let in_rotation = devices.iter().filter(|device| device.selectable).count();
+    match in_rotation {
+        0 => "No devices in rotation".to_string(),
+        n => format!("{n} of {} devices in rotation", devices.len()),
+    }
+}
+
+/// Label of the entry that opens the volume slider, with the accelerator it gets when the menu is
+/// built, for updating it in place.
+pub fn volume_label(volume_percent: Option<u32>) -> String {
+    format!("&{}", volume_text(volume_percent))
+}
+
+fn volume_text(volume_percent: Option<u32>) -> String {
+    match volume_percent {
+        Some(percent) => format!("Volume: {percent}%"),
+        None => "Volume".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{AudioBackend, MixFormat};
+    use crate::plugins::PluginItem;
+    use crate::profiles::Profile;
+    use crate::simulation::headset_backend;
+    use windows::Win32::Media::Audio::{eCapture, eRender};
+
+    fn devices() -> Vec<AudioDevice> {
+        let mut devices = headset_backend().devices(eRender).unwrap();
+        devices[0].selectable = true;
+        devices[1].selectable = false;
+        devices
+    }
+
+    // Every item in the menu and its submenus.
+    fn items(entries: &[MenuEntry]) -> Vec<MenuItem> {
+        entries
+            .iter()
+            .flat_map(|entry| match entry {
+                MenuEntry::Item(item) => vec![item.clone()],
+                MenuEntry::Separator => vec![],
+                MenuEntry::Submenu { entries, .. } => items(entries),
+            })
+            .collect()
+    }
+
+    // The ID given to the entry for `key`.
+    fn id(model: &MenuModel, key: MenuKey) -> u32 {
+        model.ids.id(&key).unwrap()
+    }
+
+    fn find(model: &MenuModel, id: u32) -> MenuItem {
+        items(&model.entries)
+            .into_iter()
+            .find(|item| item.id == id)
+            .unwrap()
+    }
+
+    #[test]
+    fn current_device_has_the_radio_mark() {
+        let devices = devices();
+        let model = MenuModel::build(
+            &devices,
+            &devices[1].id,
+            &Endpoints::default(),
+            &Config::default(),
+            &MachineConfig::default(),
+            &[],
+            None,
+        );
+        let speakers = find(&model, id(&model, MenuKey::Output(devices[0].id.clone())));
+        let headset = find(&model, id(&model, MenuKey::Output(devices[1].id.clone())));
+        assert!(speakers.radio && headset.radio);
+        assert!(!speakers.checked);
+        assert!(headset.checked);
+        // Checkmarks are only for the rotation.
+        assert!(find(&model, id(&model, MenuKey::Rotation(devices[0].id.clone()))).checked);
+        assert!(!find(&model, id(&model, MenuKey::Rotation(devices[1].id.clone()))).checked);
+        assert!(!items(&model.entries)
+            .iter()
+            .any(|item| item.disabled && item.label == devices[1].friendly_name.as_str()));
+    }
+
+    #[test]
+    fn microphones_have_a_submenu() {
+        let devices = devices();
+        let microphones = headset_backend().devices(eCapture).unwrap();
+        let model = MenuModel::build(
+            &devices,
+            &devices[0].id,
+            &Endpoints {
+                devices: &microphones,
+                current_id: &microphones[1].id,
+            },
+            &Config::default(),
+            &MachineConfig::default(),
+            &[],
+            None,
+        );
+        let radio: Vec<_> = items(&model.entries)
+            .into_iter()
+            .filter(|item| item.radio && item.id != 0)
+            .take(devices.len() + microphones.len())
+            .map(|item| (item.id, item.checked))
+            .collect();
+        assert!(matches!(
+            &model.entries[devices.len() + 8],
+            MenuEntry::Submenu { label, entries }
+                if label == "&Microphone" && entries.len() == microphones.len()
+        ));
+        assert_eq!(
+            radio,
+            [
+                (id(&model, MenuKey::Output(devices[0].id.clone())), true),
+                (id(&model, MenuKey::Output(devices[1].id.clone())), false),
+                (
+                    id(&model, MenuKey::Microphone(microphones[0].id.clone())),
+                    false
+                ),
+                (
+                    id(&model, MenuKey::Microphone(microphones[1].id.clone())),
+                    true
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn kiosk_leaves_out_the_rotation() {
+        let devices = devices();
+        let model = MenuModel::build(
+            &devices,
+            &devices[0].id,
+            &Endpoints::default(),
+            &Config::default(),
+            &MachineConfig {
+                kiosk: true,
+                ..Default::default()
+            },
+            &[],
+            None,
+        );
+        assert_eq!(
+            model.ids.id(&MenuKey::Rotation(devices[0].id.clone())),
+            None
+        );
+        // Staff can still choose which device plays.
+        assert!(!find(&model, id(&model, MenuKey::Output(devices[1].id.clone()))).disabled);
+    }
+
+    #[test]
+    fn accelerators_are_distinct() {
+        let mut devices = devices();
+        devices[0].friendly_name = "Speakers & Subwoofer".into();
+        devices[1].friendly_name = "Speakers".into();
+        let model = MenuModel::build(
+            &devices,
+            &devices[0].id,
+            &Endpoints::default(),
+            &Config::default(),
+            &MachineConfig::default(),
+            &[],
+            Some(45),
+        );
+        let labels: Vec<_> = model
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                MenuEntry::Item(item) if !item.disabled => Some(item.label.as_str()),
+                MenuEntry::Submenu { label, .. } => Some(label.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            labels,
+            [
+                "&About",
+                "&Volume: 45%",
+                "&Mute",
+                "V&olume mixer",
+                "&Set volume",
+                "Spea&kers && Subwoofer",
+                "&1 Speakers",
+                "&Profiles",
+                "&Rotation",
+                "&Include",
+                "&Copy device details",
+                "Switch microphone &with output",
+                "Switch communications &device too",
+                "Switch &focused app only",
+                "&Logging",
+                "Mai&ntenance",
+                "&Usage insights",
+                "S&ettings",
+                "E&xit",
+            ]
+        );
+        assert_eq!(find(&model, POPUP_VOLUME_ID).label, volume_label(Some(45)));
+    }
+
+    #[test]
+    fn exit_can_be_hidden() {
+        let devices = devices();
+        let config = Config {
+            show_exit: false,
+            ..Default::default()
+        };
+        let model = MenuModel::build(
+            &devices,
+            &devices[0].id,
+            &Endpoints::default(),
+            &config,
+            &MachineConfig::default(),
+            &[],
+            None,
+        );
+        assert!(!items(&model.entries)
+            .iter()
+            .any(|item| item.id == POPUP_EXIT_ID));
+        assert!(matches!(
+            model.entries.last(),
+            Some(MenuEntry::Item(item)) if item.id == POPUP_SETTINGS_ID
+        ));
+    }
+
+    #[test]
+    fn device_details_head_their_submenus() {
+        let mut devices = devices();
+        devices[0].description = Some("Speakers".to_string());
+        devices[0].adapter = Some("USB Dock Audio".to_string());
+        devices[0].location = Some("Port_#0002.Hub_#0001".to_string());
+        devices[0].mix_format = Some(MixFormat {
+            sample_rate: 48_000,
+            bits: 24,
+            channels: 2,
+            float: false,
+        });
+        let model = MenuModel::build(
+            &devices,
+            &devices[0].id,
+            &Endpoints::default(),
+            &Config::default(),
+            &MachineConfig::default(),
+            &[],
+            None,
+        );
+        let Some(MenuEntry::Submenu { entries, .. }) = model.entries.iter().find(
+            |entry| matches!(entry, MenuEntry::Submenu { label, .. } if label == "&Copy device details"),
+        ) else {
+            panic!("No device details submenu");
+        };
+        let labels = |entry: &MenuEntry| match entry {
+            MenuEntry::Submenu { entries, .. } => items(entries)
+                .into_iter()
+                .map(|item| item.label)
+                .collect::<Vec<_>>(),
+            _ => panic!("Not a submenu"),
+        };
+        assert_eq!(
+            labels(&entries[0]),
+            [
+                "Speakers on USB Dock Audio (Port_#0002.Hub_#0001)",
+                "Shared mode: 48 kHz, 24-bit, stereo",
+                "&Copy name",
+                "Copy &device ID",
+            ]
+        );
+        // Nothing to say about the other one.
+        assert_eq!(labels(&entries[1]), ["&Copy name", "Copy &device ID"]);
+    }
+
+    #[test]
+    fn profiles_have_a_submenu() {
+        let devices = devices();
+        let config = Config {
+            profiles: vec![
+                Profile {
+                    name: "Gaming".to_string(),
+                    hotkey: Some("Ctrl+Alt+1".parse().unwrap()),
+                    ..Default::default()
+                },
+                Profile {
+                    name: "Meetings".to_string(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let model = MenuModel::build(
+            &devices,
+            &devices[0].id,
+            &Endpoints::default(),
+            &config,
+            &MachineConfig::default(),
+            &[],
+            None,
+        );
+        let Some(MenuEntry::Submenu { label, entries }) = model.entries.iter().find(
+            |entry| matches!(entry, MenuEntry::Submenu { label, .. } if label.contains("Profiles")),
+        ) else {
+            panic!("No profiles submenu");
+        };
+        assert_eq!(label, "&Profiles");
+        assert_eq!(
+            items(entries)
+                .iter()
+                .map(|item| (item.id, item.label.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    id(&model, MenuKey::Profile("Gaming".to_string())),
+                    "&Gaming\tCtrl+Alt+1"
+                ),
+                (
+                    id(&model, MenuKey::Profile("Meetings".to_string())),
+                    "&Meetings"
+                ),
+                (POPUP_SAVE_PROFILE_ID, "&Save current setup as profile"),
+            ]
+        );
+    }
+
+    #[test]
+    fn new_entries_are_marked() {
+        let devices = devices();
+        let mut config = Config {
+            profiles: vec![Profile {
+                name: "Gaming".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        config.whats_new.badges = vec![POPUP_SAVE_PROFILE_ID, POPUP_INSIGHTS_ID];
+        let model = MenuModel::build(
+            &devices,
+            &devices[0].id,
+            &Endpoints::default(),
+            &config,
+            &MachineConfig::default(),
+            &[],
+            None,
+        );
+        let marked: Vec<_> = model
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                MenuEntry::Item(MenuItem { label, .. }) | MenuEntry::Submenu { label, .. }
+                    if label.ends_with(NEW_BADGE) =>
+                {
+                    Some(label.as_str())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(marked, ["&Profiles\tNew", "&Usage insights\tNew"]);
+    }
+
+    #[test]
+    fn problems_go_under_the_title() {
+        let mut devices = devices();
+        devices[0].friendly_name = "Wireless Headset".into();
+        let model = MenuModel::build(
+            &devices,
+            &devices[0].id,
+            &Endpoints::default(),
+            &Config::default(),
+            &MachineConfig::default(),
+            &[],
+            None,
+        )
+        .with_problems();
+        assert_eq!(
+            model.entries[1],
+            MenuEntry::Item(MenuItem {
+                id: POPUP_PROBLEMS_ID,
+                // Other entries took the W and the H.
+                label: "Wha&t's wrong?".to_string(),
+                checked: false,
+                disabled: false,
+                radio: false,
+            })
+        );
+    }
+
+    #[test]
+    fn falls_back_to_digits() {
+        let mut used = HashSet::from(['a', 'b']);
+        assert_eq!(with_accelerator("ab", true, &mut used), "&1 ab");
+        assert_eq!(with_accelerator("A&B\tFX", false, &mut used), "A&&B\tFX");
+    }
+
+    #[test]
+    fn status_counts_devices_in_rotation() {
+        let mut devices = devices();
+        assert_eq!(status_label(&devices), "1 of 2 devices in rotation");
+        devices[0].selectable = false;
+        assert_eq!(status_label(&devices), "No devices in rotation");
+    }
+
+    #[test]
+    fn plugin_items_follow_the_settings() {
+        let devices = devices();
+        let plugin = Plugin {
+            path: "plugins/audio.exe".into(),
+            name: "audio".to_string(),
+            items: vec![PluginItem {
+                id: "restart".to_string(),
+                label: "Restart audio service".to_string(),
+            }],
+        };
+        let model = MenuModel::build(
+            &devices,
+            &devices[0].id,
+            &Endpoints::default(),
+            &Config::default(),
+            &MachineConfig::default(),
+            std::slice::from_ref(&plugin),
+            None,
+        );
+        let settings = model
+            .entries
+            .iter()
+            .position(
+                |entry| matches!(entry, MenuEntry::Item(item) if item.id == POPUP_SETTINGS_ID),
+            )
+            .unwrap();
+        assert!(matches!(
+            &model.entries[settings + 2],
+            MenuEntry::Item(item)
+                if model.ids.key(item.id)
+                    == Some(&MenuKey::Plugin("audio".to_string(), "restart".to_string()))
+        ));
+    }
+
+    #[test]
+    fn safe_mode_only_switches_devices() {
+        let devices = devices();
+        let model = MenuModel::safe_mode(&devices, &devices[0].id, &Endpoints::default(), None);
+        let ids: Vec<_> = items(&model.entries)
+            .iter()
+            .filter(|item| !item.disabled)
+            .map(|item| item.id)
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                POPUP_ABOUT_ID,
+                POPUP_VOLUME_ID,
+                POPUP_MUTE_ID,
+                POPUP_MIXER_ID,
+                POPUP_VOLUME_UP_ID,
+                POPUP_VOLUME_DOWN_ID,
+                id(&model, MenuKey::Volume(100)),
+                id(&model, MenuKey::Volume(75)),
+                id(&model, MenuKey::Volume(50)),
+                id(&model, MenuKey::Volume(25)),
+                id(&model, MenuKey::Output(devices[0].id.clone())),
+                id(&model, MenuKey::Output(devices[1].id.clone())),
+                POPUP_EXIT_ID,
+            ]
+        );
+    }
+
+    #[test]
+    fn volume_can_be_set_from_a_submenu() {
+        let devices = devices();
+        let model = MenuModel::build(
+            &devices,
+            &devices[0].id,
+            &Endpoints::default(),
+            &Config::default(),
+            &MachineConfig::default(),
+            &[],
+            None,
+        );
+        let levels = model
+            .entries
+            .iter()
+            .find_map(|entry| match entry {
+                MenuEntry::Submenu { label, entries } if label == "&Set volume" => Some(entries),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            items(levels)
+                .iter()
+                .map(|item| item.label.as_str())
+                .collect::<Vec<_>>(),
+            ["&Up\t+10%", "&Down\t-10%", "&100%", "&75%", "&50%", "&25%"]
+        );
+        let seventy_five = find(&model, id(&model, MenuKey::Volume(75)));
+        assert_eq!(seventy_five.label, "&75%");
+        assert_eq!(model.ids.key(POPUP_VOLUME_UP_ID), None);
+    }
+
+    #[test]
+    fn night_limit_is_offered_with_night_mode() {
+        let devices = devices();
+        let mut config = Config::default();
+        let build = |config: &Config, kiosk| {
+            MenuModel::build(
+                &devices,
+                &devices[0].id,
+                &Endpoints::default(),
+                config,
+                &MachineConfig {
+                    kiosk,
+                    ..Default::default()
+                },
+                &[],
+                None,
+            )
+        };
+        assert!(!items(&build(&config, false).entries)
+            .iter()
+            .any(|item| item.id == POPUP_NIGHT_LIMIT_ID));
+        config.night_mode.enabled = true;
+        assert_eq!(
+            find(&build(&config, false), POPUP_NIGHT_LIMIT_ID).label,
+            "&Night volume limit"
+        );
+        // Left to the night on a shared machine.
+        assert!(!items(&build(&config, true).entries)
+            .iter()
+            .any(|item| item.id == POPUP_NIGHT_LIMIT_ID));
+    }
+
+    #[test]
+    fn menu_ids_map_back_to_devices() {
+        let devices = devices();
+        let model = MenuModel::build(
+            &devices,
+            &devices[0].id,
+            &Endpoints::default(),
+            &Config::default(),
+            &MachineConfig::default(),
+            &[],
+            None,
+        );
+        for device in &devices {
+            let switch = id(&model, MenuKey::Output(device.id.clone()));
+            assert_eq!(
+                model.ids.key(switch),
+                Some(&MenuKey::Output(device.id.clone()))
+            );
+            // The device's other entries don't switch to it.
+            assert_ne!(switch, id(&model, MenuKey::Rotation(device.id.clone())));
+        }
+        assert_eq!(model.ids.key(POPUP_EXIT_ID), None);
+    }
+
+    #[test]
+    fn assigned_ids_never_collide() {
+        let mut ids = MenuIds::default();
+        // The first two have the same CRC-16, which the IDs used to be.
+        let keys: Vec<_> = [405, 800]
+            .into_iter()
+            .chain(0..2000)
+            .map(|i| MenuKey::Output(format!("{{0.0.0.00000000}}.{{speakers-{i}}}")))
+            .collect();
+        let assigned: HashSet<_> = keys.iter().map(|key| ids.assign(key.clone())).collect();
+        // Less the two repeated.
+        assert_eq!(assigned.len(), keys.len() - 2);
+        for key in &keys {
+            let id = ids.id(key).unwrap();
+            assert!(id > POPUP_SNAPSHOT_ID && id <= 0xffff);
+            assert_eq!(ids.key(id), Some(key));
+            assert_eq!(ids.assign(key.clone()), id);
+        }
+    }
+
+    #[test]
+    fn snapshots_follow_the_profiles() {
+        let devices = devices();
+        let model = MenuModel::build(
+            &devices,
+            &devices[0].id,
+            &Endpoints::default(),
+            &Config::default(),
+            &MachineConfig::default(),
+            &[],
+            None,
+        )
+        .with_snapshots(&["before game".to_string()]);
+        let labels: Vec<_> = model
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                MenuEntry::Submenu { label, .. } => Some(label.replace('&', "")),
+                _ => None,
+            })
+            .collect();
+        let profiles = labels.iter().position(|label| label == "Profiles").unwrap();
+        assert_eq!(labels[profiles + 1], "Snapshots");
+        assert_eq!(
+            find(&model, POPUP_SNAPSHOT_ID).label,
+            "&Snapshot audio state"
+        );
+        assert_eq!(
+            find(
+                &model,
+                id(&model, MenuKey::Snapshot("before game".to_string()))
+            )
+            .label,
+            "&Restore before game"
+        );
+        let safe_mode = MenuModel::safe_mode(&devices, &devices[0].id, &Endpoints::default(), None);
+        assert_eq!(safe_mode.clone().with_snapshots(&[]), safe_mode);
+    }
+
+    #[test]
+    fn apps_playing_are_listed_after_the_devices() {
+        let devices = devices();
+        let model = MenuModel::build(
+            &devices,
+            &devices[0].id,
+            &Endpoints::default(),
+            &Config::default(),
+            &MachineConfig::default(),
+            &[],
+            None,
+        );
+        let session = |name: &str, active| AudioSession {
+            name: name.to_string(),
+            active,
+        };
+        let model = model.with_sessions(&[
+            (
+                "Headphones".to_string(),
+                vec![session("Spotify", true), session("Teams", false)],
+            ),
+            ("Speakers".to_string(), vec![]),
+        ]);
+        let index = devices.len() + 8;
+        assert_eq!(model.playing, Some(index));
+        assert!(matches!(&model.entries[index - 1], MenuEntry::Item(item) if item.radio));
+        let MenuEntry::Submenu { label, entries } = &model.entries[index] else {
+            panic!("No submenu after the devices");
+        };
+        assert_eq!(label, "Pla&ying");
+        assert_eq!(
+            entries,
+            &[MenuEntry::Submenu {
+                label: "&Headphones".to_string(),
+                entries: vec![
+                    MenuEntry::Item(MenuItem {
+                        id: 0,
+                        label: "Spotify\tPlaying".to_string(),
+                        checked: false,
+                        disabled: true,
+                        radio: false,
+                    }),
+                    MenuEntry::Item(MenuItem {
+                        id: 0,
+                        label: "Teams".to_string(),
+                        checked: false,
+                        disabled: true,
+                        radio: false,
+                    }),
+                ],
+            }]
+        );
+        assert!(matches!(model.entries[index + 1], MenuEntry::Separator));
+    }
+
+    #[test]
+    fn saved_log_level_has_the_radio_mark() {
+        let devices = devices();
+        let config = Config {
+            log_level: LogLevel::Debug,
+            ..Default::default()
+        };
+        let model = MenuModel::build(
+            &devices,
+            &devices[0].id,
+            &Endpoints::default(),
+            &config,
+            &MachineConfig::default(),
+            &[],
+            None,
+        );
+        for level in LogLevel::ALL {
+            let item = find(&model, id(&model, MenuKey::LogLevel(level)));
+            assert!(item.radio);
+            assert_eq!(item.checked, level == LogLevel::Debug);
+        }
+    }
+}
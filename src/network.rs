@@ -0,0 +1,90 @@
+use log::debug;
+use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+use windows::Win32::Networking::NetworkListManager::{
+    INetwork, INetworkListManager, INetworkListManagerEvents, INetworkListManagerEvents_Impl,
+    NetworkListManager, NLM_CONNECTIVITY, NLM_ENUM_NETWORK_CONNECTED,
+};
+use windows::Win32::System::Com::{
+    CoCreateInstance, IConnectionPoint, IConnectionPointContainer, CLSCTX_ALL,
+};
+use windows::Win32::UI::WindowsAndMessaging::{PostMessageW, WM_APP};
+use windows_core::{implement, Interface};
+
+use sound_switcheroo::error::SwitcherooError;
+
+/// Posted to the window when the machine's network connectivity changes, e.g. joining a network.
+pub const WM_NETWORK_CHANGED: u32 = WM_APP + 0x57;
+
+/// The names Windows shows for the networks connected to now.
+pub fn connected_networks() -> Result<Vec<String>, SwitcherooError> {
+    unsafe {
+        let manager: INetworkListManager = CoCreateInstance(&NetworkListManager, None, CLSCTX_ALL)?;
+        let networks = manager.GetNetworks(NLM_ENUM_NETWORK_CONNECTED)?;
+        let mut names = Vec::new();
+        loop {
+            let mut network: [Option<INetwork>; 1] = [None];
+            let mut fetched = 0;
+            networks.Next(&mut network, Some(&mut fetched))?;
+            match network {
+                [Some(network)] if fetched == 1 => names.push(network.GetName()?.to_string()),
+                _ => break,
+            }
+        }
+        Ok(names)
+    }
+}
+
+/// Tells a window about changes to network connectivity until dropped.
+pub struct NetworkNotifications {
+    connection_point: IConnectionPoint,
+    cookie: u32,
+}
+
+impl NetworkNotifications {
+    pub fn register(window: HWND) -> Result<Self, SwitcherooError> {
+        unsafe {
+            let manager: INetworkListManager =
+                CoCreateInstance(&NetworkListManager, None, CLSCTX_ALL)?;
+            let connection_point = manager
+                .cast::<IConnectionPointContainer>()?
+                .FindConnectionPoint(&INetworkListManagerEvents::IID)?;
+            let sink: INetworkListManagerEvents = Sink {
+                window: window.0 as isize,
+            }
+            .into();
+            let cookie = connection_point.Advise(&sink)?;
+            Ok(Self {
+                connection_point,
+                cookie,
+            })
+        }
+    }
+}
+
+impl Drop for NetworkNotifications {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.connection_point.Unadvise(self.cookie);
+        }
+    }
+}
+
+// Only posts to the window, which finds out for itself which networks are connected.
+#[implement(INetworkListManagerEvents)]
+struct Sink {
+    window: isize,
+}
+
+impl INetworkListManagerEvents_Impl for Sink_Impl {
+    fn ConnectivityChanged(&self, connectivity: NLM_CONNECTIVITY) -> windows_core::Result<()> {
+        debug!("Network connectivity changed to {}", connectivity.0);
+        unsafe {
+            PostMessageW(
+                Some(HWND(self.window as *mut _)),
+                WM_NETWORK_CHANGED,
+                WPARAM::default(),
+                LPARAM::default(),
+            )
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use std::sync::Once;
+use windows::Win32::Foundation::{COLORREF, HWND, LPARAM, LRESULT, RECT, WPARAM};
+use windows::Win32::Graphics::Gdi::{
+    BeginPaint, CreateFontIndirectW, CreateSolidBrush, DeleteObject, DrawTextW, EndPaint, FillRect,
+    InvalidateRect, SelectObject, SetBkMode, SetTextColor, DT_END_ELLIPSIS, DT_NOPREFIX,
+    DT_SINGLELINE, DT_VCENTER, FW_SEMIBOLD, HFONT, PAINTSTRUCT, TRANSPARENT,
+};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, DestroyWindow, DrawIconEx, GetClientRect, GetWindowLongPtrW,
+    KillTimer, RegisterClassExW, SetLayeredWindowAttributes, SetTimer, SetWindowLongPtrW,
+    SetWindowPos, ShowWindow, SystemParametersInfoW, DI_NORMAL, GWLP_USERDATA, HICON, HWND_TOPMOST,
+    LWA_ALPHA, SPI_GETWORKAREA, SWP_NOACTIVATE, SWP_SHOWWINDOW, SW_HIDE,
+    SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS, WM_NCDESTROY, WM_PAINT, WM_TIMER, WNDCLASSEXW,
+    WS_EX_LAYERED, WS_EX_NOACTIVATE, WS_EX_TOOLWINDOW, WS_EX_TOPMOST, WS_EX_TRANSPARENT, WS_POPUP,
+};
+use windows_strings::w;
+
+use crate::dpi;
+use sound_switcheroo::error::SwitcherooError;
+
+// Sizes at 100% scaling, scaled to the DPI of the monitor it's shown on.
+const WIDTH: i32 = 360;
+const HEIGHT: i32 = 64;
+const ICON_SIZE: i32 = 32;
+const PADDING: i32 = 16;
+// How far above the taskbar it sits.
+const MARGIN: i32 = 48;
+// Out of 255, for the translucency.
+const OPACITY: u8 = 230;
+const HIDE_TIMER_ID: usize = 1;
+const HIDE_AFTER_MS: u32 = 1500;
+
+static REGISTER_CLASS: Once = Once::new();
+
+/// A translucent overlay near the bottom of the screen, like the one Windows shows for the volume
+/// keys, that briefly shows the device just switched to. Clicks go through it to whatever is
+/// underneath, and it never takes the focus.
+pub struct Osd {
+    window: HWND,
+}
+
+// What's drawn, kept in the window's user data.
+struct State {
+    icon: Option<HICON>,
+    text: Vec<u16>,
+    dark_mode: bool,
+    // Of the monitor it was last shown on, with the font made for it.
+    dpi: u32,
+    font: HFONT,
+}
+
+impl Osd {
+    /// Creates the overlay, hidden until shown.
+    pub fn create() -> Result<Self, SwitcherooError> {
+        unsafe {
+            let module = GetModuleHandleW(None)?;
+            REGISTER_CLASS.call_once(|| {
+                RegisterClassExW(&WNDCLASSEXW {
+                    cbSize: std::mem::size_of::<WNDCLASSEXW>() as u32,
+                    lpfnWndProc: Some(osd_callback),
+                    hInstance: module.into(),
+                    lpszClassName: w!("SoundSwitcherooOsd"),
+                    ..Default::default()
+                });
+            });
+            let window = CreateWindowExW(
+                WS_EX_LAYERED
+                    | WS_EX_TRANSPARENT
+                    | WS_EX_TOPMOST
+                    | WS_EX_TOOLWINDOW
+                    | WS_EX_NOACTIVATE,
+                w!("SoundSwitcherooOsd"),
+                w!("Sound Switcheroo"),
+                WS_POPUP,
+                0,
+                0,
+                WIDTH,
+                HEIGHT,
+                None,
+                None,
+                Some(module.into()),
+                None,
+            )?;
+            SetLayeredWindowAttributes(window, COLORREF(0), OPACITY, LWA_ALPHA)?;
+            let state = State {
+                icon: None,
+                text: Vec::new(),
+                dark_mode: false,
+                dpi: dpi::DEFAULT_DPI,
+                font: create_font(dpi::DEFAULT_DPI),
+            };
+            SetWindowLongPtrW(
+                window,
+                GWLP_USERDATA,
+                Box::into_raw(Box::new(state)) as isize,
+            );
+            Ok(Self { window })
+        }
+    }
+
+    /// Shows `text` next to `icon` for a moment, starting the moment over if it's already showing.
+    pub fn show(&self, icon: HICON, text: &str, dark_mode: bool) {
+        unsafe {
+            let Some(state) =
+                (GetWindowLongPtrW(self.window, GWLP_USERDATA) as *mut State).as_mut()
+            else {
+                return;
+            };
+            state.icon = Some(icon);
+            state.text = text.encode_utf16().collect();
+            state.dark_mode = dark_mode;
+            let mut work_area = RECT::default();
+            if SystemParametersInfoW(
+                SPI_GETWORKAREA,
+                0,
+                Some(&mut work_area as *mut _ as *mut _),
+                SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+            )
+            .is_err()
+            {
+                log::error!("Failed to find the work area for the OSD");
+            }
+            let dpi = dpi::for_point(
+                (work_area.left + work_area.right) / 2,
+                (work_area.top + work_area.bottom) / 2,
+            );
+            if dpi != state.dpi {
+                let _ = DeleteObject(state.font.into());
+                state.font = create_font(dpi);
+                state.dpi = dpi;
+            }
+            let scale = |size| dpi::scale(size, dpi);
+            let _ = InvalidateRect(Some(self.window), None, true);
+            let _ = SetWindowPos(
+                self.window,
+                Some(HWND_TOPMOST),
+                (work_area.left + work_area.right - scale(WIDTH)) / 2,
+                work_area.bottom - scale(HEIGHT + MARGIN),
+                scale(WIDTH),
+                scale(HEIGHT),
+                SWP_NOACTIVATE | SWP_SHOWWINDOW,
+            );
+            SetTimer(Some(self.window), HIDE_TIMER_ID, HIDE_AFTER_MS, None);
+        }
+    }
+}
+
+impl Drop for Osd {
+    fn drop(&mut self) {
+        let _ = unsafe { DestroyWindow(self.window) };
+    }
+}
+
+// The system's message font at `dpi`, made bigger.
+fn create_font(dpi: u32) -> HFONT {
+    let mut font = dpi::message_font(dpi);
+    font.lfHeight = font.lfHeight.saturating_mul(3) / 2;
+    font.lfWeight = FW_SEMIBOLD.0 as i32;
+    unsafe { CreateFontIndirectW(&font) }
+}
+
+unsafe fn paint(hwnd: HWND, state: &mut State) {
+    unsafe {
+        let mut paint = PAINTSTRUCT::default();
+        let dc = BeginPaint(hwnd, &mut paint);
+        let mut rect = RECT::default();
+        let _ = GetClientRect(hwnd, &mut rect);
+        // COLORREFs are 0x00bbggrr.
+        let (background, foreground) = if state.dark_mode {
+            (0x00202020, 0x00ffffff)
+        } else {
+            (0x00f3f3f3, 0x00000000)
+        };
+        let brush = CreateSolidBrush(COLORREF(background));
+        FillRect(dc, &rect, brush);
+        let _ = DeleteObject(brush.into());
+        let scale = |size| dpi::scale(size, state.dpi);
+        if let Some(icon) = state.icon {
+            let _ = DrawIconEx(
+                dc,
+                scale(PADDING),
+                (rect.bottom - scale(ICON_SIZE)) / 2,
+                icon,
+                scale(ICON_SIZE),
+                scale(ICON_SIZE),
+                0,
+                None,
+                DI_NORMAL,
+            );
+        }
+        SetBkMode(dc, TRANSPARENT);
+        SetTextColor(dc, COLORREF(foreground));
+        let old_font = SelectObject(dc, state.font.into());
+        rect.left = scale(PADDING * 2 + ICON_SIZE);
+        rect.right -= scale(PADDING);
+        DrawTextW(
+            dc,
+            &mut state.text,
+            &mut rect,
+            DT_SINGLELINE | DT_VCENTER | DT_END_ELLIPSIS | DT_NOPREFIX,
+        );
+        SelectObject(dc, old_font);
+        let _ = EndPaint(hwnd, &paint);
+    }
+}
+
+unsafe extern "system" fn osd_callback(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    unsafe {
+        let state = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut State;
+        match msg {
+            WM_PAINT if !state.is_null() => {
+                paint(hwnd, &mut *state);
+                LRESULT(0)
+            }
+            WM_TIMER if wparam.0 == HIDE_TIMER_ID => {
+                let _ = KillTimer(Some(hwnd), HIDE_TIMER_ID);
+                let _ = ShowWindow(hwnd, SW_HIDE);
+                LRESULT(0)
+            }
+            WM_NCDESTROY => {
+                if !state.is_null() {
+                    SetWindowLongPtrW(hwnd, GWLP_USERDATA, 0);
+                    let state = Box::from_raw(state);
+                    let _ = DeleteObject(state.font.into());
+                }
+                DefWindowProcW(hwnd, msg, wparam, lparam)
+            }
+            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+        }
+    }
+}
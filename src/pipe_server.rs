@@ -0,0 +1,406 @@
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::windows::io::FromRawHandle;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use windows::Win32::Foundation::{CloseHandle, ERROR_PIPE_CONNECTED, GENERIC_READ, HWND, LPARAM};
+use windows::Win32::Storage::FileSystem::{
+    CreateFileW, FILE_FLAGS_AND_ATTRIBUTES, FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_SHARE_NONE,
+    OPEN_EXISTING, PIPE_ACCESS_DUPLEX,
+};
+use windows::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, GetNamedPipeClientProcessId, PIPE_READMODE_BYTE,
+    PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+};
+use windows::Win32::UI::WindowsAndMessaging::{SendMessageW, WM_APP};
+
+use sound_switcheroo::error::SwitcherooError;
+use sound_switcheroo::ipc_auth::Authenticator;
+use sound_switcheroo::safe_strings::with_wide_str;
+
+/// Sent (not posted) to the window from the server's thread for each authenticated request, with
+/// LPARAM pointing to an `Exchange` for the window to answer.
+pub const WM_PIPE_REQUEST: u32 = WM_APP + 0x4f;
+
+// JSON-RPC's own error codes.
+const PARSE_ERROR: i32 = -32700;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+// The app's, from the range JSON-RPC leaves for servers.
+const FAILED: i32 = -32000;
+const UNAUTHORIZED: i32 = -32001;
+
+// The longest request taken, newline included, well beyond any real one. Clients that send longer
+// lines are disconnected before the token is even checked.
+const MAX_REQUEST_BYTES: u64 = 64 * 1024;
+
+/// What clients can ask the app to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    ListDevices,
+    GetDefault,
+    // To the device with this ID or (part of) this name.
+    Switch { device: String },
+    // Mutes or unmutes the current output.
+    ToggleMute,
+}
+
+/// A command passed to the window, and its result.
+#[derive(Debug)]
+pub struct Exchange {
+    pub command: Command,
+    pub result: Result<Value, String>,
+}
+
+// A JSON-RPC 2.0 request, one per line, e.g. `{"jsonrpc": "2.0", "id": 1, "method": "switch",
+// "params": {"token": "...", "device": "Headset"}}`.
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Params,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Params {
+    // From the `ipc_token` file next to the config.
+    token: String,
+    device: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Response {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+#[derive(Serialize)]
+struct RpcError {
+    code: i32,
+    message: String,
+}
+
+impl Request {
+    fn command(&self) -> Result<Command, RpcError> {
+        match self.method.as_str() {
+            "list_devices" => Ok(Command::ListDevices),
+            "get_default" => Ok(Command::GetDefault),
+            "switch" => match &self.params.device {
+                Some(device) => Ok(Command::Switch {
+                    device: device.clone(),
+                }),
+                None => Err(RpcError {
+                    code: INVALID_PARAMS,
+                    message: "switch needs a device".to_string(),
+                }),
+            },
+            "toggle_mute" => Ok(Command::ToggleMute),
+            method => Err(RpcError {
+                code: METHOD_NOT_FOUND,
+                message: format!("Unknown method {method}"),
+            }),
+        }
+    }
+}
+
+/// Answers one line from a client: checks its token with `authenticate`, then has `execute` carry
+/// out the command.
+fn respond(
+    line: &str,
+    authenticate: impl FnOnce(&str) -> Result<(), SwitcherooError>,
+    execute: impl FnOnce(Command) -> Result<Value, String>,
+) -> String {
+    let (id, outcome) = match serde_json::from_str::<Request>(line) {
+        Err(e) => (
+            Value::Null,
+            Err(RpcError {
+                code: PARSE_ERROR,
+                message: e.to_string(),
+            }),
+        ),
+        Ok(request) => {
+            let outcome = match authenticate(&request.params.token) {
+                Err(e) => Err(RpcError {
+                    code: UNAUTHORIZED,
+                    message: e.to_string(),
+                }),
+                Ok(()) => request.command().and_then(|command| {
+                    execute(command).map_err(|message| RpcError {
+                        code: FAILED,
+                        message,
+                    })
+                }),
+            };
+            (request.id, outcome)
+        }
+    };
+    let (result, error) = match outcome {
+        Ok(result) => (Some(result), None),
+        Err(error) => (None, Some(error)),
+    };
+    serde_json::to_string(&Response {
+        jsonrpc: "2.0",
+        id,
+        result,
+        error,
+    })
+    .unwrap_or_default()
+}
+
+/// Serves commands on a named pipe, on its own thread until dropped, with each client on an
+/// instance and thread of its own. Only local clients presenting the token (and, if configured,
+/// running an allowed executable) get an answer.
+pub struct PipeServer {
+    name: String,
+    stopping: Arc<AtomicBool>,
+}
+
+impl PipeServer {
+    pub fn start(window: HWND, name: String, authenticator: Authenticator) -> Self {
+        let stopping = Arc::new(AtomicBool::new(false));
+        let window = window.0 as isize;
+        std::thread::spawn({
+            let name = name.clone();
+            let stopping = stopping.clone();
+            move || {
+                let window = HWND(window as *mut _);
+                if let Err(e) = serve(window, &name, &authenticator, &stopping) {
+                    error!("Stopped serving commands on {name}: {e}");
+                }
+            }
+        });
+        info!("Serving commands on {name}");
+        Self { name, stopping }
+    }
+}
+
+impl Drop for PipeServer {
+    fn drop(&mut self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        // Wakes the server, which always has an instance waiting for the next client.
+        unsafe {
+            if let Ok(handle) = with_wide_str(&self.name, |name| {
+                CreateFileW(
+                    name,
+                    GENERIC_READ.0,
+                    FILE_SHARE_NONE,
+                    None,
+                    OPEN_EXISTING,
+                    FILE_FLAGS_AND_ATTRIBUTES(0),
+                    None,
+                )
+            }) {
+                let _ = CloseHandle(handle);
+            }
+        }
+    }
+}
+
+fn serve(
+    window: HWND,
+    name: &str,
+    authenticator: &Authenticator,
+    stopping: &AtomicBool,
+) -> Result<(), SwitcherooError> {
+    let mut first = true;
+    while !stopping.load(Ordering::SeqCst) {
+        // Being the first instance means no other process got to the name first. The ones after it
+        // are made while it (or another of ours) is still open, so the name stays ours.
+        let mut open_mode = PIPE_ACCESS_DUPLEX;
+        if first {
+            open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+            first = false;
+        }
+        let pipe = unsafe {
+            with_wide_str(name, |wide_name| {
+                CreateNamedPipeW(
+                    wide_name,
+                    open_mode,
+                    PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                    PIPE_UNLIMITED_INSTANCES,
+                    4096,
+                    4096,
+                    0,
+                    None,
+                )
+            })
+        };
+        if pipe.is_invalid() {
+            return Err(windows::core::Error::from_win32().into());
+        }
+        // Closed when dropped, disconnecting the client.
+        let pipe_file = unsafe { File::from_raw_handle(pipe.0) };
+        unsafe {
+            if let Err(e) = ConnectNamedPipe(pipe, None)
+                && e.code() != ERROR_PIPE_CONNECTED.to_hresult()
+            {
+                return Err(e.into());
+            }
+        }
+        if stopping.load(Ordering::SeqCst) {
+            break;
+        }
+        let mut client_pid = 0;
+        unsafe { GetNamedPipeClientProcessId(pipe, &mut client_pid)? };
+        debug!("Pipe client {client_pid} connected");
+        // A client that stays connected doesn't hold up the next one.
+        let window = window.0 as isize;
+        let authenticator = authenticator.clone();
+        std::thread::spawn(move || {
+            let window = HWND(window as *mut _);
+            if let Err(e) = serve_client(window, &pipe_file, client_pid, &authenticator) {
+                debug!("Pipe client {client_pid} went away: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+// Answers the client's requests until it disconnects.
+fn serve_client(
+    window: HWND,
+    pipe: &File,
+    client_pid: u32,
+    authenticator: &Authenticator,
+) -> io::Result<()> {
+    let mut writer = pipe;
+    let mut reader = BufReader::new(pipe);
+    let mut line = String::new();
+    while read_request(&mut reader, &mut line)? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = respond(
+            &line,
+            |token| authenticator.check_process(token, client_pid),
+            |command| execute(window, command),
+        );
+        writer.write_all(response.as_bytes())?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+// Reads the next line into `line`, returning false once the client has disconnected, or an error if
+// the line is longer than `MAX_REQUEST_BYTES`.
+fn read_request(reader: &mut impl BufRead, line: &mut String) -> io::Result<bool> {
+    line.clear();
+    let read = reader.take(MAX_REQUEST_BYTES).read_line(line)?;
+    if read == 0 {
+        return Ok(false);
+    }
+    if read as u64 == MAX_REQUEST_BYTES && !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Request longer than {MAX_REQUEST_BYTES} bytes"),
+        ));
+    }
+    Ok(true)
+}
+
+// Has the window carry out the command, on its own thread.
+fn execute(window: HWND, command: Command) -> Result<Value, String> {
+    debug!("Pipe command: {command:?}");
+    let mut exchange = Exchange {
+        command,
+        // Left as it is if the window has gone.
+        result: Err("Sound Switcheroo is closing".to_string()),
+    };
+    unsafe {
+        SendMessageW(
+            window,
+            WM_PIPE_REQUEST,
+            None,
+            Some(LPARAM(&mut exchange as *mut _ as isize)),
+        );
+    }
+    exchange.result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn answer(line: &str) -> Value {
+        let response = respond(
+            line,
+            |token| match token {
+                "secret" => Ok(()),
+                _ => Err(SwitcherooError::Other("Invalid token".to_string())),
+            },
+            |command| match command {
+                Command::Switch { device } if device == "missing" => {
+                    Err("No output device matches \"missing\"".to_string())
+                }
+                command => Ok(json!(format!("{command:?}"))),
+            },
+        );
+        serde_json::from_str(&response).unwrap()
+    }
+
+    #[test]
+    fn carries_out_authenticated_commands() {
+        assert_eq!(
+            answer(
+                r#"{"jsonrpc": "2.0", "id": 7, "method": "switch", "params": {"token": "secret", "device": "Headset"}}"#
+            ),
+            json!({"jsonrpc": "2.0", "id": 7, "result": "Switch { device: \"Headset\" }"})
+        );
+        assert_eq!(
+            answer(r#"{"id": "a", "method": "toggle_mute", "params": {"token": "secret"}}"#)
+                ["result"],
+            "ToggleMute"
+        );
+    }
+
+    #[test]
+    fn reports_errors_with_json_rpc_codes() {
+        let code = |line: &str| answer(line)["error"]["code"].as_i64().unwrap();
+        assert_eq!(code("not json"), -32700);
+        assert_eq!(
+            code(r#"{"id": 1, "method": "list_devices", "params": {"token": "guess"}}"#),
+            -32001
+        );
+        assert_eq!(
+            code(r#"{"id": 1, "method": "reboot", "params": {"token": "secret"}}"#),
+            -32601
+        );
+        assert_eq!(
+            code(r#"{"id": 1, "method": "switch", "params": {"token": "secret"}}"#),
+            -32602
+        );
+        assert_eq!(
+            code(
+                r#"{"id": 1, "method": "switch", "params": {"token": "secret", "device": "missing"}}"#
+            ),
+            -32000
+        );
+        // Unknown methods aren't revealed without the token.
+        assert_eq!(code(r#"{"id": 1, "method": "reboot"}"#), -32001);
+    }
+
+    #[test]
+    fn disconnects_clients_sending_overlong_requests() {
+        let mut line = String::new();
+        let mut reader = io::Cursor::new(b"{}\n\n".to_vec());
+        assert!(read_request(&mut reader, &mut line).unwrap());
+        assert_eq!(line, "{}\n");
+        assert!(read_request(&mut reader, &mut line).unwrap());
+        assert!(!read_request(&mut reader, &mut line).unwrap());
+
+        let mut flood = io::Cursor::new(vec![b'x'; MAX_REQUEST_BYTES as usize * 2]);
+        assert!(read_request(&mut flood, &mut line).is_err());
+        assert!(line.len() as u64 <= MAX_REQUEST_BYTES);
+    }
+}
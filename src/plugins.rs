@@ -0,0 +1,204 @@
+use crate::bail;
+use crate::error::SwitcherooError;
+use log::{debug, error, info};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Read, Write};
+use std::os::windows::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+use windows::Win32::UI::WindowsAndMessaging::{PostMessageW, WM_APP};
+
+/// Posted to the main window when a plugin has handled one of its menu items. LPARAM is a
+/// `Box<String>` describing the outcome, owned by the receiver.
+pub const WM_PLUGIN_FINISHED: u32 = WM_APP + 0x4b;
+
+/// Posted to the main window once the plugins have been found. LPARAM is a `Box<Vec<Plugin>>`,
+/// owned by the receiver.
+pub const WM_PLUGINS_DISCOVERED: u32 = WM_APP + 0x5a;
+
+// Keeps a console window from flashing up for each query.
+const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+// A plugin that takes longer is killed.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Files in the plugins directory run as plugins. Batch files are run through `cmd` by `Command`.
+const EXTENSIONS: &[&str] = &["exe", "bat", "cmd"];
+
+/// What a plugin is sent on stdin, as a single JSON object.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum Request {
+    // Asks for the items to add to the menu.
+    MenuItems,
+    // One of its items was chosen.
+    Activate { id: String },
+}
+
+/// What a plugin writes to stdout in reply, as a single JSON object. Every field is optional.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Response {
+    // In reply to `menu_items`.
+    pub items: Vec<PluginItem>,
+    // Shown in a notification after `activate`.
+    pub message: Option<String>,
+    // Shown instead of `message` if the plugin failed.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PluginItem {
+    // Sent back when the item is chosen.
+    pub id: String,
+    pub label: String,
+}
+
+/// An executable in the plugins directory and the menu items it contributes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plugin {
+    pub path: PathBuf,
+    // The file name without its extension, shown in notifications.
+    pub name: String,
+    pub items: Vec<PluginItem>,
+}
+
+/// Runs `discover` on another thread, so slow plugins don't hold up startup, and posts what it
+/// found to `window` as `WM_PLUGINS_DISCOVERED`.
+pub fn discover_in_background(window: HWND, dir: PathBuf) {
+    let window = window.0 as isize;
+    std::thread::spawn(move || {
+        let plugins = Box::new(discover(&dir));
+        unsafe {
+            let _ = PostMessageW(
+                Some(HWND(window as *mut _)),
+                WM_PLUGINS_DISCOVERED,
+                WPARAM::default(),
+                LPARAM(Box::into_raw(plugins) as isize),
+            );
+        }
+    });
+}
+
+/// Finds the plugins in `dir` and asks each for its menu items. Plugins that fail are logged and
+/// left out.
+pub fn discover(dir: &Path) -> Vec<Plugin> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        debug!("No plugins directory at {}", dir.display());
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| {
+                    EXTENSIONS
+                        .iter()
+                        .any(|known| known.eq_ignore_ascii_case(extension))
+                })
+        })
+        .collect();
+    paths.sort();
+    paths
+        .into_iter()
+        .filter_map(|path| match query(&path, &Request::MenuItems) {
+            Ok(response) => {
+                let name = path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                info!("Plugin {name} adds {} menu items", response.items.len());
+                Some(Plugin {
+                    path,
+                    name,
+                    items: response.items,
+                })
+            }
+            Err(e) => {
+                error!("Plugin {} failed: {e}", path.display());
+                None
+            }
+        })
+        .collect()
+}
+
+/// Tells a plugin one of its items was chosen, and describes the outcome.
+pub fn activate(plugin: &Plugin, id: &str) -> String {
+    match query(&plugin.path, &Request::Activate { id: id.to_string() }) {
+        Ok(Response {
+            error: Some(error), ..
+        }) => format!("{}: {error}", plugin.name),
+        Ok(Response {
+            message: Some(message),
+            ..
+        }) => format!("{}: {message}", plugin.name),
+        Ok(_) => format!("{}: done.", plugin.name),
+        Err(e) => format!("{} failed: {e}", plugin.name),
+    }
+}
+
+// Runs a plugin with the request on stdin and reads its reply from stdout.
+fn query(path: &Path, request: &Request) -> Result<Response, SwitcherooError> {
+    debug!("Sending {request:?} to plugin {}", path.display());
+    let mut child = Command::new(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .creation_flags(CREATE_NO_WINDOW)
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        serde_json::to_writer(&mut stdin, request)?;
+        stdin.write_all(b"\n")?;
+    }
+    let Some(mut stdout) = child.stdout.take() else {
+        bail!("No stdout");
+    };
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut output = String::new();
+        let _ = sender.send(stdout.read_to_string(&mut output).map(|_| output));
+    });
+    let output = match receiver.recv_timeout(TIMEOUT) {
+        Ok(output) => output?,
+        Err(_) => {
+            let _ = child.kill();
+            bail!("No reply within {} seconds", TIMEOUT.as_secs());
+        }
+    };
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("Exited with {status}");
+    }
+    Ok(serde_json::from_str(&output)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speaks_json() {
+        assert_eq!(
+            serde_json::to_string(&Request::MenuItems).unwrap(),
+            r#"{"request":"menu_items"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Request::Activate {
+                id: "restart".to_string()
+            })
+            .unwrap(),
+            r#"{"request":"activate","id":"restart"}"#
+        );
+        let response: Response = serde_json::from_str(
+            r#"{"items": [{"id": "restart", "label": "Restart audio service"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(response.items[0].label, "Restart audio service");
+        assert_eq!(response.message, None);
+    }
+}
@@ -0,0 +1,80 @@
+use log::info;
+use windows::Win32::Foundation::{HANDLE, HWND, LPARAM};
+use windows::Win32::System::Power::{
+    GetSystemPowerStatus, RegisterPowerSettingNotification, UnregisterPowerSettingNotification,
+    HPOWERNOTIFY, POWERBROADCAST_SETTING, SYSTEM_POWER_STATUS,
+};
+use windows::Win32::System::SystemServices::GUID_POWER_SAVING_STATUS;
+use windows::Win32::UI::WindowsAndMessaging::DEVICE_NOTIFY_WINDOW_HANDLE;
+
+use sound_switcheroo::error::SwitcherooError;
+
+/// How much background work the app should be doing given the machine's power state. Anything
+/// that polls, animates, or keeps devices open speculatively should check this first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PowerPolicy {
+    battery_saver: bool,
+}
+
+impl PowerPolicy {
+    /// Reads the current battery saver state.
+    pub fn query() -> Result<Self, SwitcherooError> {
+        let mut status = SYSTEM_POWER_STATUS::default();
+        unsafe { GetSystemPowerStatus(&mut status)? };
+        Ok(Self {
+            battery_saver: status.SystemStatusFlag == 1,
+        })
+    }
+
+    /// Whether to cut back on background activity: no polling, longer intervals, no animations.
+    pub fn reduce_background_activity(&self) -> bool {
+        self.battery_saver
+    }
+
+    /// Updates the policy from a `PBT_POWERSETTINGCHANGE` broadcast, ignoring other settings.
+    /// Returns whether it changed, so what runs in the background can be started or stopped.
+    ///
+    /// # Safety
+    /// `lparam` must be the `LPARAM` of a `WM_POWERBROADCAST` message with
+    /// `PBT_POWERSETTINGCHANGE`.
+    pub unsafe fn handle_setting_change(&mut self, lparam: LPARAM) -> bool {
+        let setting = unsafe { &*(lparam.0 as *const POWERBROADCAST_SETTING) };
+        if setting.PowerSetting != GUID_POWER_SAVING_STATUS || setting.DataLength < 1 {
+            return false;
+        }
+        let battery_saver = setting.Data[0] != 0;
+        if battery_saver == self.battery_saver {
+            return false;
+        }
+        info!(
+            "Battery saver turned {}",
+            if battery_saver { "on" } else { "off" }
+        );
+        self.battery_saver = battery_saver;
+        true
+    }
+}
+
+/// Delivers battery saver changes to a window as `WM_POWERBROADCAST` messages until dropped.
+pub struct PowerNotifications(HPOWERNOTIFY);
+
+impl PowerNotifications {
+    pub fn register(window: HWND) -> Result<Self, SwitcherooError> {
+        let handle = unsafe {
+            RegisterPowerSettingNotification(
+                HANDLE(window.0),
+                &GUID_POWER_SAVING_STATUS,
+                DEVICE_NOTIFY_WINDOW_HANDLE,
+            )?
+        };
+        Ok(Self(handle))
+    }
+}
+
+impl Drop for PowerNotifications {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = UnregisterPowerSettingNotification(self.0);
+        }
+    }
+}
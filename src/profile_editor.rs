@@ -0,0 +1,405 @@
+use log::debug;
+use std::sync::Once;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::UI::WindowsAndMessaging::{
+    DefWindowProcW, GetParent, GetWindow, GetWindowLongPtrW, SendMessageW, SetWindowLongPtrW,
+    BN_CLICKED, GWLP_USERDATA, GW_OWNER, LBN_SELCHANGE, WM_APP, WM_COMMAND, WM_NCDESTROY, WM_SIZE,
+};
+use windows_strings::w;
+
+use crate::controls::{self, Form};
+use sound_switcheroo::backend::AudioDevice;
+use sound_switcheroo::error::SwitcherooError;
+use sound_switcheroo::hotkey::Hotkey;
+use sound_switcheroo::profiles::{self, Profile};
+
+/// Sent to the settings window's owner when edited profiles are saved. LPARAM points to a
+/// `ProfileChanges`, which is only valid during the message.
+pub const WM_SETTINGS_PROFILES_CHANGED: u32 = WM_APP + 0x48;
+
+/// Saved profiles, and what the owner did to the rules that applied them.
+#[derive(Debug)]
+pub struct ProfileChanges<'a> {
+    pub profiles: &'a [Profile],
+    // (old name, new name) of each profile renamed since the last save.
+    pub renamed: Vec<(String, String)>,
+    // Names of the rules the owner turned off, as the profile they applied was deleted.
+    pub turned_off: Vec<String>,
+}
+
+// Control IDs, received in WM_COMMAND.
+const ID_LIST: u16 = 100;
+const ID_NEW: u16 = 101;
+const ID_DELETE: u16 = 102;
+const ID_SAVE: u16 = 103;
+
+const LEAVE_ALONE: &str = "(leave alone)";
+
+static REGISTER_CLASS: Once = Once::new();
+
+// Kept in the page's user data.
+struct Editor {
+    form: Form,
+    name: HWND,
+    output: HWND,
+    input: HWND,
+    output_volume: HWND,
+    input_volume: HWND,
+    hotkey: HWND,
+    icon: HWND,
+    apply_at_startup: HWND,
+    apply_on_resume: HWND,
+    // The profiles being edited, saved only when Save is pressed.
+    profiles: Vec<Profile>,
+    // The name each profile had when last saved, None for new ones, to tell renames from deletions.
+    saved_names: Vec<Option<String>>,
+    selected: Option<usize>,
+    // (ID, name) of the known endpoints.
+    outputs: Vec<(String, String)>,
+    inputs: Vec<(String, String)>,
+    // The device ID behind each entry of the output and input dropdowns, None being "leave alone".
+    output_choices: Vec<Option<String>>,
+    input_choices: Vec<Option<String>>,
+}
+
+impl Editor {
+    fn fill_list(&self) {
+        controls::fill_list(
+            self.form.list,
+            self.profiles.iter().map(|profile| {
+                if profile.name.trim().is_empty() {
+                    "(unnamed)"
+                } else {
+                    profile.name.as_str()
+                }
+            }),
+            self.selected,
+        );
+    }
+
+    // Shows the selected profile in the fields, which are emptied if there isn't one.
+    fn load_fields(&mut self) {
+        let profile = self
+            .selected
+            .and_then(|selected| self.profiles.get(selected))
+            .cloned()
+            .unwrap_or_default();
+        controls::set_text(self.name, &profile.name);
+        let percent = |volume: Option<u32>| volume.map(|v| v.to_string()).unwrap_or_default();
+        controls::set_text(self.output_volume, &percent(profile.output_volume));
+        controls::set_text(self.input_volume, &percent(profile.input_volume));
+        controls::set_text(
+            self.hotkey,
+            &profile
+                .hotkey
+                .map(|hotkey| hotkey.to_string())
+                .unwrap_or_default(),
+        );
+        controls::set_text(self.icon, profile.icon.as_deref().unwrap_or_default());
+        controls::set_checked(self.apply_at_startup, profile.apply_at_startup);
+        controls::set_checked(self.apply_on_resume, profile.apply_on_resume);
+        self.output_choices = controls::fill_device_combo(
+            self.output,
+            Some(LEAVE_ALONE),
+            &self.outputs,
+            profile.output.as_ref(),
+        );
+        self.input_choices = controls::fill_device_combo(
+            self.input,
+            Some(LEAVE_ALONE),
+            &self.inputs,
+            profile.input.as_ref(),
+        );
+    }
+
+    // Copies the fields into the selected profile. Fails, leaving it alone, if the hotkey or a
+    // volume can't be understood.
+    fn store_fields(&mut self) -> Result<(), String> {
+        let Some(selected) = self.selected else {
+            return Ok(());
+        };
+        let hotkey = match controls::text(self.hotkey).trim() {
+            "" => None,
+            text => Some(text.parse::<Hotkey>().map_err(|e| e.to_string())?),
+        };
+        let output_volume = profiles::parse_volume(&controls::text(self.output_volume))?;
+        let input_volume = profiles::parse_volume(&controls::text(self.input_volume))?;
+        let profile = &mut self.profiles[selected];
+        profile.name = controls::text(self.name).trim().to_string();
+        profile.output = controls::combo_choice(self.output, &self.output_choices);
+        profile.input = controls::combo_choice(self.input, &self.input_choices);
+        profile.output_volume = output_volume;
+        profile.input_volume = input_volume;
+        profile.hotkey = hotkey;
+        profile.icon =
+            Some(controls::text(self.icon).trim().to_string()).filter(|icon| !icon.is_empty());
+        profile.apply_at_startup = controls::checked(self.apply_at_startup);
+        profile.apply_on_resume = controls::checked(self.apply_on_resume);
+        Ok(())
+    }
+
+    fn set_status(&self, text: &str) {
+        controls::set_text(self.form.status, text);
+    }
+
+    // Moves to another profile, keeping the edits to this one.
+    fn select(&mut self, selected: Option<usize>) {
+        if let Err(e) = self.store_fields() {
+            self.set_status(&e);
+            self.fill_list();
+            return;
+        }
+        self.selected = selected;
+        self.fill_list();
+        self.load_fields();
+        self.set_status("");
+    }
+
+    fn add(&mut self) {
+        if let Err(e) = self.store_fields() {
+            self.set_status(&e);
+            return;
+        }
+        self.profiles.push(Profile {
+            name: format!("Profile {}", self.profiles.len() + 1),
+            ..Default::default()
+        });
+        self.saved_names.push(None);
+        self.selected = Some(self.profiles.len() - 1);
+        self.fill_list();
+        self.load_fields();
+        self.set_status("");
+    }
+
+    fn delete(&mut self) {
+        let Some(selected) = self.selected else {
+            return;
+        };
+        self.profiles.remove(selected);
+        self.saved_names.remove(selected);
+        self.selected = if self.profiles.is_empty() {
+            None
+        } else {
+            Some(selected.min(self.profiles.len() - 1))
+        };
+        self.fill_list();
+        self.load_fields();
+        self.set_status("Deleted. Save to keep the change.");
+    }
+
+    // Validates the profiles and, unless something stops them being used, sends them to the
+    // settings window's owner.
+    fn save(&mut self, page: HWND) {
+        if let Err(e) = self.store_fields() {
+            self.set_status(&e);
+            return;
+        }
+        self.fill_list();
+        let known_devices: Vec<&str> = self
+            .outputs
+            .iter()
+            .chain(&self.inputs)
+            .map(|(id, _)| id.as_str())
+            .collect();
+        let problems = profiles::validate(&self.profiles, &known_devices);
+        let report = problems
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n");
+        if problems.iter().any(profiles::Problem::is_blocking) {
+            self.set_status(&format!("Not saved.\n{report}"));
+            return;
+        }
+        debug!("Saving {} profiles", self.profiles.len());
+        let mut changes = ProfileChanges {
+            profiles: &self.profiles,
+            renamed: self
+                .saved_names
+                .iter()
+                .zip(&self.profiles)
+                .filter_map(|(saved_name, profile)| {
+                    let saved_name = saved_name.as_ref()?;
+                    (*saved_name != profile.name)
+                        .then(|| (saved_name.clone(), profile.name.clone()))
+                })
+                .collect(),
+            turned_off: Vec::new(),
+        };
+        unsafe {
+            if let Ok(settings) = GetParent(page)
+                && let Ok(owner) = GetWindow(settings, GW_OWNER)
+            {
+                SendMessageW(
+                    owner,
+                    WM_SETTINGS_PROFILES_CHANGED,
+                    None,
+                    Some(LPARAM(&mut changes as *mut _ as isize)),
+                );
+            }
+        }
+        let mut status = format!("Saved.\n{report}");
+        if !changes.turned_off.is_empty() {
+            status.push_str(&format!(
+                "\nTurned off rules applying a deleted profile: {}.",
+                changes.turned_off.join(", ")
+            ));
+        }
+        self.saved_names = self
+            .profiles
+            .iter()
+            .map(|profile| Some(profile.name.clone()))
+            .collect();
+        self.set_status(&status);
+    }
+}
+
+/// Creates the profiles page as a hidden child of the settings window, editing `profiles` with
+/// dropdowns of the known `outputs` and `inputs`.
+pub fn create(
+    parent: HWND,
+    profiles: &[Profile],
+    outputs: &[AudioDevice],
+    inputs: &[AudioDevice],
+) -> Result<HWND, SwitcherooError> {
+    let page = controls::create_page(
+        parent,
+        w!("SoundSwitcherooProfiles"),
+        &REGISTER_CLASS,
+        Some(editor_callback),
+    )?;
+    let list = controls::list(page, ID_LIST)?;
+    let name = controls::edit(page)?;
+    let output = controls::dropdown(page, 0, false)?;
+    let input = controls::dropdown(page, 0, false)?;
+    let output_volume = controls::edit(page)?;
+    let input_volume = controls::edit(page)?;
+    let hotkey = controls::edit(page)?;
+    let icon = controls::edit(page)?;
+    let apply_at_startup = controls::checkbox(page, "Apply at s&tartup")?;
+    let apply_on_resume = controls::checkbox(page, "Apply on &resume from sleep")?;
+    let mut editor = Editor {
+        form: Form {
+            list,
+            rows: vec![
+                (controls::label(page, "Name")?, name, false),
+                (controls::label(page, "Output")?, output, true),
+                (controls::label(page, "Input")?, input, true),
+                (
+                    controls::label(page, "Output volume %")?,
+                    output_volume,
+                    false,
+                ),
+                (
+                    controls::label(page, "Input volume %")?,
+                    input_volume,
+                    false,
+                ),
+                (controls::label(page, "Hotkey")?, hotkey, false),
+                (controls::label(page, "Tray icon")?, icon, false),
+                (controls::label(page, "")?, apply_at_startup, false),
+                (controls::label(page, "")?, apply_on_resume, false),
+            ],
+            status: controls::label(page, "")?,
+            buttons: vec![
+                controls::button(page, "&New", ID_NEW)?,
+                controls::button(page, "&Delete", ID_DELETE)?,
+                controls::button(page, "&Save", ID_SAVE)?,
+            ],
+        },
+        name,
+        output,
+        input,
+        output_volume,
+        input_volume,
+        hotkey,
+        icon,
+        apply_at_startup,
+        apply_on_resume,
+        profiles: profiles.to_vec(),
+        saved_names: profiles
+            .iter()
+            .map(|profile| Some(profile.name.clone()))
+            .collect(),
+        selected: (!profiles.is_empty()).then_some(0),
+        outputs: controls::device_names(outputs),
+        inputs: controls::device_names(inputs),
+        output_choices: Vec::new(),
+        input_choices: Vec::new(),
+    };
+    editor.fill_list();
+    editor.load_fields();
+    unsafe {
+        SetWindowLongPtrW(
+            page,
+            GWLP_USERDATA,
+            Box::into_raw(Box::new(editor)) as isize,
+        );
+    }
+    Ok(page)
+}
+
+/// Refreshes the dropdowns after endpoints come or go, keeping what's being edited.
+pub fn update_devices(page: HWND, outputs: &[AudioDevice], inputs: &[AudioDevice]) {
+    let Some(editor) =
+        (unsafe { (GetWindowLongPtrW(page, GWLP_USERDATA) as *mut Editor).as_mut() })
+    else {
+        return;
+    };
+    let output = controls::combo_choice(editor.output, &editor.output_choices);
+    let input = controls::combo_choice(editor.input, &editor.input_choices);
+    editor.outputs = controls::device_names(outputs);
+    editor.inputs = controls::device_names(inputs);
+    editor.output_choices = controls::fill_device_combo(
+        editor.output,
+        Some(LEAVE_ALONE),
+        &editor.outputs,
+        output.as_ref(),
+    );
+    editor.input_choices = controls::fill_device_combo(
+        editor.input,
+        Some(LEAVE_ALONE),
+        &editor.inputs,
+        input.as_ref(),
+    );
+}
+
+unsafe extern "system" fn editor_callback(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    unsafe {
+        let editor = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut Editor;
+        match msg {
+            WM_SIZE if !editor.is_null() => {
+                (*editor).form.layout(hwnd);
+                LRESULT(0)
+            }
+            WM_COMMAND if !editor.is_null() => {
+                let editor = &mut *editor;
+                let id = (wparam.0 & 0xffff) as u16;
+                let notification = ((wparam.0 >> 16) & 0xffff) as u32;
+                match (id, notification) {
+                    (ID_LIST, LBN_SELCHANGE) => {
+                        editor.select(controls::list_selection(editor.form.list))
+                    }
+                    (ID_NEW, BN_CLICKED) => editor.add(),
+                    (ID_DELETE, BN_CLICKED) => editor.delete(),
+                    (ID_SAVE, BN_CLICKED) => editor.save(hwnd),
+                    _ => {}
+                }
+                LRESULT(0)
+            }
+            WM_NCDESTROY => {
+                if !editor.is_null() {
+                    SetWindowLongPtrW(hwnd, GWLP_USERDATA, 0);
+                    drop(Box::from_raw(editor));
+                }
+                DefWindowProcW(hwnd, msg, wparam, lparam)
+            }
+            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+        }
+    }
+}
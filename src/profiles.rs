@@ -0,0 +1,325 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::backend::FormFactor;
+use crate::hotkey::Hotkey;
+
+/// The built-in icons a profile can show in the tray, by name, and the kind of device each is
+/// drawn for.
+pub const BUILT_IN_ICONS: [(&str, FormFactor); 7] = [
+    ("headphones", FormFactor::Headphones),
+    ("headset", FormFactor::Headset),
+    ("speakers", FormFactor::Speakers),
+    ("display", FormFactor::DigitalAudioDisplayDevice),
+    ("line_level", FormFactor::LineLevel),
+    ("spdif", FormFactor::Spdif),
+    ("unknown", FormFactor::Unknown),
+];
+
+/// A named set of default devices and their volumes to switch to together, e.g. "Gaming" or
+/// "Calls".
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub name: String,
+    // Endpoint IDs of the output and microphone to make the defaults. None leaves it alone.
+    pub output: Option<String>,
+    pub input: Option<String>,
+    // Percentages to set the output's and microphone's volumes to. None leaves them alone.
+    pub output_volume: Option<u32>,
+    pub input_volume: Option<u32>,
+    // Endpoint IDs to make the communications defaults, when they aren't the same as the output and
+    // microphone. None leaves them alone.
+    pub communications_output: Option<String>,
+    pub communications_input: Option<String>,
+    pub hotkey: Option<Hotkey>,
+    // Applied when the app starts and when the computer wakes from sleep, so each session starts
+    // from a known setup. At most one profile may have each.
+    pub apply_at_startup: bool,
+    pub apply_on_resume: bool,
+    // Shown in the tray instead of the device's icon while the output the profile applied is still
+    // the default, e.g. to tell "Streaming" from "Meetings" at a glance. See `ProfileIcon::parse`.
+    pub icon: Option<String>,
+}
+
+/// What a profile's tray icon is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileIcon {
+    BuiltIn(FormFactor),
+    File(PathBuf),
+}
+
+impl ProfileIcon {
+    /// Reads a profile's `icon`: one of the names in `BUILT_IN_ICONS`, otherwise the path of an
+    /// .ico file, relative to `dir` unless it's absolute.
+    pub fn parse(icon: &str, dir: &Path) -> Self {
+        let icon = icon.trim();
+        match BUILT_IN_ICONS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(icon))
+        {
+            Some((_, form_factor)) => ProfileIcon::BuiltIn(*form_factor),
+            None => ProfileIcon::File(dir.join(icon)),
+        }
+    }
+}
+
+/// Names a profile saved from the current setup after its output and microphone, without the
+/// adapter Windows adds in brackets, e.g. "Headphones + Microphone". Numbered if `profiles`
+/// already have the name.
+pub fn setup_name(output: Option<&str>, input: Option<&str>, profiles: &[Profile]) -> String {
+    let short = |name: &str| name.split(" (").next().unwrap_or(name).trim().to_string();
+    let name = match (output.map(short), input.map(short)) {
+        (Some(output), Some(input)) => format!("{output} + {input}"),
+        (Some(name), None) | (None, Some(name)) => name,
+        (None, None) => "Current setup".to_string(),
+    };
+    let taken = |candidate: &str| {
+        profiles
+            .iter()
+            .any(|profile| profile.name.trim().eq_ignore_ascii_case(candidate))
+    };
+    if !taken(&name) {
+        return name;
+    }
+    (2..)
+        .map(|n| format!("{name} {n}"))
+        .find(|candidate| !taken(candidate))
+        .unwrap()
+}
+
+/// Reads a volume typed into the profile editor: a percentage, with or without the %, or nothing
+/// to leave the volume alone.
+pub fn parse_volume(text: &str) -> Result<Option<u32>, String> {
+    let text = text.trim().trim_end_matches('%').trim_end();
+    if text.is_empty() {
+        return Ok(None);
+    }
+    match text.parse::<u32>() {
+        Ok(percent) if percent <= 100 => Ok(Some(percent)),
+        _ => Err(format!("\"{text}\" isn't a volume from 0 to 100.")),
+    }
+}
+
+/// Something wrong with a set of profiles, found by `validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    EmptyName,
+    DuplicateName(String),
+    // (profile name, device ID) of a device that isn't connected. It may just be unplugged, so
+    // this doesn't stop the profiles being saved.
+    MissingDevice(String, String),
+    // (hotkey, names of the profiles sharing it).
+    DuplicateHotkey(Hotkey, Vec<String>),
+    // (when, names of the profiles) when more than one is applied at startup or on resume.
+    SeveralApplied(&'static str, Vec<String>),
+}
+
+impl Problem {
+    /// Whether the profiles can't be saved with this problem.
+    pub fn is_blocking(&self) -> bool {
+        !matches!(self, Problem::MissingDevice(..))
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::EmptyName => write!(f, "Every profile needs a name."),
+            Problem::DuplicateName(name) => {
+                write!(f, "There is more than one profile called \"{name}\".")
+            }
+            Problem::MissingDevice(name, id) => {
+                write!(f, "\"{name}\" uses a device that isn't connected ({id}).")
+            }
+            Problem::DuplicateHotkey(hotkey, names) => write!(
+                f,
+                "{hotkey} is the hotkey for more than one profile: {}.",
+                names.join(", ")
+            ),
+            Problem::SeveralApplied(when, names) => write!(
+                f,
+                "Only one profile can be applied {when}: {}.",
+                names.join(", ")
+            ),
+        }
+    }
+}
+
+/// Checks `profiles` against each other and the endpoint IDs in `known_devices`.
+pub fn validate(profiles: &[Profile], known_devices: &[&str]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut names: HashMap<String, usize> = HashMap::new();
+    let mut hotkeys: Vec<(Hotkey, Vec<String>)> = Vec::new();
+    for profile in profiles {
+        let name = profile.name.trim();
+        if name.is_empty() {
+            if !problems.contains(&Problem::EmptyName) {
+                problems.push(Problem::EmptyName);
+            }
+        } else {
+            let count = names.entry(name.to_lowercase()).or_default();
+            *count += 1;
+            if *count == 2 {
+                problems.push(Problem::DuplicateName(name.to_string()));
+            }
+        }
+        for id in profile.output.iter().chain(&profile.input) {
+            if !known_devices.contains(&id.as_str()) {
+                problems.push(Problem::MissingDevice(name.to_string(), id.clone()));
+            }
+        }
+        if let Some(hotkey) = profile.hotkey {
+            match hotkeys.iter_mut().find(|(other, _)| *other == hotkey) {
+                Some((_, names)) => names.push(name.to_string()),
+                None => hotkeys.push((hotkey, vec![name.to_string()])),
+            }
+        }
+    }
+    problems.extend(
+        hotkeys
+            .into_iter()
+            .filter(|(_, names)| names.len() > 1)
+            .map(|(hotkey, names)| Problem::DuplicateHotkey(hotkey, names)),
+    );
+    let applied = |chosen: fn(&Profile) -> bool| -> Vec<String> {
+        profiles
+            .iter()
+            .filter(|profile| chosen(profile))
+            .map(|profile| profile.name.trim().to_string())
+            .collect()
+    };
+    for (when, names) in [
+        ("at startup", applied(|profile| profile.apply_at_startup)),
+        ("on resume", applied(|profile| profile.apply_on_resume)),
+    ] {
+        if names.len() > 1 {
+            problems.push(Problem::SeveralApplied(when, names));
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(name: &str, output: &str, hotkey: &str) -> Profile {
+        Profile {
+            name: name.to_string(),
+            output: Some(output.to_string()),
+            hotkey: Some(hotkey.parse().unwrap()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn icons_are_built_in_or_files() {
+        let dir = Path::new(r"C:\Users\me\AppData\Roaming\sound-switcheroo");
+        assert_eq!(
+            ProfileIcon::parse(" Headset", dir),
+            ProfileIcon::BuiltIn(FormFactor::Headset)
+        );
+        assert_eq!(
+            ProfileIcon::parse("icons/stream.ico", dir),
+            ProfileIcon::File(dir.join("icons/stream.ico"))
+        );
+        if cfg!(windows) {
+            assert_eq!(
+                ProfileIcon::parse(r"D:\meetings.ico", dir),
+                ProfileIcon::File(PathBuf::from(r"D:\meetings.ico"))
+            );
+        }
+    }
+
+    #[test]
+    fn finds_conflicts() {
+        let profiles = [
+            profile("Gaming", "headset", "Ctrl+Alt+1"),
+            profile("gaming ", "speakers", "Ctrl+Alt+2"),
+            profile("Calls", "bluetooth", "Alt+Ctrl+1"),
+            profile("", "speakers", "Ctrl+Alt+3"),
+        ];
+        let problems = validate(&profiles, &["headset", "speakers"]);
+        assert_eq!(
+            problems,
+            [
+                Problem::DuplicateName("gaming".to_string()),
+                Problem::MissingDevice("Calls".to_string(), "bluetooth".to_string()),
+                Problem::EmptyName,
+                Problem::DuplicateHotkey(
+                    "Ctrl+Alt+1".parse().unwrap(),
+                    vec!["Gaming".to_string(), "Calls".to_string()]
+                ),
+            ]
+        );
+        assert!(!problems[1].is_blocking());
+        assert!(problems[3].is_blocking());
+    }
+
+    #[test]
+    fn only_one_profile_is_applied_at_startup() {
+        let mut profiles = [
+            profile("Desk", "speakers", "Ctrl+Alt+1"),
+            profile("Calls", "headset", "Ctrl+Alt+2"),
+        ];
+        profiles[0].apply_at_startup = true;
+        profiles[1].apply_on_resume = true;
+        assert!(validate(&profiles, &["headset", "speakers"]).is_empty());
+        profiles[1].apply_at_startup = true;
+        let problems = validate(&profiles, &["headset", "speakers"]);
+        assert_eq!(
+            problems,
+            [Problem::SeveralApplied(
+                "at startup",
+                vec!["Desk".to_string(), "Calls".to_string()]
+            )]
+        );
+        assert!(problems[0].is_blocking());
+    }
+
+    #[test]
+    fn valid_profiles_have_no_problems() {
+        let profiles = [
+            profile("Gaming", "headset", "Ctrl+Alt+1"),
+            Profile {
+                name: "Quiet".to_string(),
+                ..Default::default()
+            },
+        ];
+        assert!(validate(&profiles, &["headset"]).is_empty());
+    }
+
+    #[test]
+    fn names_setups_after_their_devices() {
+        let profiles = [Profile {
+            name: "headphones + microphone".to_string(),
+            ..Default::default()
+        }];
+        assert_eq!(
+            setup_name(
+                Some("Speakers (Realtek(R) Audio)"),
+                Some("Microphone (USB Audio)"),
+                &profiles
+            ),
+            "Speakers + Microphone"
+        );
+        assert_eq!(
+            setup_name(Some("Headphones"), Some("Microphone"), &profiles),
+            "Headphones + Microphone 2"
+        );
+        assert_eq!(setup_name(None, Some("Microphone"), &[]), "Microphone");
+        assert_eq!(setup_name(None, None, &[]), "Current setup");
+    }
+
+    #[test]
+    fn parses_volumes() {
+        assert_eq!(parse_volume(""), Ok(None));
+        assert_eq!(parse_volume(" 40 "), Ok(Some(40)));
+        assert_eq!(parse_volume("100%"), Ok(Some(100)));
+        assert!(parse_volume("101").is_err());
+        assert!(parse_volume("loud").is_err());
+    }
+}
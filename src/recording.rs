@@ -0,0 +1,170 @@
+use log::{debug, error};
+use std::cell::RefCell;
+use std::fs;
+use std::path::PathBuf;
+use windows::Win32::Media::Audio::{eCapture, EDataFlow, ERole};
+
+use crate::backend::{AudioBackend, AudioDevice, AudioSession};
+use crate::error::SwitcherooError;
+use crate::simulation::Fixture;
+
+/// Passes everything through to another backend while writing what it reports to a fixture
+/// file, which can be replayed with `--simulate` or attached to a bug report.
+pub struct RecordingBackend {
+    inner: Box<dyn AudioBackend>,
+    path: PathBuf,
+    fixture: RefCell<Fixture>,
+}
+
+impl RecordingBackend {
+    pub fn new(inner: Box<dyn AudioBackend>, path: PathBuf) -> Self {
+        Self {
+            inner,
+            path,
+            fixture: RefCell::new(Fixture::default()),
+        }
+    }
+
+    // Sets the part of the fixture `field` picks to `value`, writing the file only if that changed,
+    // as the devices and defaults are asked for far more often than they change.
+    fn record<T: PartialEq>(&self, value: T, field: impl FnOnce(&mut Fixture) -> &mut T) {
+        {
+            let mut fixture = self.fixture.borrow_mut();
+            let recorded = field(&mut fixture);
+            if *recorded == value {
+                return;
+            }
+            *recorded = value;
+        }
+        let result = serde_json::to_string_pretty(&*self.fixture.borrow())
+            .map_err(SwitcherooError::from)
+            .and_then(|json| fs::write(&self.path, json).map_err(SwitcherooError::from));
+        match result {
+            Ok(()) => debug!("Recorded fixture to: {}", self.path.display()),
+            Err(e) => error!("Failed to record fixture: {e}"),
+        }
+    }
+}
+
+impl AudioBackend for RecordingBackend {
+    fn devices(&self, flow: EDataFlow) -> Result<Vec<AudioDevice>, SwitcherooError> {
+        let devices = self.inner.devices(flow)?;
+        self.record(devices.clone(), |fixture| {
+            if flow == eCapture {
+                &mut fixture.capture
+            } else {
+                &mut fixture.render
+            }
+        });
+        Ok(devices)
+    }
+
+    fn default_device(&self, flow: EDataFlow, role: ERole) -> Result<String, SwitcherooError> {
+        let device_id = self.inner.default_device(flow, role)?;
+        self.record(Some(device_id.clone()), |fixture| {
+            if flow == eCapture {
+                &mut fixture.default_capture
+            } else {
+                &mut fixture.default_render
+            }
+        });
+        Ok(device_id)
+    }
+
+    fn set_default_device(&self, device_id: &str, role: ERole) -> Result<(), SwitcherooError> {
+        self.inner.set_default_device(device_id, role)
+    }
+
+    fn volume(&self, device_id: &str) -> Result<f32, SwitcherooError> {
+        self.inner.volume(device_id)
+    }
+
+    fn set_volume(&self, device_id: &str, level: f32) -> Result<(), SwitcherooError> {
+        self.inner.set_volume(device_id, level)
+    }
+
+    fn muted(&self, device_id: &str) -> Result<bool, SwitcherooError> {
+        self.inner.muted(device_id)
+    }
+
+    fn set_muted(&self, device_id: &str, muted: bool) -> Result<(), SwitcherooError> {
+        self.inner.set_muted(device_id, muted)
+    }
+
+    fn balance(&self, device_id: &str) -> Result<Option<i32>, SwitcherooError> {
+        self.inner.balance(device_id)
+    }
+
+    fn set_balance(&self, device_id: &str, balance: i32) -> Result<(), SwitcherooError> {
+        self.inner.set_balance(device_id, balance)
+    }
+
+    fn set_effects_bypassed(&self, device_id: &str, bypassed: bool) -> Result<(), SwitcherooError> {
+        self.inner.set_effects_bypassed(device_id, bypassed)
+    }
+
+    fn sessions(&self, device_id: &str) -> Result<Vec<AudioSession>, SwitcherooError> {
+        self.inner.sessions(device_id)
+    }
+
+    fn check_playback(&self, device_id: &str) -> Result<(), SwitcherooError> {
+        self.inner.check_playback(device_id)
+    }
+
+    fn check_switching(&self) -> Result<(), SwitcherooError> {
+        self.inner.check_switching()
+    }
+
+    fn read_mix_formats(&self, devices: &mut [AudioDevice]) {
+        self.inner.read_mix_formats(devices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::{headset_backend, SimulatedBackend};
+    use windows::Win32::Media::Audio::{eConsole, eRender};
+
+    fn recording_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{name}-{}.json", std::process::id()))
+    }
+
+    #[test]
+    fn recording_replays_the_same_devices() {
+        let path = recording_path("recording");
+        let recorder = RecordingBackend::new(Box::new(headset_backend()), path.clone());
+        let recorded_render = recorder.devices(eRender).unwrap();
+        recorder.devices(eCapture).unwrap();
+        recorder.default_device(eRender, eConsole).unwrap();
+
+        let replayed = SimulatedBackend::load(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        let replayed_render = replayed.devices(eRender).unwrap();
+        assert_eq!(
+            replayed_render.iter().map(|d| &d.id).collect::<Vec<_>>(),
+            recorded_render.iter().map(|d| &d.id).collect::<Vec<_>>()
+        );
+        assert_eq!(replayed.devices(eCapture).unwrap().len(), 2);
+        assert_eq!(
+            replayed.default_device(eRender, eConsole).unwrap(),
+            "{0.0.0.00000000}.{speakers}"
+        );
+    }
+
+    #[test]
+    fn unchanged_devices_are_not_written_again() {
+        let path = recording_path("recording-unchanged");
+        let recorder = RecordingBackend::new(Box::new(headset_backend()), path.clone());
+        recorder.devices(eRender).unwrap();
+        recorder.default_device(eRender, eConsole).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        recorder.devices(eRender).unwrap();
+        recorder.default_device(eRender, eConsole).unwrap();
+        assert!(!path.exists());
+        recorder.devices(eCapture).unwrap();
+        assert!(path.exists());
+        let _ = fs::remove_file(&path);
+    }
+}
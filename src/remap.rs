@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+
+use sound_switcheroo::backend::AudioDevice;
+use sound_switcheroo::config::{Config, DeviceConfig};
+use sound_switcheroo::rules::{RuleAction, Trigger};
+
+// Fewer devices than this changing ID at once is more likely a device plugged into another port,
+// which Windows also gives a new ID, than a driver update.
+const MIN_CHANGED: usize = 2;
+
+/// A device that seems to have come back under a new endpoint ID, as driver updates can do to
+/// every device at once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remap {
+    pub old_id: String,
+    pub new_id: String,
+    pub name: String,
+}
+
+/// Pairs connected devices that have nothing set yet with disconnected ones saved under the same
+/// name, when enough of them have changed at once. Names shared by more than one device on either
+/// side are left alone, as there's no telling which is which.
+pub fn find(config: &Config, devices: &[AudioDevice]) -> Vec<Remap> {
+    let mut new_ids: HashMap<String, Vec<&str>> = HashMap::new();
+    for device in devices {
+        if config.devices.get(&device.id).is_none_or(is_fresh) {
+            new_ids
+                .entry(device.friendly_name.to_string().to_lowercase())
+                .or_default()
+                .push(&device.id);
+        }
+    }
+    let mut old_ids: HashMap<String, Vec<(&str, &str)>> = HashMap::new();
+    for (id, device_config) in &config.devices {
+        if let Some(name) = &device_config.name
+            && !devices.iter().any(|device| &device.id == id)
+        {
+            old_ids
+                .entry(name.to_lowercase())
+                .or_default()
+                .push((id, name));
+        }
+    }
+    let mut remaps: Vec<Remap> = new_ids
+        .iter()
+        .filter_map(
+            |(key, new)| match (new.as_slice(), old_ids.get(key)?.as_slice()) {
+                ([new_id], [(old_id, name)]) => Some(Remap {
+                    old_id: old_id.to_string(),
+                    new_id: new_id.to_string(),
+                    name: name.to_string(),
+                }),
+                _ => None,
+            },
+        )
+        .collect();
+    if remaps.len() < MIN_CHANGED {
+        return Vec::new();
+    }
+    remaps.sort_by(|a, b| a.name.cmp(&b.name));
+    remaps
+}
+
+// Whether nothing has been set for a device beyond what's saved on first seeing it.
+fn is_fresh(device_config: &DeviceConfig) -> bool {
+    device_config.order.is_none()
+        && device_config.last_used.is_none()
+        && device_config.remote_bridge.is_none()
+        && device_config.form_factor.is_none()
+        && device_config.volume.is_none()
+        && device_config.balance.is_none()
+}
+
+/// Moves everything saved under the old IDs to the new ones: the devices' own settings and their
+/// uses in profiles, rules, hotkeys and the priority list.
+pub fn apply(config: &mut Config, remaps: &[Remap]) {
+    for remap in remaps {
+        if let Some(device_config) = config.devices.remove(&remap.old_id) {
+            config.devices.insert(remap.new_id.clone(), device_config);
+        }
+    }
+    let renamed = |id: &mut String| {
+        if let Some(remap) = remaps.iter().find(|remap| &remap.old_id == id) {
+            *id = remap.new_id.clone();
+        }
+    };
+    for profile in &mut config.profiles {
+        profile.output.iter_mut().for_each(renamed);
+        profile.communications_output.iter_mut().for_each(renamed);
+    }
+    for rule in &mut config.rules {
+        if let Trigger::DeviceConnected { device } = &mut rule.trigger {
+            renamed(device);
+        }
+        if let RuleAction::SwitchDevice { device } = &mut rule.action {
+            renamed(device);
+        }
+    }
+    config.hotkeys.devices.values_mut().for_each(renamed);
+    config.priority.iter_mut().for_each(renamed);
+}
+
+/// Stops the old IDs being offered again, when the user says the devices aren't the same.
+pub fn forget(config: &mut Config, remaps: &[Remap]) {
+    for remap in remaps {
+        if let Some(device_config) = config.devices.get_mut(&remap.old_id) {
+            device_config.name = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sound_switcheroo::profiles::Profile;
+    use sound_switcheroo::rules::{Rule, RulePart};
+
+    fn device(id: &str, name: &str) -> AudioDevice {
+        AudioDevice::new(id, name, Default::default())
+    }
+
+    fn saved(name: &str, order: u32) -> DeviceConfig {
+        DeviceConfig {
+            order: Some(order),
+            name: Some(name.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn finds_devices_back_under_new_ids() {
+        let mut config = Config::default();
+        config
+            .devices
+            .insert("{old-speakers}".into(), saved("Speakers", 0));
+        config
+            .devices
+            .insert("{old-headset}".into(), saved("Headset", 1));
+        config.devices.insert("{old-tv}".into(), saved("TV", 2));
+        // Just seen, so nothing's been set for it yet.
+        config.devices.insert(
+            "{new-headset}".into(),
+            DeviceConfig {
+                ask: true,
+                name: Some("Headset".to_string()),
+                ..Default::default()
+            },
+        );
+        let devices = [
+            device("{new-speakers}", "Speakers"),
+            device("{new-headset}", "Headset"),
+            device("{monitor}", "Monitor"),
+        ];
+        assert_eq!(
+            find(&config, &devices),
+            [
+                Remap {
+                    old_id: "{old-headset}".into(),
+                    new_id: "{new-headset}".into(),
+                    name: "Headset".into(),
+                },
+                Remap {
+                    old_id: "{old-speakers}".into(),
+                    new_id: "{new-speakers}".into(),
+                    name: "Speakers".into(),
+                },
+            ]
+        );
+        // One device changing is more likely another port.
+        assert!(find(&config, &devices[..1]).is_empty());
+        let remaps = find(&config, &devices);
+        forget(&mut config, &remaps);
+        assert!(find(&config, &devices).is_empty());
+    }
+
+    #[test]
+    fn moves_settings_to_new_ids() {
+        let mut config = Config::default();
+        config.devices.insert("{old}".into(), saved("Headset", 3));
+        config.profiles.push(Profile {
+            name: "Calls".to_string(),
+            output: Some("{old}".to_string()),
+            ..Default::default()
+        });
+        config.rules.push(Rule {
+            trigger: Trigger::DeviceConnected {
+                device: "{old}".to_string(),
+            },
+            action: RuleAction::SwitchDevice {
+                device: "{old}".to_string(),
+            },
+            ..Default::default()
+        });
+        config
+            .hotkeys
+            .devices
+            .insert("Ctrl+Alt+1".parse().unwrap(), "{old}".to_string());
+        config.priority = vec!["{other}".to_string(), "{old}".to_string()];
+        apply(
+            &mut config,
+            &[Remap {
+                old_id: "{old}".into(),
+                new_id: "{new}".into(),
+                name: "Headset".into(),
+            }],
+        );
+        assert!(!config.devices.contains_key("{old}"));
+        assert_eq!(config.devices["{new}"].order, Some(3));
+        assert_eq!(config.profiles[0].output.as_deref(), Some("{new}"));
+        assert_eq!(config.rules[0].trigger.value(), "{new}");
+        assert_eq!(config.rules[0].action.value(), "{new}");
+        assert_eq!(config.hotkeys.device_hotkeys()[0].1, "{new}");
+        assert_eq!(config.priority, ["{other}", "{new}"]);
+    }
+}
@@ -0,0 +1,354 @@
+use serde::{Deserialize, Serialize};
+
+/// What `select_next` needs to know about a device. Kept free of Windows types so the rotation
+/// logic can be tested anywhere.
+pub trait RotationEntry {
+    fn id(&self) -> &str;
+    fn selectable(&self) -> bool;
+    // Identifies the physical device the endpoint belongs to, if known.
+    fn container(&self) -> Option<u128>;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    #[default]
+    Forward,
+    Backward,
+}
+
+/// How `select_next` walks the list of devices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RotationPolicy {
+    pub direction: Direction,
+    // Whether to go back to the start of the list after the last device.
+    pub wrap_around: bool,
+    // Whether to pass over other endpoints of the same physical device as the current one.
+    pub skip_siblings: bool,
+}
+
+impl RotationPolicy {
+    /// The same policy going the other way.
+    pub fn reversed(self) -> Self {
+        let direction = match self.direction {
+            Direction::Forward => Direction::Backward,
+            Direction::Backward => Direction::Forward,
+        };
+        Self { direction, ..self }
+    }
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        Self {
+            direction: Direction::Forward,
+            wrap_around: true,
+            skip_siblings: false,
+        }
+    }
+}
+
+/// Picks the device to switch to after `current`, or `None` if there is nowhere to go.
+///
+/// If `current` isn't in `devices` the first selectable device in the policy's direction is
+/// picked.
+pub fn select_next<'a, D: RotationEntry>(
+    current: &str,
+    devices: &'a [D],
+    policy: &RotationPolicy,
+) -> Option<&'a D> {
+    let current_index = devices.iter().position(|d| d.id() == current);
+    let current_container = current_index.and_then(|i| devices[i].container());
+
+    // Device indices in the order they should be considered.
+    let len = devices.len();
+    let order: Vec<usize> = match (current_index, policy.direction) {
+        (None, Direction::Forward) => (0..len).collect(),
+        (None, Direction::Backward) => (0..len).rev().collect(),
+        (Some(i), Direction::Forward) => {
+            let after = i + 1..len;
+            if policy.wrap_around {
+                after.chain(0..i).collect()
+            } else {
+                after.collect()
+            }
+        }
+        (Some(i), Direction::Backward) => {
+            let before = (0..i).rev();
+            if policy.wrap_around {
+                before.chain((i + 1..len).rev()).collect()
+            } else {
+                before.collect()
+            }
+        }
+    };
+
+    order.into_iter().map(|i| &devices[i]).find(|d| {
+        d.selectable()
+            && d.id() != current
+            && !(policy.skip_siblings
+                && current_container.is_some()
+                && d.container() == current_container)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestDevice {
+        id: String,
+        selectable: bool,
+        container: Option<u128>,
+    }
+
+    impl RotationEntry for TestDevice {
+        fn id(&self) -> &str {
+            &self.id
+        }
+
+        fn selectable(&self) -> bool {
+            self.selectable
+        }
+
+        fn container(&self) -> Option<u128> {
+            self.container
+        }
+    }
+
+    fn device(id: &str, selectable: bool, container: Option<u128>) -> TestDevice {
+        TestDevice {
+            id: id.to_string(),
+            selectable,
+            container,
+        }
+    }
+
+    fn next_id(current: &str, devices: &[TestDevice], policy: RotationPolicy) -> Option<String> {
+        select_next(current, devices, &policy).map(|d| d.id.clone())
+    }
+
+    #[test]
+    fn empty_list_selects_nothing() {
+        assert_eq!(next_id("a", &[], RotationPolicy::default()), None);
+    }
+
+    #[test]
+    fn none_selectable_selects_nothing() {
+        let devices = [device("a", false, None), device("b", false, None)];
+        assert_eq!(next_id("a", &devices, RotationPolicy::default()), None);
+        assert_eq!(
+            next_id("missing", &devices, RotationPolicy::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn only_current_selectable_stays_put() {
+        let devices = [device("a", true, None), device("b", false, None)];
+        assert_eq!(next_id("a", &devices, RotationPolicy::default()), None);
+    }
+
+    #[test]
+    fn unselectable_current_still_moves_on() {
+        let devices = [
+            device("a", true, None),
+            device("b", false, None),
+            device("c", true, None),
+        ];
+        assert_eq!(
+            next_id("b", &devices, RotationPolicy::default()),
+            Some("c".into())
+        );
+    }
+
+    #[test]
+    fn current_removed_starts_from_the_beginning() {
+        let devices = [
+            device("a", false, None),
+            device("b", true, None),
+            device("c", true, None),
+        ];
+        assert_eq!(
+            next_id("removed", &devices, RotationPolicy::default()),
+            Some("b".into())
+        );
+    }
+
+    #[test]
+    fn current_removed_backward_starts_from_the_end() {
+        let devices = [
+            device("a", true, None),
+            device("b", true, None),
+            device("c", false, None),
+        ];
+        let policy = RotationPolicy {
+            direction: Direction::Backward,
+            wrap_around: false,
+            ..Default::default()
+        };
+        assert_eq!(next_id("removed", &devices, policy), Some("b".into()));
+    }
+
+    #[test]
+    fn wraparound_skips_unselectable_devices() {
+        let devices = [
+            device("a", false, None),
+            device("b", true, None),
+            device("c", true, None),
+            device("d", false, None),
+        ];
+        assert_eq!(
+            next_id("c", &devices, RotationPolicy::default()),
+            Some("b".into())
+        );
+    }
+
+    #[test]
+    fn ties_resolve_to_the_nearest_candidate() {
+        // Several devices are eligible; the closest in the direction of travel wins.
+        let devices = [
+            device("a", true, None),
+            device("b", true, None),
+            device("c", true, None),
+            device("d", true, None),
+        ];
+        assert_eq!(
+            next_id("b", &devices, RotationPolicy::default()),
+            Some("c".into())
+        );
+        let backward = RotationPolicy {
+            direction: Direction::Backward,
+            ..Default::default()
+        };
+        assert_eq!(next_id("b", &devices, backward), Some("a".into()));
+    }
+
+    #[test]
+    fn duplicate_ids_use_the_first_occurrence() {
+        let devices = [
+            device("a", true, None),
+            device("b", true, None),
+            device("a", true, None),
+            device("c", true, None),
+        ];
+        assert_eq!(
+            next_id("a", &devices, RotationPolicy::default()),
+            Some("b".into())
+        );
+    }
+
+    #[test]
+    fn skip_siblings_ignores_devices_without_container() {
+        let devices = [
+            device("a", true, None),
+            device("b", true, None),
+            device("c", true, Some(1)),
+        ];
+        let policy = RotationPolicy {
+            skip_siblings: true,
+            ..Default::default()
+        };
+        assert_eq!(next_id("a", &devices, policy), Some("b".into()));
+    }
+
+    #[test]
+    fn skip_siblings_with_only_siblings_stays_put() {
+        let devices = [device("a", true, Some(1)), device("b", true, Some(1))];
+        let policy = RotationPolicy {
+            skip_siblings: true,
+            ..Default::default()
+        };
+        assert_eq!(next_id("a", &devices, policy), None);
+    }
+
+    #[test]
+    fn forward_picks_next_selectable() {
+        let devices = [
+            device("a", true, None),
+            device("b", false, None),
+            device("c", true, None),
+        ];
+        assert_eq!(
+            next_id("a", &devices, RotationPolicy::default()),
+            Some("c".into())
+        );
+    }
+
+    #[test]
+    fn forward_wraps_around() {
+        let devices = [device("a", true, None), device("b", true, None)];
+        assert_eq!(
+            next_id("b", &devices, RotationPolicy::default()),
+            Some("a".into())
+        );
+    }
+
+    #[test]
+    fn forward_without_wrap_stops_at_end() {
+        let devices = [device("a", true, None), device("b", true, None)];
+        let policy = RotationPolicy {
+            wrap_around: false,
+            ..Default::default()
+        };
+        assert_eq!(next_id("b", &devices, policy), None);
+    }
+
+    #[test]
+    fn backward_picks_previous_and_wraps() {
+        let devices = [
+            device("a", true, None),
+            device("b", true, None),
+            device("c", true, None),
+        ];
+        let policy = RotationPolicy {
+            direction: Direction::Backward,
+            ..Default::default()
+        };
+        assert_eq!(next_id("b", &devices, policy), Some("a".into()));
+        assert_eq!(next_id("a", &devices, policy), Some("c".into()));
+    }
+
+    #[test]
+    fn backward_without_wrap_stops_at_start() {
+        let devices = [device("a", true, None), device("b", true, None)];
+        let policy = RotationPolicy {
+            direction: Direction::Backward,
+            wrap_around: false,
+            ..Default::default()
+        };
+        assert_eq!(next_id("a", &devices, policy), None);
+    }
+
+    #[test]
+    fn skips_siblings_of_current() {
+        let devices = [
+            device("a", true, Some(1)),
+            device("b", true, Some(1)),
+            device("c", true, Some(2)),
+        ];
+        let policy = RotationPolicy {
+            skip_siblings: true,
+            ..Default::default()
+        };
+        assert_eq!(next_id("a", &devices, policy), Some("c".into()));
+        assert_eq!(
+            next_id("a", &devices, RotationPolicy::default()),
+            Some("b".into())
+        );
+    }
+
+    #[test]
+    fn reversed_goes_back_the_way_it_came() {
+        let devices = [
+            device("a", true, None),
+            device("b", true, None),
+            device("c", true, None),
+        ];
+        let policy = RotationPolicy::default();
+        assert_eq!(next_id("b", &devices, policy), Some("c".into()));
+        assert_eq!(next_id("b", &devices, policy.reversed()), Some("a".into()));
+        assert_eq!(policy.reversed().reversed(), policy);
+    }
+}
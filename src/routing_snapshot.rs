@@ -0,0 +1,272 @@
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use windows::Win32::Media::Audio::{
+    eCapture, eCommunications, eConsole, eMultimedia, eRender, EDataFlow, ERole,
+};
+
+use sound_switcheroo::backend::AudioBackend;
+use sound_switcheroo::error::SwitcherooError;
+use sound_switcheroo::rules;
+
+/// Where the routing snapshots for the config at `config_path` are kept, one file per snapshot.
+pub fn snapshots_dir(config_path: &Path) -> PathBuf {
+    config_path.with_file_name("snapshots")
+}
+
+/// The default endpoint of one data flow for each role, if there is one.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoleDefaults {
+    pub console: Option<String>,
+    pub multimedia: Option<String>,
+    pub communications: Option<String>,
+}
+
+impl RoleDefaults {
+    fn read(backend: &dyn AudioBackend, flow: EDataFlow) -> Self {
+        let default = |role| backend.default_device(flow, role).ok();
+        Self {
+            console: default(eConsole),
+            multimedia: default(eMultimedia),
+            communications: default(eCommunications),
+        }
+    }
+
+    fn roles(&self) -> [(ERole, Option<&String>); 3] {
+        [
+            (eConsole, self.console.as_ref()),
+            (eMultimedia, self.multimedia.as_ref()),
+            (eCommunications, self.communications.as_ref()),
+        ]
+    }
+}
+
+/// The volume of one endpoint when the snapshot was taken.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EndpointLevel {
+    pub id: String,
+    // Only for reading the file, and for saying which endpoints are missing on restore.
+    pub name: String,
+    pub volume: u32,
+    #[serde(default)]
+    pub muted: bool,
+}
+
+/// How audio is routed at one moment: the default outputs and inputs for every role, and every
+/// endpoint's volume and mute. Taken before running apps known to rearrange audio, and restored
+/// after.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoutingSnapshot {
+    // Local date and time, as `YYYY-MM-DD HH:MM`.
+    pub taken: String,
+    pub outputs: RoleDefaults,
+    pub inputs: RoleDefaults,
+    pub levels: Vec<EndpointLevel>,
+}
+
+impl RoutingSnapshot {
+    /// Reads the current routing. Endpoints whose volume can't be read are left out.
+    pub fn take(backend: &dyn AudioBackend) -> Result<Self, SwitcherooError> {
+        let (date, time) = rules::local_time();
+        let mut levels = Vec::new();
+        for flow in [eRender, eCapture] {
+            for device in backend.devices(flow)? {
+                let level = backend
+                    .volume(&device.id)
+                    .and_then(|volume| Ok((volume, backend.muted(&device.id)?)));
+                match level {
+                    Ok((volume, muted)) => levels.push(EndpointLevel {
+                        id: device.id,
+                        name: device.friendly_name.to_string(),
+                        volume: (volume * 100.0).round() as u32,
+                        muted,
+                    }),
+                    Err(e) => debug!("Leaving {} out of the snapshot: {e}", device.friendly_name),
+                }
+            }
+        }
+        Ok(Self {
+            taken: format!("{date} {time}"),
+            outputs: RoleDefaults::read(backend, eRender),
+            inputs: RoleDefaults::read(backend, eCapture),
+            levels,
+        })
+    }
+
+    /// Puts the routing back as it was, skipping endpoints that are no longer connected. Returns
+    /// the names of those skipped.
+    pub fn restore(&self, backend: &dyn AudioBackend) -> Result<Vec<String>, SwitcherooError> {
+        let mut connected = Vec::new();
+        for flow in [eRender, eCapture] {
+            connected.extend(backend.devices(flow)?.into_iter().map(|device| device.id));
+        }
+        let mut missing = Vec::new();
+        for level in &self.levels {
+            if !connected.contains(&level.id) {
+                missing.push(level.name.clone());
+                continue;
+            }
+            // One endpoint refusing its level shouldn't hold up the rest of the routing.
+            if let Err(e) = backend.set_volume(&level.id, level.volume.min(100) as f32 / 100.0) {
+                warn!("Failed to restore the volume of {}: {e}", level.name);
+            }
+            if let Err(e) = backend.set_muted(&level.id, level.muted) {
+                warn!("Failed to restore the mute of {}: {e}", level.name);
+            }
+        }
+        for (role, device_id) in self.outputs.roles().into_iter().chain(self.inputs.roles()) {
+            let Some(device_id) = device_id else {
+                continue;
+            };
+            if connected.contains(device_id) {
+                debug!("Restoring {device_id} as the default for {role:?}");
+                backend.set_default_device(device_id, role)?;
+            } else if !self.levels.iter().any(|level| &level.id == device_id) {
+                missing.push(device_id.clone());
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Saves the snapshot as `name` in `dir`, replacing any snapshot of that name.
+    pub fn save(&self, dir: &Path, name: &str) -> Result<(), SwitcherooError> {
+        let path = path(dir, name)?;
+        info!("Saving routing snapshot to {}", path.display());
+        fs::create_dir_all(dir)?;
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(dir: &Path, name: &str) -> Result<Self, SwitcherooError> {
+        let path = path(dir, name)?;
+        match fs::read_to_string(&path) {
+            Ok(text) => Ok(serde_json::from_str(&text)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(SwitcherooError::Config(format!(
+                "There's no snapshot called \"{name}\""
+            ))),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+// Names Windows keeps for devices, whatever the extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// The file a snapshot called `name` is kept in, as long as the name makes a valid file name.
+fn path(dir: &Path, name: &str) -> Result<PathBuf, SwitcherooError> {
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if name.trim().is_empty()
+        || name.starts_with('.')
+        || RESERVED_NAMES
+            .iter()
+            .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+        || name
+            .chars()
+            .any(|c| c.is_control() || r#"<>:"/\|?*"#.contains(c))
+    {
+        return Err(SwitcherooError::Config(format!(
+            "\"{name}\" can't be used as a snapshot name"
+        )));
+    }
+    Ok(dir.join(format!("{name}.json")))
+}
+
+/// The name for a snapshot taken now from the menu, from the local date and time.
+pub fn name_for_now() -> String {
+    let (date, time) = rules::local_time();
+    format!("{date} {}", time.replace(':', "."))
+}
+
+/// The names of the snapshots saved in `dir`, newest first.
+pub fn names(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut snapshots: Vec<_> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.extension()? != "json" {
+                return None;
+            }
+            let modified = entry.metadata().and_then(|m| m.modified()).ok()?;
+            Some((modified, path.file_stem()?.to_string_lossy().into_owned()))
+        })
+        .collect();
+    snapshots.sort_by(|a, b| b.cmp(a));
+    snapshots.into_iter().map(|(_, name)| name).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sound_switcheroo::simulation::headset_backend;
+
+    const SPEAKERS: &str = "{0.0.0.00000000}.{speakers}";
+    const HEADSET: &str = "{0.0.0.00000000}.{headset}";
+
+    #[test]
+    fn restores_defaults_and_levels() {
+        let backend = headset_backend();
+        backend.set_volume(SPEAKERS, 0.3).unwrap();
+        backend.set_muted(HEADSET, true).unwrap();
+        let snapshot = RoutingSnapshot::take(&backend).unwrap();
+        assert_eq!(snapshot.outputs.console.as_deref(), Some(SPEAKERS));
+        assert_eq!(snapshot.levels.len(), 4);
+
+        backend.set_default_device(HEADSET, eConsole).unwrap();
+        backend.set_volume(SPEAKERS, 0.9).unwrap();
+        backend.set_muted(HEADSET, false).unwrap();
+        assert!(snapshot.restore(&backend).unwrap().is_empty());
+        assert_eq!(backend.default_device(eRender, eConsole).unwrap(), SPEAKERS);
+        assert_eq!(backend.volume(SPEAKERS).unwrap(), 0.3);
+        assert!(backend.muted(HEADSET).unwrap());
+    }
+
+    #[test]
+    fn skips_endpoints_that_are_gone() {
+        let snapshot = RoutingSnapshot {
+            outputs: RoleDefaults {
+                console: Some("{gone}".to_string()),
+                ..Default::default()
+            },
+            levels: vec![EndpointLevel {
+                id: "{gone}".to_string(),
+                name: "Old Speakers".to_string(),
+                volume: 50,
+                muted: false,
+            }],
+            ..Default::default()
+        };
+        let backend = headset_backend();
+        assert_eq!(snapshot.restore(&backend).unwrap(), ["Old Speakers"]);
+        assert_eq!(backend.default_device(eRender, eConsole).unwrap(), SPEAKERS);
+    }
+
+    #[test]
+    fn saves_under_names_that_make_file_names() {
+        let dir = std::env::temp_dir().join("sound-switcheroo-routing-snapshots-test");
+        let _ = fs::remove_dir_all(&dir);
+        let snapshot = RoutingSnapshot::take(&headset_backend()).unwrap();
+        snapshot.save(&dir, "before game").unwrap();
+        assert_eq!(
+            RoutingSnapshot::load(&dir, "before game").unwrap(),
+            snapshot
+        );
+        assert_eq!(names(&dir), ["before game"]);
+        assert!(RoutingSnapshot::load(&dir, "missing").is_err());
+        assert!(snapshot.save(&dir, "../escape").is_err());
+        assert!(snapshot.save(&dir, " ").is_err());
+        assert!(snapshot.save(&dir, "con").is_err());
+        assert!(snapshot.save(&dir, "COM1.backup").is_err());
+        assert!(snapshot.save(&dir, "console").is_ok());
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,448 @@
+use log::debug;
+use std::sync::Once;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::UI::WindowsAndMessaging::{
+    DefWindowProcW, GetParent, GetWindow, GetWindowLongPtrW, SendMessageW, SetWindowLongPtrW,
+    BN_CLICKED, CBN_SELCHANGE, GWLP_USERDATA, GW_OWNER, LBN_SELCHANGE, WM_APP, WM_COMMAND,
+    WM_NCDESTROY, WM_SIZE,
+};
+use windows_strings::w;
+
+use crate::controls::{self, Form};
+use sound_switcheroo::backend::AudioDevice;
+use sound_switcheroo::error::SwitcherooError;
+use sound_switcheroo::profiles::Profile;
+use sound_switcheroo::rules::{self, Parameter, Rule, RuleAction, RulePart, Trigger};
+
+/// Sent to the settings window's owner when edited rules are saved. LPARAM points to the new
+/// `Vec<Rule>`, which is only valid during the message.
+pub const WM_SETTINGS_RULES_CHANGED: u32 = WM_APP + 0x49;
+/// Sent to the settings window's owner to act as if a rule's trigger had happened. LPARAM points
+/// to a `RuleTest`, whose `outcome` the owner fills in.
+pub const WM_SETTINGS_TEST_RULE: u32 = WM_APP + 0x4a;
+
+/// A rule to try out from the editor, without waiting for its trigger.
+#[derive(Debug)]
+pub struct RuleTest {
+    pub rule: Rule,
+    // Only describe what the action would do.
+    pub dry_run: bool,
+    // What happened, to show in the editor.
+    pub outcome: String,
+}
+
+// Control IDs, received in WM_COMMAND.
+const ID_LIST: u16 = 100;
+const ID_NEW: u16 = 101;
+const ID_DELETE: u16 = 102;
+const ID_SAVE: u16 = 103;
+const ID_TRIGGER_KIND: u16 = 104;
+const ID_ACTION_KIND: u16 = 105;
+const ID_TEST: u16 = 106;
+
+static REGISTER_CLASS: Once = Once::new();
+
+// The dropdowns for one side of a rule: which kind of trigger or action, and its value.
+struct Part {
+    kind: HWND,
+    value_label: HWND,
+    value: HWND,
+    // (value, label) of each entry in the value dropdown, which can also be typed into.
+    choices: Vec<(String, String)>,
+}
+
+impl Part {
+    fn new(page: HWND, kind_id: u16) -> Result<Self, SwitcherooError> {
+        Ok(Self {
+            kind: controls::dropdown(page, kind_id, false)?,
+            value_label: controls::label(page, "")?,
+            value: controls::dropdown(page, 0, true)?,
+            choices: Vec::new(),
+        })
+    }
+
+    // Shows `value` as the value of `kind`, offering what suits it.
+    fn show<P: RulePart>(&mut self, kind: usize, value: &str, known: &Known) {
+        let (_, parameter, label) = P::KINDS[kind];
+        controls::set_text(self.value_label, label);
+        self.choices = match parameter {
+            Parameter::Device => {
+                let mut choices = known.outputs.clone();
+                if !value.is_empty() && !choices.iter().any(|(id, _)| id == value) {
+                    choices.push((value.to_string(), format!("(not connected) {value}")));
+                }
+                choices
+            }
+            Parameter::Profile => known
+                .profiles
+                .iter()
+                .map(|name| (name.clone(), name.clone()))
+                .collect(),
+            Parameter::Docked => vec![
+                ("docked".to_string(), "Docked".to_string()),
+                ("undocked".to_string(), "Undocked".to_string()),
+            ],
+            Parameter::Text => Vec::new(),
+        };
+        let selected = self.choices.iter().position(|(choice, _)| choice == value);
+        controls::fill_combo(
+            self.value,
+            self.choices.iter().map(|(_, label)| label.as_str()),
+            selected,
+        );
+        if selected.is_none() {
+            controls::set_text(self.value, value);
+        }
+    }
+
+    fn load<P: RulePart>(&mut self, part: &P, known: &Known) {
+        controls::fill_combo(
+            self.kind,
+            P::KINDS.iter().map(|(name, _, _)| *name),
+            Some(part.kind()),
+        );
+        self.show::<P>(part.kind(), &part.value(), known);
+    }
+
+    fn read<P: RulePart>(&self) -> P {
+        let text = controls::text(self.value);
+        // A chosen entry stands for its value, found by its place as two devices can share a
+        // name. Anything typed over it is taken as it is.
+        let value = controls::combo_selection(self.value)
+            .and_then(|index| self.choices.get(index))
+            .filter(|(_, label)| *label == text)
+            .map_or(text.trim().to_string(), |(value, _)| value.clone());
+        P::from_parts(controls::combo_selection(self.kind).unwrap_or(0), value)
+    }
+}
+
+// What the value dropdowns offer.
+struct Known {
+    // (ID, name) of the output devices.
+    outputs: Vec<(String, String)>,
+    profiles: Vec<String>,
+}
+
+// Kept in the page's user data.
+struct Editor {
+    form: Form,
+    name: HWND,
+    enabled: HWND,
+    revert: HWND,
+    dry_run: HWND,
+    trigger: Part,
+    action: Part,
+    // The rules being edited, saved only when Save is pressed.
+    rules: Vec<Rule>,
+    selected: Option<usize>,
+    known: Known,
+}
+
+impl Editor {
+    fn fill_list(&self) {
+        let labels: Vec<String> = self
+            .rules
+            .iter()
+            .map(|rule| {
+                let name = if rule.name.trim().is_empty() {
+                    "(unnamed)"
+                } else {
+                    rule.name.as_str()
+                };
+                if rule.enabled {
+                    name.to_string()
+                } else {
+                    format!("{name} (off)")
+                }
+            })
+            .collect();
+        controls::fill_list(
+            self.form.list,
+            labels.iter().map(String::as_str),
+            self.selected,
+        );
+    }
+
+    // Shows the selected rule in the fields, which are emptied if there isn't one.
+    fn load_fields(&mut self) {
+        let rule = self
+            .selected
+            .and_then(|selected| self.rules.get(selected))
+            .cloned()
+            .unwrap_or_default();
+        controls::set_text(self.name, &rule.name);
+        controls::set_checked(self.enabled, rule.enabled);
+        self.trigger.load(&rule.trigger, &self.known);
+        self.action.load(&rule.action, &self.known);
+        controls::set_checked(self.revert, rule.revert);
+    }
+
+    // Copies the fields into the selected rule.
+    fn store_fields(&mut self) {
+        let Some(rule) = self
+            .selected
+            .and_then(|selected| self.rules.get_mut(selected))
+        else {
+            return;
+        };
+        rule.name = controls::text(self.name).trim().to_string();
+        rule.enabled = controls::checked(self.enabled);
+        rule.trigger = self.trigger.read();
+        rule.action = self.action.read();
+        rule.revert = controls::checked(self.revert);
+    }
+
+    fn set_status(&self, text: &str) {
+        controls::set_text(self.form.status, text);
+    }
+
+    // Moves to another rule, keeping the edits to this one.
+    fn select(&mut self, selected: Option<usize>) {
+        self.store_fields();
+        self.selected = selected;
+        self.fill_list();
+        self.load_fields();
+        self.set_status("");
+    }
+
+    fn add(&mut self) {
+        self.store_fields();
+        self.rules.push(Rule {
+            name: format!("Rule {}", self.rules.len() + 1),
+            ..Default::default()
+        });
+        self.selected = Some(self.rules.len() - 1);
+        self.fill_list();
+        self.load_fields();
+        self.set_status("");
+    }
+
+    fn delete(&mut self) {
+        let Some(selected) = self.selected else {
+            return;
+        };
+        self.rules.remove(selected);
+        self.selected = if self.rules.is_empty() {
+            None
+        } else {
+            Some(selected.min(self.rules.len() - 1))
+        };
+        self.fill_list();
+        self.load_fields();
+        self.set_status("Deleted. Save to keep the change.");
+    }
+
+    // Validates the rules and, if they're all complete, sends them to the settings window's owner.
+    fn save(&mut self, page: HWND) {
+        self.store_fields();
+        self.fill_list();
+        let profile_names: Vec<&str> = self.known.profiles.iter().map(String::as_str).collect();
+        let problems = rules::validate(&self.rules, &profile_names);
+        if !problems.is_empty() {
+            self.set_status(&format!("Not saved.\n{}", problems.join("\n")));
+            return;
+        }
+        debug!("Saving {} rules", self.rules.len());
+        send_to_owner(
+            page,
+            WM_SETTINGS_RULES_CHANGED,
+            &self.rules as *const _ as isize,
+        );
+        self.set_status("Saved.");
+    }
+
+    // Has the settings window's owner carry out the selected rule as it stands in the editor,
+    // saved or not, and shows what happened.
+    fn test(&mut self, page: HWND) {
+        self.store_fields();
+        self.fill_list();
+        let Some(rule) = self.selected.and_then(|selected| self.rules.get(selected)) else {
+            return;
+        };
+        let profile_names: Vec<&str> = self.known.profiles.iter().map(String::as_str).collect();
+        let problems = rules::validate(std::slice::from_ref(rule), &profile_names);
+        if !problems.is_empty() {
+            self.set_status(&format!("Can't test.\n{}", problems.join("\n")));
+            return;
+        }
+        let mut test = RuleTest {
+            rule: rule.clone(),
+            dry_run: controls::checked(self.dry_run),
+            outcome: "Nothing happened.".to_string(),
+        };
+        send_to_owner(page, WM_SETTINGS_TEST_RULE, &mut test as *mut _ as isize);
+        self.set_status(&test.outcome);
+    }
+}
+
+fn send_to_owner(page: HWND, msg: u32, lparam: isize) {
+    unsafe {
+        if let Ok(settings) = GetParent(page)
+            && let Ok(owner) = GetWindow(settings, GW_OWNER)
+        {
+            SendMessageW(owner, msg, None, Some(LPARAM(lparam)));
+        }
+    }
+}
+
+fn profile_names(profiles: &[Profile]) -> Vec<String> {
+    profiles
+        .iter()
+        .map(|profile| profile.name.clone())
+        .collect()
+}
+
+/// Creates the rules page as a hidden child of the settings window, editing `rules` with the
+/// known `outputs` and `profiles` to choose from.
+pub fn create(
+    parent: HWND,
+    rules: &[Rule],
+    outputs: &[AudioDevice],
+    profiles: &[Profile],
+) -> Result<HWND, SwitcherooError> {
+    let page = controls::create_page(
+        parent,
+        w!("SoundSwitcherooRules"),
+        &REGISTER_CLASS,
+        Some(editor_callback),
+    )?;
+    let list = controls::list(page, ID_LIST)?;
+    let name = controls::edit(page)?;
+    let enabled = controls::checkbox(page, "&Enabled")?;
+    let trigger = Part::new(page, ID_TRIGGER_KIND)?;
+    let action = Part::new(page, ID_ACTION_KIND)?;
+    let revert = controls::checkbox(page, "Switch &back when the device is disconnected")?;
+    let dry_run = controls::checkbox(page, "D&ry run")?;
+    // Only pretend, unless asked otherwise.
+    controls::set_checked(dry_run, true);
+    let mut editor = Editor {
+        form: Form {
+            list,
+            rows: vec![
+                (controls::label(page, "Name")?, name, false),
+                (controls::label(page, "")?, enabled, false),
+                (controls::label(page, "When")?, trigger.kind, true),
+                (trigger.value_label, trigger.value, true),
+                (controls::label(page, "Then")?, action.kind, true),
+                (action.value_label, action.value, true),
+                (controls::label(page, "")?, revert, false),
+            ],
+            status: controls::label(page, "")?,
+            buttons: vec![
+                controls::button(page, "&New", ID_NEW)?,
+                controls::button(page, "&Delete", ID_DELETE)?,
+                controls::button(page, "&Save", ID_SAVE)?,
+                controls::button(page, "&Test", ID_TEST)?,
+                dry_run,
+            ],
+        },
+        name,
+        enabled,
+        revert,
+        dry_run,
+        trigger,
+        action,
+        rules: rules.to_vec(),
+        selected: (!rules.is_empty()).then_some(0),
+        known: Known {
+            outputs: controls::device_names(outputs),
+            profiles: profile_names(profiles),
+        },
+    };
+    editor.fill_list();
+    editor.load_fields();
+    unsafe {
+        SetWindowLongPtrW(
+            page,
+            GWLP_USERDATA,
+            Box::into_raw(Box::new(editor)) as isize,
+        );
+    }
+    Ok(page)
+}
+
+/// Points the rules being edited at profiles renamed from the first name of a pair to the second,
+/// and turns off those applying one no longer among `profiles`, as was done to the saved rules.
+pub fn follow_profiles(page: HWND, renamed: &[(String, String)], profiles: &[Profile]) {
+    let Some(editor) =
+        (unsafe { (GetWindowLongPtrW(page, GWLP_USERDATA) as *mut Editor).as_mut() })
+    else {
+        return;
+    };
+    editor.store_fields();
+    let names: Vec<&str> = profiles
+        .iter()
+        .map(|profile| profile.name.as_str())
+        .collect();
+    rules::follow_profiles(&mut editor.rules, renamed, &names);
+    editor.known.profiles = profile_names(profiles);
+    editor.fill_list();
+    editor.load_fields();
+}
+
+/// Refreshes the choices after devices come or go or profiles are saved, keeping what's being
+/// edited.
+pub fn update(page: HWND, outputs: &[AudioDevice], profiles: &[Profile]) {
+    let Some(editor) =
+        (unsafe { (GetWindowLongPtrW(page, GWLP_USERDATA) as *mut Editor).as_mut() })
+    else {
+        return;
+    };
+    let trigger: Trigger = editor.trigger.read();
+    let action: RuleAction = editor.action.read();
+    editor.known = Known {
+        outputs: controls::device_names(outputs),
+        profiles: profile_names(profiles),
+    };
+    editor.trigger.load(&trigger, &editor.known);
+    editor.action.load(&action, &editor.known);
+}
+
+unsafe extern "system" fn editor_callback(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    unsafe {
+        let editor = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut Editor;
+        match msg {
+            WM_SIZE if !editor.is_null() => {
+                (*editor).form.layout(hwnd);
+                LRESULT(0)
+            }
+            WM_COMMAND if !editor.is_null() => {
+                let editor = &mut *editor;
+                let id = (wparam.0 & 0xffff) as u16;
+                let notification = ((wparam.0 >> 16) & 0xffff) as u32;
+                match (id, notification) {
+                    (ID_LIST, LBN_SELCHANGE) => {
+                        editor.select(controls::list_selection(editor.form.list))
+                    }
+                    (ID_TRIGGER_KIND, CBN_SELCHANGE) => {
+                        let kind = controls::combo_selection(editor.trigger.kind).unwrap_or(0);
+                        editor.trigger.show::<Trigger>(kind, "", &editor.known);
+                    }
+                    (ID_ACTION_KIND, CBN_SELCHANGE) => {
+                        let kind = controls::combo_selection(editor.action.kind).unwrap_or(0);
+                        editor.action.show::<RuleAction>(kind, "", &editor.known);
+                    }
+                    (ID_NEW, BN_CLICKED) => editor.add(),
+                    (ID_DELETE, BN_CLICKED) => editor.delete(),
+                    (ID_SAVE, BN_CLICKED) => editor.save(hwnd),
+                    (ID_TEST, BN_CLICKED) => editor.test(hwnd),
+                    _ => {}
+                }
+                LRESULT(0)
+            }
+            WM_NCDESTROY => {
+                if !editor.is_null() {
+                    SetWindowLongPtrW(hwnd, GWLP_USERDATA, 0);
+                    drop(Box::from_raw(editor));
+                }
+                DefWindowProcW(hwnd, msg, wparam, lparam)
+            }
+            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+        }
+    }
+}
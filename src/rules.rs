@@ -0,0 +1,556 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use windows::Win32::System::SystemInformation::GetLocalTime;
+
+/// What a trigger or action's value is, so the editor can offer the right choices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parameter {
+    // An output device's endpoint ID.
+    Device,
+    // A profile's name.
+    Profile,
+    // "docked" or "undocked".
+    Docked,
+    Text,
+}
+
+/// A trigger or action as the rules editor shows it: one of several kinds, each with a value.
+pub trait RulePart: Sized {
+    /// (name, what the value is, label for the value) of each kind, in the order `kind` numbers
+    /// them.
+    const KINDS: &'static [(&'static str, Parameter, &'static str)];
+
+    fn kind(&self) -> usize;
+    fn value(&self) -> String;
+    fn from_parts(kind: usize, value: String) -> Self;
+}
+
+/// What sets a rule off.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Trigger {
+    // An output device, by endpoint ID, being plugged in or enabled.
+    DeviceConnected { device: String },
+    // An app, by executable name (e.g. `Discord.exe`), coming to the foreground.
+    AppForeground { executable: String },
+    // Every day at a local time, as `HH:MM`.
+    Schedule { at: String },
+    // Connecting to a network, by the name Windows shows for it.
+    Network { name: String },
+    // Docking or undocking a laptop.
+    Dock { docked: bool },
+}
+
+impl RulePart for Trigger {
+    const KINDS: &'static [(&'static str, Parameter, &'static str)] = &[
+        ("Device connected", Parameter::Device, "Device"),
+        ("App in foreground", Parameter::Text, "App"),
+        ("Time of day", Parameter::Text, "Time"),
+        ("Network connected", Parameter::Text, "Network"),
+        ("Dock", Parameter::Docked, "When"),
+    ];
+
+    fn kind(&self) -> usize {
+        match self {
+            Trigger::DeviceConnected { .. } => 0,
+            Trigger::AppForeground { .. } => 1,
+            Trigger::Schedule { .. } => 2,
+            Trigger::Network { .. } => 3,
+            Trigger::Dock { .. } => 4,
+        }
+    }
+
+    fn value(&self) -> String {
+        match self {
+            Trigger::DeviceConnected { device } => device.clone(),
+            Trigger::AppForeground { executable } => executable.clone(),
+            Trigger::Schedule { at } => at.clone(),
+            Trigger::Network { name } => name.clone(),
+            Trigger::Dock { docked: true } => "docked".to_string(),
+            Trigger::Dock { docked: false } => "undocked".to_string(),
+        }
+    }
+
+    fn from_parts(kind: usize, value: String) -> Self {
+        match kind {
+            1 => Trigger::AppForeground { executable: value },
+            2 => Trigger::Schedule { at: value },
+            3 => Trigger::Network { name: value },
+            4 => Trigger::Dock {
+                docked: value != "undocked",
+            },
+            _ => Trigger::DeviceConnected { device: value },
+        }
+    }
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trigger::DeviceConnected { device } => write!(f, "device {device} is connected"),
+            Trigger::AppForeground { executable } => {
+                write!(f, "{executable} comes to the foreground")
+            }
+            Trigger::Schedule { at } => write!(f, "it's {at}"),
+            Trigger::Network { name } => write!(f, "network \"{name}\" is connected"),
+            Trigger::Dock { docked: true } => write!(f, "docked"),
+            Trigger::Dock { docked: false } => write!(f, "undocked"),
+        }
+    }
+}
+
+/// What a rule does when it's set off.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleAction {
+    // Makes an output device, by endpoint ID, the default.
+    SwitchDevice { device: String },
+    // Switches to the devices of a profile, by name.
+    ApplyProfile { profile: String },
+    // Shows a notification from the tray icon.
+    Notify { message: String },
+    // Runs a command line, e.g. a script.
+    RunHook { command: String },
+}
+
+impl RulePart for RuleAction {
+    const KINDS: &'static [(&'static str, Parameter, &'static str)] = &[
+        ("Switch device", Parameter::Device, "Device"),
+        ("Apply profile", Parameter::Profile, "Profile"),
+        ("Notify", Parameter::Text, "Message"),
+        ("Run hook", Parameter::Text, "Command"),
+    ];
+
+    fn kind(&self) -> usize {
+        match self {
+            RuleAction::SwitchDevice { .. } => 0,
+            RuleAction::ApplyProfile { .. } => 1,
+            RuleAction::Notify { .. } => 2,
+            RuleAction::RunHook { .. } => 3,
+        }
+    }
+
+    fn value(&self) -> String {
+        match self {
+            RuleAction::SwitchDevice { device } => device.clone(),
+            RuleAction::ApplyProfile { profile } => profile.clone(),
+            RuleAction::Notify { message } => message.clone(),
+            RuleAction::RunHook { command } => command.clone(),
+        }
+    }
+
+    fn from_parts(kind: usize, value: String) -> Self {
+        match kind {
+            1 => RuleAction::ApplyProfile { profile: value },
+            2 => RuleAction::Notify { message: value },
+            3 => RuleAction::RunHook { command: value },
+            _ => RuleAction::SwitchDevice { device: value },
+        }
+    }
+}
+
+impl fmt::Display for RuleAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleAction::SwitchDevice { device } => write!(f, "switch to device {device}"),
+            RuleAction::ApplyProfile { profile } => write!(f, "apply profile \"{profile}\""),
+            RuleAction::Notify { message } => write!(f, "show \"{message}\""),
+            RuleAction::RunHook { command } => write!(f, "run `{command}`"),
+        }
+    }
+}
+
+/// A trigger and what to do when it happens, kept in the `rules` section of the config.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rule {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    pub trigger: Trigger,
+    pub action: RuleAction,
+    // With a device connected trigger, switches back to the output that was the default before
+    // once the device is disconnected again.
+    #[serde(default)]
+    pub revert: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            enabled: true,
+            trigger: Trigger::DeviceConnected {
+                device: String::new(),
+            },
+            action: RuleAction::SwitchDevice {
+                device: String::new(),
+            },
+            revert: false,
+        }
+    }
+}
+
+/// The enabled rules set off by the output device with endpoint ID `device_id` connecting.
+pub fn connected<'a>(rules: &'a [Rule], device_id: &'a str) -> impl Iterator<Item = &'a Rule> {
+    rules.iter().filter(move |rule| {
+        rule.enabled
+            && matches!(&rule.trigger, Trigger::DeviceConnected { device } if device == device_id)
+    })
+}
+
+/// The enabled rules scheduled for `time`, written as `HH:MM`.
+pub fn scheduled<'a>(rules: &'a [Rule], time: &str) -> impl Iterator<Item = &'a Rule> {
+    let time = parse_time(time);
+    rules.iter().filter(move |rule| {
+        rule.enabled
+            && time.is_some()
+            && matches!(&rule.trigger, Trigger::Schedule { at } if parse_time(at) == time)
+    })
+}
+
+/// Whether any enabled rule is set off at a time of day.
+pub fn watches_schedule(rules: &[Rule]) -> bool {
+    rules
+        .iter()
+        .any(|rule| rule.enabled && matches!(rule.trigger, Trigger::Schedule { .. }))
+}
+
+/// The enabled rules set off by the app with this executable file name (e.g. `Discord.exe`) coming
+/// to the foreground, ignoring case as Windows does.
+pub fn foreground<'a>(rules: &'a [Rule], executable: &'a str) -> impl Iterator<Item = &'a Rule> {
+    rules.iter().filter(move |rule| {
+        rule.enabled
+            && matches!(&rule.trigger, Trigger::AppForeground { executable: e }
+                if e.trim().eq_ignore_ascii_case(executable))
+    })
+}
+
+/// Whether any enabled rule waits for an app coming to the foreground.
+pub fn watches_foreground(rules: &[Rule]) -> bool {
+    rules
+        .iter()
+        .any(|rule| rule.enabled && matches!(rule.trigger, Trigger::AppForeground { .. }))
+}
+
+/// The enabled rules set off by connecting to the network with this name.
+pub fn network_connected<'a>(rules: &'a [Rule], name: &'a str) -> impl Iterator<Item = &'a Rule> {
+    rules.iter().filter(move |rule| {
+        rule.enabled && matches!(&rule.trigger, Trigger::Network { name: n } if n.trim() == name)
+    })
+}
+
+/// The enabled rules set off by docking, or undocking if `docked` is false.
+pub fn dock_changed(rules: &[Rule], docked: bool) -> impl Iterator<Item = &Rule> {
+    rules
+        .iter()
+        .filter(move |rule| rule.enabled && rule.trigger == Trigger::Dock { docked })
+}
+
+/// Points the rules applying a profile renamed from the first name of a pair to the second, and
+/// turns off those left applying one that isn't among `profile_names`, returning their names.
+pub fn follow_profiles(
+    rules: &mut [Rule],
+    renamed: &[(String, String)],
+    profile_names: &[&str],
+) -> Vec<String> {
+    let mut turned_off = Vec::new();
+    for rule in rules {
+        let RuleAction::ApplyProfile { profile } = &mut rule.action else {
+            continue;
+        };
+        if let Some((_, new_name)) = renamed.iter().find(|(old_name, _)| old_name == profile) {
+            *profile = new_name.clone();
+        } else if rule.enabled && !profile_names.contains(&profile.as_str()) {
+            rule.enabled = false;
+            turned_off.push(rule.name.clone());
+        }
+    }
+    turned_off
+}
+
+/// The local date and time now, as `YYYY-MM-DD` and `HH:MM`.
+pub fn local_time() -> (String, String) {
+    let now = unsafe { GetLocalTime() };
+    (
+        format!("{:04}-{:02}-{:02}", now.wYear, now.wMonth, now.wDay),
+        format!("{:02}:{:02}", now.wHour, now.wMinute),
+    )
+}
+
+/// How many milliseconds until the local clock next turns over to a new minute.
+pub fn until_next_minute_ms() -> u32 {
+    let now = unsafe { GetLocalTime() };
+    60_000 - (now.wSecond as u32 * 1_000 + now.wMilliseconds as u32).min(59_999)
+}
+
+/// Reads a time of day written as `HH:MM`, as (hours, minutes).
+pub fn parse_time(text: &str) -> Option<(u32, u32)> {
+    let (hours, minutes) = text.trim().split_once(':')?;
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok()?;
+    (hours < 24 && minutes < 60).then_some((hours, minutes))
+}
+
+/// Describes what's wrong with `rules`, given the names of the profiles they can apply. Any
+/// problem stops them being saved.
+pub fn validate(rules: &[Rule], profile_names: &[&str]) -> Vec<String> {
+    let mut problems = Vec::new();
+    for rule in rules {
+        let name = rule.name.trim();
+        if name.is_empty() {
+            problems.push("Every rule needs a name.".to_string());
+            continue;
+        }
+        let trigger = rule.trigger.value();
+        let action = rule.action.value();
+        for (value, (_, _, label)) in [
+            (&trigger, Trigger::KINDS[rule.trigger.kind()]),
+            (&action, RuleAction::KINDS[rule.action.kind()]),
+        ] {
+            if value.trim().is_empty() {
+                problems.push(format!(
+                    "\"{name}\" is missing its {}.",
+                    label.to_lowercase()
+                ));
+            }
+        }
+        if let Trigger::Schedule { at } = &rule.trigger
+            && !at.trim().is_empty()
+            && parse_time(at).is_none()
+        {
+            problems.push(format!("\"{name}\": \"{at}\" isn't a time like 07:30."));
+        }
+        if rule.revert && !matches!(rule.trigger, Trigger::DeviceConnected { .. }) {
+            problems.push(format!(
+                "\"{name}\" can only switch back when its trigger is a device connecting."
+            ));
+        }
+        if let RuleAction::ApplyProfile { profile } = &rule.action
+            && !profile.is_empty()
+            && !profile_names.contains(&profile.as_str())
+        {
+            problems.push(format!(
+                "\"{name}\" applies a profile that doesn't exist: {profile}."
+            ));
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialises_as_tagged_objects() {
+        let rule = Rule {
+            name: "Headset".to_string(),
+            enabled: true,
+            trigger: Trigger::DeviceConnected {
+                device: "{headset}".to_string(),
+            },
+            action: RuleAction::ApplyProfile {
+                profile: "Calls".to_string(),
+            },
+            revert: true,
+        };
+        let json = serde_json::to_value(&rule).unwrap();
+        assert_eq!(json["trigger"]["type"], "device_connected");
+        assert_eq!(json["action"]["profile"], "Calls");
+        let parsed: Rule = serde_json::from_str(
+            r#"{"trigger": {"type": "dock", "docked": false},
+                "action": {"type": "notify", "message": "Undocked"}}"#,
+        )
+        .unwrap();
+        assert!(parsed.enabled);
+        assert!(!parsed.revert);
+        assert_eq!(parsed.trigger.value(), "undocked");
+        assert_eq!(
+            format!("When {}, {}.", parsed.trigger, parsed.action),
+            "When undocked, show \"Undocked\"."
+        );
+    }
+
+    #[test]
+    fn parts_round_trip() {
+        for kind in 0..Trigger::KINDS.len() {
+            let trigger = Trigger::from_parts(kind, "docked".to_string());
+            assert_eq!(trigger.kind(), kind);
+            assert_eq!(trigger.value(), "docked");
+        }
+        for kind in 0..RuleAction::KINDS.len() {
+            let action = RuleAction::from_parts(kind, "x".to_string());
+            assert_eq!(action.kind(), kind);
+            assert_eq!(action.value(), "x");
+        }
+    }
+
+    #[test]
+    fn finds_problems() {
+        let rule = |name: &str, trigger, action| Rule {
+            name: name.to_string(),
+            enabled: true,
+            trigger,
+            action,
+            revert: false,
+        };
+        let rules = [
+            rule(
+                "Morning",
+                Trigger::Schedule {
+                    at: "7.30".to_string(),
+                },
+                RuleAction::ApplyProfile {
+                    profile: "Work".to_string(),
+                },
+            ),
+            rule(
+                "Games",
+                Trigger::AppForeground {
+                    executable: " ".to_string(),
+                },
+                RuleAction::ApplyProfile {
+                    profile: "Gaming".to_string(),
+                },
+            ),
+            Rule {
+                revert: true,
+                ..rule(
+                    "Lunch",
+                    Trigger::Schedule {
+                        at: "12:00".to_string(),
+                    },
+                    RuleAction::Notify {
+                        message: "Lunch".to_string(),
+                    },
+                )
+            },
+        ];
+        assert_eq!(
+            validate(&rules, &["Gaming"]),
+            [
+                "\"Morning\": \"7.30\" isn't a time like 07:30.",
+                "\"Morning\" applies a profile that doesn't exist: Work.",
+                "\"Games\" is missing its app.",
+                "\"Lunch\" can only switch back when its trigger is a device connecting.",
+            ]
+        );
+        assert_eq!(parse_time("07:30"), Some((7, 30)));
+        assert_eq!(parse_time("24:00"), None);
+    }
+
+    #[test]
+    fn finds_rules_for_a_connected_device() {
+        let rule = |name: &str, device: &str, enabled| Rule {
+            name: name.to_string(),
+            enabled,
+            trigger: Trigger::DeviceConnected {
+                device: device.to_string(),
+            },
+            ..Default::default()
+        };
+        let rules = [
+            rule("Headset", "{headset}", true),
+            rule("Speakers", "{speakers}", true),
+            rule("Old headset", "{headset}", false),
+            Rule {
+                name: "Docked".to_string(),
+                trigger: Trigger::Dock { docked: true },
+                ..Default::default()
+            },
+        ];
+        let names: Vec<_> = connected(&rules, "{headset}")
+            .map(|rule| rule.name.as_str())
+            .collect();
+        assert_eq!(names, ["Headset"]);
+        assert_eq!(connected(&rules, "{tv}").count(), 0);
+    }
+
+    #[test]
+    fn finds_rules_scheduled_for_a_time() {
+        let rule = |name: &str, at: &str| Rule {
+            name: name.to_string(),
+            trigger: Trigger::Schedule { at: at.to_string() },
+            ..Default::default()
+        };
+        let rules = [
+            rule("Morning", "7:30"),
+            rule("Evening", "19:00"),
+            rule("Broken", "soon"),
+        ];
+        let names: Vec<_> = scheduled(&rules, "07:30")
+            .map(|rule| rule.name.as_str())
+            .collect();
+        assert_eq!(names, ["Morning"]);
+        assert_eq!(scheduled(&rules, "soon").count(), 0);
+    }
+
+    #[test]
+    fn finds_rules_for_apps_networks_and_docking() {
+        let rule = |name: &str, trigger| Rule {
+            name: name.to_string(),
+            trigger,
+            ..Default::default()
+        };
+        let rules = [
+            rule(
+                "Discord",
+                Trigger::AppForeground {
+                    executable: "Discord.exe".to_string(),
+                },
+            ),
+            rule(
+                "Office",
+                Trigger::Network {
+                    name: "Office Wi-Fi".to_string(),
+                },
+            ),
+            rule("Desk", Trigger::Dock { docked: true }),
+        ];
+        let names = |found: Vec<&Rule>| -> Vec<String> {
+            found.into_iter().map(|rule| rule.name.clone()).collect()
+        };
+        assert_eq!(
+            names(foreground(&rules, "discord.EXE").collect()),
+            ["Discord"]
+        );
+        assert_eq!(foreground(&rules, "Teams.exe").count(), 0);
+        assert!(watches_foreground(&rules));
+        assert!(!watches_foreground(&rules[1..]));
+        assert_eq!(
+            names(network_connected(&rules, "Office Wi-Fi").collect()),
+            ["Office"]
+        );
+        assert_eq!(names(dock_changed(&rules, true).collect()), ["Desk"]);
+        assert_eq!(dock_changed(&rules, false).count(), 0);
+    }
+
+    #[test]
+    fn follows_renamed_and_deleted_profiles() {
+        let rule = |name: &str, profile: &str| Rule {
+            name: name.to_string(),
+            action: RuleAction::ApplyProfile {
+                profile: profile.to_string(),
+            },
+            ..Default::default()
+        };
+        let mut rules = [
+            rule("Calls", "Meetings"),
+            rule("Games", "Gaming"),
+            rule("Music", "Music"),
+            Rule::default(),
+        ];
+        let renamed = [("Meetings".to_string(), "Calls".to_string())];
+        let turned_off = follow_profiles(&mut rules, &renamed, &["Calls", "Music"]);
+        assert_eq!(turned_off, ["Games"]);
+        assert_eq!(rules[0].action.value(), "Calls");
+        assert!(rules[0].enabled);
+        assert!(!rules[1].enabled);
+        assert!(rules[2].enabled && rules[3].enabled);
+    }
+}
@@ -1,3 +1,7 @@
+use serde::{Deserialize, Serialize};
+use std::cell::OnceCell;
+use std::fmt;
+use std::ops::Deref;
 use windows_strings::PCWSTR;
 use windows_strings::PWSTR;
 
@@ -24,3 +28,149 @@ where
     let pwstr = PWSTR(wide_data.as_mut_ptr());
     f(pwstr)
 }
+
+/// Encodes `text` into a fixed-size, null-terminated buffer, such as a tooltip's, ending it with
+/// "…" if it doesn't fit. It's only cut between characters, so surrogate pairs stay whole.
+pub fn truncated_wide<const N: usize>(text: &str) -> [u16; N] {
+    truncated_from_wide(&text.encode_utf16().collect::<Vec<_>>())
+}
+
+/// `truncated_wide` for text that's already UTF-16, such as a `WideString`'s, up to its null if it
+/// has one.
+pub fn truncated_from_wide<const N: usize>(wide: &[u16]) -> [u16; N] {
+    let mut buffer = [0u16; N];
+    // Leaving room for the null.
+    let Some(capacity) = N.checked_sub(1) else {
+        return buffer;
+    };
+    let text = match wide.iter().position(|&unit| unit == 0) {
+        Some(length) => &wide[..length],
+        None => wide,
+    };
+    let fits = text.len() <= capacity;
+    let mut length = if fits {
+        text.len()
+    } else {
+        capacity.saturating_sub(1)
+    };
+    // Not between the halves of a surrogate pair.
+    if !fits && length > 0 && (0xD800..0xDC00).contains(&text[length - 1]) {
+        length -= 1;
+    }
+    buffer[..length].copy_from_slice(&text[..length]);
+    if !fits && capacity > 0 {
+        buffer[length] = '…' as u16;
+    }
+    buffer
+}
+
+/// A string that keeps its null-terminated UTF-16 form around once it has been needed, so strings
+/// shown repeatedly in the UI (such as device names) aren't re-encoded every time.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct WideString {
+    text: String,
+    wide: OnceCell<Vec<u16>>,
+}
+
+impl WideString {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            wide: OnceCell::new(),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// The null-terminated UTF-16 form, encoded on first use.
+    pub fn wide(&self) -> &[u16] {
+        self.wide.get_or_init(|| {
+            let mut wide_data: Vec<u16> = self.text.encode_utf16().collect();
+            wide_data.push(0); // Null terminate
+            wide_data
+        })
+    }
+
+    /// Replaces the text, e.g. when a device is renamed, dropping the stale encoding.
+    pub fn set(&mut self, text: impl Into<String>) {
+        self.text = text.into();
+        self.wide = OnceCell::new();
+    }
+}
+
+// By the text alone, whether or not it has been encoded yet.
+impl PartialEq for WideString {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl Deref for WideString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl fmt::Display for WideString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl From<String> for WideString {
+    fn from(text: String) -> Self {
+        Self::new(text)
+    }
+}
+
+impl From<&str> for WideString {
+    fn from(text: &str) -> Self {
+        Self::new(text)
+    }
+}
+
+impl From<WideString> for String {
+    fn from(s: WideString) -> Self {
+        s.text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(wide: &[u16]) -> String {
+        let length = wide.iter().position(|&unit| unit == 0).unwrap();
+        String::from_utf16(&wide[..length]).unwrap()
+    }
+
+    #[test]
+    fn long_text_is_cut_with_an_ellipsis() {
+        assert_eq!(decode(&truncated_wide::<128>("Speakers")), "Speakers");
+        let name = "Headset Earphone (WH-1000XM4 Hands-Free AG Audio) ".repeat(3);
+        let tip = truncated_wide::<128>(&name);
+        assert_eq!(tip[127], 0);
+        let tip = decode(&tip);
+        assert_eq!(tip.encode_utf16().count(), 127);
+        assert!(tip.ends_with('…'));
+        assert!(name.starts_with(tip.trim_end_matches('…')));
+        // Exactly full.
+        assert_eq!(decode(&truncated_wide::<4>("abc")), "abc");
+        assert_eq!(decode(&truncated_wide::<4>("abcd")), "ab…");
+    }
+
+    #[test]
+    fn surrogate_pairs_are_never_split() {
+        // Each emoji takes two UTF-16 units, so the third doesn't fit beside the ellipsis.
+        assert_eq!(decode(&truncated_wide::<7>("🎧🎧🎧🎧")), "🎧🎧…");
+        assert_eq!(decode(&truncated_wide::<6>("a🎧🎧🎧")), "a🎧…");
+        assert_eq!(decode(&truncated_wide::<1>("🎧")), "");
+        let name = WideString::new("a🎧🎧🎧");
+        assert_eq!(decode(&truncated_from_wide::<6>(name.wide())), "a🎧…");
+    }
+}
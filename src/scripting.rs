@@ -0,0 +1,365 @@
+use log::{debug, error, info};
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Map, Scope, AST, INT};
+use std::cell::RefCell;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::SystemTime;
+
+// Scripts are stopped after this many operations, so a runaway loop can't hang the app.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+// Files in the scripts directory with this extension are loaded.
+const EXTENSION: &str = "rhai";
+
+/// An output device as scripts see it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptDevice {
+    pub id: String,
+    pub name: String,
+    pub selectable: bool,
+    // Percent, if it could be read.
+    pub volume: Option<u32>,
+}
+
+impl ScriptDevice {
+    fn to_map(&self) -> Map {
+        let mut map = Map::new();
+        map.insert("id".into(), self.id.clone().into());
+        map.insert("name".into(), self.name.clone().into());
+        map.insert("selectable".into(), self.selectable.into());
+        map.insert(
+            "volume".into(),
+            self.volume
+                .map_or(Dynamic::UNIT, |volume| (volume as INT).into()),
+        );
+        map
+    }
+}
+
+/// The state of the app that scripts can read while they run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScriptState {
+    pub devices: Vec<ScriptDevice>,
+    pub default_device: String,
+}
+
+/// Something a script asked for, carried out by the app once the script returns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptCommand {
+    // By ID or (part of) the name, as on the command line.
+    SetDefault(String),
+    SetVolume(String, u32),
+    Notify(String, String),
+}
+
+/// Something that happened, handled by scripts defining a function of the same name that takes
+/// the device as a map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    // The output was switched, to the device given.
+    Switch,
+    DeviceAdded,
+}
+
+impl Event {
+    fn function(self) -> &'static str {
+        match self {
+            Event::Switch => "on_switch",
+            Event::DeviceAdded => "on_device_added",
+        }
+    }
+}
+
+// What the functions registered with the engine read and write.
+#[derive(Default)]
+struct Shared {
+    state: ScriptState,
+    commands: Vec<ScriptCommand>,
+}
+
+struct Script {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    // Missing if it didn't compile.
+    ast: Option<AST>,
+}
+
+/// The scripts in a directory, run in the app by an embedded Rhai engine. Scripts are loaded in
+/// file name order, their top-level code runs once when they're loaded, and they're loaded again
+/// whenever they change.
+pub struct Scripts {
+    dir: PathBuf,
+    engine: Engine,
+    shared: Rc<RefCell<Shared>>,
+    scripts: Vec<Script>,
+}
+
+impl Scripts {
+    pub fn new(dir: PathBuf) -> Self {
+        let shared = Rc::new(RefCell::new(Shared::default()));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| info!("Script: {text}"));
+        engine.on_debug(|text, source, position| {
+            debug!("Script {}{position:?}: {text}", source.unwrap_or_default())
+        });
+        let reader = shared.clone();
+        engine.register_fn("devices", move || -> Array {
+            reader
+                .borrow()
+                .state
+                .devices
+                .iter()
+                .map(|device| device.to_map().into())
+                .collect()
+        });
+        let reader = shared.clone();
+        engine.register_fn("default_device", move || -> String {
+            reader.borrow().state.default_device.clone()
+        });
+        let reader = shared.clone();
+        engine.register_fn("volume", move |id: &str| -> Dynamic {
+            reader
+                .borrow()
+                .state
+                .devices
+                .iter()
+                .find(|device| device.id == id)
+                .and_then(|device| device.volume)
+                .map_or(Dynamic::UNIT, |volume| (volume as INT).into())
+        });
+        let writer = shared.clone();
+        engine.register_fn("set_default", move |device: &str| {
+            writer
+                .borrow_mut()
+                .commands
+                .push(ScriptCommand::SetDefault(device.to_string()));
+        });
+        let writer = shared.clone();
+        engine.register_fn("set_volume", move |device: &str, percent: INT| {
+            writer.borrow_mut().commands.push(ScriptCommand::SetVolume(
+                device.to_string(),
+                percent.clamp(0, 100) as u32,
+            ));
+        });
+        let writer = shared.clone();
+        engine.register_fn("notify", move |title: &str, text: &str| {
+            writer
+                .borrow_mut()
+                .commands
+                .push(ScriptCommand::Notify(title.to_string(), text.to_string()));
+        });
+        Self {
+            dir,
+            engine,
+            shared,
+            scripts: Vec::new(),
+        }
+    }
+
+    /// Whether there are no scripts loaded, so there's no need to fire events.
+    pub fn is_empty(&self) -> bool {
+        self.scripts.iter().all(|script| script.ast.is_none())
+    }
+
+    /// Loads the scripts that are new or have changed since last time, running their top-level
+    /// code, and forgets those that have gone. Returns what they asked for. `state` is only called
+    /// for if there's something to run.
+    pub fn reload(&mut self, state: impl FnOnce() -> ScriptState) -> Vec<ScriptCommand> {
+        let mut found: Vec<(PathBuf, Option<SystemTime>)> = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| {
+                    entry
+                        .path()
+                        .extension()
+                        .is_some_and(|extension| extension.eq_ignore_ascii_case(EXTENSION))
+                })
+                .map(|entry| {
+                    let modified = entry.metadata().and_then(|m| m.modified()).ok();
+                    (entry.path(), modified)
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        found.sort();
+        let mut scripts = Vec::new();
+        let mut loaded = Vec::new();
+        for (path, modified) in found {
+            match self.scripts.iter().position(|script| script.path == path) {
+                Some(i) if self.scripts[i].modified == modified => {
+                    scripts.push(self.scripts.swap_remove(i));
+                }
+                previous => {
+                    if let Some(i) = previous {
+                        self.scripts.swap_remove(i);
+                    }
+                    info!("Loading script {}", path.display());
+                    let ast = fs::read_to_string(&path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|text| self.engine.compile(text).map_err(|e| e.to_string()))
+                        .inspect_err(|e| error!("Failed to load {}: {e}", path.display()))
+                        .ok();
+                    if ast.is_some() {
+                        loaded.push(scripts.len());
+                    }
+                    scripts.push(Script {
+                        path,
+                        modified,
+                        ast,
+                    });
+                }
+            }
+        }
+        for script in &self.scripts {
+            info!("Script {} was removed", script.path.display());
+        }
+        self.scripts = scripts;
+        if loaded.is_empty() {
+            return Vec::new();
+        }
+        self.run(state(), |engine, script, ast| {
+            if loaded.contains(&script) {
+                engine.run_ast(ast)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Calls the function handling `event` in each script that defines one, with `device`.
+    pub fn fire(
+        &mut self,
+        event: Event,
+        device: &ScriptDevice,
+        state: ScriptState,
+    ) -> Vec<ScriptCommand> {
+        let function = event.function();
+        let device = device.to_map();
+        self.run(state, |engine, _, ast| {
+            if ast
+                .iter_functions()
+                .any(|f| f.name == function && f.params.len() == 1)
+            {
+                // Not running the top-level code again.
+                let options = CallFnOptions::new().eval_ast(false);
+                let _ = engine.call_fn_with_options::<Dynamic>(
+                    options,
+                    &mut Scope::new(),
+                    ast,
+                    function,
+                    (device.clone(),),
+                )?;
+            }
+            Ok(())
+        })
+    }
+
+    // Runs `call` for each loaded script with `state` to read, logging any that fail, and
+    // returns the commands they left.
+    fn run(
+        &mut self,
+        state: ScriptState,
+        call: impl Fn(&Engine, usize, &AST) -> Result<(), Box<rhai::EvalAltResult>>,
+    ) -> Vec<ScriptCommand> {
+        self.shared.borrow_mut().state = state;
+        for (i, script) in self.scripts.iter().enumerate() {
+            if let Some(ast) = &script.ast
+                && let Err(e) = call(&self.engine, i, ast)
+            {
+                error!("Script {} failed: {e}", script.path.display());
+            }
+        }
+        std::mem::take(&mut self.shared.borrow_mut().commands)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::Duration;
+
+    fn state() -> ScriptState {
+        ScriptState {
+            devices: vec![
+                ScriptDevice {
+                    id: "{speakers}".to_string(),
+                    name: "Speakers".to_string(),
+                    selectable: true,
+                    volume: Some(40),
+                },
+                ScriptDevice {
+                    id: "{headset}".to_string(),
+                    name: "Headset".to_string(),
+                    selectable: true,
+                    volume: None,
+                },
+            ],
+            default_device: "{speakers}".to_string(),
+        }
+    }
+
+    #[test]
+    fn scripts_handle_events_and_are_reloaded() {
+        let dir = std::env::temp_dir().join(format!("scripts-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("quiet.rhai");
+        fs::write(
+            &path,
+            r#"
+            fn on_switch(device) {
+                if device.name == "Headset" && volume(default_device()) > 30 {
+                    set_volume("{speakers}", 30);
+                    notify("Switched", device.name + " of " + devices().len());
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        fs::write(dir.join("broken.rhai"), "fn (").unwrap();
+        fs::write(dir.join("notes.txt"), "not a script").unwrap();
+        let mut scripts = Scripts::new(dir.clone());
+        assert!(scripts.reload(state).is_empty());
+        let headset = state().devices[1].clone();
+        assert_eq!(
+            scripts.fire(Event::Switch, &headset, state()),
+            [
+                ScriptCommand::SetVolume("{speakers}".to_string(), 30),
+                ScriptCommand::Notify("Switched".to_string(), "Headset of 2".to_string()),
+            ]
+        );
+        assert!(scripts
+            .fire(Event::DeviceAdded, &headset, state())
+            .is_empty());
+
+        // Top-level code runs as soon as it's loaded, but not again for events.
+        fs::write(
+            &path,
+            r#"set_default("Headset"); fn on_device_added(device) { set_default(device.id); }"#,
+        )
+        .unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(
+            scripts.reload(state),
+            [ScriptCommand::SetDefault("Headset".to_string())]
+        );
+        assert!(scripts.reload(state).is_empty());
+        assert_eq!(
+            scripts.fire(Event::DeviceAdded, &headset, state()),
+            [ScriptCommand::SetDefault("{headset}".to_string())]
+        );
+
+        fs::remove_file(&path).unwrap();
+        assert!(scripts.reload(state).is_empty());
+        assert!(scripts
+            .fire(Event::DeviceAdded, &headset, state())
+            .is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,477 @@
+use log::debug;
+use std::sync::{Once, OnceLock};
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, RECT, WPARAM};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::Controls::{
+    LBItemFromPt, MakeDragList, DL_BEGINDRAG, DL_CANCELDRAG, DL_DRAGGING, DL_DROPPED,
+    DL_MOVECURSOR, DRAGLISTINFO, DRAGLISTMSGSTRING, NMHDR, TCIF_TEXT, TCITEMW, TCM_ADJUSTRECT,
+    TCM_GETCURSEL, TCM_INSERTITEMW, TCN_SELCHANGE,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, GetClientRect, GetWindow, GetWindowLongPtrW, MoveWindow,
+    RegisterClassExW, RegisterWindowMessageW, SendMessageW, SetWindowLongPtrW, ShowWindow,
+    CW_USEDEFAULT, GWLP_USERDATA, GW_OWNER, LBS_HASSTRINGS, LBS_NOINTEGRALHEIGHT, LBS_NOTIFY,
+    SW_HIDE, SW_SHOW, WINDOW_STYLE, WM_APP, WM_DESTROY, WM_NCDESTROY, WM_NOTIFY, WM_SIZE,
+    WNDCLASSEXW, WS_BORDER, WS_CHILD, WS_CLIPCHILDREN, WS_CLIPSIBLINGS, WS_EX_APPWINDOW,
+    WS_EX_CONTROLPARENT, WS_OVERLAPPEDWINDOW, WS_TABSTOP, WS_VISIBLE, WS_VSCROLL,
+};
+use windows_strings::w;
+
+use crate::asio::{self, AsioDriver};
+use crate::behavior_page;
+use crate::controls;
+use crate::dpi;
+use crate::hotkey_editor;
+use crate::profile_editor;
+use crate::rule_editor;
+use sound_switcheroo::backend::AudioDevice;
+use sound_switcheroo::config::Config;
+use sound_switcheroo::error::SwitcherooError;
+use sound_switcheroo::profiles::Profile;
+use sound_switcheroo::safe_strings::{with_wide_str, with_wide_str_mut};
+use sound_switcheroo::window_placement;
+
+/// Sent to the window's owner when the devices have been dragged into a new order. LPARAM points
+/// to a `Vec<String>` of their IDs in that order, which is only valid during the message.
+pub const WM_SETTINGS_ORDER_CHANGED: u32 = WM_APP + 0x46;
+/// Sent to the window's owner as the window closes, with its HWND in WPARAM, to remember where it
+/// was.
+pub const WM_SETTINGS_CLOSING: u32 = WM_APP + 0x47;
+
+/// Name of the window for `window_placement::placement_key`.
+pub const PLACEMENT_NAME: &str = "settings";
+
+const LEGEND: &str = "Devices with a check mark take turns when you left click the tray icon, \
+                      in this order. Drag them to change it.";
+const MARGIN: i32 = 8;
+const LEGEND_HEIGHT: i32 = 36;
+
+// Tabs, in order.
+const TAB_DEVICES: usize = 0;
+const TAB_HOTKEYS: usize = 1;
+const TAB_PROFILES: usize = 2;
+const TAB_RULES: usize = 3;
+const TAB_BEHAVIOR: usize = 4;
+const TAB_DIAGNOSTICS: usize = 5;
+const TAB_NAMES: [&str; 6] = [
+    "Devices",
+    "Hotkeys",
+    "Profiles",
+    "Rules",
+    "Behavior",
+    "Diagnostics",
+];
+
+static REGISTER_CLASS: Once = Once::new();
+
+// Sent to the window by its drag list, with a DRAGLISTINFO in LPARAM.
+fn drag_list_message() -> u32 {
+    static MESSAGE: OnceLock<u32> = OnceLock::new();
+    *MESSAGE.get_or_init(|| unsafe { RegisterWindowMessageW(DRAGLISTMSGSTRING) })
+}
+
+// Kept in the window's user data.
+struct State {
+    tabs: HWND,
+    list: HWND,
+    legend: HWND,
+    hotkeys_page: HWND,
+    profiles_page: HWND,
+    rules_page: HWND,
+    behavior_page: HWND,
+    // Read-only text about the ASIO drivers.
+    diagnostics: HWND,
+    // Listed when the window opens, as they only change when software is installed.
+    asio_drivers: Vec<AsioDriver>,
+    // (ID, label) of each device, in the order shown.
+    devices: Vec<(String, String)>,
+    // The order before the current drag, to go back to if it's cancelled.
+    before_drag: Option<Vec<(String, String)>>,
+    // Where the dragged device is now.
+    dragging: usize,
+}
+
+impl State {
+    fn fill_list(&self, selected: Option<usize>) {
+        controls::fill_list(
+            self.list,
+            self.devices.iter().map(|(_, label)| label.as_str()),
+            selected,
+        );
+    }
+
+    fn show_tab(&self, tab: usize) {
+        let shown = |page| if tab == page { SW_SHOW } else { SW_HIDE };
+        unsafe {
+            let _ = ShowWindow(self.legend, shown(TAB_DEVICES));
+            let _ = ShowWindow(self.list, shown(TAB_DEVICES));
+            let _ = ShowWindow(self.hotkeys_page, shown(TAB_HOTKEYS));
+            let _ = ShowWindow(self.profiles_page, shown(TAB_PROFILES));
+            let _ = ShowWindow(self.rules_page, shown(TAB_RULES));
+            let _ = ShowWindow(self.behavior_page, shown(TAB_BEHAVIOR));
+            let _ = ShowWindow(self.diagnostics, shown(TAB_DIAGNOSTICS));
+        }
+    }
+
+    fn notify_owner(&self, window: HWND) {
+        let ids: Vec<String> = self.devices.iter().map(|(id, _)| id.clone()).collect();
+        unsafe {
+            if let Ok(owner) = GetWindow(window, GW_OWNER) {
+                SendMessageW(
+                    owner,
+                    WM_SETTINGS_ORDER_CHANGED,
+                    None,
+                    Some(LPARAM(&ids as *const _ as isize)),
+                );
+            }
+        }
+    }
+}
+
+fn entries(devices: &[AudioDevice]) -> Vec<(String, String)> {
+    devices
+        .iter()
+        .map(|device| {
+            let mark = if device.selectable {
+                "\u{2713}"
+            } else {
+                "    "
+            };
+            (
+                device.id.clone(),
+                format!("{mark}  {}", device.friendly_name),
+            )
+        })
+        .collect()
+}
+
+/// Opens the settings window where it was last left on these monitors, listing `devices` in
+/// rotation order, with further tabs for editing the hotkeys, profiles (of those and the
+/// `inputs`), rules and on/off settings in `config`, and one listing the ASIO drivers. Reordering
+/// the devices sends `WM_SETTINGS_ORDER_CHANGED` to `owner`, and saving a tab its own message,
+/// such as `WM_SETTINGS_PROFILES_CHANGED`.
+pub fn show(
+    owner: HWND,
+    devices: &[AudioDevice],
+    inputs: &[AudioDevice],
+    config: &Config,
+) -> Result<HWND, SwitcherooError> {
+    unsafe {
+        let module = GetModuleHandleW(None)?;
+        REGISTER_CLASS.call_once(|| {
+            RegisterClassExW(&WNDCLASSEXW {
+                cbSize: std::mem::size_of::<WNDCLASSEXW>() as u32,
+                lpfnWndProc: Some(settings_callback),
+                hInstance: module.into(),
+                lpszClassName: w!("SoundSwitcherooSettings"),
+                ..Default::default()
+            });
+        });
+
+        // Its pages are laid out in pixels, so it's left to Windows to scale.
+        let window = dpi::unaware(|| {
+            CreateWindowExW(
+                WS_EX_APPWINDOW | WS_EX_CONTROLPARENT,
+                w!("SoundSwitcherooSettings"),
+                w!("Sound Switcheroo Settings"),
+                WS_OVERLAPPEDWINDOW | WS_CLIPCHILDREN,
+                CW_USEDEFAULT,
+                CW_USEDEFAULT,
+                540,
+                420,
+                Some(owner),
+                None,
+                Some(module.into()),
+                None,
+            )
+        })?;
+        let legend = with_wide_str(LEGEND, |legend| {
+            CreateWindowExW(
+                Default::default(),
+                w!("STATIC"),
+                legend,
+                WS_CHILD | WS_VISIBLE,
+                0,
+                0,
+                0,
+                0,
+                Some(window),
+                None,
+                Some(module.into()),
+                None,
+            )
+        })?;
+        let list = CreateWindowExW(
+            Default::default(),
+            w!("LISTBOX"),
+            w!(""),
+            WS_CHILD
+                | WS_VISIBLE
+                | WS_TABSTOP
+                | WS_BORDER
+                | WS_VSCROLL
+                | WINDOW_STYLE((LBS_NOTIFY | LBS_HASSTRINGS | LBS_NOINTEGRALHEIGHT) as u32),
+            0,
+            0,
+            0,
+            0,
+            Some(window),
+            None,
+            Some(module.into()),
+            None,
+        )?;
+        let hotkeys_page =
+            hotkey_editor::create(window, &config.hotkeys, devices, &config.profiles)?;
+        let profiles_page = profile_editor::create(window, &config.profiles, devices, inputs)?;
+        let rules_page = rule_editor::create(window, &config.rules, devices, &config.profiles)?;
+        let behavior_page = behavior_page::create(window, config)?;
+        let asio_drivers = asio::drivers();
+        let diagnostics = controls::read_only_text(window, &asio::report(&asio_drivers, devices))?;
+        // Made last so it's below the pages it holds.
+        let tabs = CreateWindowExW(
+            Default::default(),
+            w!("SysTabControl32"),
+            w!(""),
+            WS_CHILD | WS_VISIBLE | WS_TABSTOP | WS_CLIPSIBLINGS,
+            0,
+            0,
+            0,
+            0,
+            Some(window),
+            None,
+            Some(module.into()),
+            None,
+        )?;
+        for (index, name) in TAB_NAMES.iter().enumerate() {
+            with_wide_str_mut(name, |name| {
+                let item = TCITEMW {
+                    mask: TCIF_TEXT,
+                    pszText: name,
+                    ..Default::default()
+                };
+                SendMessageW(
+                    tabs,
+                    TCM_INSERTITEMW,
+                    Some(WPARAM(index)),
+                    Some(LPARAM(&item as *const _ as isize)),
+                )
+            });
+        }
+        for control in [tabs, legend, list] {
+            controls::set_font(control);
+        }
+        if !MakeDragList(list).as_bool() {
+            log::error!("Failed to make the device list draggable");
+        }
+        let state = Box::new(State {
+            tabs,
+            list,
+            legend,
+            hotkeys_page,
+            profiles_page,
+            rules_page,
+            behavior_page,
+            diagnostics,
+            asio_drivers,
+            devices: entries(devices),
+            before_drag: None,
+            dragging: 0,
+        });
+        state.fill_list(None);
+        state.show_tab(TAB_DEVICES);
+        SetWindowLongPtrW(window, GWLP_USERDATA, Box::into_raw(state) as isize);
+
+        let key =
+            window_placement::placement_key(PLACEMENT_NAME, &window_placement::monitor_layout());
+        let restored = match config.windows.get(&key) {
+            Some(placement) => window_placement::restore(window, placement)?,
+            None => false,
+        };
+        if !restored {
+            let _ = ShowWindow(window, SW_SHOW);
+        }
+        layout(window);
+        Ok(window)
+    }
+}
+
+/// Has the rules page follow profiles renamed from the first name of a pair to the second, or
+/// deleted, before they're shown again.
+pub fn follow_profiles(window: HWND, renamed: &[(String, String)], profiles: &[Profile]) {
+    unsafe {
+        if let Some(state) = (GetWindowLongPtrW(window, GWLP_USERDATA) as *mut State).as_ref() {
+            rule_editor::follow_profiles(state.rules_page, renamed, profiles);
+        }
+    }
+}
+
+/// Shows the devices, profiles and settings again after they've changed elsewhere.
+pub fn update(window: HWND, devices: &[AudioDevice], inputs: &[AudioDevice], config: &Config) {
+    unsafe {
+        let Some(state) = (GetWindowLongPtrW(window, GWLP_USERDATA) as *mut State).as_mut() else {
+            return;
+        };
+        hotkey_editor::update(state.hotkeys_page, devices, &config.profiles);
+        profile_editor::update_devices(state.profiles_page, devices, inputs);
+        rule_editor::update(state.rules_page, devices, &config.profiles);
+        behavior_page::update(state.behavior_page, config);
+        controls::set_text(
+            state.diagnostics,
+            &asio::report(&state.asio_drivers, devices),
+        );
+        if state.before_drag.is_some() {
+            // Not while the user is dragging them around.
+            return;
+        }
+        state.devices = entries(devices);
+        state.fill_list(None);
+    }
+}
+
+// Fits the tabs to the window, and the pages inside them.
+fn layout(window: HWND) {
+    unsafe {
+        let Some(state) = (GetWindowLongPtrW(window, GWLP_USERDATA) as *const State).as_ref()
+        else {
+            return;
+        };
+        let mut client = Default::default();
+        if GetClientRect(window, &mut client).is_err() {
+            return;
+        }
+        let _ = MoveWindow(
+            state.tabs,
+            MARGIN,
+            MARGIN,
+            client.right - 2 * MARGIN,
+            client.bottom - 2 * MARGIN,
+            true,
+        );
+        // The area inside the tabs, in the window's coordinates as the tabs start at the margin.
+        let mut page = RECT {
+            left: 0,
+            top: 0,
+            right: client.right - 2 * MARGIN,
+            bottom: client.bottom - 2 * MARGIN,
+        };
+        SendMessageW(
+            state.tabs,
+            TCM_ADJUSTRECT,
+            Some(WPARAM(0)),
+            Some(LPARAM(&mut page as *mut _ as isize)),
+        );
+        let left = page.left + 2 * MARGIN;
+        let top = page.top + 2 * MARGIN;
+        let width = page.right - page.left - 2 * MARGIN;
+        let height = page.bottom - page.top - 2 * MARGIN;
+        let _ = MoveWindow(state.legend, left, top, width, LEGEND_HEIGHT, true);
+        let list_top = top + LEGEND_HEIGHT + MARGIN;
+        let _ = MoveWindow(
+            state.list,
+            left,
+            list_top,
+            width,
+            top + height - list_top,
+            true,
+        );
+        for page in [
+            state.hotkeys_page,
+            state.profiles_page,
+            state.rules_page,
+            state.behavior_page,
+            state.diagnostics,
+        ] {
+            let _ = MoveWindow(page, left, top, width, height, true);
+        }
+    }
+}
+
+// Moves the dragged device along with the cursor, so the new order shows as it's dragged.
+fn handle_drag(window: HWND, state: &mut State, info: &DRAGLISTINFO) -> LRESULT {
+    let item = unsafe { LBItemFromPt(state.list, info.ptCursor, true) };
+    match info.uNotification {
+        DL_BEGINDRAG => {
+            if item < 0 {
+                return LRESULT(0);
+            }
+            state.before_drag = Some(state.devices.clone());
+            state.dragging = item as usize;
+            LRESULT(1)
+        }
+        DL_DRAGGING => {
+            if item >= 0 && item as usize != state.dragging {
+                let device = state.devices.remove(state.dragging);
+                state.dragging = item as usize;
+                state.devices.insert(state.dragging, device);
+                state.fill_list(Some(state.dragging));
+            }
+            LRESULT(DL_MOVECURSOR as isize)
+        }
+        DL_DROPPED => {
+            if state
+                .before_drag
+                .take()
+                .is_some_and(|before| before != state.devices)
+            {
+                debug!("Devices reordered");
+                state.notify_owner(window);
+            }
+            LRESULT(0)
+        }
+        DL_CANCELDRAG => {
+            if let Some(before) = state.before_drag.take() {
+                state.devices = before;
+                state.fill_list(None);
+            }
+            LRESULT(0)
+        }
+        _ => LRESULT(0),
+    }
+}
+
+unsafe extern "system" fn settings_callback(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    unsafe {
+        let state = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *mut State;
+        match msg {
+            WM_SIZE => {
+                layout(hwnd);
+                LRESULT(0)
+            }
+            WM_NOTIFY if !state.is_null() => {
+                let header = &*(lparam.0 as *const NMHDR);
+                if header.hwndFrom == (*state).tabs && header.code == TCN_SELCHANGE {
+                    let tab = SendMessageW((*state).tabs, TCM_GETCURSEL, None, None).0;
+                    (*state).show_tab(tab as usize);
+                }
+                LRESULT(0)
+            }
+            msg if msg == drag_list_message() && !state.is_null() => {
+                handle_drag(hwnd, &mut *state, &*(lparam.0 as *const DRAGLISTINFO))
+            }
+            WM_DESTROY => {
+                if let Ok(owner) = GetWindow(hwnd, GW_OWNER) {
+                    SendMessageW(
+                        owner,
+                        WM_SETTINGS_CLOSING,
+                        Some(WPARAM(hwnd.0 as usize)),
+                        None,
+                    );
+                }
+                LRESULT(0)
+            }
+            WM_NCDESTROY => {
+                if !state.is_null() {
+                    SetWindowLongPtrW(hwnd, GWLP_USERDATA, 0);
+                    drop(Box::from_raw(state));
+                }
+                DefWindowProcW(hwnd, msg, wparam, lparam)
+            }
+            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+        }
+    }
+}
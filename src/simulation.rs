@@ -0,0 +1,359 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use windows::Win32::Media::Audio::{eCapture, eRender, EDataFlow, ERole};
+
+use crate::backend::{AudioBackend, AudioDevice, AudioSession, Effects};
+use crate::config::Config;
+use crate::error::SwitcherooError;
+
+/// A device list, either hand-written or recorded from a real machine with `--record`.
+/// Loaded with `--simulate` for working on the UI without particular hardware, and by tests to
+/// reproduce bug reports.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fixture {
+    pub render: Vec<AudioDevice>,
+    pub capture: Vec<AudioDevice>,
+    // Defaults to the first device of each kind.
+    pub default_render: Option<String>,
+    pub default_capture: Option<String>,
+}
+
+/// A diagnostics snapshot of a user's machine: the devices as recorded with `--record`, plus the
+/// config they were used with under `config`. Loaded with `--import-snapshot` to reproduce menu
+/// and rotation bugs exactly as reported.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Snapshot {
+    #[serde(flatten)]
+    pub devices: Fixture,
+    #[serde(default)]
+    pub config: Option<Config>,
+}
+
+impl Snapshot {
+    pub fn load(path: &Path) -> Result<Self, SwitcherooError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Writes the snapshot's config (or a blank one, if it has none) to `config_path`, and
+    /// returns a backend serving its devices.
+    pub fn import(self, config_path: &Path) -> Result<SimulatedBackend, SwitcherooError> {
+        let config = self.config.unwrap_or_default();
+        info!(
+            "Importing snapshot with {} device settings to {}",
+            config.devices.len(),
+            config_path.display()
+        );
+        config.save_to(config_path)?;
+        Ok(SimulatedBackend::from_fixture(self.devices))
+    }
+}
+
+/// Serves a fake device list and only logs when asked to change the default device.
+pub struct SimulatedBackend {
+    render: Vec<AudioDevice>,
+    capture: Vec<AudioDevice>,
+    default_render: RefCell<Option<String>>,
+    default_capture: RefCell<Option<String>>,
+    // Devices that haven't been changed are at full volume.
+    volumes: RefCell<HashMap<String, f32>>,
+    muted: RefCell<HashSet<String>>,
+    // Devices that haven't been changed are centred.
+    balances: RefCell<HashMap<String, i32>>,
+    // Effects that have been bypassed or re-enabled since loading.
+    effects: RefCell<HashMap<String, Effects>>,
+}
+
+impl SimulatedBackend {
+    pub fn load(path: &Path) -> Result<Self, SwitcherooError> {
+        let fixture: Fixture = serde_json::from_str(&fs::read_to_string(path)?)?;
+        info!(
+            "Simulating {} output and {} input devices from {}",
+            fixture.render.len(),
+            fixture.capture.len(),
+            path.display()
+        );
+        Ok(Self::from_fixture(fixture))
+    }
+
+    fn check_known(&self, device_id: &str) -> Result<(), SwitcherooError> {
+        if self
+            .render
+            .iter()
+            .chain(&self.capture)
+            .any(|d| d.id == device_id)
+        {
+            Ok(())
+        } else {
+            Err(SwitcherooError::DeviceNotFound(format!(
+                "Unknown simulated device: {device_id}"
+            )))
+        }
+    }
+
+    pub fn from_fixture(fixture: Fixture) -> Self {
+        Self {
+            default_render: RefCell::new(
+                fixture
+                    .default_render
+                    .or_else(|| fixture.render.first().map(|d| d.id.clone())),
+            ),
+            default_capture: RefCell::new(
+                fixture
+                    .default_capture
+                    .or_else(|| fixture.capture.first().map(|d| d.id.clone())),
+            ),
+            render: fixture.render,
+            capture: fixture.capture,
+            volumes: RefCell::default(),
+            muted: RefCell::default(),
+            balances: RefCell::default(),
+            effects: RefCell::default(),
+        }
+    }
+}
+
+/// Speakers and a USB headset, with the headset's microphone and a webcam's, from
+/// `tests/fixtures/headset.json`. For tests, the binary's included, which only see the library as
+/// it's normally built and so can't use anything `cfg(test)`: they get it through the
+/// `test-fixtures` feature instead.
+#[cfg(any(test, feature = "test-fixtures"))]
+#[doc(hidden)]
+pub fn headset_backend() -> SimulatedBackend {
+    SimulatedBackend::from_fixture(
+        serde_json::from_str(include_str!("../tests/fixtures/headset.json"))
+            .expect("The headset fixture is valid"),
+    )
+}
+
+impl AudioBackend for SimulatedBackend {
+    fn devices(&self, flow: EDataFlow) -> Result<Vec<AudioDevice>, SwitcherooError> {
+        let mut devices = if flow == eCapture {
+            self.capture.clone()
+        } else {
+            self.render.clone()
+        };
+        for device in &mut devices {
+            if let Some(&effects) = self.effects.borrow().get(&device.id) {
+                device.effects = effects;
+            }
+        }
+        Ok(devices)
+    }
+
+    fn default_device(&self, flow: EDataFlow, _role: ERole) -> Result<String, SwitcherooError> {
+        let default = if flow == eCapture {
+            &self.default_capture
+        } else {
+            &self.default_render
+        };
+        default
+            .borrow()
+            .clone()
+            .ok_or_else(|| SwitcherooError::Other("No simulated devices".to_string()))
+    }
+
+    fn set_default_device(&self, device_id: &str, role: ERole) -> Result<(), SwitcherooError> {
+        self.check_known(device_id)?;
+        let flow = if self.capture.iter().any(|d| d.id == device_id) {
+            eCapture
+        } else {
+            eRender
+        };
+        info!("Simulated switch to {device_id} for {role:?}");
+        let default = if flow == eCapture {
+            &self.default_capture
+        } else {
+            &self.default_render
+        };
+        *default.borrow_mut() = Some(device_id.to_string());
+        Ok(())
+    }
+
+    fn volume(&self, device_id: &str) -> Result<f32, SwitcherooError> {
+        self.check_known(device_id)?;
+        Ok(self.volumes.borrow().get(device_id).copied().unwrap_or(1.0))
+    }
+
+    fn set_volume(&self, device_id: &str, level: f32) -> Result<(), SwitcherooError> {
+        self.check_known(device_id)?;
+        info!("Simulated volume of {device_id} set to {level}");
+        self.volumes
+            .borrow_mut()
+            .insert(device_id.to_string(), level.clamp(0.0, 1.0));
+        Ok(())
+    }
+
+    fn muted(&self, device_id: &str) -> Result<bool, SwitcherooError> {
+        self.check_known(device_id)?;
+        Ok(self.muted.borrow().contains(device_id))
+    }
+
+    fn set_muted(&self, device_id: &str, muted: bool) -> Result<(), SwitcherooError> {
+        self.check_known(device_id)?;
+        info!("Simulated mute of {device_id} set to {muted}");
+        if muted {
+            self.muted.borrow_mut().insert(device_id.to_string());
+        } else {
+            self.muted.borrow_mut().remove(device_id);
+        }
+        Ok(())
+    }
+
+    fn balance(&self, device_id: &str) -> Result<Option<i32>, SwitcherooError> {
+        self.check_known(device_id)?;
+        Ok(Some(
+            self.balances.borrow().get(device_id).copied().unwrap_or(0),
+        ))
+    }
+
+    fn set_balance(&self, device_id: &str, balance: i32) -> Result<(), SwitcherooError> {
+        self.check_known(device_id)?;
+        info!("Simulated balance of {device_id} set to {balance}");
+        self.balances
+            .borrow_mut()
+            .insert(device_id.to_string(), balance.clamp(-100, 100));
+        Ok(())
+    }
+
+    fn set_effects_bypassed(&self, device_id: &str, bypassed: bool) -> Result<(), SwitcherooError> {
+        self.check_known(device_id)?;
+        info!("Simulated effects of {device_id} bypassed: {bypassed}");
+        let effects = if bypassed {
+            Effects::Bypassed
+        } else {
+            Effects::Enabled
+        };
+        self.effects
+            .borrow_mut()
+            .insert(device_id.to_string(), effects);
+        Ok(())
+    }
+
+    fn sessions(&self, device_id: &str) -> Result<Vec<AudioSession>, SwitcherooError> {
+        // No apps play through simulated devices.
+        self.check_known(device_id)?;
+        Ok(Vec::new())
+    }
+
+    fn check_playback(&self, device_id: &str) -> Result<(), SwitcherooError> {
+        self.check_known(device_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows::Win32::Media::Audio::{eConsole, Headset};
+
+    #[test]
+    fn replays_recorded_devices() {
+        let backend = headset_backend();
+        let render = backend.devices(eRender).unwrap();
+        assert_eq!(render.len(), 2);
+        assert_eq!(render[1].form_factor, Headset);
+        assert!(render[1].container_id.is_some());
+        assert_eq!(backend.devices(eCapture).unwrap().len(), 2);
+        assert_eq!(
+            backend.default_device(eRender, eConsole).unwrap(),
+            "{0.0.0.00000000}.{speakers}"
+        );
+    }
+
+    #[test]
+    fn imports_snapshot_with_its_config() {
+        let snapshot: Snapshot = serde_json::from_str(
+            r#"{
+                "render": [{"id": "{tv}", "friendly_name": "TV", "form_factor": 0}],
+                "config": {"devices": {"{tv}": {"selectable": false}}}
+            }"#,
+        )
+        .unwrap();
+        let config_path =
+            std::env::temp_dir().join(format!("snapshot-config-{}.json", std::process::id()));
+        let backend = snapshot.import(&config_path).unwrap();
+        assert_eq!(backend.default_device(eRender, eConsole).unwrap(), "{tv}");
+        let config = Config::load_from(&config_path).unwrap();
+        assert!(!config.devices["{tv}"].selectable);
+        let _ = fs::remove_file(config_path);
+    }
+
+    #[test]
+    fn switching_updates_the_default() {
+        let backend = headset_backend();
+        backend
+            .set_default_device("{0.0.0.00000000}.{headset}", eConsole)
+            .unwrap();
+        assert_eq!(
+            backend.default_device(eRender, eConsole).unwrap(),
+            "{0.0.0.00000000}.{headset}"
+        );
+        // Capture defaults are tracked separately.
+        assert_eq!(
+            backend.default_device(eCapture, eConsole).unwrap(),
+            "{0.0.1.00000000}.{webcam-mic}"
+        );
+        assert!(backend.set_default_device("unknown", eConsole).is_err());
+    }
+
+    #[test]
+    fn volume_is_tracked_per_device() {
+        let backend = headset_backend();
+        let headset = "{0.0.0.00000000}.{headset}";
+        assert_eq!(backend.volume(headset).unwrap(), 1.0);
+        backend.set_volume(headset, 0.45).unwrap();
+        assert_eq!(backend.volume(headset).unwrap(), 0.45);
+        assert_eq!(backend.volume("{0.0.0.00000000}.{speakers}").unwrap(), 1.0);
+        backend.set_volume(headset, 1.5).unwrap();
+        assert_eq!(backend.volume(headset).unwrap(), 1.0);
+        assert!(backend.volume("unknown").is_err());
+    }
+
+    #[test]
+    fn mute_is_tracked_per_device() {
+        let backend = headset_backend();
+        let headset = "{0.0.0.00000000}.{headset}";
+        backend.set_muted(headset, true).unwrap();
+        assert!(backend.muted(headset).unwrap());
+        assert!(!backend.muted("{0.0.0.00000000}.{speakers}").unwrap());
+        backend.set_muted(headset, false).unwrap();
+        assert!(!backend.muted(headset).unwrap());
+        assert!(backend.set_muted("unknown", true).is_err());
+    }
+
+    #[test]
+    fn effects_can_be_bypassed() {
+        let backend = headset_backend();
+        let headset = "{0.0.0.00000000}.{headset}";
+        backend.set_effects_bypassed(headset, true).unwrap();
+        let render = backend.devices(eRender).unwrap();
+        assert_eq!(render[1].effects, Effects::Bypassed);
+        assert_eq!(render[0].effects, Effects::None);
+        backend.set_effects_bypassed(headset, false).unwrap();
+        assert_eq!(
+            backend.devices(eRender).unwrap()[1].effects,
+            Effects::Enabled
+        );
+        assert!(backend.set_effects_bypassed("unknown", true).is_err());
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let fixture: Fixture =
+            serde_json::from_str(include_str!("../tests/fixtures/headset.json")).unwrap();
+        let json = serde_json::to_string(&fixture).unwrap();
+        let reloaded: Fixture = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            reloaded.render[1].container_id,
+            fixture.render[1].container_id
+        );
+        assert_eq!(
+            reloaded.render[1].form_factor,
+            fixture.render[1].form_factor
+        );
+    }
+}
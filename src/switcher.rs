@@ -0,0 +1,434 @@
+use log::{debug, error, info};
+use std::time::{Duration, Instant, SystemTime};
+use windows::Win32::Media::Audio::{eConsole, eRender};
+
+use crate::actions::{Action, RateLimiter, MIN_SWITCH_INTERVAL};
+use crate::backend::{AudioBackend, AudioDevice};
+use crate::bail;
+use crate::config::Config;
+use crate::endpoints::{renamed_devices, set_default_device, switch_microphone_to_sibling};
+use crate::error::SwitcherooError;
+use crate::rotation::{self, RotationPolicy};
+use crate::voicemeeter;
+
+/// The outputs to switch between, and what switching needs to remember: the output switched away
+/// from, the one Voicemeeter plays through, and when the last action was let through.
+pub struct Switcher {
+    backend: Box<dyn AudioBackend>,
+    outputs: Vec<AudioDevice>,
+    // The output used before the current one, for swapping back to it.
+    previous_output: Option<String>,
+    // The output Voicemeeter was last pointed at, when switching through it.
+    voicemeeter_output: Option<String>,
+    limiter: RateLimiter,
+    // Set while carrying out an action, so the ones it sets off itself aren't rate limited.
+    dispatching: bool,
+}
+
+impl Switcher {
+    /// Switches between `outputs`, which should already have the config applied to them.
+    pub fn new(backend: Box<dyn AudioBackend>, outputs: Vec<AudioDevice>) -> Self {
+        Self {
+            backend,
+            outputs,
+            previous_output: None,
+            voicemeeter_output: None,
+            limiter: RateLimiter::new(MIN_SWITCH_INTERVAL),
+            dispatching: false,
+        }
+    }
+
+    pub fn backend(&self) -> &dyn AudioBackend {
+        self.backend.as_ref()
+    }
+
+    /// The connected outputs, as last listed.
+    pub fn outputs(&self) -> &[AudioDevice] {
+        &self.outputs
+    }
+
+    pub fn outputs_mut(&mut self) -> &mut Vec<AudioDevice> {
+        &mut self.outputs
+    }
+
+    pub fn previous_output(&self) -> Option<&str> {
+        self.previous_output.as_deref()
+    }
+
+    /// Lists the outputs again, applying `config` to them and adding the new ones to it.
+    /// `after_outage` is whether the outputs couldn't be listed before, as then none of them count
+    /// as connected.
+    pub fn refresh(
+        &mut self,
+        config: &mut Config,
+        after_outage: bool,
+    ) -> Result<Refresh, SwitcherooError> {
+        let mut devices = self.backend.devices(eRender)?;
+        self.backend.read_mix_formats(&mut devices);
+        let known_devices = config.devices.len();
+        config.apply_to_devices(&mut devices);
+        debug!("Refreshed devices: {} outputs", devices.len());
+        let renamed = renamed_devices(&self.outputs, &devices);
+        for (old_name, new_name) in &renamed {
+            info!("{old_name} was renamed to {new_name}");
+        }
+        let (connected, disconnected) = if after_outage {
+            (Vec::new(), false)
+        } else {
+            (
+                devices
+                    .iter()
+                    .filter(|device| !self.outputs.iter().any(|d| d.id == device.id))
+                    .map(|device| device.id.clone())
+                    .collect(),
+                self.outputs
+                    .iter()
+                    .any(|device| !devices.iter().any(|d| d.id == device.id)),
+            )
+        };
+        self.outputs = devices;
+        Ok(Refresh {
+            renamed,
+            connected,
+            disconnected,
+            new_devices: config.devices.len() > known_devices,
+        })
+    }
+}
+
+/// What changed when the outputs were listed again.
+#[derive(Debug, Default)]
+pub struct Refresh {
+    /// The (old, new) names of the outputs that were renamed.
+    pub renamed: Vec<(String, String)>,
+    /// The IDs of the outputs that weren't there before.
+    pub connected: Vec<String>,
+    /// Whether any output that was there before has gone.
+    pub disconnected: bool,
+    /// Whether the config gained devices it hadn't seen before.
+    pub new_devices: bool,
+}
+
+impl Refresh {
+    pub fn outputs_changed(&self) -> bool {
+        !self.connected.is_empty() || self.disconnected
+    }
+}
+
+/// The app a `Switcher` switches for. It keeps the switcher and the config, and carries out the
+/// actions the rate limiter lets through; the rotation, swapping back and switching, including
+/// remembering volumes and going through Voicemeeter, come with it.
+pub trait SwitchHost {
+    fn switcher(&self) -> &Switcher;
+    fn switcher_mut(&mut self) -> &mut Switcher;
+    fn config(&self) -> &Config;
+    fn config_mut(&mut self) -> &mut Config;
+
+    /// Carries out an action the rate limiter let through.
+    fn carry_out(&mut self, action: Action) -> Result<(), SwitcherooError>;
+
+    /// Called once `device` is the output in use, to show it and keep track of it. `elapsed` is how
+    /// long making it the default took, or `None` if Voicemeeter was pointed at it instead.
+    fn switched(
+        &mut self,
+        device: &AudioDevice,
+        elapsed: Option<Duration>,
+    ) -> Result<(), SwitcherooError>;
+
+    /// The output the app in the foreground has its audio sent to, if only its audio is switched
+    /// and it's been given one.
+    fn focused_app_output(&self) -> Option<String> {
+        None
+    }
+
+    /// Switches to an output the user picked from the menu, a hotkey or the rotation. Apps that
+    /// can switch only the app in the foreground do that here.
+    fn switch_chosen(&mut self, device: &AudioDevice) -> Result<(), SwitcherooError> {
+        self.switch_to(device)
+    }
+
+    /// The highest volume, in percent, a remembered volume is restored to on `device_id`.
+    fn volume_limit(&self, _device_id: &str) -> u32 {
+        100
+    }
+
+    /// Carries out an action, unless it comes too soon after the last one.
+    fn dispatch(&mut self, action: Action) -> Result<(), SwitcherooError> {
+        self.dispatch_limited(action).map(|_| ())
+    }
+
+    /// Carries out an action, failing if it comes too soon after the last one, for callers that
+    /// report what they did.
+    fn try_dispatch(&mut self, action: Action) -> Result<(), SwitcherooError> {
+        if !self.dispatch_limited(action)? {
+            bail!("Too soon after the last switch");
+        }
+        Ok(())
+    }
+
+    /// Carries out an action, returning whether it was rather than dropped by the rate limiter.
+    /// Actions set off by the one being carried out, such as a profile's switches, always go ahead.
+    fn dispatch_limited(&mut self, action: Action) -> Result<bool, SwitcherooError> {
+        let switcher = self.switcher_mut();
+        if !switcher.dispatching && !switcher.limiter.allow(Instant::now()) {
+            debug!("Ignoring {action:?}, too soon after the last switch");
+            return Ok(false);
+        }
+        self.dispatch_unlimited(action).map(|_| true)
+    }
+
+    /// Carries out an action whatever the rate limiter says, for the ones the app sets off itself
+    /// that mustn't be lost to a click just before, such as profiles applied at startup or on
+    /// resume.
+    fn dispatch_unlimited(&mut self, action: Action) -> Result<(), SwitcherooError> {
+        let nested = std::mem::replace(&mut self.switcher_mut().dispatching, true);
+        let result = self.carry_out(action);
+        self.switcher_mut().dispatching = nested;
+        result
+    }
+
+    /// The output device in use: the default device, unless switching through Voicemeeter, where
+    /// it's the one Voicemeeter was last pointed at.
+    fn current_output(&self) -> Result<String, SwitcherooError> {
+        if self.config().voicemeeter.enabled
+            && let Some(device_id) = &self.switcher().voicemeeter_output
+        {
+            return Ok(device_id.clone());
+        }
+        self.switcher().backend.default_device(eRender, eConsole)
+    }
+
+    /// The connected output with this ID.
+    fn output_with_id(&self, device_id: &str) -> Result<AudioDevice, SwitcherooError> {
+        match self.switcher().outputs.iter().find(|d| d.id == device_id) {
+            Some(device) => Ok(device.clone()),
+            None => bail!("No such device: {device_id}"),
+        }
+    }
+
+    /// The devices the Include toggles leave in the menu and rotation, always including the
+    /// current one so it's clear what's playing.
+    fn included_devices(&self, current_device_id: &str) -> Vec<AudioDevice> {
+        self.switcher()
+            .outputs
+            .iter()
+            .filter(|device| device.id == current_device_id || !self.config().excludes(device))
+            .cloned()
+            .collect()
+    }
+
+    /// Switches to the next output in the rotation, from the focused app's if only its audio is
+    /// switched.
+    fn next_device(&mut self, policy: RotationPolicy) -> Result<(), SwitcherooError> {
+        let current_device = match self.focused_app_output() {
+            Some(device_id) => device_id,
+            None => self.current_output()?,
+        };
+        debug!("Switching to next device from: {current_device}");
+        let Some(cand_device) = rotation::select_next(
+            &current_device,
+            &self.included_devices(&current_device),
+            &policy,
+        )
+        .cloned() else {
+            debug!("No other selectable devices found");
+            return Ok(());
+        };
+        self.switch_chosen(&cand_device)
+    }
+
+    /// Switches back to the output used before the current one.
+    fn swap_devices(&mut self) -> Result<(), SwitcherooError> {
+        let switcher = self.switcher();
+        let Some(previous) = switcher
+            .previous_output
+            .as_ref()
+            .and_then(|previous| switcher.outputs.iter().find(|d| &d.id == previous).cloned())
+        else {
+            debug!("No previous device to swap to");
+            return Ok(());
+        };
+        self.switch_to(&previous)
+    }
+
+    /// Makes the connected device highest in the priority list the default, if it isn't already.
+    fn switch_to_highest_priority(&mut self) -> Result<(), SwitcherooError> {
+        let Some(device) = self
+            .config()
+            .highest_priority(&self.switcher().outputs)
+            .cloned()
+        else {
+            return Ok(());
+        };
+        if self.current_output()? == device.id {
+            return Ok(());
+        }
+        info!(
+            "{} is the highest priority device connected",
+            device.friendly_name
+        );
+        self.dispatch(Action::SwitchTo(device.id))
+    }
+
+    /// Makes `device` the output in use, through Voicemeeter if it's set up, remembering the
+    /// volume of the one switched away from and restoring the new one's.
+    fn switch_to(&mut self, device: &AudioDevice) -> Result<(), SwitcherooError> {
+        info!("Switching to device: {:}", device.friendly_name);
+        if let Ok(current) = self.current_output()
+            && current != device.id
+        {
+            if self.config().remember_volumes
+                && let Ok(volume) = self.switcher().backend.volume(&current)
+            {
+                self.config_mut()
+                    .remember_volume(&current, (volume * 100.0).round() as u32);
+            }
+            self.switcher_mut().previous_output = Some(current);
+        }
+        let roles = self.config().roles();
+        let through_voicemeeter = self.config().voicemeeter.enabled
+            && !self.config().looks_virtual(&device.friendly_name)
+            // The default device stays on Voicemeeter, which plays through this one instead.
+            && match voicemeeter::Remote::connect().and_then(|remote| {
+                remote.set_hardware_out(self.config().voicemeeter.bus, &device.friendly_name)
+            }) {
+                Ok(()) => {
+                    self.switcher_mut().voicemeeter_output = Some(device.id.clone());
+                    true
+                }
+                Err(e) => {
+                    error!("Switching the default device, as Voicemeeter can't be used: {e}");
+                    self.switcher_mut().voicemeeter_output = None;
+                    false
+                }
+            };
+        let elapsed = if through_voicemeeter {
+            None
+        } else {
+            let started = Instant::now();
+            set_default_device(self.switcher().backend(), &device.id, &roles)?;
+            Some(started.elapsed())
+        };
+        if let Some(percent) = self.config().remembered_volume(&device.id) {
+            let percent = percent.min(self.volume_limit(&device.id));
+            debug!("Restoring {}'s volume to {percent}%", device.friendly_name);
+            if let Err(e) = self
+                .switcher()
+                .backend
+                .set_volume(&device.id, percent as f32 / 100.0)
+            {
+                error!("Failed to restore volume: {e}");
+            }
+        }
+        if let Some(balance) = self
+            .config()
+            .devices
+            .get(&device.id)
+            .and_then(|device_config| device_config.balance)
+            && let Err(e) = self.switcher().backend.set_balance(&device.id, balance)
+        {
+            error!("Failed to restore balance: {e}");
+        }
+        // Kept for pausing unused devices, which needs it saved, and for guessing which device to
+        // keep warm.
+        let config = self.config();
+        if config.pause_unused_after_days.is_some() || config.warm_standby {
+            self.config_mut().record_use(&device.id, SystemTime::now());
+        }
+        if self.config().switch_microphone_with_output
+            && let Err(e) = switch_microphone_to_sibling(self.switcher().backend(), device, &roles)
+        {
+            error!("Failed to switch microphone: {e}");
+        }
+        self.switched(device, elapsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::headset_backend;
+
+    // Switches on a simulated backend, and does nothing else.
+    struct Host {
+        switcher: Switcher,
+        config: Config,
+        carried_out: Vec<Action>,
+    }
+
+    impl Host {
+        fn new(mut config: Config) -> Self {
+            let backend = headset_backend();
+            let mut devices = backend.devices(eRender).unwrap();
+            config.apply_to_devices(&mut devices);
+            Self {
+                switcher: Switcher::new(Box::new(backend), devices),
+                config,
+                carried_out: Vec::new(),
+            }
+        }
+    }
+
+    impl SwitchHost for Host {
+        fn switcher(&self) -> &Switcher {
+            &self.switcher
+        }
+
+        fn switcher_mut(&mut self) -> &mut Switcher {
+            &mut self.switcher
+        }
+
+        fn config(&self) -> &Config {
+            &self.config
+        }
+
+        fn config_mut(&mut self) -> &mut Config {
+            &mut self.config
+        }
+
+        fn carry_out(&mut self, action: Action) -> Result<(), SwitcherooError> {
+            self.carried_out.push(action);
+            Ok(())
+        }
+
+        fn switched(
+            &mut self,
+            _: &AudioDevice,
+            _: Option<Duration>,
+        ) -> Result<(), SwitcherooError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn next_device_switches_the_backend() {
+        let mut host = Host::new(Config::default());
+        host.next_device(RotationPolicy::default()).unwrap();
+        assert_eq!(host.current_output().unwrap(), "{0.0.0.00000000}.{headset}");
+        assert_eq!(
+            host.switcher.previous_output(),
+            Some("{0.0.0.00000000}.{speakers}")
+        );
+        // Once the speakers are out of the rotation, there's nothing else to switch to.
+        host.config
+            .devices
+            .get_mut("{0.0.0.00000000}.{speakers}")
+            .unwrap()
+            .selectable = false;
+        host.config.apply_to_devices(host.switcher.outputs_mut());
+        host.next_device(RotationPolicy::default()).unwrap();
+        assert_eq!(host.current_output().unwrap(), "{0.0.0.00000000}.{headset}");
+    }
+
+    #[test]
+    fn dispatches_are_rate_limited() {
+        let mut host = Host::new(Config::default());
+        host.dispatch(Action::NextDevice).unwrap();
+        assert!(host.try_dispatch(Action::SwapDevices).is_err());
+        host.dispatch_unlimited(Action::NextMicrophone).unwrap();
+        assert_eq!(
+            host.carried_out,
+            [Action::NextDevice, Action::NextMicrophone]
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use windows::Win32::Globalization::{GetLocaleInfoEx, LOCALE_SDECIMAL};
+use windows_core::PCWSTR;
+
+/// The tooltip when the config doesn't say otherwise: just the device's name.
+pub const DEFAULT_TEMPLATE: &str = "{name}";
+
+/// What the placeholders in a tooltip template stand for.
+#[derive(Debug, Clone, Default)]
+pub struct TooltipValues<'a> {
+    pub name: &'a str,
+    // Percent, if it could be read.
+    pub volume: Option<u32>,
+    // The shared mode sample rate in Hz, if it could be read.
+    pub sample_rate: Option<u32>,
+}
+
+/// Whether `template` uses `placeholder` (given without the braces), so it's only looked up when
+/// needed.
+pub fn uses(template: &str, placeholder: &str) -> bool {
+    template.contains(&format!("{{{placeholder}}}"))
+}
+
+/// Fills in `{name}`, `{volume}` (a percentage, without the `%`) and `{rate}` (in kHz) in
+/// `template`, writing decimals with `decimal_separator`. Values that couldn't be read show as
+/// "?", and anything else in braces is left as it is.
+pub fn format(template: &str, values: &TooltipValues, decimal_separator: &str) -> String {
+    let unknown = || "?".to_string();
+    let rate = values.sample_rate.map_or_else(unknown, |rate| {
+        let khz = format!("{}", rate as f64 / 1000.0);
+        khz.replace('.', decimal_separator)
+    });
+    let volume = values
+        .volume
+        .map_or_else(unknown, |volume| volume.to_string());
+    // In one pass, so braces in a device's name aren't taken for placeholders.
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = [
+            ("{name}", values.name),
+            ("{volume}", &volume),
+            ("{rate}", &rate),
+        ]
+        .into_iter()
+        .find(|(placeholder, _)| rest.starts_with(placeholder));
+        match value {
+            Some((placeholder, value)) => {
+                text.push_str(value);
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                text.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+/// The user's decimal separator from their regional settings, or "." if it can't be read.
+pub fn decimal_separator() -> String {
+    let mut buffer = [0u16; 8];
+    let length = unsafe { GetLocaleInfoEx(PCWSTR::null(), LOCALE_SDECIMAL, Some(&mut buffer)) };
+    match length {
+        // Includes the null.
+        2.. => String::from_utf16_lossy(&buffer[..length as usize - 1]),
+        _ => ".".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADSET: TooltipValues = TooltipValues {
+        name: "Headset Earphone",
+        volume: Some(45),
+        sample_rate: Some(44_100),
+    };
+
+    #[test]
+    fn fills_in_placeholders() {
+        assert_eq!(format(DEFAULT_TEMPLATE, &HEADSET, "."), "Headset Earphone");
+        assert_eq!(
+            format("{name} · {volume}% · {rate}kHz", &HEADSET, "."),
+            "Headset Earphone · 45% · 44.1kHz"
+        );
+        assert_eq!(
+            format(
+                "{rate} kHz",
+                &TooltipValues {
+                    sample_rate: Some(48_000),
+                    ..HEADSET
+                },
+                ","
+            ),
+            "48 kHz"
+        );
+        assert_eq!(format("{rate} kHz", &HEADSET, ","), "44,1 kHz");
+    }
+
+    #[test]
+    fn shows_what_cant_be_read() {
+        let values = TooltipValues {
+            name: "Speakers",
+            ..Default::default()
+        };
+        assert_eq!(
+            format("{name}: {volume}% {unknown}", &values, "."),
+            "Speakers: ?% {unknown}"
+        );
+        assert!(uses("{name} {rate}", "rate"));
+        assert!(!uses("{name} rate", "rate"));
+    }
+
+    #[test]
+    fn leaves_placeholders_in_values_alone() {
+        let values = TooltipValues {
+            name: "Speakers {volume}",
+            ..Default::default()
+        };
+        assert_eq!(
+            format("{{name}} {name} {volume}", &values, "."),
+            "{Speakers {volume}} Speakers {volume} ?"
+        );
+    }
+}
@@ -0,0 +1,131 @@
+use log::{debug, warn};
+use windows::Win32::Foundation::HWND;
+use windows::Win32::UI::Shell::{
+    Shell_NotifyIconW, NIF_GUID, NIM_ADD, NIM_DELETE, NOTIFYICONDATAW, NOTIFYICONIDENTIFIER,
+    NOTIFY_ICON_DATA_FLAGS,
+};
+use windows_core::GUID;
+
+use sound_switcheroo::error::SwitcherooError;
+
+// The ID the icon gets when its GUID can't be used. Only has to be unique to the window.
+const FALLBACK_UID: u32 = 1;
+
+/// How the shell knows the tray icon: by the instance's GUID, so Windows remembers whether it's
+/// shown or hidden, or else by an ID that's only unique to the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrayIconId {
+    Guid(GUID),
+    Uid(u32),
+}
+
+impl TrayIconId {
+    /// The start of a `NOTIFYICONDATAW` for the icon, to be given the rest of its fields. Any
+    /// `uFlags` set must include `flags()`.
+    pub fn data(self, window: HWND) -> NOTIFYICONDATAW {
+        let mut data = NOTIFYICONDATAW {
+            cbSize: std::mem::size_of::<NOTIFYICONDATAW>() as u32,
+            hWnd: window,
+            uFlags: self.flags(),
+            ..Default::default()
+        };
+        match self {
+            Self::Guid(guid) => data.guidItem = guid,
+            Self::Uid(uid) => data.uID = uid,
+        }
+        data
+    }
+
+    /// The flags saying how the icon is identified.
+    pub fn flags(self) -> NOTIFY_ICON_DATA_FLAGS {
+        match self {
+            Self::Guid(_) => NIF_GUID,
+            Self::Uid(_) => NOTIFY_ICON_DATA_FLAGS(0),
+        }
+    }
+
+    pub fn identifier(self, window: HWND) -> NOTIFYICONIDENTIFIER {
+        let mut identifier = NOTIFYICONIDENTIFIER {
+            cbSize: std::mem::size_of::<NOTIFYICONIDENTIFIER>() as u32,
+            hWnd: window,
+            ..Default::default()
+        };
+        match self {
+            Self::Guid(guid) => identifier.guidItem = guid,
+            Self::Uid(uid) => identifier.uID = uid,
+        }
+        identifier
+    }
+}
+
+/// Adds the icon described by `data` (apart from how it's identified) to the tray under `guid`.
+///
+/// A crash can leave an icon with the same GUID behind, which makes adding it fail, so that one is
+/// removed and adding tried again. If that fails too, the GUID belongs to another app (Windows ties
+/// it to the path of the executable that first used it, so it happens to a copy run from
+/// elsewhere), and the icon is added without it.
+pub fn add(data: &NOTIFYICONDATAW, guid: GUID) -> Result<TrayIconId, SwitcherooError> {
+    let add = |id: TrayIconId| add_as(data, id);
+    let id = TrayIconId::Guid(guid);
+    let Err(e) = add(id) else {
+        return Ok(id);
+    };
+    warn!("Failed to add the tray icon, removing any left over from before: {e}");
+    let removed = unsafe { Shell_NotifyIconW(NIM_DELETE, &id.data(data.hWnd)).as_bool() };
+    debug!("Left over icon removed: {removed}");
+    let Err(e) = add(id) else {
+        return Ok(id);
+    };
+    warn!("The tray icon's GUID seems to belong to another app, so adding it without: {e}");
+    let id = TrayIconId::Uid(FALLBACK_UID);
+    match add(id) {
+        Ok(()) => Ok(id),
+        Err(e) => Err(SwitcherooError::Tray(format!(
+            "Couldn't add the icon to the notification area: {e}"
+        ))),
+    }
+}
+
+/// Adds the icon again as `id`, which `add` returned, after the taskbar was recreated without it
+/// (as happens when Explorer restarts).
+pub fn restore(data: &NOTIFYICONDATAW, id: TrayIconId) -> Result<(), SwitcherooError> {
+    add_as(data, id).map_err(|e| {
+        SwitcherooError::Tray(format!(
+            "Couldn't add the icon back to the notification area: {e}"
+        ))
+    })
+}
+
+fn add_as(data: &NOTIFYICONDATAW, id: TrayIconId) -> windows_core::Result<()> {
+    unsafe {
+        let identified = id.data(data.hWnd);
+        Shell_NotifyIconW(
+            NIM_ADD,
+            &NOTIFYICONDATAW {
+                uFlags: data.uFlags | id.flags(),
+                guidItem: identified.guidItem,
+                uID: identified.uID,
+                ..*data
+            },
+        )
+        .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows::Win32::UI::Shell::NIF_TIP;
+
+    #[test]
+    fn only_guid_icons_are_flagged_as_such() {
+        let guid = GUID::from_u128(0x1234);
+        let data = TrayIconId::Guid(guid).data(HWND::default());
+        assert_eq!(data.guidItem, guid);
+        assert_eq!(data.uFlags, NIF_GUID);
+        let data = TrayIconId::Uid(FALLBACK_UID).data(HWND::default());
+        assert_eq!(data.uID, FALLBACK_UID);
+        assert_eq!(data.guidItem, GUID::zeroed());
+        assert_eq!(NIF_TIP | TrayIconId::Uid(1).flags(), NIF_TIP);
+    }
+}
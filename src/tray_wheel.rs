@@ -0,0 +1,139 @@
+use log::debug;
+use std::sync::{Mutex, PoisonError};
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, POINT, RECT, WPARAM};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::Shell::Shell_NotifyIconGetRect;
+use windows::Win32::UI::WindowsAndMessaging::{
+    CallNextHookEx, PostMessageW, SetWindowsHookExW, UnhookWindowsHookEx, HC_ACTION, HHOOK,
+    MSLLHOOKSTRUCT, WHEEL_DELTA, WH_MOUSE_LL, WM_APP, WM_MOUSEMOVE, WM_MOUSEWHEEL,
+};
+
+use crate::tray_icon::TrayIconId;
+use sound_switcheroo::error::SwitcherooError;
+
+/// Posted to the window when the wheel turns over the tray icon, with WPARAM the distance turned
+/// as an `i16` (`WHEEL_DELTA` per notch, positive away from the user).
+pub const WM_TRAY_WHEEL: u32 = WM_APP + 0x50;
+
+// What the hook needs, as hook procedures can't be given any state of their own.
+struct Watch {
+    window: isize,
+    hook: isize,
+    // Where the icon is on the screen.
+    icon: RECT,
+}
+
+static WATCH: Mutex<Option<Watch>> = Mutex::new(None);
+
+/// Watches the wheel while the cursor is over the tray icon identified by `window` and `icon`, as
+/// the icon itself is never sent wheel messages. Meant to be called when the cursor moves onto the
+/// icon; watching stops once it moves off again. Does nothing if already watching.
+pub fn watch(window: HWND, icon: TrayIconId) -> Result<(), SwitcherooError> {
+    let mut watch = WATCH.lock().unwrap_or_else(PoisonError::into_inner);
+    if watch.is_some() {
+        return Ok(());
+    }
+    unsafe {
+        let icon = Shell_NotifyIconGetRect(&icon.identifier(window))?;
+        let hook = SetWindowsHookExW(
+            WH_MOUSE_LL,
+            Some(mouse_hook),
+            Some(GetModuleHandleW(None)?.into()),
+            0,
+        )?;
+        debug!("Watching the wheel over the tray icon at {icon:?}");
+        *watch = Some(Watch {
+            window: window.0 as isize,
+            hook: hook.0 as isize,
+            icon,
+        });
+    }
+    Ok(())
+}
+
+/// Stops watching the wheel, e.g. when the tray icon goes away.
+pub fn stop() {
+    let watch = WATCH.lock().unwrap_or_else(PoisonError::into_inner).take();
+    if let Some(watch) = watch {
+        unsafe {
+            let _ = UnhookWindowsHookEx(HHOOK(watch.hook as *mut _));
+        }
+    }
+}
+
+/// Turns the distances in `WM_TRAY_WHEEL` into whole notches. Smooth-scrolling wheels and
+/// touchpads send less than a notch at a time, which is kept until it adds up to one.
+#[derive(Debug, Default)]
+pub struct Notches {
+    // Turned but not yet counted, less than a notch either way.
+    remainder: i32,
+}
+
+impl Notches {
+    /// Adds `delta` to what's been turned, returning how many whole notches that makes.
+    pub fn add(&mut self, delta: i16) -> i32 {
+        let delta = delta as i32;
+        // Turning back the other way starts again.
+        if delta.signum() == -self.remainder.signum() {
+            self.remainder = 0;
+        }
+        let notch = WHEEL_DELTA as i32;
+        let turned = self.remainder + delta;
+        self.remainder = turned % notch;
+        turned / notch
+    }
+}
+
+fn contains(rect: &RECT, point: POINT) -> bool {
+    (rect.left..rect.right).contains(&point.x) && (rect.top..rect.bottom).contains(&point.y)
+}
+
+unsafe extern "system" fn mouse_hook(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    unsafe {
+        if code == HC_ACTION as i32 {
+            let event = &*(lparam.0 as *const MSLLHOOKSTRUCT);
+            let watched = WATCH
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .as_ref()
+                .map(|watch| (watch.window, contains(&watch.icon, event.pt)));
+            match (wparam.0 as u32, watched) {
+                (WM_MOUSEWHEEL, Some((window, true))) => {
+                    let delta = (event.mouseData >> 16) as u16;
+                    let _ = PostMessageW(
+                        Some(HWND(window as *mut _)),
+                        WM_TRAY_WHEEL,
+                        WPARAM(delta as usize),
+                        LPARAM(0),
+                    );
+                    // Taken, so whatever is under the taskbar doesn't scroll as well.
+                    return LRESULT(1);
+                }
+                (WM_MOUSEMOVE, Some((_, false))) => stop(),
+                _ => {}
+            }
+        }
+        CallNextHookEx(None, code, wparam, lparam)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_partial_notches_up() {
+        let mut notches = Notches::default();
+        assert_eq!(notches.add(120), 1);
+        assert_eq!(notches.add(-240), -2);
+        assert_eq!(notches.add(0), 0);
+        assert_eq!(notches.add(30), 0);
+        assert_eq!(notches.add(60), 0);
+        assert_eq!(notches.add(150), 2);
+        assert_eq!(notches.add(-20), 0);
+        // What was turned the other way is dropped.
+        assert_eq!(notches.add(100), 0);
+        assert_eq!(notches.add(-90), 0);
+        assert_eq!(notches.add(-30), -1);
+    }
+}
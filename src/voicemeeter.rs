@@ -0,0 +1,122 @@
+use log::{debug, info};
+use std::ffi::{c_char, CString};
+use std::path::{Path, PathBuf};
+use windows::Win32::Foundation::{FreeLibrary, HMODULE};
+use windows::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryW};
+use windows_strings::s;
+
+use crate::bail;
+use crate::error::SwitcherooError;
+use crate::safe_strings::with_wide_str;
+
+// Where Voicemeeter's (32-bit) installer records the install directory.
+const UNINSTALL_KEYS: [&str; 2] = [
+    r"Software\WOW6432Node\Microsoft\Windows\CurrentVersion\Uninstall\VB:Voicemeeter {17359A74-1236-5467}",
+    r"Software\Microsoft\Windows\CurrentVersion\Uninstall\VB:Voicemeeter {17359A74-1236-5467}",
+];
+
+// What GetProcAddress returns, whatever the function's real signature.
+type Proc = unsafe extern "system" fn() -> isize;
+type Login = unsafe extern "system" fn() -> i32;
+type Logout = unsafe extern "system" fn() -> i32;
+// The parameter's name is ANSI, and its value UTF-16.
+type SetParameterString = unsafe extern "system" fn(*const c_char, *const u16) -> i32;
+
+/// Whether an endpoint is one of Voicemeeter's virtual inputs or outputs.
+pub fn is_voicemeeter(friendly_name: &str) -> bool {
+    friendly_name.to_lowercase().contains("voicemeeter")
+}
+
+/// Voicemeeter's remote API, loaded from its install directory and logged in to until dropped.
+pub struct Remote {
+    module: HMODULE,
+    logout: Logout,
+    set_parameter_string: SetParameterString,
+}
+
+impl Remote {
+    pub fn connect() -> Result<Self, SwitcherooError> {
+        let dll = install_dir()?.join(if cfg!(target_pointer_width = "64") {
+            "VoicemeeterRemote64.dll"
+        } else {
+            "VoicemeeterRemote.dll"
+        });
+        debug!("Loading {}", dll.display());
+        unsafe {
+            let module = with_wide_str(&dll.to_string_lossy(), |dll| LoadLibraryW(dll))?;
+            let (Some(login), Some(logout), Some(set_parameter_string)) = (
+                GetProcAddress(module, s!("VBVMR_Login")),
+                GetProcAddress(module, s!("VBVMR_Logout")),
+                GetProcAddress(module, s!("VBVMR_SetParameterStringW")),
+            ) else {
+                let _ = FreeLibrary(module);
+                bail!("{} isn't Voicemeeter's remote API", dll.display());
+            };
+            let remote = Self {
+                module,
+                logout: std::mem::transmute::<Proc, Logout>(logout),
+                set_parameter_string: std::mem::transmute::<Proc, SetParameterString>(
+                    set_parameter_string,
+                ),
+            };
+            // 1 means Voicemeeter isn't running, which leaves nothing to re-target.
+            match std::mem::transmute::<Proc, Login>(login)() {
+                0 => Ok(remote),
+                1 => bail!("Voicemeeter isn't running"),
+                code => bail!("Failed to connect to Voicemeeter ({code})"),
+            }
+        }
+    }
+
+    /// Makes a Windows output device, by name, the hardware out of `bus` (0 for A1).
+    pub fn set_hardware_out(&self, bus: u32, device_name: &str) -> Result<(), SwitcherooError> {
+        info!("Setting Voicemeeter's A{} to {device_name}", bus + 1);
+        let parameter = CString::new(format!("Bus[{bus}].device.wdm"))?;
+        let result = with_wide_str(device_name, |value| unsafe {
+            (self.set_parameter_string)(parameter.as_ptr(), value.as_ptr())
+        });
+        if result != 0 {
+            bail!("Voicemeeter refused to use {device_name} ({result})");
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Remote {
+    fn drop(&mut self) {
+        unsafe {
+            (self.logout)();
+            let _ = FreeLibrary(self.module);
+        }
+    }
+}
+
+fn install_dir() -> Result<PathBuf, SwitcherooError> {
+    for key in UNINSTALL_KEYS {
+        if let Ok(uninstaller) = windows_registry::LOCAL_MACHINE
+            .open(key)
+            .and_then(|key| key.get_string("UninstallString"))
+            && let Some(dir) = Path::new(uninstaller.trim_matches('"')).parent()
+        {
+            return Ok(dir.to_path_buf());
+        }
+    }
+    bail!("Voicemeeter isn't installed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_voicemeeter_endpoints() {
+        assert!(is_voicemeeter(
+            "VoiceMeeter Input (VB-Audio VoiceMeeter VAIO)"
+        ));
+        assert!(is_voicemeeter(
+            "Voicemeeter Out B1 (VB-Audio Voicemeeter VAIO)"
+        ));
+        assert!(!is_voicemeeter("CABLE Input (VB-Audio Virtual Cable)"));
+        assert!(!is_voicemeeter("Speakers (Realtek(R) Audio)"));
+    }
+}
@@ -0,0 +1,281 @@
+use log::debug;
+use std::sync::Once;
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::Controls::{
+    InitCommonControlsEx, ICC_BAR_CLASSES, INITCOMMONCONTROLSEX, TBM_SETPAGESIZE, TBM_SETPOS,
+    TBM_SETRANGE, TBM_SETTIC, TBS_HORZ, TBS_NOTICKS, TB_ENDTRACK, TRACKBAR_CLASSW,
+};
+use windows::Win32::UI::Input::KeyboardAndMouse::SetFocus;
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, DestroyWindow, GetDlgCtrlID, GetDlgItem, GetWindow,
+    RegisterClassExW, SendMessageW, SetForegroundWindow, SetWindowPos, ShowWindow, GW_OWNER, HMENU,
+    HTCAPTION, HTCLIENT, SWP_NOACTIVATE, SWP_NOMOVE, SWP_NOZORDER, SW_SHOW, WA_INACTIVE,
+    WINDOW_STYLE, WM_ACTIVATE, WM_APP, WM_DPICHANGED, WM_EXITSIZEMOVE, WM_HSCROLL, WM_NCHITTEST,
+    WM_USER, WNDCLASSEXW, WS_BORDER, WS_CHILD, WS_EX_TOOLWINDOW, WS_EX_TOPMOST, WS_POPUP,
+    WS_VISIBLE,
+};
+use windows_strings::w;
+
+use crate::dpi;
+use sound_switcheroo::error::SwitcherooError;
+use sound_switcheroo::window_placement::{self, WindowPlacement};
+
+/// Sent to the popup's owner whenever the slider moves, with the new volume percentage in WPARAM.
+pub const WM_VOLUME_CHANGED: u32 = WM_APP + 0x44;
+/// Sent to the popup's owner after the popup has been dragged somewhere, with its HWND in WPARAM.
+pub const WM_VOLUME_POPUP_MOVED: u32 = WM_APP + 0x45;
+/// Sent to the popup's owner whenever the balance slider moves, with the new balance (-100 for
+/// left only to 100 for right only) as an `i32` in WPARAM. LPARAM is 1 once it has stopped
+/// moving, i.e. it was let go of or the popup is closing, and 0 otherwise.
+pub const WM_BALANCE_CHANGED: u32 = WM_APP + 0x51;
+
+/// Name of the popup for `window_placement::placement_key`.
+pub const PLACEMENT_NAME: &str = "volume";
+
+// Sizes at 100% scaling, scaled to the DPI of the monitor the popup is on.
+const WIDTH: i32 = 220;
+const HEIGHT: i32 = 44;
+// Added below the volume for the balance slider.
+const BALANCE_HEIGHT: i32 = 32;
+const VOLUME_SLIDER_ID: i32 = 1;
+const BALANCE_SLIDER_ID: i32 = 2;
+// Not defined by the windows crate.
+const TBM_GETPOS: u32 = WM_USER;
+
+static REGISTER_CLASS: Once = Once::new();
+
+// The popup's size, with or without the balance slider.
+fn size(balance: bool, dpi: u32) -> (i32, i32) {
+    let height = HEIGHT + if balance { BALANCE_HEIGHT } else { 0 };
+    (dpi::scale(WIDTH, dpi), dpi::scale(height, dpi))
+}
+
+// Lays out the sliders for the popup's DPI.
+unsafe fn place_sliders(popup: HWND, dpi: u32) {
+    let scale = |size| dpi::scale(size, dpi);
+    let sliders = [
+        (VOLUME_SLIDER_ID, 8, HEIGHT - 18),
+        (BALANCE_SLIDER_ID, HEIGHT - 8, BALANCE_HEIGHT - 2),
+    ];
+    for (id, top, height) in sliders {
+        unsafe {
+            if let Ok(slider) = GetDlgItem(Some(popup), id) {
+                let _ = SetWindowPos(
+                    slider,
+                    None,
+                    scale(8),
+                    scale(top),
+                    scale(WIDTH - 18),
+                    scale(height),
+                    SWP_NOZORDER | SWP_NOACTIVATE,
+                );
+            }
+        }
+    }
+}
+
+/// Opens a small slider for the volume, with its bottom right corner at (`x`, `y`) (as the tray is
+/// usually in the bottom right of the screen) unless it was dragged to `placement` before. It sends
+/// `WM_VOLUME_CHANGED` to `owner` as it's adjusted by dragging, the mouse wheel or the keyboard,
+/// and closes when clicked away from. Stereo devices, which have a `balance`, also get a slider
+/// for it underneath that sends `WM_BALANCE_CHANGED`.
+pub fn show(
+    owner: HWND,
+    x: i32,
+    y: i32,
+    placement: Option<&WindowPlacement>,
+    percent: u32,
+    balance: Option<i32>,
+) -> Result<HWND, SwitcherooError> {
+    unsafe {
+        let module = GetModuleHandleW(None)?;
+        REGISTER_CLASS.call_once(|| {
+            InitCommonControlsEx(&INITCOMMONCONTROLSEX {
+                dwSize: std::mem::size_of::<INITCOMMONCONTROLSEX>() as u32,
+                dwICC: ICC_BAR_CLASSES,
+            })
+            .ok()
+            .unwrap_or_else(|e| log::error!("Failed to initialise common controls: {e}"));
+            RegisterClassExW(&WNDCLASSEXW {
+                cbSize: std::mem::size_of::<WNDCLASSEXW>() as u32,
+                lpfnWndProc: Some(popup_callback),
+                hInstance: module.into(),
+                lpszClassName: w!("SoundSwitcherooVolume"),
+                ..Default::default()
+            });
+        });
+
+        let (width, height) = size(balance.is_some(), dpi::for_point(x, y));
+        let popup = CreateWindowExW(
+            WS_EX_TOOLWINDOW | WS_EX_TOPMOST,
+            w!("SoundSwitcherooVolume"),
+            w!("Volume"),
+            WS_POPUP | WS_BORDER,
+            (x - width).max(0),
+            (y - height).max(0),
+            width,
+            height,
+            Some(owner),
+            None,
+            Some(module.into()),
+            None,
+        )?;
+        let slider = CreateWindowExW(
+            Default::default(),
+            TRACKBAR_CLASSW,
+            w!(""),
+            WS_CHILD | WS_VISIBLE | WINDOW_STYLE(TBS_HORZ | TBS_NOTICKS),
+            0,
+            0,
+            0,
+            0,
+            Some(popup),
+            Some(HMENU(VOLUME_SLIDER_ID as *mut _)),
+            Some(module.into()),
+            None,
+        )?;
+        SendMessageW(
+            slider,
+            TBM_SETRANGE,
+            Some(WPARAM(1)),
+            Some(LPARAM((100 << 16) as isize)),
+        );
+        SendMessageW(slider, TBM_SETPAGESIZE, None, Some(LPARAM(10)));
+        SendMessageW(
+            slider,
+            TBM_SETPOS,
+            Some(WPARAM(1)),
+            Some(LPARAM(percent.min(100) as isize)),
+        );
+        if let Some(balance) = balance {
+            // Ticked in the middle, as centred is where it usually belongs.
+            let balance_slider = CreateWindowExW(
+                Default::default(),
+                TRACKBAR_CLASSW,
+                w!(""),
+                WS_CHILD | WS_VISIBLE | WINDOW_STYLE(TBS_HORZ),
+                0,
+                0,
+                0,
+                0,
+                Some(popup),
+                Some(HMENU(BALANCE_SLIDER_ID as *mut _)),
+                Some(module.into()),
+                None,
+            )?;
+            // Trackbar positions are offset by 100, as the range can't start below zero.
+            SendMessageW(
+                balance_slider,
+                TBM_SETRANGE,
+                Some(WPARAM(1)),
+                Some(LPARAM((200 << 16) as isize)),
+            );
+            SendMessageW(balance_slider, TBM_SETPAGESIZE, None, Some(LPARAM(10)));
+            SendMessageW(balance_slider, TBM_SETTIC, None, Some(LPARAM(100)));
+            SendMessageW(
+                balance_slider,
+                TBM_SETPOS,
+                Some(WPARAM(1)),
+                Some(LPARAM((balance.clamp(-100, 100) + 100) as isize)),
+            );
+        }
+        let restored = match placement {
+            Some(placement) => window_placement::restore(popup, placement)?,
+            None => false,
+        };
+        let dpi = dpi::for_window(popup);
+        if restored {
+            // Where it was dragged to, but sized for whether there's a balance slider this time,
+            // and for the monitor that's on.
+            let (width, height) = size(balance.is_some(), dpi);
+            SetWindowPos(
+                popup,
+                None,
+                0,
+                0,
+                width,
+                height,
+                SWP_NOMOVE | SWP_NOZORDER | SWP_NOACTIVATE,
+            )?;
+        }
+        place_sliders(popup, dpi);
+        if !restored {
+            let _ = ShowWindow(popup, SW_SHOW);
+        }
+        // Needed to be told when the user clicks elsewhere, and for the wheel to reach the slider.
+        let _ = SetForegroundWindow(popup);
+        SetFocus(Some(slider))?;
+        Ok(popup)
+    }
+}
+
+unsafe extern "system" fn popup_callback(
+    hwnd: HWND,
+    msg: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    unsafe {
+        match msg {
+            WM_HSCROLL => {
+                let slider = HWND(lparam.0 as *mut _);
+                let position = SendMessageW(slider, TBM_GETPOS, None, None).0;
+                let finished = (wparam.0 & 0xffff) as u32 == TB_ENDTRACK;
+                let (message, value) = match GetDlgCtrlID(slider) {
+                    BALANCE_SLIDER_ID => (WM_BALANCE_CHANGED, position - 100),
+                    _ => (WM_VOLUME_CHANGED, position),
+                };
+                if let Ok(owner) = GetWindow(hwnd, GW_OWNER) {
+                    SendMessageW(
+                        owner,
+                        message,
+                        Some(WPARAM(value as usize)),
+                        Some(LPARAM(finished as isize)),
+                    );
+                }
+                LRESULT(0)
+            }
+            // Dragged by anywhere but the slider.
+            WM_NCHITTEST => match DefWindowProcW(hwnd, msg, wparam, lparam) {
+                LRESULT(hit) if hit == HTCLIENT as isize => LRESULT(HTCAPTION as isize),
+                hit => hit,
+            },
+            WM_EXITSIZEMOVE => {
+                if let Ok(owner) = GetWindow(hwnd, GW_OWNER) {
+                    SendMessageW(
+                        owner,
+                        WM_VOLUME_POPUP_MOVED,
+                        Some(WPARAM(hwnd.0 as usize)),
+                        None,
+                    );
+                }
+                LRESULT(0)
+            }
+            // Dragged to a monitor with another scale, or the scale changed.
+            WM_DPICHANGED => {
+                dpi::apply_suggested_rect(hwnd, lparam.0);
+                place_sliders(hwnd, (wparam.0 & 0xffff) as u32);
+                LRESULT(0)
+            }
+            WM_ACTIVATE if (wparam.0 & 0xffff) as u32 == WA_INACTIVE => {
+                debug!("Closing volume popup");
+                // The wheel and keyboard don't always end with TB_ENDTRACK.
+                if let Ok(balance_slider) = GetDlgItem(Some(hwnd), BALANCE_SLIDER_ID)
+                    && let Ok(owner) = GetWindow(hwnd, GW_OWNER)
+                {
+                    let position = SendMessageW(balance_slider, TBM_GETPOS, None, None).0;
+                    SendMessageW(
+                        owner,
+                        WM_BALANCE_CHANGED,
+                        Some(WPARAM((position - 100) as usize)),
+                        Some(LPARAM(1)),
+                    );
+                }
+                let _ = DestroyWindow(hwnd);
+                LRESULT(0)
+            }
+            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::fmt;
+use std::time::Duration;
+use windows::Win32::Media::Audio::{IAudioClient, AUDCLNT_SHAREMODE_SHARED};
+use windows::Win32::System::Com::{CoTaskMemFree, CLSCTX_ALL};
+
+use sound_switcheroo::com;
+use sound_switcheroo::error::SwitcherooError;
+use sound_switcheroo::rotation::{self, RotationEntry, RotationPolicy};
+use sound_switcheroo::safe_strings::with_wide_str;
+
+// The buffer the standby client asks for, in 100ns units. It's never played, so any will do.
+const STANDBY_BUFFER: i64 = 1_000_000;
+
+/// The device the next switch will most likely go to from `current`: the one the rotation goes to
+/// next, as the hotkeys and left click do, or else the most recently used of the rest, going by
+/// `last_used` (in Unix time).
+pub fn predict<'a, D: RotationEntry>(
+    current: &str,
+    devices: &'a [D],
+    policy: &RotationPolicy,
+    last_used: impl Fn(&str) -> Option<u64>,
+) -> Option<&'a D> {
+    rotation::select_next(current, devices, policy).or_else(|| {
+        devices
+            .iter()
+            .filter(|device| device.id() != current && device.selectable())
+            .filter_map(|device| Some((last_used(device.id())?, device)))
+            .max_by_key(|(used, _)| *used)
+            .map(|(_, device)| device)
+    })
+}
+
+/// A render client opened on an output ahead of switching to it, so the audio engine already has
+/// the endpoint up when the switch comes. Closed when dropped.
+pub struct WarmStandby {
+    device_id: String,
+    _client: IAudioClient,
+}
+
+impl WarmStandby {
+    pub fn open(device_id: &str) -> Result<Self, SwitcherooError> {
+        unsafe {
+            let device_enumerator = com::device_enumerator()?;
+            let device = with_wide_str(device_id, |id| device_enumerator.GetDevice(id))?;
+            let client: IAudioClient = device.Activate(CLSCTX_ALL, None)?;
+            let format = client.GetMixFormat()?;
+            // Initialised but never started, so nothing is played.
+            let initialized =
+                client.Initialize(AUDCLNT_SHAREMODE_SHARED, 0, STANDBY_BUFFER, 0, format, None);
+            CoTaskMemFree(Some(format as *const _));
+            initialized?;
+            Ok(Self {
+                device_id: device_id.to_string(),
+                _client: client,
+            })
+        }
+    }
+
+    pub fn device_id(&self) -> &str {
+        &self.device_id
+    }
+}
+
+/// How long making a device the default has taken, split by whether it was kept warm, for telling
+/// whether warm standby is worth it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SwitchTimings {
+    warm: (u32, Duration),
+    cold: (u32, Duration),
+}
+
+impl SwitchTimings {
+    pub fn record(&mut self, warm: bool, elapsed: Duration) {
+        let (count, total) = if warm { &mut self.warm } else { &mut self.cold };
+        *count += 1;
+        *total += elapsed;
+    }
+
+    /// The average of the warm switches and of the cold ones, if there have been any.
+    pub fn averages(&self) -> (Option<Duration>, Option<Duration>) {
+        let average = |(count, total): (u32, Duration)| (count > 0).then(|| total / count);
+        (average(self.warm), average(self.cold))
+    }
+}
+
+/// The averages and how many switches each is over, e.g. "warm: 12 ms over 3, cold: 48 ms over
+/// 5".
+impl fmt::Display for SwitchTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (warm, cold) = self.averages();
+        let describe = |average: Option<Duration>, count: u32| match average {
+            Some(average) => format!("{} ms over {count}", average.as_millis()),
+            None => "none yet".to_string(),
+        };
+        write!(
+            f,
+            "warm: {}, cold: {}",
+            describe(warm, self.warm.0),
+            describe(cold, self.cold.0)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Device(&'static str, bool);
+
+    impl RotationEntry for Device {
+        fn id(&self) -> &str {
+            self.0
+        }
+
+        fn selectable(&self) -> bool {
+            self.1
+        }
+
+        fn container(&self) -> Option<u128> {
+            None
+        }
+    }
+
+    #[test]
+    fn predicts_the_rotation_then_the_last_used() {
+        let devices = [
+            Device("speakers", true),
+            Device("headset", true),
+            Device("monitor", false),
+            Device("dock", true),
+        ];
+        let last_used = |id: &str| match id {
+            "speakers" => Some(100),
+            "headset" => Some(300),
+            "monitor" => Some(400),
+            _ => None,
+        };
+        let next = |current, policy| predict(current, &devices, &policy, last_used).unwrap().0;
+        assert_eq!(next("speakers", RotationPolicy::default()), "headset");
+        assert_eq!(next("headset", RotationPolicy::default()), "dock");
+        // The end of the list, with nowhere for the rotation to go.
+        let no_wrap = RotationPolicy {
+            wrap_around: false,
+            ..Default::default()
+        };
+        assert_eq!(next("dock", no_wrap), "headset");
+    }
+
+    #[test]
+    fn averages_warm_and_cold_switches_apart() {
+        let mut timings = SwitchTimings::default();
+        assert_eq!(timings.to_string(), "warm: none yet, cold: none yet");
+        timings.record(false, Duration::from_millis(40));
+        timings.record(false, Duration::from_millis(60));
+        timings.record(true, Duration::from_millis(12));
+        assert_eq!(
+            timings.averages(),
+            (
+                Some(Duration::from_millis(12)),
+                Some(Duration::from_millis(50))
+            )
+        );
+        assert_eq!(
+            timings.to_string(),
+            "warm: 12 ms over 1, cold: 50 ms over 2"
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use crate::error::SwitcherooError;
+use serde::{Deserialize, Serialize};
+use windows::Win32::Foundation::{HWND, LPARAM, RECT};
+use windows::Win32::Graphics::Gdi::{
+    EnumDisplayMonitors, MonitorFromRect, HDC, HMONITOR, MONITOR_DEFAULTTONULL,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetWindowPlacement, SetWindowPlacement, SW_SHOWMAXIMIZED, SW_SHOWNORMAL, WINDOWPLACEMENT,
+};
+use windows_core::BOOL;
+
+/// Where a window was left, as its restored (not maximised) rectangle in the workspace
+/// coordinates that `GetWindowPlacement` uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowPlacement {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+    #[serde(default)]
+    pub maximized: bool,
+}
+
+/// Key for `Config::windows`, so each window is remembered separately for each arrangement of
+/// monitors (e.g. docked and undocked).
+pub fn placement_key(window: &str, monitor_layout: &str) -> String {
+    format!("{window}@{monitor_layout}")
+}
+
+/// Describes the connected monitors by their positions and sizes, e.g.
+/// `0,0,1920x1080;1920,0,2560x1440`.
+pub fn monitor_layout() -> String {
+    unsafe extern "system" fn collect(
+        _monitor: HMONITOR,
+        _dc: HDC,
+        rect: *mut RECT,
+        monitors: LPARAM,
+    ) -> BOOL {
+        unsafe {
+            (*(monitors.0 as *mut Vec<RECT>)).push(*rect);
+        }
+        true.into()
+    }
+
+    let mut monitors: Vec<RECT> = Vec::new();
+    unsafe {
+        let _ = EnumDisplayMonitors(
+            None,
+            None,
+            Some(collect),
+            LPARAM(&mut monitors as *mut _ as isize),
+        );
+    }
+    monitors.sort_by_key(|rect| (rect.left, rect.top));
+    monitors
+        .iter()
+        .map(|rect| {
+            format!(
+                "{},{},{}x{}",
+                rect.left,
+                rect.top,
+                rect.right - rect.left,
+                rect.bottom - rect.top
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// Reads where `window` is now.
+pub fn get(window: HWND) -> Result<WindowPlacement, SwitcherooError> {
+    let mut placement = WINDOWPLACEMENT {
+        length: std::mem::size_of::<WINDOWPLACEMENT>() as u32,
+        ..Default::default()
+    };
+    unsafe { GetWindowPlacement(window, &mut placement)? };
+    let rect = placement.rcNormalPosition;
+    Ok(WindowPlacement {
+        left: rect.left,
+        top: rect.top,
+        right: rect.right,
+        bottom: rect.bottom,
+        maximized: placement.showCmd == SW_SHOWMAXIMIZED.0 as u32,
+    })
+}
+
+/// Moves `window` back to where it was left and shows it. Returns false without touching it if
+/// that's no longer on any monitor, e.g. because the monitor was unplugged.
+pub fn restore(window: HWND, placement: &WindowPlacement) -> Result<bool, SwitcherooError> {
+    let rect = RECT {
+        left: placement.left,
+        top: placement.top,
+        right: placement.right,
+        bottom: placement.bottom,
+    };
+    if unsafe { MonitorFromRect(&rect, MONITOR_DEFAULTTONULL) }.is_invalid() {
+        return Ok(false);
+    }
+    let show = if placement.maximized {
+        SW_SHOWMAXIMIZED
+    } else {
+        SW_SHOWNORMAL
+    };
+    unsafe {
+        SetWindowPlacement(
+            window,
+            &WINDOWPLACEMENT {
+                length: std::mem::size_of::<WINDOWPLACEMENT>() as u32,
+                showCmd: show.0 as u32,
+                rcNormalPosition: rect,
+                ..Default::default()
+            },
+        )?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_json() {
+        let placement = WindowPlacement {
+            left: -1900,
+            top: 20,
+            right: -1680,
+            bottom: 64,
+            maximized: false,
+        };
+        let json = serde_json::to_string(&placement).unwrap();
+        assert_eq!(
+            serde_json::from_str::<WindowPlacement>(&json).unwrap(),
+            placement
+        );
+        assert_eq!(
+            placement_key("volume", "0,0,1920x1080"),
+            "volume@0,0,1920x1080"
+        );
+    }
+}